@@ -1,7 +1,10 @@
 MEMORY
 {
   /* NOTE K = KiBi = 1024 bytes */
-  FLASH : ORIGIN = 0x08000000, LENGTH = 512K 
+  /* sectors 0 to 5 hold the program */
+  FLASH : ORIGIN = 0x08000000, LENGTH = 256K
+  /* sectors 6 and 7 (128K each) are reserved for the configuration, see `interfaces::InternalFlash` */
+  CONFIG : ORIGIN = 0x08040000, LENGTH = 256K
   RAM : ORIGIN = 0x20000000, LENGTH = 128K
 }
 
@@ -9,3 +12,6 @@ MEMORY
 /* The stack is of the full descending type. */
 /* NOTE Do NOT modify `_stack_start` unless you know what you are doing */
 _stack_start = ORIGIN(RAM) + LENGTH(RAM);
+
+/* The `.uninit` section of cortex-m-rt's link.x is `NOLOAD` in RAM, it keeps the crash report */
+/* through a reset, see `interfaces::CrashStorage` */
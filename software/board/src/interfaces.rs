@@ -1,13 +1,25 @@
-use core::cell::RefCell;
+use core::cell::{Cell, RefCell};
+use core::mem::MaybeUninit;
+use core::sync::atomic::{AtomicU32, Ordering};
 
+use bbqueue::{Consumer, Producer};
+use cortex_m::interrupt::Mutex;
 use embedded_hal::PwmPin;
+use firmware::crash::{self, CrashReport, ResetCause, UnitState, CRASH_MESSAGE_SIZE};
+use firmware::recovery::MAX_RUN_UNITS;
 use firmware::traits;
-use stm32f4xx_hal::adc::Adc;
+use heapless::{String, Vec};
+use stm32f4xx_hal::flash::{FlashExt, LockedFlash};
 use stm32f4xx_hal::gpio::GpioExt;
-use stm32f4xx_hal::gpio::{Alternate, Analog, Input, Output, Pin, PinState, PushPull};
-use stm32f4xx_hal::pac::{Peripherals, ADC1};
+use stm32f4xx_hal::gpio::{Alternate, ErasedPin, Input, Output, Pin, PinState, PushPull};
+use stm32f4xx_hal::pac::{self, Peripherals, TIM1};
+use stm32f4xx_hal::rtc::{Lsi, Rtc};
 use stm32f4xx_hal::timer::pwm::PwmChannel;
 use stm32f4xx_hal::timer::{Instance, PwmHz};
+use stm32f4xx_hal::watchdog::IndependentWatchdog;
+use time::PrimitiveDateTime;
+
+use crate::uart::QUEUE_SIZE;
 
 // +--------------------------------------------------------------------------+
 // |                               GPIO Output                                |
@@ -36,29 +48,53 @@ impl traits::GpioOutput for GpioOutput<'A', 5, Output<PushPull>> {
     }
 }
 
+/// A GPIO output with the pin erased from its type, so the outputs of all battery test units
+/// share one type
+pub struct ErasedGpioOutput {
+    pin: ErasedPin<Output<PushPull>>,
+}
+
+impl ErasedGpioOutput {
+    pub fn new(pin: ErasedPin<Output<PushPull>>) -> Self {
+        Self { pin }
+    }
+}
+
+impl traits::GpioOutput for ErasedGpioOutput {
+    fn set_output(&mut self, value: bool) {
+        self.pin.set_state(PinState::from(value));
+    }
+
+    fn get_output(&mut self) -> bool {
+        match self.pin.get_state() {
+            PinState::High => true,
+            PinState::Low => false,
+        }
+    }
+}
+
 // +--------------------------------------------------------------------------+
-// |                                ADC Input                                 |
+// |                                Shared Bus                                |
 // +--------------------------------------------------------------------------+
 
-pub struct AdcInput<const P: char, const N: u8, MODE = Analog> {
-    adc: Adc<ADC1>,
-    pin: Pin<P, N, MODE>,
+/// A bus that is used by several drivers, like the I2C bus all ADS7828s are connected to
+pub struct SharedBus<T> {
+    inner: Mutex<RefCell<T>>,
 }
 
-impl<const P: char, const N: u8, MODE> AdcInput<P, N, MODE> {
-    pub fn new(pin: Pin<P, N, MODE>, adc: Adc<ADC1>) -> Self {
-        Self { pin, adc }
+impl<T> SharedBus<T> {
+    pub fn new(value: T) -> Self {
+        Self {
+            inner: Mutex::new(RefCell::new(value)),
+        }
     }
 }
 
-impl traits::AdcInput for AdcInput<'A', 0, Analog> {
-    fn get_voltage(&mut self) -> f32 {
-        let sample = self.adc.convert(
-            &mut self.pin,
-            stm32f4xx_hal::adc::config::SampleTime::Cycles_112,
-        );
+impl<T> traits::SharedResource for SharedBus<T> {
+    type Target = T;
 
-        self.adc.sample_to_millivolts(sample) as f32 / 1000.0
+    fn lock<R>(&self, f: impl FnOnce(&mut T) -> R) -> R {
+        cortex_m::interrupt::free(|cs| f(&mut self.inner.borrow(cs).borrow_mut()))
     }
 }
 
@@ -93,15 +129,356 @@ where
     }
 }
 
+/// The load PWM of a battery test unit, one of the four channels of TIM1. The channels are
+/// distinct types, this allows all battery test units to share one type.
+pub enum LoadPwmChannel {
+    Channel1(PwmChannel<TIM1, 0>),
+    Channel2(PwmChannel<TIM1, 1>),
+    Channel3(PwmChannel<TIM1, 2>),
+    Channel4(PwmChannel<TIM1, 3>),
+}
+
+macro_rules! dispatch_load_pwm_channel {
+    ($self:expr, $channel:ident => $body:expr) => {
+        match $self {
+            LoadPwmChannel::Channel1($channel) => $body,
+            LoadPwmChannel::Channel2($channel) => $body,
+            LoadPwmChannel::Channel3($channel) => $body,
+            LoadPwmChannel::Channel4($channel) => $body,
+        }
+    };
+}
+
+impl PwmPin for LoadPwmChannel {
+    type Duty = u16;
+
+    fn disable(&mut self) {
+        dispatch_load_pwm_channel!(self, channel => channel.disable())
+    }
+
+    fn enable(&mut self) {
+        dispatch_load_pwm_channel!(self, channel => channel.enable())
+    }
+
+    fn get_duty(&self) -> Self::Duty {
+        dispatch_load_pwm_channel!(self, channel => channel.get_duty())
+    }
+
+    fn get_max_duty(&self) -> Self::Duty {
+        dispatch_load_pwm_channel!(self, channel => channel.get_max_duty())
+    }
+
+    fn set_duty(&mut self, duty: Self::Duty) {
+        dispatch_load_pwm_channel!(self, channel => channel.set_duty(duty))
+    }
+}
+
+// +--------------------------------------------------------------------------+
+// |                                  Flash                                   |
+// +--------------------------------------------------------------------------+
+
+/// The `CONFIG` region of `memory.x`, offsets count from its start
+pub struct InternalFlash {
+    flash: LockedFlash,
+}
+
+impl InternalFlash {
+    /// Offset of sector 6 from the start of the flash
+    const START: usize = 0x4_0000;
+    const FIRST_SECTOR: u8 = 6;
+    const SECTOR_SIZE: u32 = 128 * 1024;
+
+    pub fn new(flash: LockedFlash) -> Self {
+        Self { flash }
+    }
+}
+
+impl traits::Flash for InternalFlash {
+    fn sector_size(&self) -> u32 {
+        Self::SECTOR_SIZE
+    }
+
+    fn read(&mut self, offset: u32, buf: &mut [u8]) {
+        let start = Self::START + offset as usize;
+        buf.copy_from_slice(&self.flash.read()[start..start + buf.len()]);
+    }
+
+    fn write(&mut self, offset: u32, data: &[u8]) -> Result<(), traits::FlashError> {
+        self.flash
+            .unlocked()
+            .program(Self::START + offset as usize, data.iter())
+            .map_err(|_| traits::FlashError)
+    }
+
+    /// Takes one to two seconds, the CPU stalls while it fetches instructions from the flash
+    fn erase(&mut self, sector: u8) -> Result<(), traits::FlashError> {
+        self.flash
+            .unlocked()
+            .erase(Self::FIRST_SECTOR + sector)
+            .map_err(|_| traits::FlashError)
+    }
+}
+
+// +--------------------------------------------------------------------------+
+// |                                  Clocks                                  |
+// +--------------------------------------------------------------------------+
+
+/// The time of the last read of the monotonic clock, in ms, for the panic handler
+pub static UPTIME_MILLIS: AtomicU32 = AtomicU32::new(0);
+
+/// Extends the 32 bit µs counter of the monotonic timer to 64 bit. It has to be read at least
+/// once per overflow, every 71 minutes.
+pub struct MonotonicClock {
+    /// reads the counter
+    now: fn() -> u32,
+    /// counter at the last read
+    last: u32,
+    /// overflows so far, in the upper 32 bits
+    high: u64,
+}
+
+impl MonotonicClock {
+    pub fn new(now: fn() -> u32) -> Self {
+        Self {
+            now,
+            last: 0,
+            high: 0,
+        }
+    }
+}
+
+impl traits::SystemTime for MonotonicClock {
+    fn get_micros(&mut self) -> u64 {
+        let now = (self.now)();
+        if now < self.last {
+            self.high += 1 << 32;
+        }
+        self.last = now;
+        let micros = self.high | now as u64;
+        UPTIME_MILLIS.store((micros / 1000) as u32, Ordering::Relaxed);
+        micros
+    }
+}
+
+/// The RTC of the STM32, clocked by the LSI
+pub struct RealTimeClock {
+    rtc: Rtc<Lsi>,
+}
+
+impl RealTimeClock {
+    pub fn new(rtc: Rtc<Lsi>) -> Self {
+        Self { rtc }
+    }
+}
+
+impl traits::RealTimeClock for RealTimeClock {
+    /// Only the years from 1970 to 2069
+    fn set_datetime(&mut self, datetime: PrimitiveDateTime) -> Result<(), traits::RtcError> {
+        self.rtc
+            .set_datetime(&datetime)
+            .map_err(|_| traits::RtcError)
+    }
+
+    fn get_datetime(&mut self) -> PrimitiveDateTime {
+        self.rtc.get_datetime()
+    }
+}
+
+// +--------------------------------------------------------------------------+
+// |                                 Watchdog                                 |
+// +--------------------------------------------------------------------------+
+
+pub struct Watchdog {
+    iwdg: IndependentWatchdog,
+    caused_reset: bool,
+}
+
+impl Watchdog {
+    /// `caused_reset` from the reset flags of the RCC, `iwdg` is running already
+    pub fn new(iwdg: IndependentWatchdog, caused_reset: bool) -> Self {
+        Self { iwdg, caused_reset }
+    }
+}
+
+impl traits::Watchdog for Watchdog {
+    fn feed(&mut self) {
+        self.iwdg.feed();
+    }
+
+    fn caused_reset(&self) -> bool {
+        self.caused_reset
+    }
+}
+
+/// The 20 backup registers of the RTC, they keep their content through a reset as long as the
+/// backup domain isn't reset
+pub struct BackupRegisters {}
+
+impl BackupRegisters {
+    const COUNT: usize = 20;
+
+    pub fn new() -> Self {
+        Self {}
+    }
+
+    fn registers() -> &'static [pac::rtc::BKPR; Self::COUNT] {
+        // the RTC belongs to `Rtc`, which never touches the backup registers
+        #[allow(unsafe_code)]
+        let rtc = unsafe { &*pac::RTC::ptr() };
+        &rtc.bkpr
+    }
+}
+
+impl traits::RetainedMemory for BackupRegisters {
+    fn capacity(&self) -> usize {
+        Self::COUNT * 4
+    }
+
+    fn read(&mut self, buf: &mut [u8]) {
+        for (bytes, register) in buf.chunks_mut(4).zip(Self::registers()) {
+            let value = register.read().bkp().bits().to_le_bytes();
+            bytes.copy_from_slice(&value[..bytes.len()]);
+        }
+    }
+
+    fn write(&mut self, data: &[u8]) {
+        for (bytes, register) in data.chunks(4).zip(Self::registers()) {
+            let mut value = [0; 4];
+            value[..bytes.len()].copy_from_slice(bytes);
+            register.write(|w| w.bkp().bits(u32::from_le_bytes(value)));
+        }
+    }
+}
+
+// +--------------------------------------------------------------------------+
+// |                               Crash Report                               |
+// +--------------------------------------------------------------------------+
+
+/// The cause of the last reset from the reset flags of the RCC, they have to be cleared after
+pub fn reset_cause(csr: &pac::rcc::csr::R) -> ResetCause {
+    // the reset pin is pulled low on every reset, its flag is always set
+    if csr.wdgrstf().bit_is_set() || csr.wwdgrstf().bit_is_set() {
+        ResetCause::Watchdog
+    } else if csr.sftrstf().bit_is_set() {
+        ResetCause::Software
+    } else if csr.lpwrrstf().bit_is_set() {
+        ResetCause::LowPower
+    } else if csr.porrstf().bit_is_set() {
+        ResetCause::PowerOn
+    } else if csr.borrstf().bit_is_set() {
+        ResetCause::Brownout
+    } else {
+        ResetCause::Pin
+    }
+}
+
+/// How the board started, for the panic handler
+static RESET_CAUSE: Mutex<Cell<ResetCause>> = Mutex::new(Cell::new(ResetCause::PowerOn));
+/// What the units did at the last update, for the panic handler
+static UNIT_STATES: Mutex<RefCell<Vec<UnitState, MAX_RUN_UNITS>>> =
+    Mutex::new(RefCell::new(Vec::new()));
+
+/// Keeps the cause of the reset at the start for the crash report
+pub fn set_reset_cause(reset_cause: ResetCause) {
+    cortex_m::interrupt::free(|cs| RESET_CAUSE.borrow(cs).set(reset_cause));
+}
+
+/// Keeps what the units do for the crash report, after every update
+pub fn set_unit_states(units: Vec<UnitState, MAX_RUN_UNITS>) {
+    cortex_m::interrupt::free(|cs| *UNIT_STATES.borrow(cs).borrow_mut() = units);
+}
+
+/// The report of a panic with the state that was kept before it
+pub fn crash_report(message: String<CRASH_MESSAGE_SIZE>) -> CrashReport {
+    cortex_m::interrupt::free(|cs| CrashReport {
+        message,
+        uptime: UPTIME_MILLIS.load(Ordering::Relaxed) as u64,
+        reset_cause: RESET_CAUSE.borrow(cs).get(),
+        // empty if it panicked while the states were replaced
+        units: UNIT_STATES
+            .borrow(cs)
+            .try_borrow()
+            .map(|units| units.clone())
+            .unwrap_or_default(),
+    })
+}
+
+/// The crash report lives at the start of the RAM that isn't initialized, it keeps its content
+/// through a reset. The `.uninit` section of the linker script of cortex-m-rt is `NOLOAD`, the
+/// startup code neither zeroes nor copies it.
+// `link_section` on a static is unsafe, the linker could place it over other data. It's sound
+// here, the section only holds statics that start uninitialized, and `MaybeUninit` of bytes
+// is valid with any content.
+#[allow(unsafe_code)]
+#[link_section = ".uninit.CRASH_RECORD"]
+static mut CRASH_RECORD: MaybeUninit<[u8; crash::RECORD_SIZE]> = MaybeUninit::uninit();
+
+/// Reserved RAM for the crash report, the panic handler writes it
+pub struct CrashStorage {}
+
+impl CrashStorage {
+    pub fn new() -> Self {
+        Self {}
+    }
+
+    fn record() -> *mut u8 {
+        // only accessed in the panic handler and at the start, never at the same time
+        #[allow(unsafe_code)]
+        unsafe {
+            CRASH_RECORD.as_mut_ptr() as *mut u8
+        }
+    }
+}
+
+impl traits::RetainedMemory for CrashStorage {
+    fn capacity(&self) -> usize {
+        crash::RECORD_SIZE
+    }
+
+    fn read(&mut self, buf: &mut [u8]) {
+        for (i, byte) in buf.iter_mut().take(crash::RECORD_SIZE).enumerate() {
+            // the content is random after power up, volatile keeps the compiler from assuming
+            // anything about it
+            #[allow(unsafe_code)]
+            unsafe {
+                *byte = Self::record().add(i).read_volatile();
+            }
+        }
+    }
+
+    fn write(&mut self, data: &[u8]) {
+        for (i, &byte) in data.iter().take(crash::RECORD_SIZE).enumerate() {
+            #[allow(unsafe_code)]
+            unsafe {
+                Self::record().add(i).write_volatile(byte);
+            }
+        }
+    }
+}
+
 // +--------------------------------------------------------------------------+
 // |                             Serial Receiver                              |
 // +--------------------------------------------------------------------------+
 
-pub struct SerialReceiver {}
+/// Reads the packages the USART2 interrupt puts into `cons_rx`
+pub struct SerialReceiver {
+    cons_rx: Consumer<'static, QUEUE_SIZE>,
+}
+
+impl SerialReceiver {
+    pub fn new(cons_rx: Consumer<'static, QUEUE_SIZE>) -> Self {
+        Self { cons_rx }
+    }
+}
 
 impl traits::SerialReceiver for SerialReceiver {
-    fn receive(&mut self, cb: impl FnMut(firmware::msg_types::MsgTypes)) {
-        unimplemented!()
+    fn receive(&mut self) -> Option<Result<firmware::msg_types::MsgTypes, traits::InvalidPacket>> {
+        let mut received = None;
+        transmission::receive::receive::<firmware::msg_types::MsgTypes, QUEUE_SIZE>(
+            &mut self.cons_rx,
+            |val| received = Some(val.map_err(|_| traits::InvalidPacket)),
+        );
+        received
     }
 }
 
@@ -109,10 +486,19 @@ impl traits::SerialReceiver for SerialReceiver {
 // |                            Serial Transmitter                            |
 // +--------------------------------------------------------------------------+
 
-pub struct SerialTransmitter {}
+pub struct SerialTransmitter {
+    prod_tx: Producer<'static, QUEUE_SIZE>,
+}
+
+impl SerialTransmitter {
+    pub fn new(prod_tx: Producer<'static, QUEUE_SIZE>) -> Self {
+        Self { prod_tx }
+    }
+}
 
 impl traits::SerialTransmitter for SerialTransmitter {
     fn transmit(&mut self, msg: firmware::msg_types::MsgTypes) {
-        unimplemented!()
+        // if the client doesn't read fast enough the message is dropped
+        transmission::send::send(&mut self.prod_tx, msg).ok();
     }
 }
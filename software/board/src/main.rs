@@ -8,88 +8,135 @@ use stm32f4xx_hal::gpio::Alternate;
 use stm32f4xx_hal::timer::PwmChannel;
 
 use crate::interfaces::*;
-use bbqueue::{BBBuffer, Consumer, Producer};
+use bbqueue::BBBuffer;
 use firmware::msg_types::MsgTypes;
 use heapless::String;
-use stm32f4xx_hal::block;
 use stm32f4xx_hal::serial::Event;
+use stm32f4xx_hal::timer::MonoTimerUs;
 use stm32f4xx_hal::{
-    adc::{
-        config::{AdcConfig, SampleTime, Sequence},
-        Adc,
-    },
-    gpio::{Analog, Output, Pin, PushPull},
+    gpio::{Output, Pin, PushPull},
+    i2c::I2c,
     pac,
     prelude::*,
-    rtc::{Lse, Lsi, Rtc},
+    rtc::Rtc,
     serial::*,
+    spi::Spi,
     timer,
 };
-use systick_monotonic::{fugit::Duration, Systick};
-use time::PrimitiveDateTime;
-use transmission::{
-    receive::receive,
-    send::{send, setup},
-};
+use transmission::send::{send, setup};
 // use firmware::
 
 mod interfaces;
 mod panic_handler;
-
+mod spi_flash;
+mod uart;
+
+type I2cBus = interfaces::SharedBus<I2c<pac::I2C1, (Pin<'B', 8>, Pin<'B', 9>)>>;
+type AdcChannel = firmware::ads7828::Ads7828Channel<'static, I2cBus>;
+type CurrentSensor = firmware::sensors::ShuntCurrentSensor<AdcChannel>;
+type TemperatureSensor = firmware::sensors::NtcThermistor<AdcChannel>;
+type ChargeDoneInput = firmware::sensors::AdcDigitalInput<AdcChannel>;
+type LogStorage = spi_flash::Sst25vf080b<
+    Spi<pac::SPI3, (Pin<'C', 10>, Pin<'C', 11>, Pin<'C', 12>)>,
+    Pin<'D', 2, Output<PushPull>>,
+>;
 type Firmware = firmware::Firmware<
     interfaces::SerialReceiver,
     interfaces::SerialTransmitter,
     interfaces::GpioOutput<'A', 5, Output<PushPull>>,
-    interfaces::AdcInput<'A', 0, Analog>,
-    interfaces::PwmOutput<PwmChannel<pac::TIM1, 1>>,
+    interfaces::InternalFlash,
+    LogStorage,
+    interfaces::MonotonicClock,
+    interfaces::RealTimeClock,
+    interfaces::Watchdog,
+    interfaces::BackupRegisters,
+    interfaces::CrashStorage,
+    AdcChannel,
+    CurrentSensor,
+    TemperatureSensor,
+    interfaces::PwmOutput<interfaces::LoadPwmChannel>,
+    interfaces::ErasedGpioOutput,
+    ChargeDoneInput,
+    BATTERY_TEST_UNITS,
 >;
 type BatteryTestUnit = firmware::BatteryTestUnit<
-    interfaces::AdcInput<'A', 0, Analog>,
-    interfaces::PwmOutput<PwmChannel<pac::TIM1, 1>>,
+    AdcChannel,
+    CurrentSensor,
+    TemperatureSensor,
+    interfaces::PwmOutput<interfaces::LoadPwmChannel>,
+    interfaces::ErasedGpioOutput,
+    ChargeDoneInput,
 >;
+
+/// One load PWM channel of TIM1 per battery test unit, every ADS7828 samples two of them
+const BATTERY_TEST_UNITS: usize = 4;
+/// Speed of USART2 to the client, the client has to use the same
+const BAUD_RATE: u32 = 921_600;
+/// Resets the board if the battery test units weren't updated for this long, in ms. Has to be
+/// longer than `MAX_SAMPLE_PERIOD` and `WATCHDOG_TOLERANCE` together.
+const WATCHDOG_TIMEOUT: u32 = 2000;
+
 #[app(device = stm32f4xx_hal::pac, peripherals = true, dispatchers = [TIM2 ])]
 mod app {
+    use embedded_hal::PwmPin as _;
     use firmware::{
-        traits::{PwmOutput, SerialReceiver},
-        BatteryTestUnitMode,
+        ads7828::{self, Ads7828},
+        clock::Clock,
+        config::{ConfigError, DEFAULT_SAMPLE_PERIOD},
+        crash::ResetCause,
+        datalog::MeasurementLog,
+        limits::SafetyLimits,
+        recovery::Recovery,
+        sensors::{
+            AdcDigitalInput, NtcModel, NtcThermistor, ShuntCurrentSensor, INA181A2_GAIN,
+            SHUNT_RESISTANCE,
+        },
+        traits::SerialTransmitter as _,
     };
     use heapless::pool::Box;
-    use stm32f4xx_hal::{
-        gpio::Analog,
-        pac::TIM1,
-        timer::{Channel, Pwm, PwmChannel},
-    };
+    use stm32f4xx_hal::dma::StreamsTuple;
+    use stm32f4xx_hal::flash::LockedFlash;
+    use stm32f4xx_hal::watchdog::IndependentWatchdog;
 
-    use crate::interfaces::SerialTransmitter;
+    use crate::interfaces::{
+        BackupRegisters, CrashStorage, ErasedGpioOutput, InternalFlash, LoadPwmChannel,
+        MonotonicClock, RealTimeClock, SerialReceiver, SerialTransmitter, SharedBus, Watchdog,
+    };
+    use crate::uart::{DmaReceiver, DmaTransmitter, QUEUE_SIZE};
 
     use super::*;
 
-    static UART_RX_BUFFER: BBBuffer<1024> = BBBuffer::new();
-    static UART_TX_BUFFER: BBBuffer<1024> = BBBuffer::new();
+    /// Voltage of the AP2138N-2.5 reference on the hat, in V
+    const ADC_REFERENCE_VOLTAGE: f32 = 2.5;
+
+    static UART_RX_BUFFER: BBBuffer<QUEUE_SIZE> = BBBuffer::new();
+    static UART_TX_BUFFER: BBBuffer<QUEUE_SIZE> = BBBuffer::new();
 
     #[shared]
     struct Shared {
-        prod_tx: Producer<'static, 1024>,
-        cons_rx: Consumer<'static, 1024>,
         // adc: Adc<pac::ADC1>,
-        rtc: Rtc<Lsi>,
         fm: Firmware,
+        #[lock_free]
+        receiver: DmaReceiver,
     }
 
     #[local]
     struct Local<'_> {
-        rx: Rx<pac::USART2>,
-        tx: Tx<pac::USART2>,
-
-        prod_rx: Producer<'static, 1024>,
-        cons_tx: Consumer<'static, 1024>,
+        transmitter: DmaTransmitter,
     }
 
-    #[monotonic(binds = SysTick, default = true)]
-    type Tonic = Systick<1000>;
+    #[monotonic(binds = TIM5, default = true)]
+    type Tonic = MonoTimerUs<pac::TIM5>;
 
-    #[init]
+    #[init(local = [i2c: Option<I2cBus> = None])]
     fn init(mut ctx: init::Context) -> (Shared, Local, init::Monotonics) {
+        let reset_cause = interfaces::reset_cause(&ctx.device.RCC.csr.read());
+        ctx.device.RCC.csr.modify(|_, w| w.rmvf().set_bit());
+        interfaces::set_reset_cause(reset_cause);
+        // the HAL resets the backup domain if the LSI isn't ready when the RTC is set up, a reset
+        // turns it off
+        ctx.device.RCC.csr.modify(|_, w| w.lsion().set_bit());
+        while ctx.device.RCC.csr.read().lsirdy().bit_is_clear() {}
         let rcc = ctx.device.RCC.constrain();
         let _clocks = rcc.cfgr.sysclk(48.MHz()).freeze();
 
@@ -102,73 +149,168 @@ mod app {
 
         // let led = Pin::<'A', 5, Output<PushPull>>::new();
 
-        let config = AdcConfig::default();
-
-        let analog = gpioa.pa0.into_analog();
-        let mut adc = Adc::adc1(ctx.device.ADC1, true, config);
-
-        adc.configure_channel(&analog, Sequence::One, SampleTime::Cycles_112);
-        adc.enable();
-        adc.start_conversion();
-
-        let a = AdcInput::new(analog, adc);
-
-        let mut pwm_pin = gpioa.pa9.into_alternate();
-        let mut pwm = ctx.device.TIM1.pwm_hz(pwm_pin, 50.kHz(), &_clocks).split();
-
-        let max_duty = pwm.get_max_duty();
-        pwm.enable();
-        // pwm.set_duty(390);
-
-        let mut p = interfaces::PwmOutput::<PwmChannel<TIM1, 1>> { pwm };
+        // all sensors of the battery test units are sampled by the ADS7828s on the hat
+        let gpiob = ctx.device.GPIOB.split();
+        let i2c = ctx
+            .device
+            .I2C1
+            .i2c((gpiob.pb8, gpiob.pb9), 400.kHz(), &_clocks);
+        let i2c: &'static I2cBus = ctx.local.i2c.insert(SharedBus::new(i2c));
+
+        let pwm_pins = (
+            gpioa.pa8.into_alternate(),
+            gpioa.pa9.into_alternate(),
+            gpioa.pa10.into_alternate(),
+            gpioa.pa11.into_alternate(),
+        );
+        let (pwm_1, pwm_2, pwm_3, pwm_4) =
+            ctx.device.TIM1.pwm_hz(pwm_pins, 50.kHz(), &_clocks).split();
+        let max_duty = pwm_1.get_max_duty();
+
+        // SIG_CHARGE of every unit enables its TP5000 charger
+        let gpioc = ctx.device.GPIOC.split();
+        let outputs = [
+            (
+                LoadPwmChannel::Channel1(pwm_1),
+                gpioc.pc0.into_push_pull_output().erase(),
+            ),
+            (
+                LoadPwmChannel::Channel2(pwm_2),
+                gpioc.pc1.into_push_pull_output().erase(),
+            ),
+            (
+                LoadPwmChannel::Channel3(pwm_3),
+                gpioc.pc2.into_push_pull_output().erase(),
+            ),
+            (
+                LoadPwmChannel::Channel4(pwm_4),
+                gpioc.pc3.into_push_pull_output().erase(),
+            ),
+        ];
+
+        // unit n uses the inputs 0..=3 or 4..=7 of the ADS7828 with the address pins n / 2,
+        // the fourth input samples ~SIG_CHRG_DONE
+        let mut id = 0;
+        let battery_units = outputs.map(|(mut pwm, charger_enable)| {
+            let ads7828 = Ads7828::new(
+                id / 2,
+                ads7828::Reference::External(ADC_REFERENCE_VOLTAGE),
+                ads7828::PowerMode::AlwaysOn,
+            );
+            let first_input = (id % 2) * 4;
+            let channel =
+                |offset| ads7828.channel(i2c, ads7828::Channel::SingleEnded(first_input + offset));
+
+            pwm.enable();
+            let unit = BatteryTestUnit::new(
+                id,
+                channel(0),
+                ShuntCurrentSensor::new(channel(1), INA181A2_GAIN, SHUNT_RESISTANCE, 0.0),
+                NtcThermistor::new(
+                    channel(2),
+                    NtcModel::ntc_10k(3950.0),
+                    10_000.0,
+                    ADC_REFERENCE_VOLTAGE,
+                ),
+                interfaces::PwmOutput { pwm },
+                ErasedGpioOutput::new(charger_enable),
+                AdcDigitalInput::new(channel(3), ADC_REFERENCE_VOLTAGE / 2.0, true),
+            );
+            id += 1;
+            unit
+        });
 
-        p.set_duty_cycle(550);
+        // the SST25VF080B on the hat holds the measurement log
+        let gpiod = ctx.device.GPIOD.split();
+        let spi = ctx.device.SPI3.spi(
+            (gpioc.pc10, gpioc.pc11, gpioc.pc12),
+            embedded_hal::spi::MODE_0,
+            8.MHz(),
+            &_clocks,
+        );
+        let mut log_storage = spi_flash::Sst25vf080b::new(spi, gpiod.pd2.into_push_pull_output());
+        let log_storage_found = log_storage.init().is_ok();
 
         // let val = adc.current_sample();
         // let val = adc.convert(&analog, SampleTime::Cycles_112);
 
-        let mut mono = Systick::new(ctx.core.SYST, 48_000_000);
+        // 32 bit µs counter, the firmware extends it to 64 bit
+        let mono = ctx.device.TIM5.monotonic_us(&_clocks);
 
         let mut s = Serial::new(
             ctx.device.USART2,
             (gpioa.pa2, gpioa.pa3),
             Config::default()
-                .baudrate(115200.bps())
+                .baudrate(BAUD_RATE.bps())
                 .wordlength_8()
-                .parity_none(),
+                .parity_none()
+                .dma(stm32f4xx_hal::serial::config::DmaConfig::TxRx),
             &_clocks,
         )
         .unwrap();
 
-        s.listen(Event::Rxne);
+        s.listen(Event::Idle);
 
         let (tx, rx) = s.split();
         let (prod_rx, cons_rx) = UART_RX_BUFFER.try_split().unwrap();
         let (mut prod_tx, cons_tx) = UART_TX_BUFFER.try_split().unwrap();
+        let streams = StreamsTuple::new(ctx.device.DMA1);
+        let receiver = DmaReceiver::new(streams.5, rx, prod_rx);
+        let transmitter = DmaTransmitter::new(streams.6, tx, cons_tx);
 
-        let mut rtc = Rtc::lsi_with_config(ctx.device.RTC, &mut ctx.device.PWR, 249, 127);
-        rtc.set_datetime(&time::PrimitiveDateTime::new(
-            time::Date::from_calendar_date(2000, time::Month::January, 1).unwrap(),
-            time::Time::from_hms(0, 0, 0).unwrap(),
-        ));
+        // keeps the date and the backup registers through a reset, the client sets the date
+        let rtc = Rtc::lsi_with_config(ctx.device.RTC, &mut ctx.device.PWR, 249, 127);
+
+        let mut iwdg = IndependentWatchdog::new(ctx.device.IWDG);
+        iwdg.start(WATCHDOG_TIMEOUT.millis());
 
         // btu.set_mode(BatteryTestUnitMode::Discharging(1.3));
 
+        setup(&mut prod_tx);
+        send(&mut prod_tx, MsgTypes::Msg(String::from("Init done"))).unwrap();
+        send(&mut prod_tx, MsgTypes::SampleAdcResult(max_duty)).unwrap();
+
         let mut fm = Firmware {
-            serial_receiver: interfaces::SerialReceiver {},
-            serial_transmitter: SerialTransmitter {},
+            serial_receiver: SerialReceiver::new(cons_rx),
+            serial_transmitter: SerialTransmitter::new(prod_tx),
             on_board_led: GpioOutput::new(led),
-            btu1: BatteryTestUnit::new(a, p),
+            flash: InternalFlash::new(LockedFlash::new(ctx.device.FLASH)),
+            sample_period: DEFAULT_SAMPLE_PERIOD,
+            log_storage,
+            log: MeasurementLog::new(),
+            monotonic: MonotonicClock::new(|| monotonics::now().ticks()),
+            rtc: RealTimeClock::new(rtc),
+            clock: Clock::new(),
+            watchdog: Watchdog::new(iwdg, reset_cause == ResetCause::Watchdog),
+            retained: BackupRegisters::new(),
+            crash_storage: CrashStorage::new(),
+            recovery: Recovery::new(),
+            battery_units,
         };
+        fm.load_time();
 
-        fm.btu1.set_mode(BatteryTestUnitMode::Discharging(1.3));
+        // the defaults until a configuration is saved
+        for btu in fm.battery_units.iter_mut() {
+            btu.set_limits(SafetyLimits::LIFEPO4);
+        }
+        match fm.load_config() {
+            Ok(()) => {}
+            Err(ConfigError::Empty) => fm
+                .serial_transmitter
+                .transmit(MsgTypes::Msg(String::from("No configuration stored"))),
+            Err(error) => fm.serial_transmitter.transmit(MsgTypes::ConfigError(error)),
+        }
+        if !log_storage_found {
+            fm.serial_transmitter
+                .transmit(MsgTypes::Msg(String::from("External flash not found")));
+        } else if let Err(error) = fm.log.recover(&mut fm.log_storage) {
+            fm.serial_transmitter.transmit(MsgTypes::LogError(error));
+        }
+        fm.check_crash_report(reset_cause);
+        fm.check_interrupted_tests();
 
         blink::spawn().ok();
         update_btu::spawn().ok();
-
-        setup(&mut prod_tx);
-        send(&mut prod_tx, MsgTypes::Msg(String::from("Init done"))).unwrap();
-        send(&mut prod_tx, MsgTypes::SampleAdcResult(max_duty)).unwrap();
         // send(&mut prod_tx, MsgTypes::SampleAdcResult(1234)).unwrap();
         // send(&mut prod_tx, MsgTypes::SampleAdcResult(t.millisecond())).unwrap();
 
@@ -187,118 +329,58 @@ mod app {
 
         (
             Shared {
-                prod_tx,
-                cons_rx,
                 // adc,
-                rtc,
                 fm,
+                receiver,
             },
-            Local {
-                rx,
-                tx,
-                prod_rx,
-                cons_tx,
-            },
+            Local { transmitter },
             init::Monotonics(mono),
         )
     }
 
-    #[task(shared = [ rtc, prod_tx, fm ], priority = 4)]
+    /// Runs whenever the next battery test unit has to be sampled, the firmware measures the time
+    /// since the last run on the monotonic clock
+    #[task(shared = [fm], priority = 4)]
     fn update_btu(mut ctx: update_btu::Context) {
-        let t = ctx.shared.rtc.lock(|rtc| rtc.get_datetime());
-        let time = t.second() as f32 / 2.0;
-
-        ctx.shared.fm.lock(|fm| {
-            fm.update_battery_units(time, 0.0);
-        });
-
-        // ctx.shared.btu.lock(|btu| {
-        //     btu.update(time, 0.0);
-        // });
-
-        ctx.shared.prod_tx.lock(|prod_tx| {
-            send(prod_tx, MsgTypes::SampleAdcResult(time as u16)).unwrap();
+        let period_ms = ctx.shared.fm.lock(|fm| {
+            fm.update();
+            interfaces::set_unit_states(fm.get_unit_states());
+            fm.get_update_period() as u32
         });
+        // sends the telemetry right away
+        rtic::pend(pac::Interrupt::DMA1_STREAM6);
 
-        update_btu::spawn_after(Duration::<u64, 1, 1000>::from_ticks(100)).ok();
+        update_btu::spawn_after(period_ms.millis()).ok();
     }
 
-    #[task(local = [tx, cons_tx], shared =[fm, prod_tx, cons_rx,  rtc], priority = 4)]
+    /// Toggles the LED, feeds the watchdog and lets the firmware handle the received commands
+    #[task(shared = [fm], priority = 4)]
     fn blink(mut ctx: blink::Context) {
-        macro_rules! handle_msg {
-            ($ctx:expr, $msg:expr) => {
-                match $msg {
-                    MsgTypes::Ping(number) => {
-                        $ctx.shared.prod_tx.lock(|prod_tx| {
-                            send(prod_tx, MsgTypes::Ping(number + 1)).unwrap();
-                        });
-                    }
-                    // MsgTypes::SampleAdc(channel) => {
-                    // $ctx.shared.prod_tx.lock(|prod_tx| {
-                    // $ctx.shared.adc.lock(|adc| {
-                    //     let val = adc.convert(ctx.local.adc1, SampleTime::Cycles_112);
-                    //     send(prod_tx, MsgTypes::SampleAdcResult(val)).unwrap();
-                    // });
-                    // });
-                    // }
-                    _ => {}
-                }
-            };
-        }
-
-        // let t = monotonics::now()
-        //     .checked_duration_since(Systick::zero())
-        //     .unwrap();
-        // ctx.shared.prod_tx.lock(|prod_tx| {
-        //     send(prod_tx, MsgTypes::SampleAdcResult(t.to_millis() as u32)).unwrap();
-        // });
-
         ctx.shared.fm.lock(|fm| {
             fm.toggle_on_board_led();
+            fm.update_watchdog();
+            fm.update_serial();
         });
+        rtic::pend(pac::Interrupt::DMA1_STREAM6);
 
-        match ctx.local.cons_tx.read() {
-            Ok(rgr) => {
-                rgr.buf()
-                    .iter()
-                    .for_each(|&byte| block!(ctx.local.tx.write(byte)).unwrap());
-
-                let len = rgr.len();
-                rgr.release(len);
-            }
-            _ => (),
-        };
+        blink::spawn_after(50.millis()).ok();
+    }
 
-        ctx.shared.cons_rx.lock(|cons_rx| {
-            receive(cons_rx, |val| match val {
-                Ok(msg) => {
-                    handle_msg!(ctx, msg);
-                }
-                Err(_) => {
-                    ctx.shared.prod_tx.lock(|prod_tx| {
-                        send(
-                            prod_tx,
-                            MsgTypes::Msg(String::from("Board dropped an invalid packet")),
-                        )
-                        .unwrap();
-                    });
-                }
-            });
-        });
+    /// The line went idle after the client sent something
+    #[task(binds = USART2, shared = [receiver], priority = 5)]
+    fn serial_idle(ctx: serial_idle::Context) {
+        ctx.shared.receiver.on_interrupt();
+    }
 
-        blink::spawn_after(Duration::<u64, 1, 1000>::from_ticks(50)).ok();
+    /// The DMA filled one of the RX buffers
+    #[task(binds = DMA1_STREAM5, shared = [receiver], priority = 5)]
+    fn serial_rx(ctx: serial_rx::Context) {
+        ctx.shared.receiver.on_interrupt();
     }
 
-    #[task(binds = USART2, local = [rx, prod_rx])]
-    fn serial(ctx: serial::Context) {
-        match block!(ctx.local.rx.read()) {
-            Ok(byte) => {
-                if let Ok(mut wgr) = ctx.local.prod_rx.grant_exact(1) {
-                    wgr[0] = byte;
-                    wgr.commit(1);
-                }
-            }
-            Err(_) => {}
-        }
+    /// The DMA sent a part of the TX queue, or the firmware transmitted something
+    #[task(binds = DMA1_STREAM6, local = [transmitter], priority = 5)]
+    fn serial_tx(ctx: serial_tx::Context) {
+        ctx.local.transmitter.on_interrupt();
     }
 }
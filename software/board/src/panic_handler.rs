@@ -1,13 +1,28 @@
 use bbqueue::BBBuffer;
 use core::fmt::Write;
+use firmware::crash;
 use firmware::msg_types::MsgTypes;
 use heapless::String;
 use stm32f4xx_hal::block;
+use stm32f4xx_hal::watchdog::IndependentWatchdog;
 use stm32f4xx_hal::{pac, prelude::*, serial::*};
 use transmission::send::{send, setup};
 
+use crate::interfaces::{self, CrashStorage};
+
+/// Resets the board after the panic, the firmware sends the crash report to the client at the
+/// next start. Otherwise the LED blinks until the board is reset by hand.
+const RESET_AFTER_PANIC: bool = true;
+
 #[panic_handler]
 fn panic(info: &core::panic::PanicInfo) -> ! {
+    // saved first, the report survives a panic in the code below
+    let mut message = String::new();
+    // cut off if it is too long
+    write!(message, "{}", info).ok();
+    let report = interfaces::crash_report(message);
+    crash::save(&mut CrashStorage::new(), &report);
+
     #[allow(unsafe_code)]
     let dp = unsafe { pac::Peripherals::steal() };
 
@@ -17,33 +32,41 @@ fn panic(info: &core::panic::PanicInfo) -> ! {
     let rcc = dp.RCC.constrain();
     let _clocks = rcc.cfgr.sysclk(48.MHz()).freeze();
 
-    // TODO: Move the config to a variable and use it in the init function
     let mut tx: Tx<pac::USART2, u8> = Serial::tx(
         dp.USART2,
         gpioa.pa2,
         Config::default()
-            .baudrate(115200.bps())
+            .baudrate(crate::BAUD_RATE.bps())
             .wordlength_8()
             .parity_none(),
         &_clocks,
     )
     .unwrap();
 
-    let mut msg: String<128> = heapless::String::new();
-    write!(msg, "{}", info).unwrap();
-
     let buf: BBBuffer<128> = BBBuffer::new();
     let (mut prod, mut cons) = buf.try_split().unwrap();
 
     setup(&mut prod);
-    send(&mut prod, MsgTypes::Msg(msg)).unwrap();
+    send(
+        &mut prod,
+        MsgTypes::Msg(String::from(report.message.as_str())),
+    )
+    .unwrap();
     send(&mut prod, MsgTypes::Ping(128)).unwrap();
 
     cons.read().unwrap().iter().for_each(|&byte| {
         block!(tx.write(byte)).unwrap();
     });
+    block!(tx.flush()).ok();
+
+    if RESET_AFTER_PANIC {
+        cortex_m::peripheral::SCB::sys_reset();
+    }
 
+    // started in the init, it would reset the board
+    let mut watchdog = IndependentWatchdog::new(dp.IWDG);
     loop {
+        watchdog.feed();
         led.set_low();
         cortex_m::asm::delay(4_000_000);
         led.set_high();
@@ -0,0 +1,147 @@
+//! SST25VF080B, the 8 Mbit SPI NOR flash on the hat that holds the measurement log
+
+use embedded_hal::blocking::spi::{Transfer, Write};
+use embedded_hal::digital::v2::OutputPin;
+use firmware::traits::{BlockStorage, FlashError};
+
+const READ: u8 = 0x03;
+const SECTOR_ERASE: u8 = 0x20;
+const CHIP_ERASE: u8 = 0x60;
+const BYTE_PROGRAM: u8 = 0x02;
+const READ_STATUS: u8 = 0x05;
+const ENABLE_WRITE_STATUS: u8 = 0x50;
+const WRITE_STATUS: u8 = 0x01;
+const WRITE_ENABLE: u8 = 0x06;
+const JEDEC_ID: u8 = 0x9F;
+
+/// Set while a byte is programmed or a sector is erased
+const STATUS_BUSY: u8 = 0x01;
+/// Manufacturer SST, memory type and capacity of the SST25VF080B
+const DEVICE_ID: [u8; 3] = [0xBF, 0x25, 0x8E];
+
+pub struct Sst25vf080b<SPI, CE> {
+    spi: SPI,
+    /// ~CE, low while a command is sent
+    chip_enable: CE,
+}
+
+impl<SPI, CE> Sst25vf080b<SPI, CE>
+where
+    SPI: Transfer<u8> + Write<u8>,
+    CE: OutputPin,
+{
+    const CAPACITY: u32 = 1024 * 1024;
+    /// The smallest part that can be erased
+    const SECTOR_SIZE: u32 = 4096;
+
+    pub fn new(spi: SPI, mut chip_enable: CE) -> Self {
+        chip_enable.set_high().ok();
+        Self { spi, chip_enable }
+    }
+
+    /// Checks that the flash answers and clears the block protection it powers up with
+    pub fn init(&mut self) -> Result<(), FlashError> {
+        let mut id = [JEDEC_ID, 0, 0, 0];
+        self.transfer(&mut id)?;
+        if id[1..] != DEVICE_ID {
+            return Err(FlashError);
+        }
+
+        self.command(&[ENABLE_WRITE_STATUS])?;
+        self.command(&[WRITE_STATUS, 0])
+    }
+
+    fn select<R>(
+        &mut self,
+        f: impl FnOnce(&mut SPI) -> Result<R, FlashError>,
+    ) -> Result<R, FlashError> {
+        self.chip_enable.set_low().map_err(|_| FlashError)?;
+        let result = f(&mut self.spi);
+        self.chip_enable.set_high().map_err(|_| FlashError)?;
+        result
+    }
+
+    fn command(&mut self, bytes: &[u8]) -> Result<(), FlashError> {
+        self.select(|spi| spi.write(bytes).map_err(|_| FlashError))
+    }
+
+    fn transfer(&mut self, bytes: &mut [u8]) -> Result<(), FlashError> {
+        self.select(|spi| spi.transfer(bytes).map(|_| ()).map_err(|_| FlashError))
+    }
+
+    /// Every program or erase command has to be preceded by a write enable, the flash clears it
+    /// once it is done
+    fn write_enabled(&mut self, bytes: &[u8]) -> Result<(), FlashError> {
+        self.command(&[WRITE_ENABLE])?;
+        self.command(bytes)?;
+        self.wait_ready()
+    }
+
+    fn wait_ready(&mut self) -> Result<(), FlashError> {
+        loop {
+            let mut status = [READ_STATUS, 0];
+            self.transfer(&mut status)?;
+            if status[1] & STATUS_BUSY == 0 {
+                return Ok(());
+            }
+        }
+    }
+
+    fn check_range(address: u32, len: usize) -> Result<(), FlashError> {
+        match address.checked_add(len as u32) {
+            Some(end) if end <= Self::CAPACITY => Ok(()),
+            _ => Err(FlashError),
+        }
+    }
+}
+
+/// The command followed by the 24 bit address, most significant byte first
+fn with_address(command: u8, address: u32) -> [u8; 4] {
+    let [_, high, middle, low] = address.to_be_bytes();
+    [command, high, middle, low]
+}
+
+impl<SPI, CE> BlockStorage for Sst25vf080b<SPI, CE>
+where
+    SPI: Transfer<u8> + Write<u8>,
+    CE: OutputPin,
+{
+    fn capacity(&self) -> u32 {
+        Self::CAPACITY
+    }
+
+    fn block_size(&self) -> u32 {
+        Self::SECTOR_SIZE
+    }
+
+    fn read(&mut self, address: u32, buf: &mut [u8]) -> Result<(), FlashError> {
+        Self::check_range(address, buf.len())?;
+        self.select(|spi| {
+            spi.write(&with_address(READ, address))
+                .map_err(|_| FlashError)?;
+            spi.transfer(buf).map(|_| ()).map_err(|_| FlashError)
+        })
+    }
+
+    /// Byte by byte, every byte takes about 10 µs to program
+    fn write(&mut self, address: u32, data: &[u8]) -> Result<(), FlashError> {
+        Self::check_range(address, data.len())?;
+        for (offset, byte) in data.iter().enumerate() {
+            let [command, high, middle, low] = with_address(BYTE_PROGRAM, address + offset as u32);
+            self.write_enabled(&[command, high, middle, low, *byte])?;
+        }
+        Ok(())
+    }
+
+    /// Takes up to 25 ms
+    fn erase_block(&mut self, block: u32) -> Result<(), FlashError> {
+        let address = block * Self::SECTOR_SIZE;
+        Self::check_range(address, Self::SECTOR_SIZE as usize)?;
+        self.write_enabled(&with_address(SECTOR_ERASE, address))
+    }
+
+    /// Takes up to 50 ms
+    fn erase_all(&mut self) -> Result<(), FlashError> {
+        self.write_enabled(&[CHIP_ERASE])
+    }
+}
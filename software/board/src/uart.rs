@@ -0,0 +1,180 @@
+//! USART2 with DMA. The receiver fills two buffers in turn without stopping, whenever the line
+//! goes idle or a buffer is full the new bytes are moved into the RX queue. The transmitter sends
+//! the TX queue straight out of its grants.
+
+use core::ptr::{addr_of, addr_of_mut};
+
+use bbqueue::{Consumer, GrantR, Producer};
+use stm32f4xx_hal::dma::config::DmaConfig;
+use stm32f4xx_hal::dma::traits::{Stream, StreamISR};
+use stm32f4xx_hal::dma::{
+    CurrentBuffer, MemoryToPeripheral, PeripheralToMemory, Stream5, Stream6, Transfer,
+};
+use stm32f4xx_hal::pac::{DMA1, USART2};
+use stm32f4xx_hal::serial::{Rx, Tx};
+
+/// Size of the RX and TX queues between the interrupts and the firmware
+pub const QUEUE_SIZE: usize = 1024;
+/// Size of each of the two RX buffers, the interrupt has to empty one before the DMA filled the
+/// other one, at 921600 baud within about 0.7 ms
+const RX_BUFFER_SIZE: usize = 64;
+
+/// Only the DMA writes them, the receiver reads the bytes the DMA is done with
+static mut RX_BUFFERS: [[u8; RX_BUFFER_SIZE]; 2] = [[0; RX_BUFFER_SIZE]; 2];
+
+type RxTransfer =
+    Transfer<Stream5<DMA1>, 4, Rx<USART2>, PeripheralToMemory, &'static mut [u8; RX_BUFFER_SIZE]>;
+type TxTransfer = Transfer<Stream6<DMA1>, 4, Tx<USART2>, MemoryToPeripheral, &'static [u8]>;
+
+// +--------------------------------------------------------------------------+
+// |                               DMA Receiver                               |
+// +--------------------------------------------------------------------------+
+
+pub struct DmaReceiver {
+    transfer: RxTransfer,
+    /// buffer the next byte is read from and its position in there
+    buffer: CurrentBuffer,
+    position: usize,
+    prod_rx: Producer<'static, QUEUE_SIZE>,
+}
+
+impl DmaReceiver {
+    /// Can only be created once, `rx` has to listen for the idle line
+    pub fn new(
+        stream: Stream5<DMA1>,
+        rx: Rx<USART2>,
+        prod_rx: Producer<'static, QUEUE_SIZE>,
+    ) -> Self {
+        // handed to the DMA once, afterwards only read by `push`
+        #[allow(unsafe_code)]
+        let (first, second) = unsafe {
+            (
+                &mut *addr_of_mut!(RX_BUFFERS[0]),
+                &mut *addr_of_mut!(RX_BUFFERS[1]),
+            )
+        };
+        // switching between two buffers the DMA never stops
+        let config = DmaConfig::default()
+            .memory_increment(true)
+            .double_buffer(true)
+            .transfer_complete_interrupt(true);
+        let mut transfer =
+            Transfer::init_peripheral_to_memory(stream, rx, first, Some(second), config);
+        transfer.start(|_| {});
+        Self {
+            transfer,
+            buffer: CurrentBuffer::FirstBuffer,
+            position: 0,
+            prod_rx,
+        }
+    }
+
+    /// Moves the bytes received so far into the RX queue, on the idle line interrupt of the USART
+    /// and the transfer complete interrupt of the DMA. Bytes that don't fit into the queue are
+    /// dropped.
+    pub fn on_interrupt(&mut self) {
+        // reading SR and then DR clears the idle flag, while the line is idle DR holds no byte
+        // for the DMA
+        #[allow(unsafe_code)]
+        let usart = unsafe { &*USART2::ptr() };
+        usart.sr.read();
+        usart.dr.read();
+        self.transfer.clear_transfer_complete_interrupt();
+
+        loop {
+            let current = Stream5::<DMA1>::current_buffer();
+            if current == self.buffer {
+                let remaining = Stream5::<DMA1>::get_number_of_transfers() as usize;
+                // right after switching the buffers it counts for the other one
+                self.push(RX_BUFFER_SIZE.saturating_sub(remaining));
+                break;
+            }
+            // the DMA filled the buffer and continues with the other one
+            self.push(RX_BUFFER_SIZE);
+            self.buffer = current;
+            self.position = 0;
+        }
+    }
+
+    /// Moves the bytes up to `end` of the current buffer
+    fn push(&mut self, end: usize) {
+        if end <= self.position {
+            return;
+        }
+        let Ok(mut grant) = self.prod_rx.grant_exact(end - self.position) else {
+            self.position = end;
+            return;
+        };
+        let index = match self.buffer {
+            CurrentBuffer::FirstBuffer => 0,
+            CurrentBuffer::DoubleBuffer => 1,
+        };
+        for (offset, byte) in grant.iter_mut().enumerate() {
+            // the DMA is done with the bytes before `end`, volatile as it wrote them behind the
+            // compiler's back
+            #[allow(unsafe_code)]
+            unsafe {
+                *byte = addr_of!(RX_BUFFERS[index])
+                    .cast::<u8>()
+                    .add(self.position + offset)
+                    .read_volatile();
+            }
+        }
+        grant.commit(end - self.position);
+        self.position = end;
+    }
+}
+
+// +--------------------------------------------------------------------------+
+// |                             DMA Transmitter                              |
+// +--------------------------------------------------------------------------+
+
+pub struct DmaTransmitter {
+    transfer: TxTransfer,
+    cons_tx: Consumer<'static, QUEUE_SIZE>,
+    /// the grant the DMA sends
+    sending: Option<GrantR<'static, QUEUE_SIZE>>,
+}
+
+impl DmaTransmitter {
+    pub fn new(
+        stream: Stream6<DMA1>,
+        tx: Tx<USART2>,
+        cons_tx: Consumer<'static, QUEUE_SIZE>,
+    ) -> Self {
+        let config = DmaConfig::default()
+            .memory_increment(true)
+            .transfer_complete_interrupt(true);
+        // nothing to send until the first grant
+        let transfer = Transfer::init_memory_to_peripheral(stream, tx, &[][..], None, config);
+        Self {
+            transfer,
+            cons_tx,
+            sending: None,
+        }
+    }
+
+    /// Sends the next part of the TX queue once the DMA is done with the last one, on the transfer
+    /// complete interrupt of the DMA and after the firmware transmitted something
+    pub fn on_interrupt(&mut self) {
+        if self.sending.is_some() {
+            if !Stream6::<DMA1>::get_transfer_complete_flag() {
+                return;
+            }
+            self.transfer.clear_transfer_complete_interrupt();
+            if let Some(grant) = self.sending.take() {
+                let len = grant.len();
+                grant.release(len);
+            }
+        }
+
+        let Ok(grant) = self.cons_tx.read() else {
+            return;
+        };
+        // the grant is kept until the DMA is done with it
+        #[allow(unsafe_code)]
+        let buf = unsafe { grant.as_static_buf() };
+        self.transfer.next_transfer(buf).ok();
+        self.sending = Some(grant);
+    }
+}
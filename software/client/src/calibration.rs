@@ -0,0 +1,80 @@
+//! Text format of the calibrations of a battery test unit, the version followed by the
+//! coefficients c0 to c3 of every channel. Empty lines and everything after `#` are ignored.
+//!
+//! ```text
+//! version 3
+//! voltage -0.0102 1.0204 0 0 # V = c0 + c1 * raw + c2 * raw² + c3 * raw³
+//! current 0.012 0.998 0 0
+//! temperature 0 1 0 0
+//! ```
+
+use std::fs::File;
+use std::io::Write;
+
+use firmware::calibration::{Calibration, CalibrationSet, CALIBRATION_COEFFICIENTS};
+
+pub fn parse(text: &str) -> Result<CalibrationSet, String> {
+    let mut calibration = CalibrationSet::default();
+    let mut version = None;
+
+    for (number, line) in text.lines().enumerate() {
+        let line = line.split('#').next().unwrap().trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        let values = line.split_whitespace().collect::<Vec<_>>();
+        if let ["version", value] = values.as_slice() {
+            version = Some(
+                value
+                    .parse()
+                    .map_err(|_| format!("line {}: invalid version '{}'", number + 1, value))?,
+            );
+            continue;
+        }
+        if values.len() != CALIBRATION_COEFFICIENTS + 1 {
+            return Err(format!(
+                "line {}: expected a channel and {} coefficients",
+                number + 1,
+                CALIBRATION_COEFFICIENTS
+            ));
+        }
+
+        let channel = match values[0] {
+            "voltage" => &mut calibration.voltage,
+            "current" => &mut calibration.current,
+            "temperature" => &mut calibration.temperature,
+            name => return Err(format!("line {}: unknown channel '{}'", number + 1, name)),
+        };
+        for (coefficient, value) in channel.coefficients.iter_mut().zip(&values[1..]) {
+            *coefficient = value
+                .parse()
+                .map_err(|_| format!("line {}: invalid number '{}'", number + 1, value))?;
+        }
+    }
+
+    calibration.version = version.ok_or_else(|| "the version is missing".to_string())?;
+    calibration
+        .validate()
+        .map_err(|_| "a coefficient isn't a number".to_string())?;
+    Ok(calibration)
+}
+
+/// Writes the calibrations in the format `parse` reads
+pub fn save(path: &str, calibration: &CalibrationSet) -> std::io::Result<()> {
+    let mut file = File::create(path)?;
+    writeln!(file, "version {}", calibration.version)?;
+    let channels = [
+        ("voltage", calibration.voltage),
+        ("current", calibration.current),
+        ("temperature", calibration.temperature),
+    ];
+    for (name, Calibration { coefficients }) in channels {
+        write!(file, "{}", name)?;
+        for coefficient in coefficients {
+            write!(file, " {}", coefficient)?;
+        }
+        writeln!(file)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,201 @@
+//! CSV file the measurement log is downloaded to, one record per line. A download appends to an
+//! existing file and resumes after its last record.
+//!
+//! ```text
+//! sequence,timestamp,unit,step,voltage,current,temperature
+//! 0,1500,2,0,3.291,0.998,24.8
+//! ```
+
+use std::fs::{File, OpenOptions};
+use std::io::{ErrorKind, Write};
+
+use firmware::datalog::LogRecord;
+
+const HEADER: &str = "sequence,timestamp,unit,step,voltage,current,temperature";
+
+pub struct LogDownload {
+    pub path: String,
+    file: File,
+    /// sequence number of the next record to append
+    next: u32,
+    /// records appended since the download started
+    pub received: u32,
+    /// records that were overwritten on the board before they were downloaded
+    pub skipped: u32,
+    /// the next record may be newer than requested, the older ones were erased already
+    first: bool,
+    /// a record went missing on the way, the rest of the transfer is requested again once it
+    /// ended
+    lost: bool,
+}
+
+impl LogDownload {
+    /// Opens the file and finds the record to continue with
+    pub fn start(path: String) -> Result<Self, String> {
+        let next = match std::fs::read_to_string(&path) {
+            Ok(text) => match text.lines().rev().find(|line| !line.trim().is_empty()) {
+                Some(line) if line != HEADER => {
+                    let sequence = line.split(',').next().unwrap();
+                    sequence
+                        .parse::<u32>()
+                        .map_err(|_| format!("invalid last line '{}'", line))?
+                        + 1
+                }
+                _ => 0,
+            },
+            Err(error) if error.kind() == ErrorKind::NotFound => 0,
+            Err(error) => return Err(error.to_string()),
+        };
+
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .map_err(|error| error.to_string())?;
+        if file.metadata().map_err(|error| error.to_string())?.len() == 0 {
+            writeln!(file, "{}", HEADER).map_err(|error| error.to_string())?;
+        }
+
+        Ok(Self {
+            path,
+            file,
+            next,
+            received: 0,
+            skipped: 0,
+            first: true,
+            lost: false,
+        })
+    }
+
+    /// Sequence number to request the records from
+    pub fn get_next(&self) -> u32 {
+        self.next
+    }
+
+    pub fn append(&mut self, record: &LogRecord) -> std::io::Result<()> {
+        if self.lost || record.sequence < self.next {
+            return Ok(());
+        }
+        if record.sequence > self.next {
+            if !self.first {
+                self.lost = true;
+                return Ok(());
+            }
+            self.skipped += record.sequence - self.next;
+        }
+
+        let optional = |value: Option<String>| value.unwrap_or_default();
+        writeln!(
+            self.file,
+            "{},{},{},{},{},{},{}",
+            record.sequence,
+            record.timestamp,
+            record.unit,
+            optional(record.step.map(|step| step.to_string())),
+            record.voltage,
+            record.current,
+            optional(
+                record
+                    .temperature
+                    .map(|temperature| temperature.to_string())
+            )
+        )?;
+        self.next = record.sequence + 1;
+        self.received += 1;
+        self.first = false;
+        Ok(())
+    }
+
+    /// Whether all records up to `end` were received, otherwise the rest has to be requested
+    /// again from `get_next`
+    pub fn finish(&mut self, end: u32) -> bool {
+        self.first = true;
+        self.lost = false;
+        self.next >= end
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(sequence: u32) -> LogRecord {
+        LogRecord {
+            sequence,
+            timestamp: 1500,
+            unit: 2,
+            step: Some(0),
+            voltage: 3.25,
+            current: 1.0,
+            temperature: None,
+        }
+    }
+
+    /// A path in the temporary directory that doesn't exist yet
+    fn path(name: &str) -> String {
+        let path = std::env::temp_dir().join(format!("{}_{}.csv", name, std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        path.to_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn test_resume() {
+        let path = path("log_resume");
+        let mut download = LogDownload::start(path.clone()).unwrap();
+        assert_eq!(download.get_next(), 0);
+        download.append(&record(0)).unwrap();
+        download.append(&record(1)).unwrap();
+        assert!(!download.finish(3));
+
+        // continues after the last record of the file
+        let mut download = LogDownload::start(path.clone()).unwrap();
+        assert_eq!(download.get_next(), 2);
+        download.append(&record(2)).unwrap();
+        assert!(download.finish(3));
+
+        let text = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(
+            text.lines().collect::<Vec<_>>(),
+            [
+                HEADER,
+                "0,1500,2,0,3.25,1,",
+                "1,1500,2,0,3.25,1,",
+                "2,1500,2,0,3.25,1,"
+            ]
+        );
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_missing_records() {
+        let path = path("log_missing");
+        let mut download = LogDownload::start(path.clone()).unwrap();
+
+        // the oldest records were overwritten on the board
+        download.append(&record(5)).unwrap();
+        assert_eq!(download.skipped, 5);
+        // later a gap means a record was lost on the way
+        download.append(&record(7)).unwrap();
+        download.append(&record(8)).unwrap();
+        assert_eq!(download.received, 1);
+        assert!(!download.finish(9));
+        assert_eq!(download.get_next(), 6);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_invalid_file() {
+        let path = path("log_invalid");
+        std::fs::write(
+            &path,
+            format!("{}\n0,1500,2,0,3.25,1,\nlast,1500\n", HEADER),
+        )
+        .unwrap();
+        assert_eq!(
+            LogDownload::start(path.clone()).err(),
+            Some("invalid last line 'last,1500'".to_string())
+        );
+        std::fs::remove_file(&path).unwrap();
+    }
+}
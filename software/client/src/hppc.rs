@@ -0,0 +1,159 @@
+//! Text format of HPPC configurations, one `name value` pair per line. Empty lines and everything
+//! after `#` are ignored, values that aren't given keep their defaults.
+//!
+//! ```text
+//! nominal_capacity 3.0        # Ah, required
+//! discharge_current 3.0       # A, required
+//! discharge_pulse_current 3.0 # A, required
+//! min_voltage 2.5             # V, required
+//! soc_step 0.1
+//! rest_duration 3600          # s
+//! discharge_pulse_duration 10 # s
+//! pulse_rest_duration 40      # s
+//! charge_pulse_duration 10    # s
+//! window 10                   # s of the rest recorded before the pulses
+//! sample_interval 0.1         # s
+//! ```
+
+use std::fs::OpenOptions;
+use std::io::Write;
+
+use firmware::hppc::{HppcConfig, HppcData};
+
+pub fn parse(text: &str) -> Result<HppcConfig, String> {
+    let mut config = HppcConfig {
+        nominal_capacity: f32::NAN,
+        soc_step: 0.1,
+        discharge_current: f32::NAN,
+        rest_duration: 3600.0,
+        discharge_pulse_current: f32::NAN,
+        discharge_pulse_duration: 10.0,
+        pulse_rest_duration: 40.0,
+        charge_pulse_duration: 10.0,
+        min_voltage: f32::NAN,
+        window: 10.0,
+        sample_interval: 0.1,
+    };
+
+    for (number, line) in text.lines().enumerate() {
+        let line = line.split('#').next().unwrap().trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        let (name, value) = match line.split_whitespace().collect::<Vec<_>>().as_slice() {
+            [name, value] => (*name, *value),
+            _ => return Err(format!("line {}: expected a name and a value", number + 1)),
+        };
+        let value = value
+            .parse::<f32>()
+            .map_err(|_| format!("line {}: invalid number '{}'", number + 1, value))?;
+        let field = match name {
+            "nominal_capacity" => &mut config.nominal_capacity,
+            "soc_step" => &mut config.soc_step,
+            "discharge_current" => &mut config.discharge_current,
+            "rest_duration" => &mut config.rest_duration,
+            "discharge_pulse_current" => &mut config.discharge_pulse_current,
+            "discharge_pulse_duration" => &mut config.discharge_pulse_duration,
+            "pulse_rest_duration" => &mut config.pulse_rest_duration,
+            "charge_pulse_duration" => &mut config.charge_pulse_duration,
+            "min_voltage" => &mut config.min_voltage,
+            "window" => &mut config.window,
+            "sample_interval" => &mut config.sample_interval,
+            _ => return Err(format!("line {}: unknown value '{}'", number + 1, name)),
+        };
+        *field = value;
+    }
+
+    config
+        .validate()
+        .map_err(|_| "a required value is missing or a value is out of range".to_string())?;
+    Ok(config)
+}
+
+/// Appends the samples to `hppc_<unit>.csv`, one `step,time,voltage,current` line per sample
+pub fn save(unit: u8, data: &HppcData) -> std::io::Result<()> {
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(format!("hppc_{}.csv", unit))?;
+    for sample in &data.samples {
+        writeln!(
+            file,
+            "{},{},{},{}",
+            data.step, sample.time, sample.voltage, sample.current
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const REQUIRED: &str = "\
+nominal_capacity 3.0
+discharge_current 3.0 # 1 C
+discharge_pulse_current 6.0
+
+min_voltage 2.5
+";
+
+    #[test]
+    fn test_parse() {
+        let config = parse(&format!("{}soc_step 0.2\nwindow 5\n", REQUIRED)).unwrap();
+        assert_eq!(
+            config,
+            HppcConfig {
+                nominal_capacity: 3.0,
+                soc_step: 0.2,
+                discharge_current: 3.0,
+                rest_duration: 3600.0,
+                discharge_pulse_current: 6.0,
+                discharge_pulse_duration: 10.0,
+                pulse_rest_duration: 40.0,
+                charge_pulse_duration: 10.0,
+                min_voltage: 2.5,
+                window: 5.0,
+                sample_interval: 0.1,
+            }
+        );
+    }
+
+    #[test]
+    fn test_wrong_arguments() {
+        assert_eq!(
+            parse(&format!("{}soc_step\n", REQUIRED)),
+            Err("line 6: expected a name and a value".to_string())
+        );
+        assert!(parse(&format!("{}window 5 10\n", REQUIRED)).is_err());
+        assert_eq!(
+            parse(&format!("{}pulse_current 6.0\n", REQUIRED)),
+            Err("line 6: unknown value 'pulse_current'".to_string())
+        );
+        assert_eq!(
+            parse(&format!("{}window 5s\n", REQUIRED)),
+            Err("line 6: invalid number '5s'".to_string())
+        );
+        // the required values have no defaults
+        assert!(parse("nominal_capacity 3.0\ndischarge_current 3.0\nmin_voltage 2.5").is_err());
+    }
+
+    #[test]
+    fn test_out_of_range() {
+        for line in [
+            "soc_step 1.5",
+            "soc_step 0",
+            "discharge_current -3.0",
+            "window 4000",
+            "sample_interval -0.1",
+        ] {
+            assert_eq!(
+                parse(&format!("{}{}\n", REQUIRED, line)),
+                Err("a required value is missing or a value is out of range".to_string()),
+                "{}",
+                line
+            );
+        }
+    }
+}
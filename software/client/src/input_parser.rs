@@ -1,4 +1,13 @@
 use crate::ui::AppEvent;
+use firmware::calibration::CalibrationChannel;
+use firmware::clock;
+use firmware::conditioning::{ChannelConditioning, Filter};
+use firmware::datalog::{LogConfig, LogPolicy};
+use firmware::dcir::{DcirSchedule, PulseConfig};
+use firmware::limits::SafetyLimits;
+use firmware::sampling::SamplingConfig;
+use firmware::soc::{EcmParameters, SocEstimatorConfig};
+use firmware::BatteryTestUnitMode;
 
 pub fn try_parse(input: &String) -> Option<AppEvent> {
     let mut input = input.trim().split_whitespace();
@@ -27,7 +36,816 @@ pub fn try_parse(input: &String) -> Option<AppEvent> {
                 None
             }
         }
+        "clear" => {
+            if args.len() == 1 {
+                let unit = match args[0].parse::<u8>() {
+                    Ok(unit) => unit,
+                    Err(_) => return None,
+                };
+                Some(AppEvent::ClearFault(unit))
+            } else {
+                None
+            }
+        }
+        "mode" => {
+            let unit = match args.first().map(|unit| unit.parse::<u8>()) {
+                Some(Ok(unit)) => unit,
+                _ => return None,
+            };
+            if args.len() == 1 {
+                Some(AppEvent::Mode(unit, None))
+            } else if args.len() == 2 {
+                let mode = match args[1] {
+                    "idle" => BatteryTestUnitMode::Idle,
+                    "charge" => BatteryTestUnitMode::Charging,
+                    "rest" => BatteryTestUnitMode::Resting,
+                    _ => return None,
+                };
+                Some(AppEvent::Mode(unit, Some(mode)))
+            } else if args.len() == 3 {
+                let setpoint = match args[2].parse::<f32>() {
+                    Ok(setpoint) => setpoint,
+                    Err(_) => return None,
+                };
+                let mode = match args[1] {
+                    "cc" => BatteryTestUnitMode::DischargingConstantCurrent(setpoint),
+                    "cp" => BatteryTestUnitMode::DischargingConstantPower(setpoint),
+                    "cr" => BatteryTestUnitMode::DischargingConstantResistance(setpoint),
+                    _ => return None,
+                };
+                Some(AppEvent::Mode(unit, Some(mode)))
+            } else {
+                None
+            }
+        }
+        "pause" => {
+            if args.len() == 1 {
+                let unit = match args[0].parse::<u8>() {
+                    Ok(unit) => unit,
+                    Err(_) => return None,
+                };
+                Some(AppEvent::PauseTest(unit))
+            } else {
+                None
+            }
+        }
+        "abort" => {
+            if args.len() == 1 {
+                let unit = match args[0].parse::<u8>() {
+                    Ok(unit) => unit,
+                    Err(_) => return None,
+                };
+                Some(AppEvent::AbortTest(unit))
+            } else {
+                None
+            }
+        }
+        "status" => {
+            if args.is_empty() {
+                Some(AppEvent::Status(None))
+            } else if args.len() == 1 {
+                let unit = match args[0].parse::<u8>() {
+                    Ok(unit) => unit,
+                    Err(_) => return None,
+                };
+                Some(AppEvent::Status(Some(unit)))
+            } else {
+                None
+            }
+        }
+        "units" => {
+            if args.is_empty() {
+                Some(AppEvent::Status(None))
+            } else {
+                None
+            }
+        }
+        "limits" => {
+            let unit = match args.first().map(|unit| unit.parse::<u8>()) {
+                Some(Ok(unit)) => unit,
+                _ => return None,
+            };
+            if args.len() == 1 {
+                Some(AppEvent::Limits(unit, None))
+            } else if args.len() == 7 {
+                // "-" leaves a limit out
+                let values = args[1..]
+                    .iter()
+                    .map(|arg| match *arg {
+                        "-" => Some(None),
+                        arg => arg.parse::<f32>().ok().map(Some),
+                    })
+                    .collect::<Option<Vec<_>>>()?;
+                Some(AppEvent::Limits(
+                    unit,
+                    Some(SafetyLimits {
+                        min_voltage: values[0],
+                        max_voltage: values[1],
+                        max_current: values[2],
+                        max_temperature: values[3],
+                        max_duration: values[4],
+                        max_capacity: values[5],
+                    }),
+                ))
+            } else {
+                None
+            }
+        }
+        "program" => {
+            if args.len() == 2 {
+                let unit = match args[0].parse::<u8>() {
+                    Ok(unit) => unit,
+                    Err(_) => return None,
+                };
+                Some(AppEvent::UploadProgram(unit, args[1].to_string()))
+            } else {
+                None
+            }
+        }
+        "start" => {
+            if args.len() == 1 {
+                let unit = match args[0].parse::<u8>() {
+                    Ok(unit) => unit,
+                    Err(_) => return None,
+                };
+                Some(AppEvent::StartProgram(unit))
+            } else {
+                None
+            }
+        }
+        "stop" => {
+            if args.len() == 1 {
+                let unit = match args[0].parse::<u8>() {
+                    Ok(unit) => unit,
+                    Err(_) => return None,
+                };
+                Some(AppEvent::StopProgram(unit))
+            } else {
+                None
+            }
+        }
+        "resume" => {
+            if args.len() == 1 {
+                let unit = match args[0].parse::<u8>() {
+                    Ok(unit) => unit,
+                    Err(_) => return None,
+                };
+                Some(AppEvent::ResumeTest(unit))
+            } else {
+                None
+            }
+        }
+        "orbit" => {
+            if args.len() == 2 {
+                let unit = match args[0].parse::<u8>() {
+                    Ok(unit) => unit,
+                    Err(_) => return None,
+                };
+                Some(AppEvent::UploadOrbitProfile(unit, args[1].to_string()))
+            } else {
+                None
+            }
+        }
+        "orbits" => {
+            if args.len() == 2 {
+                let unit = match args[0].parse::<u8>() {
+                    Ok(unit) => unit,
+                    Err(_) => return None,
+                };
+                let orbits = match args[1].parse::<u16>() {
+                    Ok(orbits) => orbits,
+                    Err(_) => return None,
+                };
+                Some(AppEvent::StartOrbits(unit, orbits))
+            } else {
+                None
+            }
+        }
+        "stop_orbits" => {
+            if args.len() == 1 {
+                let unit = match args[0].parse::<u8>() {
+                    Ok(unit) => unit,
+                    Err(_) => return None,
+                };
+                Some(AppEvent::StopOrbits(unit))
+            } else {
+                None
+            }
+        }
+        "pulse" => {
+            if args.len() == 5 {
+                let unit = match args[0].parse::<u8>() {
+                    Ok(unit) => unit,
+                    Err(_) => return None,
+                };
+                let values = parse_numbers(&args[1..])?;
+                Some(AppEvent::StartPulseTest(
+                    unit,
+                    PulseConfig {
+                        base_current: values[0],
+                        pulse_current: values[1],
+                        base_duration: values[2],
+                        pulse_duration: values[3],
+                    },
+                ))
+            } else {
+                None
+            }
+        }
+        "dcir" => {
+            let unit = match args.first().map(|unit| unit.parse::<u8>()) {
+                Some(Ok(unit)) => unit,
+                _ => return None,
+            };
+            if args.len() == 2 && args[1] == "off" {
+                Some(AppEvent::SetDcirSchedule(unit, None))
+            } else if args.len() > 5 {
+                // at least one state of charge point
+                let values = parse_numbers(&args[1..])?;
+                let state_of_charge = heapless::Vec::from_slice(&values[4..]).ok()?;
+                Some(AppEvent::SetDcirSchedule(
+                    unit,
+                    Some(DcirSchedule {
+                        pulse: PulseConfig {
+                            base_current: 0.0,
+                            pulse_current: values[0],
+                            base_duration: values[1],
+                            pulse_duration: values[2],
+                        },
+                        nominal_capacity: values[3],
+                        state_of_charge,
+                    }),
+                ))
+            } else {
+                None
+            }
+        }
+        "hppc" => {
+            if args.len() == 2 {
+                let unit = match args[0].parse::<u8>() {
+                    Ok(unit) => unit,
+                    Err(_) => return None,
+                };
+                Some(AppEvent::StartHppc(unit, args[1].to_string()))
+            } else {
+                None
+            }
+        }
+        "stop_hppc" => {
+            if args.len() == 1 {
+                let unit = match args[0].parse::<u8>() {
+                    Ok(unit) => unit,
+                    Err(_) => return None,
+                };
+                Some(AppEvent::StopHppc(unit))
+            } else {
+                None
+            }
+        }
+        "ocv" => {
+            if args.len() == 2 {
+                let unit = match args[0].parse::<u8>() {
+                    Ok(unit) => unit,
+                    Err(_) => return None,
+                };
+                Some(AppEvent::StartOcv(unit, args[1].to_string()))
+            } else {
+                None
+            }
+        }
+        "stop_ocv" => {
+            if args.len() == 1 {
+                let unit = match args[0].parse::<u8>() {
+                    Ok(unit) => unit,
+                    Err(_) => return None,
+                };
+                Some(AppEvent::StopOcv(unit))
+            } else {
+                None
+            }
+        }
+        "export_ocv" => {
+            if args.len() == 2 {
+                let unit = match args[0].parse::<u8>() {
+                    Ok(unit) => unit,
+                    Err(_) => return None,
+                };
+                Some(AppEvent::ExportOcv(unit, args[1].to_string()))
+            } else {
+                None
+            }
+        }
+        "soc" => {
+            let unit = match args.first().map(|unit| unit.parse::<u8>()) {
+                Some(Ok(unit)) => unit,
+                _ => return None,
+            };
+            match args[1..] {
+                ["off"] => Some(AppEvent::SetSocEstimator(unit, None)),
+                [_, _, _, _] | [_, _, _, _, "ekf"] => {
+                    let values = parse_numbers(&args[1..5])?;
+                    Some(AppEvent::SetSocEstimator(
+                        unit,
+                        Some(SocEstimatorConfig {
+                            model: EcmParameters {
+                                capacity: values[0],
+                                r0: values[1],
+                                r1: values[2],
+                                c1: values[3],
+                            },
+                            ekf: args.len() == 6,
+                        }),
+                    ))
+                }
+                _ => None,
+            }
+        }
+        "calibrate" => {
+            if args.len() == 3 {
+                let unit = match args[0].parse::<u8>() {
+                    Ok(unit) => unit,
+                    Err(_) => return None,
+                };
+                let channel = match args[1] {
+                    "voltage" => CalibrationChannel::Voltage,
+                    "current" => CalibrationChannel::Current,
+                    "temperature" => CalibrationChannel::Temperature,
+                    _ => return None,
+                };
+                let degree = match args[2].parse::<u8>() {
+                    Ok(degree) => degree,
+                    Err(_) => return None,
+                };
+                Some(AppEvent::StartCalibration(unit, channel, degree))
+            } else {
+                None
+            }
+        }
+        "point" => {
+            if args.len() == 2 {
+                let unit = match args[0].parse::<u8>() {
+                    Ok(unit) => unit,
+                    Err(_) => return None,
+                };
+                let reference = match args[1].parse::<f32>() {
+                    Ok(reference) => reference,
+                    Err(_) => return None,
+                };
+                Some(AppEvent::AddCalibrationPoint(unit, reference))
+            } else {
+                None
+            }
+        }
+        "finish_calibration" => {
+            if args.len() == 1 {
+                let unit = match args[0].parse::<u8>() {
+                    Ok(unit) => unit,
+                    Err(_) => return None,
+                };
+                Some(AppEvent::FinishCalibration(unit))
+            } else {
+                None
+            }
+        }
+        "cancel_calibration" => {
+            if args.len() == 1 {
+                let unit = match args[0].parse::<u8>() {
+                    Ok(unit) => unit,
+                    Err(_) => return None,
+                };
+                Some(AppEvent::CancelCalibration(unit))
+            } else {
+                None
+            }
+        }
+        "save_calibration" => {
+            if args.len() == 2 {
+                let unit = match args[0].parse::<u8>() {
+                    Ok(unit) => unit,
+                    Err(_) => return None,
+                };
+                Some(AppEvent::SaveCalibration(unit, args[1].to_string()))
+            } else {
+                None
+            }
+        }
+        "load_calibration" => {
+            if args.len() == 2 {
+                let unit = match args[0].parse::<u8>() {
+                    Ok(unit) => unit,
+                    Err(_) => return None,
+                };
+                Some(AppEvent::LoadCalibration(unit, args[1].to_string()))
+            } else {
+                None
+            }
+        }
+        "save_config" => {
+            if args.is_empty() {
+                Some(AppEvent::SaveConfig)
+            } else {
+                None
+            }
+        }
+        "time" => args.is_empty().then_some(AppEvent::GetTime),
+        "set_time" => match args[..] {
+            // the clock of the computer
+            [] => {
+                let time = std::time::SystemTime::now()
+                    .duration_since(std::time::UNIX_EPOCH)
+                    .ok()?;
+                Some(AppEvent::SetTime(time.as_millis() as u64))
+            }
+            [date, time] => Some(AppEvent::SetTime(parse_datetime(date, time)?)),
+            _ => None,
+        },
+        "sample_period" => {
+            if args.is_empty() {
+                Some(AppEvent::GetSamplePeriod)
+            } else if args.len() == 1 {
+                let period = match args[0].parse::<u16>() {
+                    Ok(period) => period,
+                    Err(_) => return None,
+                };
+                Some(AppEvent::SetSamplePeriod(period))
+            } else {
+                None
+            }
+        }
+        "name" => {
+            let unit = match args.first().map(|unit| unit.parse::<u8>()) {
+                Some(Ok(unit)) => unit,
+                _ => return None,
+            };
+            match args[1..] {
+                [] => Some(AppEvent::UnitName(unit, None)),
+                // names are at most 16 bytes long
+                [name] if name.len() <= 16 => {
+                    Some(AppEvent::UnitName(unit, Some(heapless::String::from(name))))
+                }
+                _ => None,
+            }
+        }
+        "sampling" => {
+            let unit = match args.first().map(|unit| unit.parse::<u8>()) {
+                Some(Ok(unit)) => unit,
+                _ => return None,
+            };
+            let numbers = parse_numbers(&args[1..])?;
+            let config = match numbers[..] {
+                [] => return Some(AppEvent::Sampling(unit, None)),
+                [period, decimation] => SamplingConfig {
+                    period: period as u16,
+                    decimation: decimation as u16,
+                    fast_period: period as u16,
+                    fast_duration: 0,
+                    knee_slope: 0.0,
+                },
+                [period, decimation, fast_period, fast_duration, knee_slope] => SamplingConfig {
+                    period: period as u16,
+                    decimation: decimation as u16,
+                    fast_period: fast_period as u16,
+                    fast_duration: fast_duration as u32,
+                    knee_slope,
+                },
+                _ => return None,
+            };
+            Some(AppEvent::Sampling(unit, Some(config)))
+        }
+        "conditioning" => {
+            let unit = match args.first().map(|unit| unit.parse::<u8>()) {
+                Some(Ok(unit)) => unit,
+                _ => return None,
+            };
+            if args.len() == 1 {
+                return Some(AppEvent::Conditioning(unit, None));
+            }
+            let channel = match args.get(1) {
+                Some(&"voltage") => CalibrationChannel::Voltage,
+                Some(&"current") => CalibrationChannel::Current,
+                Some(&"temperature") => CalibrationChannel::Temperature,
+                _ => return None,
+            };
+            let oversampling = match args.get(2).map(|oversampling| oversampling.parse::<u8>()) {
+                Some(Ok(oversampling)) => oversampling,
+                _ => return None,
+            };
+            let filter = match args[3..] {
+                [] | ["none"] => Filter::None,
+                ["median", window] => Filter::Median(window.parse::<u8>().ok()?),
+                ["lowpass", factor] => Filter::LowPass(factor.parse::<f32>().ok()?),
+                _ => return None,
+            };
+            Some(AppEvent::Conditioning(
+                unit,
+                Some((
+                    channel,
+                    ChannelConditioning {
+                        oversampling,
+                        filter,
+                    },
+                )),
+            ))
+        }
+        "log" => {
+            let interval = match args.first().map(|interval| interval.parse::<f32>()) {
+                Some(Ok(interval)) => interval,
+                _ => return None,
+            };
+            let policy = match args[1..] {
+                [] | ["wrap"] => LogPolicy::Wrap,
+                ["stop"] => LogPolicy::StopWhenFull,
+                _ => return None,
+            };
+            Some(AppEvent::StartLog(LogConfig { interval, policy }))
+        }
+        "stop_log" => args.is_empty().then_some(AppEvent::StopLog),
+        "clear_log" => args.is_empty().then_some(AppEvent::ClearLog),
+        "log_status" => args.is_empty().then_some(AppEvent::LogStatus),
+        "download_log" => match args[..] {
+            [path] => Some(AppEvent::DownloadLog(path.to_string())),
+            _ => None,
+        },
         "quit" => Some(AppEvent::Quit),
         _ => None,
     }
 }
+
+/// `YYYY-MM-DD` and `HH:MM:SS` in UTC as Unix time in ms
+fn parse_datetime(date: &str, time: &str) -> Option<u64> {
+    let date = date
+        .split('-')
+        .map(|part| part.parse::<i32>().ok())
+        .collect::<Option<Vec<_>>>()?;
+    let time = time
+        .split(':')
+        .map(|part| part.parse::<u8>().ok())
+        .collect::<Option<Vec<_>>>()?;
+    let (&[year, month, day], &[hour, minute, second]) = (&date[..], &time[..]) else {
+        return None;
+    };
+    let month = time::Month::try_from(u8::try_from(month).ok()?).ok()?;
+    let date = time::Date::from_calendar_date(year, month, u8::try_from(day).ok()?).ok()?;
+    let time = time::Time::from_hms(hour, minute, second).ok()?;
+    Some(clock::to_unix_time(time::PrimitiveDateTime::new(
+        date, time,
+    )))
+}
+
+fn parse_numbers(args: &[&str]) -> Option<Vec<f32>> {
+    args.iter().map(|arg| arg.parse::<f32>().ok()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(input: &str) -> Option<AppEvent> {
+        try_parse(&input.to_string())
+    }
+
+    #[test]
+    fn test_program() {
+        assert!(matches!(
+            parse("program 2 charge.txt"),
+            Some(AppEvent::UploadProgram(2, path)) if path == "charge.txt"
+        ));
+        assert!(matches!(parse("start 2"), Some(AppEvent::StartProgram(2))));
+        assert!(matches!(parse("stop 3"), Some(AppEvent::StopProgram(3))));
+
+        assert!(parse("program 2").is_none());
+        assert!(parse("program 2 charge.txt other.txt").is_none());
+        assert!(parse("start").is_none());
+        assert!(parse("stop 2 3").is_none());
+
+        // units are numbered with a u8
+        assert!(parse("program 256 charge.txt").is_none());
+        assert!(parse("start -1").is_none());
+        assert!(parse("stop first").is_none());
+    }
+
+    #[test]
+    fn test_pulse() {
+        let config = PulseConfig {
+            base_current: 0.2,
+            pulse_current: 1.0,
+            base_duration: 10.0,
+            pulse_duration: 1.5,
+        };
+        assert!(matches!(
+            parse("pulse 1 0.2 1.0 10 1.5"),
+            Some(AppEvent::StartPulseTest(1, pulse)) if pulse == config
+        ));
+
+        assert!(parse("pulse 1 0.2 1.0 10").is_none());
+        assert!(parse("pulse 1 0.2 1.0 10 1.5 2").is_none());
+        assert!(parse("pulse 256 0.2 1.0 10 1.5").is_none());
+        assert!(parse("pulse 1 0.2 1A 10 1.5").is_none());
+    }
+
+    #[test]
+    fn test_dcir_schedule() {
+        let schedule = DcirSchedule {
+            pulse: PulseConfig {
+                base_current: 0.0,
+                pulse_current: 1.0,
+                base_duration: 10.0,
+                pulse_duration: 1.5,
+            },
+            nominal_capacity: 3.0,
+            state_of_charge: heapless::Vec::from_slice(&[0.9, 0.5]).unwrap(),
+        };
+        assert!(matches!(
+            parse("dcir 1 1.0 10 1.5 3.0 0.9 0.5"),
+            Some(AppEvent::SetDcirSchedule(1, Some(parsed))) if parsed == schedule
+        ));
+        assert!(matches!(
+            parse("dcir 1 off"),
+            Some(AppEvent::SetDcirSchedule(1, None))
+        ));
+
+        assert!(parse("dcir 1 1.0 10 1.5 3.0").is_none());
+        assert!(parse("dcir 1").is_none());
+        assert!(parse("dcir").is_none());
+        assert!(parse("dcir 1 off 0.5").is_none());
+
+        assert!(parse("dcir 256 off").is_none());
+        assert!(parse("dcir 1 1.0 10 1.5 3.0 half").is_none());
+        // no more than MAX_SOC_POINTS points
+        assert!(parse("dcir 1 1.0 10 1.5 3.0 1 0.9 0.8 0.7 0.6 0.5 0.4 0.3 0.2 0.1 0").is_none());
+    }
+
+    #[test]
+    fn test_hppc() {
+        assert!(matches!(
+            parse("hppc 1 hppc.txt"),
+            Some(AppEvent::StartHppc(1, path)) if path == "hppc.txt"
+        ));
+        assert!(matches!(parse("stop_hppc 1"), Some(AppEvent::StopHppc(1))));
+
+        assert!(parse("hppc 1").is_none());
+        assert!(parse("hppc 1 hppc.txt other.txt").is_none());
+        assert!(parse("stop_hppc").is_none());
+        assert!(parse("hppc 256 hppc.txt").is_none());
+        assert!(parse("stop_hppc -1").is_none());
+    }
+
+    #[test]
+    fn test_ocv() {
+        assert!(matches!(
+            parse("ocv 1 ocv.txt"),
+            Some(AppEvent::StartOcv(1, path)) if path == "ocv.txt"
+        ));
+        assert!(matches!(parse("stop_ocv 1"), Some(AppEvent::StopOcv(1))));
+        assert!(matches!(
+            parse("export_ocv 1 table.csv"),
+            Some(AppEvent::ExportOcv(1, path)) if path == "table.csv"
+        ));
+
+        assert!(parse("ocv 1").is_none());
+        assert!(parse("stop_ocv 1 2").is_none());
+        assert!(parse("export_ocv 1").is_none());
+        assert!(parse("export_ocv 1 table.csv other.csv").is_none());
+        assert!(parse("ocv 256 ocv.txt").is_none());
+        assert!(parse("stop_ocv -1").is_none());
+        assert!(parse("export_ocv one table.csv").is_none());
+    }
+
+    #[test]
+    fn test_soc_estimator() {
+        let model = EcmParameters {
+            capacity: 3.0,
+            r0: 0.05,
+            r1: 0.02,
+            c1: 1000.0,
+        };
+        assert!(matches!(
+            parse("soc 1 3.0 0.05 0.02 1000"),
+            Some(AppEvent::SetSocEstimator(1, Some(config)))
+                if config == SocEstimatorConfig { model, ekf: false }
+        ));
+        assert!(matches!(
+            parse("soc 1 3.0 0.05 0.02 1000 ekf"),
+            Some(AppEvent::SetSocEstimator(1, Some(config)))
+                if config == SocEstimatorConfig { model, ekf: true }
+        ));
+        assert!(matches!(
+            parse("soc 1 off"),
+            Some(AppEvent::SetSocEstimator(1, None))
+        ));
+
+        assert!(parse("soc").is_none());
+        assert!(parse("soc 1").is_none());
+        assert!(parse("soc 1 3.0 0.05 0.02").is_none());
+        assert!(parse("soc 1 3.0 0.05 0.02 1000 kalman").is_none());
+        assert!(parse("soc 1 3.0 0.05 0.02 1000 ekf 1").is_none());
+        assert!(parse("soc 256 off").is_none());
+        assert!(parse("soc 1 3Ah 0.05 0.02 1000").is_none());
+    }
+
+    #[test]
+    fn test_config() {
+        assert!(matches!(parse("save_config"), Some(AppEvent::SaveConfig)));
+        assert!(matches!(
+            parse("sample_period"),
+            Some(AppEvent::GetSamplePeriod)
+        ));
+        assert!(matches!(
+            parse("sample_period 250"),
+            Some(AppEvent::SetSamplePeriod(250))
+        ));
+        assert!(matches!(parse("name 1"), Some(AppEvent::UnitName(1, None))));
+        assert!(matches!(
+            parse("name 1 cell_a"),
+            Some(AppEvent::UnitName(1, Some(name))) if name == "cell_a"
+        ));
+
+        assert!(parse("save_config now").is_none());
+        assert!(parse("sample_period 250 500").is_none());
+        assert!(parse("name").is_none());
+        assert!(parse("name 1 cell a").is_none());
+
+        // the period is a u16 in ms, names are at most 16 bytes long
+        assert!(parse("sample_period 65536").is_none());
+        assert!(parse("sample_period -1").is_none());
+        assert!(parse("name 256 cell_a").is_none());
+        assert!(matches!(
+            parse("name 1 0123456789abcdef"),
+            Some(AppEvent::UnitName(1, Some(_)))
+        ));
+        assert!(parse("name 1 0123456789abcdefg").is_none());
+    }
+
+    #[test]
+    fn test_log() {
+        assert!(matches!(
+            parse("log 1.5"),
+            Some(AppEvent::StartLog(LogConfig {
+                policy: LogPolicy::Wrap,
+                ..
+            }))
+        ));
+        assert!(matches!(
+            parse("log 1.5 stop"),
+            Some(AppEvent::StartLog(config))
+                if config == LogConfig { interval: 1.5, policy: LogPolicy::StopWhenFull }
+        ));
+        assert!(matches!(parse("stop_log"), Some(AppEvent::StopLog)));
+        assert!(matches!(parse("clear_log"), Some(AppEvent::ClearLog)));
+        assert!(matches!(parse("log_status"), Some(AppEvent::LogStatus)));
+        assert!(matches!(
+            parse("download_log log.csv"),
+            Some(AppEvent::DownloadLog(path)) if path == "log.csv"
+        ));
+
+        assert!(parse("log").is_none());
+        assert!(parse("log 1.5 wrap stop").is_none());
+        assert!(parse("log 1.5 overwrite").is_none());
+        assert!(parse("log 1s").is_none());
+        assert!(parse("stop_log 1").is_none());
+        assert!(parse("clear_log all").is_none());
+        assert!(parse("download_log").is_none());
+        assert!(parse("download_log log.csv other.csv").is_none());
+    }
+
+    #[test]
+    fn test_conditioning() {
+        let conditioning = |oversampling, filter| ChannelConditioning {
+            oversampling,
+            filter,
+        };
+        assert!(matches!(
+            parse("conditioning 1"),
+            Some(AppEvent::Conditioning(1, None))
+        ));
+        assert!(matches!(
+            parse("conditioning 1 voltage 4"),
+            Some(AppEvent::Conditioning(1, Some((CalibrationChannel::Voltage, parsed))))
+                if parsed == conditioning(4, Filter::None)
+        ));
+        assert!(matches!(
+            parse("conditioning 1 current 8 median 5"),
+            Some(AppEvent::Conditioning(1, Some((CalibrationChannel::Current, parsed))))
+                if parsed == conditioning(8, Filter::Median(5))
+        ));
+        assert!(matches!(
+            parse("conditioning 1 temperature 1 lowpass 0.2"),
+            Some(AppEvent::Conditioning(1, Some((CalibrationChannel::Temperature, parsed))))
+                if parsed == conditioning(1, Filter::LowPass(0.2))
+        ));
+
+        assert!(parse("conditioning").is_none());
+        assert!(parse("conditioning 1 voltage").is_none());
+        assert!(parse("conditioning 1 voltage 4 median").is_none());
+        assert!(parse("conditioning 1 voltage 4 lowpass 0.2 0.5").is_none());
+        assert!(parse("conditioning 1 power 4").is_none());
+        assert!(parse("conditioning 1 voltage 4 mean 5").is_none());
+
+        // unit, oversampling and median window are u8
+        assert!(parse("conditioning 256 voltage 4").is_none());
+        assert!(parse("conditioning 1 voltage 256").is_none());
+        assert!(parse("conditioning 1 voltage -1").is_none());
+        assert!(parse("conditioning 1 voltage 4 median 256").is_none());
+        assert!(parse("conditioning 1 voltage 4 lowpass fast").is_none());
+    }
+}
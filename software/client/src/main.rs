@@ -1,21 +1,47 @@
 use bbqueue::BBBuffer;
-use firmware::msg_types::MsgTypes;
+use firmware::clock;
+use firmware::msg_types::{MsgTypes, Telemetry};
 use heapless::String;
 use serde::{Deserialize, Serialize};
 use serialport;
+use std::collections::BTreeMap;
 use ui::AppEvent;
 
+mod calibration;
+mod datalog;
+mod hppc;
 mod input_parser;
+mod ocv;
+mod orbit;
+mod program;
 mod serial_manager;
 mod ui;
 
 const BUFFER_SIZE: usize = 1024;
+/// The baud rate of the board
+const DEFAULT_BAUD_RATE: u32 = 921_600;
+
+/// Which calibration the results of the unit were measured with, from its latest telemetry
+fn calibration_version(telemetry: &BTreeMap<u8, Telemetry>, unit: u8) -> std::string::String {
+    match telemetry.get(&unit) {
+        Some(telemetry) => format!("calibration {}", telemetry.calibration),
+        None => std::string::String::from("calibration unknown"),
+    }
+}
 
 fn main() {
     let mut terminal = ui::setup().unwrap();
     let mut app = ui::App::default();
 
-    let port = serialport::new("COM5", 115200)
+    // e.g. the pseudo-terminal of the simulator
+    let path = std::env::args()
+        .nth(1)
+        .unwrap_or_else(|| std::string::String::from("COM5"));
+    let baud_rate = match std::env::args().nth(2) {
+        Some(rate) => rate.parse().expect("Invalid baud rate"),
+        None => DEFAULT_BAUD_RATE,
+    };
+    let port = serialport::new(path, baud_rate)
         .open()
         .expect("Couldn't open the serial port");
     let buf_tx: BBBuffer<BUFFER_SIZE> = BBBuffer::new();
@@ -26,6 +52,13 @@ fn main() {
 
     port.setup();
 
+    // path and points received so far of the OCV tables being exported
+    let mut ocv_exports = BTreeMap::new();
+    // path the next calibration received from the unit is saved to
+    let mut calibration_saves = BTreeMap::new();
+    // file the measurement log is downloaded to
+    let mut log_download: Option<datalog::LogDownload> = None;
+
     loop {
         match ui::update(&mut terminal, &mut app) {
             AppEvent::Quit => break,
@@ -40,11 +73,306 @@ fn main() {
                 app.messages.push(format!("sending sample adc {}", val));
                 port.send(MsgTypes::SampleAdc(val));
             }
+            AppEvent::ClearFault(unit) => {
+                app.messages.push(format!("sending clear fault {}", unit));
+                port.send(MsgTypes::ClearFault(unit));
+            }
+            AppEvent::UploadProgram(unit, path) => {
+                let steps = std::fs::read_to_string(&path)
+                    .map_err(|error| error.to_string())
+                    .and_then(|text| program::parse(&text));
+                match steps {
+                    Ok(steps) => {
+                        app.messages.push(format!(
+                            "uploading {} steps from {} to unit {}",
+                            steps.len(),
+                            path,
+                            unit
+                        ));
+                        port.send(MsgTypes::ClearProgram(unit));
+                        for (index, step) in steps.into_iter().enumerate() {
+                            port.send(MsgTypes::SetProgramStep(unit, index as u8, step));
+                            // the steps don't all fit into the transmit buffer at once
+                            port.update();
+                        }
+                    }
+                    Err(error) => {
+                        app.messages
+                            .push(format!("couldn't load program {}: {}", path, error));
+                    }
+                }
+            }
+            AppEvent::UploadOrbitProfile(unit, path) => {
+                let segments = std::fs::read_to_string(&path)
+                    .map_err(|error| error.to_string())
+                    .and_then(|text| orbit::parse(&text));
+                match segments {
+                    Ok(segments) => {
+                        app.messages.push(format!(
+                            "uploading {} orbit segments from {} to unit {}",
+                            segments.len(),
+                            path,
+                            unit
+                        ));
+                        port.send(MsgTypes::ClearOrbitProfile(unit));
+                        for (index, segment) in segments.into_iter().enumerate() {
+                            port.send(MsgTypes::SetOrbitSegment(unit, index as u8, segment));
+                            port.update();
+                        }
+                    }
+                    Err(error) => {
+                        app.messages
+                            .push(format!("couldn't load orbit profile {}: {}", path, error));
+                    }
+                }
+            }
+            AppEvent::StartOrbits(unit, orbits) => {
+                app.messages
+                    .push(format!("sending start {} orbits {}", orbits, unit));
+                port.send(MsgTypes::StartOrbits(unit, orbits));
+            }
+            AppEvent::StopOrbits(unit) => {
+                app.messages.push(format!("sending stop orbits {}", unit));
+                port.send(MsgTypes::StopOrbits(unit));
+            }
+            AppEvent::StartPulseTest(unit, config) => {
+                app.messages.push(format!("sending pulse test {}", unit));
+                port.send(MsgTypes::StartPulseTest(unit, config));
+            }
+            AppEvent::SetDcirSchedule(unit, schedule) => {
+                app.messages.push(format!("sending dcir schedule {}", unit));
+                port.send(MsgTypes::SetDcirSchedule(unit, schedule));
+            }
+            AppEvent::StartHppc(unit, path) => {
+                let config = std::fs::read_to_string(&path)
+                    .map_err(|error| error.to_string())
+                    .and_then(|text| hppc::parse(&text));
+                match config {
+                    Ok(config) => {
+                        app.messages
+                            .push(format!("sending start hppc {} from {}", unit, path));
+                        port.send(MsgTypes::StartHppc(unit, config));
+                    }
+                    Err(error) => {
+                        app.messages
+                            .push(format!("couldn't load hppc config {}: {}", path, error));
+                    }
+                }
+            }
+            AppEvent::StopHppc(unit) => {
+                app.messages.push(format!("sending stop hppc {}", unit));
+                port.send(MsgTypes::StopHppc(unit));
+            }
+            AppEvent::StartOcv(unit, path) => {
+                let config = std::fs::read_to_string(&path)
+                    .map_err(|error| error.to_string())
+                    .and_then(|text| ocv::parse(&text));
+                match config {
+                    Ok(config) => {
+                        app.messages
+                            .push(format!("sending start ocv {} from {}", unit, path));
+                        port.send(MsgTypes::StartOcv(unit, config));
+                    }
+                    Err(error) => {
+                        app.messages
+                            .push(format!("couldn't load ocv config {}: {}", path, error));
+                    }
+                }
+            }
+            AppEvent::StopOcv(unit) => {
+                app.messages.push(format!("sending stop ocv {}", unit));
+                port.send(MsgTypes::StopOcv(unit));
+            }
+            AppEvent::ExportOcv(unit, path) => {
+                app.messages
+                    .push(format!("downloading the ocv table of unit {}", unit));
+                ocv_exports.insert(unit, (path, Vec::new()));
+                port.send(MsgTypes::GetOcvTable(unit));
+            }
+            AppEvent::SetSocEstimator(unit, config) => {
+                app.messages.push(format!("sending soc estimator {}", unit));
+                port.send(MsgTypes::SetSocEstimator(unit, config));
+            }
+            AppEvent::StartCalibration(unit, channel, degree) => {
+                app.messages.push(format!(
+                    "sending start calibration {} {:?} degree {}",
+                    unit, channel, degree
+                ));
+                port.send(MsgTypes::StartCalibration(unit, channel, degree));
+            }
+            AppEvent::AddCalibrationPoint(unit, reference) => {
+                app.messages.push(format!(
+                    "sending calibration point {} at {}",
+                    unit, reference
+                ));
+                port.send(MsgTypes::AddCalibrationPoint(unit, reference));
+            }
+            AppEvent::FinishCalibration(unit) => {
+                app.messages
+                    .push(format!("sending finish calibration {}", unit));
+                port.send(MsgTypes::FinishCalibration(unit));
+            }
+            AppEvent::CancelCalibration(unit) => {
+                app.messages
+                    .push(format!("sending cancel calibration {}", unit));
+                port.send(MsgTypes::CancelCalibration(unit));
+            }
+            AppEvent::SaveCalibration(unit, path) => {
+                app.messages
+                    .push(format!("downloading the calibration of unit {}", unit));
+                calibration_saves.insert(unit, path);
+                port.send(MsgTypes::GetCalibration(unit));
+            }
+            AppEvent::LoadCalibration(unit, path) => {
+                let calibration = std::fs::read_to_string(&path)
+                    .map_err(|error| error.to_string())
+                    .and_then(|text| calibration::parse(&text));
+                match calibration {
+                    Ok(calibration) => {
+                        app.messages.push(format!(
+                            "sending calibration version {} from {} to unit {}",
+                            calibration.version, path, unit
+                        ));
+                        port.send(MsgTypes::SetCalibration(unit, calibration));
+                    }
+                    Err(error) => {
+                        app.messages
+                            .push(format!("couldn't load calibration {}: {}", path, error));
+                    }
+                }
+            }
+            AppEvent::SaveConfig => {
+                app.messages.push(format!("sending save config"));
+                port.send(MsgTypes::SaveConfig);
+            }
+            AppEvent::SetTime(time) => {
+                match clock::to_datetime(time) {
+                    Ok(datetime) => app.messages.push(format!("sending time {} UTC", datetime)),
+                    Err(_) => app.messages.push(format!("sending time {} ms", time)),
+                }
+                port.send(MsgTypes::SetTime(time));
+            }
+            AppEvent::GetTime => {
+                port.send(MsgTypes::GetTime);
+            }
+            AppEvent::GetSamplePeriod => {
+                port.send(MsgTypes::GetSamplePeriod);
+            }
+            AppEvent::SetSamplePeriod(period) => {
+                app.messages
+                    .push(format!("sending sample period {} ms", period));
+                port.send(MsgTypes::SetSamplePeriod(period));
+            }
+            AppEvent::UnitName(unit, Some(name)) => {
+                app.messages
+                    .push(format!("sending name {} of unit {}", name, unit));
+                port.send(MsgTypes::SetUnitName(unit, name));
+            }
+            AppEvent::UnitName(unit, None) => {
+                port.send(MsgTypes::GetUnitName(unit));
+            }
+            AppEvent::Sampling(unit, Some(config)) => {
+                app.messages.push(format!(
+                    "sending sampling of unit {}: every {} ms, telemetry of every {}. sample",
+                    unit, config.period, config.decimation
+                ));
+                port.send(MsgTypes::SetSampling(unit, config));
+            }
+            AppEvent::Sampling(unit, None) => {
+                port.send(MsgTypes::GetSampling(unit));
+            }
+            AppEvent::Conditioning(unit, Some((channel, config))) => {
+                app.messages.push(format!(
+                    "sending conditioning of the {:?} of unit {}: {} readings, {:?}",
+                    channel, unit, config.oversampling, config.filter
+                ));
+                port.send(MsgTypes::SetConditioning(unit, channel, config));
+            }
+            AppEvent::Conditioning(unit, None) => {
+                port.send(MsgTypes::GetConditioning(unit));
+            }
+            AppEvent::StartLog(config) => {
+                app.messages.push(format!(
+                    "sending start log every {} s, {:?}",
+                    config.interval, config.policy
+                ));
+                port.send(MsgTypes::StartLog(config));
+            }
+            AppEvent::StopLog => {
+                app.messages.push(format!("sending stop log"));
+                port.send(MsgTypes::StopLog);
+            }
+            AppEvent::ClearLog => {
+                app.messages.push(format!("sending clear log"));
+                port.send(MsgTypes::ClearLog);
+            }
+            AppEvent::LogStatus => {
+                port.send(MsgTypes::GetLogStatus);
+            }
+            AppEvent::DownloadLog(path) => match datalog::LogDownload::start(path.clone()) {
+                Ok(download) => {
+                    app.messages.push(format!(
+                        "downloading the log from record {} to {}",
+                        download.get_next(),
+                        path
+                    ));
+                    port.send(MsgTypes::GetLog(download.get_next()));
+                    log_download = Some(download);
+                }
+                Err(error) => {
+                    app.messages
+                        .push(format!("couldn't open {}: {}", path, error));
+                }
+            },
+            AppEvent::StartProgram(unit) => {
+                app.messages.push(format!("sending start program {}", unit));
+                port.send(MsgTypes::StartProgram(unit));
+            }
+            AppEvent::StopProgram(unit) => {
+                app.messages.push(format!("sending stop program {}", unit));
+                port.send(MsgTypes::StopProgram(unit));
+            }
+            AppEvent::ResumeTest(unit) => {
+                app.messages.push(format!("sending resume test {}", unit));
+                port.send(MsgTypes::ResumeTest(unit));
+            }
+            AppEvent::Mode(unit, Some(mode)) => {
+                app.messages
+                    .push(format!("sending mode {:?} of unit {}", mode, unit));
+                port.send(MsgTypes::SetMode(unit, mode));
+            }
+            AppEvent::Mode(unit, None) => {
+                port.send(MsgTypes::GetMode(unit));
+            }
+            AppEvent::PauseTest(unit) => {
+                app.messages.push(format!("sending pause test {}", unit));
+                port.send(MsgTypes::PauseTest(unit));
+            }
+            AppEvent::AbortTest(unit) => {
+                app.messages.push(format!("sending abort test {}", unit));
+                port.send(MsgTypes::AbortTest(unit));
+            }
+            AppEvent::Status(Some(unit)) => {
+                port.send(MsgTypes::GetStatus(unit));
+            }
+            AppEvent::Status(None) => {
+                port.send(MsgTypes::ListUnits);
+            }
+            AppEvent::Limits(unit, Some(limits)) => {
+                app.messages
+                    .push(format!("sending limits of unit {}: {:?}", unit, limits));
+                port.send(MsgTypes::SetLimits(unit, limits));
+            }
+            AppEvent::Limits(unit, None) => {
+                port.send(MsgTypes::GetLimits(unit));
+            }
             _ => {}
         }
 
         port.update();
 
+        // sequence number the log download continues with, sent once the messages are handled
+        let mut log_request = None;
         port.receive(|msg| match msg {
             MsgTypes::Msg(msg) => {
                 app.messages.push(format!("received msg: {}", msg));
@@ -52,10 +380,350 @@ fn main() {
             MsgTypes::Ping(val) => {
                 app.messages.push(format!("received ping: {}", val));
             }
+            MsgTypes::Ack => {
+                app.messages.push(format!("the board carried out the command"));
+            }
             MsgTypes::SampleAdcResult(val) => {
                 app.messages
                     .push(format!("received sample adc result: {}", val));
             }
+            MsgTypes::ConfigSaved(sequence) => {
+                app.messages
+                    .push(format!("saved the configuration as record {}", sequence));
+            }
+            MsgTypes::Time(time) => {
+                let now = std::time::SystemTime::now()
+                    .duration_since(std::time::UNIX_EPOCH)
+                    .map_or(0, |now| now.as_millis() as i64);
+                match clock::to_datetime(time) {
+                    Ok(datetime) => app.messages.push(format!(
+                        "board time {} UTC, {} ms off the computer",
+                        datetime,
+                        time as i64 - now
+                    )),
+                    Err(_) => app.messages.push(format!("board time {} ms", time)),
+                }
+            }
+            MsgTypes::ClockError(error) => {
+                app.messages.push(format!("clock error: {:?}", error));
+            }
+            MsgTypes::CrashReport(report, reset_cause) => {
+                app.messages.push(format!(
+                    "the board panicked after {:.1} h since a {:?} reset, {:?} reset: {}",
+                    report.uptime as f32 / 3_600_000.0,
+                    report.reset_cause,
+                    reset_cause,
+                    report.message
+                ));
+                for (unit, state) in report.units.iter().enumerate() {
+                    app.messages.push(format!(
+                        "unit {} was in {:?}, test {:?}",
+                        unit, state.mode, state.test
+                    ));
+                }
+            }
+            MsgTypes::WatchdogReset => {
+                app.messages
+                    .push(format!("the watchdog reset the board, the units are idle"));
+            }
+            MsgTypes::TestInterrupted(unit, run) => {
+                app.messages.push(format!(
+                    "a reset interrupted unit {} in {:?} after {:.0} s, {:.3} Ah, {:.3} Wh{}",
+                    unit,
+                    run.mode,
+                    run.duration,
+                    run.capacity,
+                    run.energy,
+                    if run.resumable {
+                        ", resume it with \"resume <unit>\""
+                    } else {
+                        ", restart it to continue"
+                    }
+                ));
+            }
+            MsgTypes::RecoveryError(unit, error) => {
+                app.messages
+                    .push(format!("unit {} recovery error: {:?}", unit, error));
+            }
+            MsgTypes::ConfigError(error) => {
+                app.messages.push(format!("config error: {:?}", error));
+            }
+            MsgTypes::Sampling(unit, config) => {
+                app.messages.push(format!(
+                    "unit {} samples every {} ms, every {} ms for {} ms after mode changes and \
+                     above {} V/s, telemetry of every {}. sample",
+                    unit,
+                    config.period,
+                    config.fast_period,
+                    config.fast_duration,
+                    config.knee_slope,
+                    config.decimation
+                ));
+            }
+            MsgTypes::SamplingError(unit, error) => {
+                app.messages
+                    .push(format!("sampling error of unit {}: {:?}", unit, error));
+            }
+            MsgTypes::Conditioning(unit, config) => {
+                app.messages.push(format!(
+                    "unit {} averages {} voltage readings, {:?}, {} current readings, {:?}, {} \
+                     temperature readings, {:?}",
+                    unit,
+                    config.voltage.oversampling,
+                    config.voltage.filter,
+                    config.current.oversampling,
+                    config.current.filter,
+                    config.temperature.oversampling,
+                    config.temperature.filter
+                ));
+            }
+            MsgTypes::ConditioningError(unit, error) => {
+                app.messages
+                    .push(format!("conditioning error of unit {}: {:?}", unit, error));
+            }
+            MsgTypes::LogStatus(status) => {
+                app.messages.push(format!(
+                    "log {}: records {} to {} of {} stored",
+                    if status.running { "running" } else { "stopped" },
+                    status.oldest,
+                    status.next as i64 - 1,
+                    status.capacity
+                ));
+            }
+            MsgTypes::LogRecord(record) => {
+                if let Some(download) = &mut log_download {
+                    if let Err(error) = download.append(&record) {
+                        app.messages
+                            .push(format!("couldn't write to {}: {}", download.path, error));
+                        log_download = None;
+                    }
+                }
+            }
+            MsgTypes::LogEnd(end) => {
+                if let Some(download) = &mut log_download {
+                    if download.finish(end) {
+                        app.messages.push(format!(
+                            "downloaded {} records to {}, {} were overwritten before",
+                            download.received, download.path, download.skipped
+                        ));
+                        log_download = None;
+                    } else {
+                        // records were lost on the way, the rest is requested again
+                        log_request = Some(download.get_next());
+                    }
+                }
+            }
+            MsgTypes::LogError(error) => {
+                app.messages.push(format!("log error: {:?}", error));
+            }
+            MsgTypes::UnitName(unit, name) => {
+                app.messages.push(format!("unit {} is named {}", unit, name));
+            }
+            MsgTypes::SamplePeriod(period) => {
+                app.messages
+                    .push(format!("the units are updated at least every {} ms", period));
+            }
+            MsgTypes::Mode(unit, mode) => {
+                app.messages.push(format!("unit {} is in {:?}", unit, mode));
+            }
+            MsgTypes::Status(unit, status) => {
+                let test = match (status.test, status.paused) {
+                    (None, _) => format!("no test"),
+                    (Some(test), Some(paused)) => format!("{:?} paused in {:?}", test, paused),
+                    (Some(test), None) => format!("{:?}, step {:?}", test, status.step),
+                };
+                app.messages.push(format!(
+                    "unit {} \"{}\": {:?}, {}, {:.0} s, {:.3} Ah, {:.3} Wh{}",
+                    unit,
+                    status.name,
+                    status.mode,
+                    test,
+                    status.duration,
+                    status.capacity,
+                    status.energy,
+                    if status.calibrating { ", calibrating" } else { "" }
+                ));
+            }
+            MsgTypes::UnitCount(count) => {
+                app.messages.push(format!("the board has {} units", count));
+            }
+            MsgTypes::Limits(unit, limits) => {
+                app.messages
+                    .push(format!("limits of unit {}: {:?}", unit, limits));
+            }
+            MsgTypes::TestAborted(unit, summary) => {
+                app.messages.push(format!(
+                    "unit {} aborted {:?} in step {:?} after {:.0} s, {:.3} Ah, {:.3} Wh ({})",
+                    unit,
+                    summary.test,
+                    summary.step,
+                    summary.duration,
+                    summary.capacity,
+                    summary.energy,
+                    calibration_version(&app.telemetry, unit)
+                ));
+            }
+            MsgTypes::UnitError(unit, error) => {
+                app.messages
+                    .push(format!("unit {} error: {:?}", unit, error));
+            }
+            MsgTypes::Fault(unit, cause) => {
+                app.messages
+                    .push(format!("received fault of unit {}: {:?}", unit, cause));
+            }
+            MsgTypes::StepTransition(unit, transition) => {
+                app.messages.push(format!(
+                    "unit {} went from step {:?} to step {:?}: {:?} after {:.0} s and {:.3} Ah ({})",
+                    unit,
+                    transition.from,
+                    transition.to,
+                    transition.reason,
+                    transition.duration,
+                    transition.capacity,
+                    calibration_version(&app.telemetry, unit)
+                ));
+            }
+            MsgTypes::ProgramError(unit, error) => {
+                app.messages
+                    .push(format!("program error of unit {}: {:?}", unit, error));
+            }
+            MsgTypes::OrbitStats(unit, stats) => {
+                app.messages.push(format!(
+                    "unit {} finished orbit {}: DoD {:.4} Ah, end of eclipse {:?} V, {:.3}..{:.3} V, -{:.4} Ah +{:.4} Ah ({})",
+                    unit,
+                    stats.orbit,
+                    stats.depth_of_discharge,
+                    stats.end_of_eclipse_voltage,
+                    stats.min_voltage,
+                    stats.max_voltage,
+                    stats.discharged,
+                    stats.charged,
+                    calibration_version(&app.telemetry, unit)
+                ));
+            }
+            MsgTypes::OrbitError(unit, error) => {
+                app.messages
+                    .push(format!("orbit error of unit {}: {:?}", unit, error));
+            }
+            MsgTypes::DcirResult(unit, result) => {
+                app.messages.push(format!(
+                    "unit {} internal resistance at SoC {:?}: ohmic {:.1} mΩ, polarization {:.1} mΩ ({:.3} V {:.3} A -> {:.3} V {:.3} A -> {:.3} V {:.3} A, {})",
+                    unit,
+                    result.state_of_charge,
+                    result.ohmic_resistance * 1000.0,
+                    result.polarization_resistance * 1000.0,
+                    result.before.voltage,
+                    result.before.current,
+                    result.after.voltage,
+                    result.after.current,
+                    result.end.voltage,
+                    result.end.current,
+                    calibration_version(&app.telemetry, unit)
+                ));
+            }
+            MsgTypes::DcirError(unit, error) => {
+                app.messages
+                    .push(format!("dcir error of unit {}: {:?}", unit, error));
+            }
+            MsgTypes::HppcData(unit, data) => {
+                if let Err(error) = hppc::save(unit, &data) {
+                    app.messages
+                        .push(format!("couldn't save hppc data of unit {}: {}", unit, error));
+                }
+                if data.last {
+                    app.messages.push(format!(
+                        "unit {} recorded the pulses of hppc step {}",
+                        unit, data.step
+                    ));
+                }
+            }
+            MsgTypes::HppcFinished(unit, end) => {
+                app.messages.push(format!(
+                    "hppc of unit {} ended after {} steps: {:?} ({})",
+                    unit,
+                    end.steps,
+                    end.reason,
+                    calibration_version(&app.telemetry, unit)
+                ));
+            }
+            MsgTypes::HppcError(unit, error) => {
+                app.messages
+                    .push(format!("hppc error of unit {}: {:?}", unit, error));
+            }
+            MsgTypes::OcvPoint(unit, index, point) => match ocv_exports.get_mut(&unit) {
+                Some((_, points)) => points.push(point),
+                None => {
+                    app.messages.push(format!(
+                        "unit {} ocv point {}: {:.3} V at SoC {:.3} after {:.0} s rest",
+                        unit, index, point.voltage, point.state_of_charge, point.rest_duration
+                    ));
+                }
+            },
+            MsgTypes::OcvTableEnd(unit, count) => {
+                if let Some((path, points)) = ocv_exports.remove(&unit) {
+                    let message = if points.len() != count as usize {
+                        format!(
+                            "received {} of {} ocv points of unit {}, try again",
+                            points.len(),
+                            count,
+                            unit
+                        )
+                    } else {
+                        match ocv::export(&path, &points) {
+                            Ok(()) => format!("exported {} ocv points to {}", count, path),
+                            Err(error) => format!("couldn't export to {}: {}", path, error),
+                        }
+                    };
+                    app.messages.push(message);
+                }
+            }
+            MsgTypes::OcvFinished(unit, end) => {
+                app.messages.push(format!(
+                    "ocv of unit {} ended with {} points: {:?} ({})",
+                    unit,
+                    end.points,
+                    end.reason,
+                    calibration_version(&app.telemetry, unit)
+                ));
+            }
+            MsgTypes::OcvError(unit, error) => {
+                app.messages
+                    .push(format!("ocv error of unit {}: {:?}", unit, error));
+            }
+            MsgTypes::SocEstimatorError(unit, error) => {
+                app.messages
+                    .push(format!("soc estimator error of unit {}: {:?}", unit, error));
+            }
+            MsgTypes::CalibrationPoint(unit, index, point) => {
+                app.messages.push(format!(
+                    "unit {} calibration point {}: raw {} at reference {}",
+                    unit, index, point.raw, point.reference
+                ));
+            }
+            MsgTypes::Calibration(unit, calibration) => {
+                app.messages.push(format!(
+                    "unit {} uses calibration {}: voltage {:?}, current {:?}, temperature {:?}",
+                    unit,
+                    calibration.version,
+                    calibration.voltage.coefficients,
+                    calibration.current.coefficients,
+                    calibration.temperature.coefficients
+                ));
+                if let Some(path) = calibration_saves.remove(&unit) {
+                    let message = match calibration::save(&path, &calibration) {
+                        Ok(()) => format!("saved the calibration of unit {} to {}", unit, path),
+                        Err(error) => format!("couldn't save to {}: {}", path, error),
+                    };
+                    app.messages.push(message);
+                }
+            }
+            MsgTypes::CalibrationError(unit, error) => {
+                app.messages
+                    .push(format!("calibration error of unit {}: {:?}", unit, error));
+            }
+            MsgTypes::Telemetry(unit, telemetry) => {
+                app.telemetry.insert(unit, telemetry);
+            }
             _ => {
                 app.messages.push(format!(
                     "received something, but this message isn't implemented for the variant"
@@ -63,6 +731,10 @@ fn main() {
             }
         });
 
+        if let Some(from) = log_request {
+            port.send(MsgTypes::GetLog(from));
+        }
+
         std::thread::sleep(std::time::Duration::from_millis(15));
     }
 
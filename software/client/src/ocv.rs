@@ -0,0 +1,167 @@
+//! Text format of OCV configurations, one `name value` pair per line. Empty lines and everything
+//! after `#` are ignored, values that aren't given keep their defaults.
+//!
+//! ```text
+//! nominal_capacity 3.0   # Ah, required
+//! increment 0.15         # Ah, required
+//! discharge_current 0.6  # A, required
+//! min_voltage 2.5        # V, required
+//! rest_duration 7200     # s
+//! min_rest_duration 1800 # s
+//! dvdt_threshold 0.00001 # V/s, 0 always rests for the whole rest duration
+//! dvdt_interval 300      # s
+//! charge true            # charge back in increments after the discharge
+//! ```
+
+use std::fs::File;
+use std::io::Write;
+
+use firmware::ocv::{OcvConfig, OcvDirection, OcvPoint};
+
+pub fn parse(text: &str) -> Result<OcvConfig, String> {
+    let mut config = OcvConfig {
+        nominal_capacity: f32::NAN,
+        increment: f32::NAN,
+        discharge_current: f32::NAN,
+        rest_duration: 7200.0,
+        min_rest_duration: 1800.0,
+        dvdt_threshold: 0.00001,
+        dvdt_interval: 300.0,
+        min_voltage: f32::NAN,
+        charge: false,
+    };
+
+    for (number, line) in text.lines().enumerate() {
+        let line = line.split('#').next().unwrap().trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        let (name, value) = match line.split_whitespace().collect::<Vec<_>>().as_slice() {
+            [name, value] => (*name, *value),
+            _ => return Err(format!("line {}: expected a name and a value", number + 1)),
+        };
+        if name == "charge" {
+            config.charge = value
+                .parse()
+                .map_err(|_| format!("line {}: expected true or false", number + 1))?;
+            continue;
+        }
+
+        let value = value
+            .parse::<f32>()
+            .map_err(|_| format!("line {}: invalid number '{}'", number + 1, value))?;
+        let field = match name {
+            "nominal_capacity" => &mut config.nominal_capacity,
+            "increment" => &mut config.increment,
+            "discharge_current" => &mut config.discharge_current,
+            "rest_duration" => &mut config.rest_duration,
+            "min_rest_duration" => &mut config.min_rest_duration,
+            "dvdt_threshold" => &mut config.dvdt_threshold,
+            "dvdt_interval" => &mut config.dvdt_interval,
+            "min_voltage" => &mut config.min_voltage,
+            _ => return Err(format!("line {}: unknown value '{}'", number + 1, name)),
+        };
+        *field = value;
+    }
+
+    config
+        .validate()
+        .map_err(|_| "a required value is missing or a value is out of range".to_string())?;
+    Ok(config)
+}
+
+/// Writes the table as CSV with one `state_of_charge,discharged,voltage,rest_duration,direction`
+/// line per point
+pub fn export(path: &str, points: &[OcvPoint]) -> std::io::Result<()> {
+    let mut file = File::create(path)?;
+    writeln!(
+        file,
+        "state_of_charge,discharged,voltage,rest_duration,direction"
+    )?;
+    for point in points {
+        let direction = match point.direction {
+            OcvDirection::Discharge => "discharge",
+            OcvDirection::Charge => "charge",
+        };
+        writeln!(
+            file,
+            "{},{},{},{},{}",
+            point.state_of_charge, point.discharged, point.voltage, point.rest_duration, direction
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const REQUIRED: &str = "\
+nominal_capacity 3.0
+increment 0.15 # 5 %
+discharge_current 0.6
+
+min_voltage 2.5
+";
+
+    #[test]
+    fn test_parse() {
+        let config = parse(&format!("{}rest_duration 3600\ncharge true\n", REQUIRED)).unwrap();
+        assert_eq!(
+            config,
+            OcvConfig {
+                nominal_capacity: 3.0,
+                increment: 0.15,
+                discharge_current: 0.6,
+                rest_duration: 3600.0,
+                min_rest_duration: 1800.0,
+                dvdt_threshold: 0.00001,
+                dvdt_interval: 300.0,
+                min_voltage: 2.5,
+                charge: true,
+            }
+        );
+    }
+
+    #[test]
+    fn test_wrong_arguments() {
+        assert_eq!(
+            parse(&format!("{}charge\n", REQUIRED)),
+            Err("line 6: expected a name and a value".to_string())
+        );
+        assert!(parse(&format!("{}rest_duration 3600 1800\n", REQUIRED)).is_err());
+        assert_eq!(
+            parse(&format!("{}charge yes\n", REQUIRED)),
+            Err("line 6: expected true or false".to_string())
+        );
+        assert_eq!(
+            parse(&format!("{}rest 3600\n", REQUIRED)),
+            Err("line 6: unknown value 'rest'".to_string())
+        );
+        assert_eq!(
+            parse(&format!("{}dvdt_interval 5min\n", REQUIRED)),
+            Err("line 6: invalid number '5min'".to_string())
+        );
+        // the required values have no defaults
+        assert!(parse("nominal_capacity 3.0\nincrement 0.15\nmin_voltage 2.5").is_err());
+    }
+
+    #[test]
+    fn test_out_of_range() {
+        for line in [
+            "increment 0",
+            "discharge_current -0.6",
+            "min_rest_duration 8000",
+            "dvdt_threshold -0.1",
+            "dvdt_interval 0",
+        ] {
+            assert_eq!(
+                parse(&format!("{}{}\n", REQUIRED, line)),
+                Err("a required value is missing or a value is out of range".to_string()),
+                "{}",
+                line
+            );
+        }
+    }
+}
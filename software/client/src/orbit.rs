@@ -0,0 +1,60 @@
+//! Text format of orbit profiles, one segment per line. Empty lines and everything after `#` are
+//! ignored, the profile is replayed from the first segment once the last one has ended.
+//!
+//! ```text
+//! # 60 minutes of sunlight, a 5 minute downlink and the rest of the eclipse in idle
+//! 3600 0 charge
+//! 300 2.5W
+//! 1800 0.4A
+//! ```
+//!
+//! Each segment is its duration in s followed by the load, either in W (`2.5W`), in A (`0.4A`) or
+//! `0` for no load, and optionally `charge` to turn the charger on.
+
+use firmware::orbit::{Segment, SegmentLoad};
+
+pub fn parse(text: &str) -> Result<Vec<Segment>, String> {
+    text.lines()
+        .enumerate()
+        .filter_map(|(number, line)| {
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() {
+                None
+            } else {
+                Some(parse_segment(line).map_err(|error| format!("line {}: {}", number + 1, error)))
+            }
+        })
+        .collect()
+}
+
+fn parse_segment(line: &str) -> Result<Segment, String> {
+    let tokens = line.split_whitespace().collect::<Vec<_>>();
+    let (duration, load, charge) = match tokens.as_slice() {
+        [duration, load] => (duration, load, false),
+        [duration, load, "charge"] => (duration, load, true),
+        _ => return Err(format!("unknown segment '{}'", line)),
+    };
+
+    Ok(Segment {
+        duration: parse_number(duration)?,
+        load: parse_load(load)?,
+        charge,
+    })
+}
+
+fn parse_load(text: &str) -> Result<SegmentLoad, String> {
+    if let Some(power) = text.strip_suffix('W') {
+        Ok(SegmentLoad::Power(parse_number(power)?))
+    } else if let Some(current) = text.strip_suffix('A') {
+        Ok(SegmentLoad::Current(parse_number(current)?))
+    } else if parse_number::<f32>(text)? == 0.0 {
+        Ok(SegmentLoad::Current(0.0))
+    } else {
+        Err(format!("load '{}' needs a unit, W or A", text))
+    }
+}
+
+fn parse_number<T: std::str::FromStr>(text: &str) -> Result<T, String> {
+    text.parse()
+        .map_err(|_| format!("invalid number '{}'", text))
+}
@@ -0,0 +1,198 @@
+//! Text format of test programs, one step per line. Empty lines and everything after `#` are
+//! ignored, steps are numbered from 0 in the order they appear.
+//!
+//! ```text
+//! # charge, rest, discharge with 1.5 A down to 2.5 V and rest again, three times in total
+//! charge until charge_done, duration 14400
+//! rest until duration 1800
+//! discharge_cc 1.5 until voltage_below 2.5
+//! rest until duration 1800
+//! loop 0 2
+//! ```
+//!
+//! Steps: `charge`, `discharge_cc A`, `discharge_cp W`, `discharge_cr Ω` and `rest`, each followed
+//! by `until` and its end conditions, as well as `loop <step> <count>` and
+//! `goto_if <condition> <step>`, which jumps if the condition is met.
+//!
+//! End conditions: `voltage_below V`, `voltage_above V`, `current_below A`, `current_above A`,
+//! `temperature_below °C`, `temperature_above °C`, `duration s`, `capacity Ah`, `charge_done`.
+
+use firmware::sequence::{Condition, EndConditions, Step};
+
+pub fn parse(text: &str) -> Result<Vec<Step>, String> {
+    text.lines()
+        .enumerate()
+        .filter_map(|(number, line)| {
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() {
+                None
+            } else {
+                Some(parse_step(line).map_err(|error| format!("line {}: {}", number + 1, error)))
+            }
+        })
+        .collect()
+}
+
+fn parse_step(line: &str) -> Result<Step, String> {
+    let (step, end) = match line.split_once(" until ") {
+        Some((step, end)) => (step, Some(parse_end_conditions(end)?)),
+        None => (line, None),
+    };
+    let tokens = step.split_whitespace().collect::<Vec<_>>();
+    let end = || end.clone().ok_or("missing end conditions".to_string());
+
+    match tokens.as_slice() {
+        ["charge"] => Ok(Step::Charge(end()?)),
+        ["discharge_cc", current] => Ok(Step::DischargeConstantCurrent(
+            parse_number(current)?,
+            end()?,
+        )),
+        ["discharge_cp", power] => Ok(Step::DischargeConstantPower(parse_number(power)?, end()?)),
+        ["discharge_cr", resistance] => Ok(Step::DischargeConstantResistance(
+            parse_number(resistance)?,
+            end()?,
+        )),
+        ["rest"] => Ok(Step::Rest(end()?)),
+        ["loop", target, count] => Ok(Step::Loop {
+            target: parse_number(target)?,
+            count: parse_number(count)?,
+        }),
+        ["goto_if", condition @ .., target] => Ok(Step::GotoIf {
+            condition: parse_condition(condition)?,
+            target: parse_number(target)?,
+        }),
+        _ => Err(format!("unknown step '{}'", step)),
+    }
+}
+
+fn parse_end_conditions(text: &str) -> Result<EndConditions, String> {
+    let mut conditions = EndConditions::new();
+    for condition in text.split(',') {
+        let tokens = condition.split_whitespace().collect::<Vec<_>>();
+        conditions
+            .push(parse_condition(&tokens)?)
+            .map_err(|_| "too many end conditions".to_string())?;
+    }
+    Ok(conditions)
+}
+
+fn parse_condition(tokens: &[&str]) -> Result<Condition, String> {
+    match tokens {
+        ["voltage_below", value] => Ok(Condition::VoltageBelow(parse_number(value)?)),
+        ["voltage_above", value] => Ok(Condition::VoltageAbove(parse_number(value)?)),
+        ["current_below", value] => Ok(Condition::CurrentBelow(parse_number(value)?)),
+        ["current_above", value] => Ok(Condition::CurrentAbove(parse_number(value)?)),
+        ["temperature_below", value] => Ok(Condition::TemperatureBelow(parse_number(value)?)),
+        ["temperature_above", value] => Ok(Condition::TemperatureAbove(parse_number(value)?)),
+        ["duration", value] => Ok(Condition::Duration(parse_number(value)?)),
+        ["capacity", value] => Ok(Condition::Capacity(parse_number(value)?)),
+        ["charge_done"] => Ok(Condition::ChargeDone),
+        _ => Err(format!("unknown condition '{}'", tokens.join(" "))),
+    }
+}
+
+fn parse_number<T: std::str::FromStr>(text: &str) -> Result<T, String> {
+    text.parse()
+        .map_err(|_| format!("invalid number '{}'", text))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn conditions(conditions: &[Condition]) -> EndConditions {
+        EndConditions::from_slice(conditions).unwrap()
+    }
+
+    #[test]
+    fn test_parse() {
+        let text = "\
+# charge and discharge twice
+charge until charge_done, duration 14400
+
+discharge_cc 1.5 until voltage_below 2.5 # down to empty
+rest until duration 1800
+goto_if temperature_above 40 2
+loop 0 1
+";
+        let program = parse(text).unwrap();
+        assert_eq!(
+            program,
+            [
+                Step::Charge(conditions(&[
+                    Condition::ChargeDone,
+                    Condition::Duration(14400.0)
+                ])),
+                Step::DischargeConstantCurrent(1.5, conditions(&[Condition::VoltageBelow(2.5)])),
+                Step::Rest(conditions(&[Condition::Duration(1800.0)])),
+                Step::GotoIf {
+                    condition: Condition::TemperatureAbove(40.0),
+                    target: 2
+                },
+                Step::Loop {
+                    target: 0,
+                    count: 1
+                },
+            ]
+        );
+        assert_eq!(parse("# nothing to do\n\n"), Ok(vec![]));
+    }
+
+    #[test]
+    fn test_constant_power_and_resistance() {
+        let text = "\
+discharge_cp 2.0 until voltage_below 2.5
+discharge_cr 1.6 until voltage_below 2.5
+";
+        let end = conditions(&[Condition::VoltageBelow(2.5)]);
+        assert_eq!(
+            parse(text),
+            Ok(vec![
+                Step::DischargeConstantPower(2.0, end.clone()),
+                Step::DischargeConstantResistance(1.6, end),
+            ])
+        );
+
+        assert!(parse("discharge_cp until voltage_below 2.5").is_err());
+        assert!(parse("discharge_cr 1.6 2.0 until voltage_below 2.5").is_err());
+        assert_eq!(
+            parse("discharge_cr 1.6"),
+            Err("line 1: missing end conditions".to_string())
+        );
+        assert_eq!(
+            parse("discharge_cp 2W until voltage_below 2.5"),
+            Err("line 1: invalid number '2W'".to_string())
+        );
+    }
+
+    #[test]
+    fn test_wrong_arguments() {
+        assert_eq!(
+            parse("rest until duration 1800\ncharge"),
+            Err("line 2: missing end conditions".to_string())
+        );
+        assert!(parse("discharge_cc until voltage_below 2.5").is_err());
+        assert!(parse("discharge_cc 1.5 2.5 until voltage_below 2.5").is_err());
+        assert!(parse("loop 0").is_err());
+        assert!(parse("rest until duration").is_err());
+        assert!(parse("rest until charge_done 1").is_err());
+        assert!(parse("goto_if 2").is_err());
+        assert_eq!(
+            parse("rest until duration 1, duration 2, duration 3, duration 4, duration 5"),
+            Err("line 1: too many end conditions".to_string())
+        );
+    }
+
+    #[test]
+    fn test_invalid_values() {
+        assert_eq!(
+            parse("discharge_cc fast until voltage_below 2.5"),
+            Err("line 1: invalid number 'fast'".to_string())
+        );
+        // the step index is a u8 and the count a u16
+        assert!(parse("loop 256 1").is_err());
+        assert!(parse("loop 0 -1").is_err());
+        assert!(parse("loop 0 65536").is_err());
+        assert!(parse("goto_if charge_done 300").is_err());
+    }
+}
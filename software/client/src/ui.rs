@@ -5,7 +5,17 @@ use crossterm::{
     terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
 };
 use easy_min_max::max;
-use std::{error::Error, io, time::Duration};
+use firmware::calibration::CalibrationChannel;
+use firmware::clock;
+use firmware::conditioning::ChannelConditioning;
+use firmware::datalog::LogConfig;
+use firmware::dcir::{DcirSchedule, PulseConfig};
+use firmware::limits::SafetyLimits;
+use firmware::msg_types::Telemetry;
+use firmware::sampling::SamplingConfig;
+use firmware::soc::SocEstimatorConfig;
+use firmware::BatteryTestUnitMode;
+use std::{collections::BTreeMap, error::Error, io, time::Duration};
 use tui::{
     backend::{Backend, CrosstermBackend},
     layout::{Constraint, Direction, Layout},
@@ -27,12 +37,74 @@ pub enum AppEvent {
     Input(String),
     SendPing(u16),
     SampleAdc(u8),
+    ClearFault(u8),
+    /// unit and the new mode, `None` asks for the mode
+    Mode(u8, Option<BatteryTestUnitMode>),
+    PauseTest(u8),
+    AbortTest(u8),
+    /// `None` asks for the status of all units
+    Status(Option<u8>),
+    /// unit and the new limits, `None` asks for the limits
+    Limits(u8, Option<SafetyLimits>),
+    /// unit and path of the program file
+    UploadProgram(u8, std::string::String),
+    /// unit and path of the orbit profile file
+    UploadOrbitProfile(u8, std::string::String),
+    /// unit and number of orbits
+    StartOrbits(u8, u16),
+    StopOrbits(u8),
+    StartPulseTest(u8, PulseConfig),
+    SetDcirSchedule(u8, Option<DcirSchedule>),
+    /// unit and path of the HPPC configuration file
+    StartHppc(u8, std::string::String),
+    StopHppc(u8),
+    /// unit and path of the OCV configuration file
+    StartOcv(u8, std::string::String),
+    StopOcv(u8),
+    /// unit and path of the CSV file
+    ExportOcv(u8, std::string::String),
+    SetSocEstimator(u8, Option<SocEstimatorConfig>),
+    /// unit, channel and degree of the polynomial
+    StartCalibration(u8, CalibrationChannel, u8),
+    /// unit and the reference value
+    AddCalibrationPoint(u8, f32),
+    FinishCalibration(u8),
+    CancelCalibration(u8),
+    /// unit and path of the calibration file
+    SaveCalibration(u8, std::string::String),
+    /// unit and path of the calibration file
+    LoadCalibration(u8, std::string::String),
+    StartProgram(u8),
+    StopProgram(u8),
+    /// continues the paused test of the unit, or the test a reset interrupted
+    ResumeTest(u8),
+    SaveConfig,
+    /// in ms
+    SetSamplePeriod(u16),
+    GetSamplePeriod,
+    /// Unix time in ms
+    SetTime(u64),
+    GetTime,
+    /// unit and the new name, `None` asks for the name
+    UnitName(u8, Option<heapless::String<16>>),
+    /// unit and the new sampling, `None` asks for the sampling
+    Sampling(u8, Option<SamplingConfig>),
+    /// unit, channel and its new conditioning, `None` asks for the conditioning of all channels
+    Conditioning(u8, Option<(CalibrationChannel, ChannelConditioning)>),
+    StartLog(LogConfig),
+    StopLog,
+    ClearLog,
+    LogStatus,
+    /// path of the CSV file, an existing file is continued
+    DownloadLog(std::string::String),
 }
 
 pub struct App {
     input: String,
     input_mode: InputMode,
     pub messages: Vec<String>,
+    /// the latest telemetry of every battery test unit, by id
+    pub telemetry: BTreeMap<u8, Telemetry>,
 }
 
 impl Default for App {
@@ -41,6 +113,7 @@ impl Default for App {
             input: String::new(),
             input_mode: InputMode::Normal,
             messages: Vec::new(),
+            telemetry: BTreeMap::new(),
         }
     }
 }
@@ -126,6 +199,7 @@ fn ui<B: Backend>(f: &mut Frame<B>, app: &App) {
             [
                 Constraint::Length(1),
                 Constraint::Length(3),
+                Constraint::Length(max!(1, app.telemetry.len()) as u16 + 2),
                 Constraint::Min(1),
             ]
             .as_ref(),
@@ -182,7 +256,57 @@ fn ui<B: Backend>(f: &mut Frame<B>, app: &App) {
         }
     }
 
-    let height = chunks[2].height as i32;
+    let telemetry = if app.telemetry.is_empty() {
+        Text::raw("no telemetry received")
+    } else {
+        Text::from(
+            app.telemetry
+                .iter()
+                .map(|(unit, telemetry)| {
+                    Spans::from(format!(
+                        "unit {} {}: {:.3} V ±{:.1} mV  {:.3} A  {}  {:.3} Ah  {:.3} Wh  {}  {}  {:?}{}  calibration {}",
+                        unit,
+                        match clock::to_datetime(telemetry.timestamp) {
+                            Ok(datetime) => datetime.time().to_string(),
+                            Err(_) => "-".to_string(),
+                        },
+                        telemetry.voltage,
+                        telemetry.stats.voltage.std_dev * 1000.0,
+                        telemetry.current,
+                        match telemetry.temperature {
+                            Some(temperature) => format!("{:.1} °C", temperature),
+                            None => "- °C".to_string(),
+                        },
+                        telemetry.capacity,
+                        telemetry.energy,
+                        match telemetry.state_of_charge {
+                            Some(soc) => format!("SoC {:.1} %", soc * 100.0),
+                            None => "SoC - %".to_string(),
+                        },
+                        match telemetry.remaining_time {
+                            Some(time) => format!(
+                                "{}:{:02} h left",
+                                time as u32 / 3600,
+                                time as u32 / 60 % 60
+                            ),
+                            None => std::string::String::new(),
+                        },
+                        telemetry.mode,
+                        match telemetry.step {
+                            Some(step) => format!(" step {}", step),
+                            None => std::string::String::new(),
+                        },
+                        telemetry.calibration
+                    ))
+                })
+                .collect::<Vec<_>>(),
+        )
+    };
+    let telemetry =
+        Paragraph::new(telemetry).block(Block::default().borders(Borders::ALL).title("Telemetry"));
+    f.render_widget(telemetry, chunks[2]);
+
+    let height = chunks[3].height as i32;
     let to_skip = max!(0, app.messages.len() as i32 - height) as usize;
 
     let messages: Vec<ListItem> = app
@@ -197,5 +321,5 @@ fn ui<B: Backend>(f: &mut Frame<B>, app: &App) {
         .collect();
     let messages =
         List::new(messages).block(Block::default().borders(Borders::ALL).title("Messages"));
-    f.render_widget(messages, chunks[2]);
+    f.render_widget(messages, chunks[3]);
 }
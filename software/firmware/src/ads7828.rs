@@ -0,0 +1,281 @@
+use embedded_hal::blocking::i2c::WriteRead;
+
+use crate::traits::{AdcInput, SharedResource};
+
+/// Voltage of the internal reference, in V
+pub const INTERNAL_REFERENCE_VOLTAGE: f32 = 2.5;
+
+const BASE_ADDRESS: u8 = 0b100_1000;
+const MAX_SAMPLE: u16 = 0x0fff;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Reference {
+    /// The internal 2.5 V reference
+    Internal,
+    /// An external reference with the given voltage, in V
+    External(f32),
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum PowerMode {
+    /// The converter powers down between conversions
+    PowerDownBetweenConversions,
+    /// The converter stays powered
+    AlwaysOn,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Channel {
+    /// One of the eight inputs (0..=7) against COM
+    SingleEnded(u8),
+    /// The given input (0..=7) against the other input of its pair, e.g. 2 against 3 or 3 against 2
+    Differential(u8),
+}
+
+impl Channel {
+    fn command_bits(&self) -> u8 {
+        let (single_ended, input) = match *self {
+            Channel::SingleEnded(input) => (1, input),
+            Channel::Differential(input) => (0, input),
+        };
+        assert!(input < 8, "the ADS7828 only has 8 inputs");
+
+        // the inputs are selected with the odd inputs in the upper half: 0, 2, 4, 6, 1, 3, 5, 7
+        (single_ended << 7) | ((input & 1) << 6) | ((input >> 1) << 4)
+    }
+}
+
+/// Configuration of one ADS7828, an 8-channel 12-bit ADC on the I2C bus. It doesn't own the bus,
+/// because all four converters on the hat share it.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Ads7828 {
+    address: u8,
+    reference: Reference,
+    power_mode: PowerMode,
+}
+
+impl Ads7828 {
+    /// `address_pins` is the state of the A1 and A0 pins (0..=3)
+    pub fn new(address_pins: u8, reference: Reference, power_mode: PowerMode) -> Self {
+        assert!(address_pins < 4, "the ADS7828 only has two address pins");
+
+        Self {
+            address: BASE_ADDRESS | address_pins,
+            reference,
+            power_mode,
+        }
+    }
+
+    pub fn get_address(&self) -> u8 {
+        self.address
+    }
+
+    /// The command byte that starts a conversion on the given channel
+    pub fn command(&self, channel: Channel) -> u8 {
+        let internal_reference_on = match self.reference {
+            Reference::Internal => 1,
+            Reference::External(_) => 0,
+        };
+        let converter_on = match self.power_mode {
+            PowerMode::PowerDownBetweenConversions => 0,
+            PowerMode::AlwaysOn => 1,
+        };
+
+        channel.command_bits() | (internal_reference_on << 3) | (converter_on << 2)
+    }
+
+    pub fn get_reference_voltage(&self) -> f32 {
+        match self.reference {
+            Reference::Internal => INTERNAL_REFERENCE_VOLTAGE,
+            Reference::External(voltage) => voltage,
+        }
+    }
+
+    /// Starts a conversion and returns the 12-bit result
+    pub fn read_raw<I2C: WriteRead>(
+        &self,
+        i2c: &mut I2C,
+        channel: Channel,
+    ) -> Result<u16, I2C::Error> {
+        let mut buf = [0u8; 2];
+        i2c.write_read(self.address, &[self.command(channel)], &mut buf)?;
+
+        Ok(u16::from_be_bytes(buf) & MAX_SAMPLE)
+    }
+
+    /// Starts a conversion and returns the result in V
+    pub fn read_voltage<I2C: WriteRead>(
+        &self,
+        i2c: &mut I2C,
+        channel: Channel,
+    ) -> Result<f32, I2C::Error> {
+        let sample = self.read_raw(i2c, channel)?;
+
+        Ok(sample as f32 * self.get_reference_voltage() / (MAX_SAMPLE + 1) as f32)
+    }
+
+    /// Exposes one channel as an `AdcInput`, the bus is shared with all other channels
+    pub fn channel<'a, S: SharedResource>(
+        &self,
+        bus: &'a S,
+        channel: Channel,
+    ) -> Ads7828Channel<'a, S> {
+        Ads7828Channel {
+            bus,
+            adc: *self,
+            channel,
+        }
+    }
+}
+
+pub struct Ads7828Channel<'a, S: SharedResource> {
+    bus: &'a S,
+    adc: Ads7828,
+    channel: Channel,
+}
+
+impl<S, I2C> AdcInput for Ads7828Channel<'_, S>
+where
+    S: SharedResource<Target = I2C>,
+    I2C: WriteRead,
+{
+    /// Returns NaN if the bus transfer failed
+    fn get_voltage(&mut self) -> f32 {
+        let (adc, channel) = (self.adc, self.channel);
+
+        self.bus
+            .lock(|i2c| adc.read_voltage(i2c, channel))
+            .unwrap_or(f32::NAN)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mocks::{MockI2c, MockI2cTransaction};
+    use core::cell::RefCell;
+
+    #[test]
+    fn test_address() {
+        macro_rules! t {
+            ($pins:expr, $address:expr) => {
+                let adc = Ads7828::new($pins, Reference::Internal, PowerMode::AlwaysOn);
+                assert_eq!(adc.get_address(), $address);
+            };
+        }
+
+        t!(0, 0x48);
+        t!(1, 0x49);
+        t!(2, 0x4a);
+        t!(3, 0x4b);
+    }
+
+    #[test]
+    fn test_command_single_ended() {
+        let adc = Ads7828::new(
+            0,
+            Reference::External(3.3),
+            PowerMode::PowerDownBetweenConversions,
+        );
+
+        let commands = (0..8)
+            .map(|input| adc.command(Channel::SingleEnded(input)))
+            .collect::<Vec<_>>();
+
+        assert_eq!(commands, [0x80, 0xc0, 0x90, 0xd0, 0xa0, 0xe0, 0xb0, 0xf0]);
+    }
+
+    #[test]
+    fn test_command_differential() {
+        let adc = Ads7828::new(
+            0,
+            Reference::External(3.3),
+            PowerMode::PowerDownBetweenConversions,
+        );
+
+        let commands = (0..8)
+            .map(|input| adc.command(Channel::Differential(input)))
+            .collect::<Vec<_>>();
+
+        assert_eq!(commands, [0x00, 0x40, 0x10, 0x50, 0x20, 0x60, 0x30, 0x70]);
+    }
+
+    #[test]
+    fn test_command_power_modes() {
+        macro_rules! t {
+            ($reference:expr, $power_mode:expr, $command:expr) => {
+                let adc = Ads7828::new(0, $reference, $power_mode);
+                assert_eq!(adc.command(Channel::SingleEnded(0)), $command);
+            };
+        }
+
+        t!(
+            Reference::External(3.3),
+            PowerMode::PowerDownBetweenConversions,
+            0x80
+        );
+        t!(Reference::External(3.3), PowerMode::AlwaysOn, 0x84);
+        t!(
+            Reference::Internal,
+            PowerMode::PowerDownBetweenConversions,
+            0x88
+        );
+        t!(Reference::Internal, PowerMode::AlwaysOn, 0x8c);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_invalid_channel() {
+        let adc = Ads7828::new(0, Reference::Internal, PowerMode::AlwaysOn);
+        adc.command(Channel::SingleEnded(8));
+    }
+
+    #[test]
+    fn test_read() {
+        let adc = Ads7828::new(1, Reference::Internal, PowerMode::AlwaysOn);
+        let mut i2c = MockI2c::new(vec![
+            MockI2cTransaction::write_read(0x49, vec![0xdc], vec![0x08, 0x00]),
+            MockI2cTransaction::write_read(0x49, vec![0x9c], vec![0xff, 0xff]),
+            MockI2cTransaction::write_read(0x49, vec![0x1c], vec![0x04, 0x00]),
+        ]);
+
+        assert_eq!(adc.read_raw(&mut i2c, Channel::SingleEnded(3)), Ok(0x800));
+        // the upper four bits are always zero
+        assert_eq!(adc.read_raw(&mut i2c, Channel::SingleEnded(2)), Ok(0xfff));
+        assert_eq!(
+            adc.read_voltage(&mut i2c, Channel::Differential(2)),
+            Ok(0.625)
+        );
+        i2c.done();
+    }
+
+    #[test]
+    fn test_channels_share_the_bus() {
+        let bus = RefCell::new(MockI2c::new(vec![
+            MockI2cTransaction::write_read(0x48, vec![0x84], vec![0x08, 0x00]),
+            MockI2cTransaction::write_read(0x4b, vec![0xc4], vec![0x04, 0x00]),
+            MockI2cTransaction::write_read(0x48, vec![0x84], vec![0x00, 0x00]),
+        ]));
+        let adc_0 = Ads7828::new(0, Reference::External(3.0), PowerMode::AlwaysOn);
+        let adc_3 = Ads7828::new(3, Reference::External(2.0), PowerMode::AlwaysOn);
+
+        let mut voltage = adc_0.channel(&bus, Channel::SingleEnded(0));
+        let mut current = adc_3.channel(&bus, Channel::SingleEnded(1));
+
+        assert_eq!(voltage.get_voltage(), 1.5);
+        assert_eq!(current.get_voltage(), 0.5);
+        assert_eq!(voltage.get_voltage(), 0.0);
+        bus.borrow().done();
+    }
+
+    #[test]
+    fn test_channel_bus_error() {
+        let bus = RefCell::new(MockI2c::new(vec![MockI2cTransaction::error(0x48)]));
+        let adc = Ads7828::new(0, Reference::Internal, PowerMode::AlwaysOn);
+
+        assert!(adc
+            .channel(&bus, Channel::SingleEnded(0))
+            .get_voltage()
+            .is_nan());
+    }
+}
@@ -0,0 +1,344 @@
+use heapless::Vec;
+use serde::{Deserialize, Serialize};
+
+/// Number of coefficients of a `Calibration`, the highest degree is one less
+pub const CALIBRATION_COEFFICIENTS: usize = 4;
+/// Maximum number of reference points of a single calibration
+pub const MAX_CALIBRATION_POINTS: usize = 8;
+
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq)]
+pub enum CalibrationChannel {
+    /// in V
+    Voltage,
+    /// in A
+    Current,
+    /// in °C
+    Temperature,
+}
+
+/// Corrects a raw value with the polynomial c0 + c1 * raw + c2 * raw² + c3 * raw³
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq)]
+pub struct Calibration {
+    pub coefficients: [f32; CALIBRATION_COEFFICIENTS],
+}
+
+impl Calibration {
+    /// Leaves the raw values as they are
+    pub const IDENTITY: Calibration = Calibration {
+        coefficients: [0.0, 1.0, 0.0, 0.0],
+    };
+
+    pub fn apply(&self, raw: f32) -> f32 {
+        self.coefficients
+            .iter()
+            .rev()
+            .fold(0.0, |value, coefficient| value * raw + coefficient)
+    }
+
+    /// Least squares fit of a polynomial of the given degree through the points, a degree of 1
+    /// with two points is a two-point calibration
+    pub fn fit(points: &[CalibrationPoint], degree: u8) -> Result<Self, CalibrationError> {
+        let size = degree as usize + 1;
+        if degree == 0 || size > CALIBRATION_COEFFICIENTS {
+            return Err(CalibrationError::InvalidDegree);
+        }
+        if points.len() < size {
+            return Err(CalibrationError::NotEnoughPoints);
+        }
+
+        // normal equations, solved in f64 because the powers of the raw values differ a lot
+        let mut matrix = [[0.0f64; CALIBRATION_COEFFICIENTS + 1]; CALIBRATION_COEFFICIENTS];
+        for point in points {
+            let raw = point.raw as f64;
+            for (row, equation) in matrix.iter_mut().enumerate().take(size) {
+                for (column, element) in equation.iter_mut().enumerate().take(size) {
+                    *element += libm::pow(raw, (row + column) as f64);
+                }
+                equation[size] += libm::pow(raw, row as f64) * point.reference as f64;
+            }
+        }
+
+        // Gaussian elimination with partial pivoting
+        for column in 0..size {
+            let pivot = (column..size)
+                .max_by(|a, b| {
+                    libm::fabs(matrix[*a][column]).total_cmp(&libm::fabs(matrix[*b][column]))
+                })
+                .unwrap();
+            if libm::fabs(matrix[pivot][column]) < 1e-12 {
+                return Err(CalibrationError::Singular);
+            }
+            matrix.swap(column, pivot);
+
+            let pivot_equation = matrix[column];
+            for (row, equation) in matrix.iter_mut().enumerate().take(size) {
+                if row != column {
+                    let factor = equation[column] / pivot_equation[column];
+                    for (element, pivot) in equation.iter_mut().zip(pivot_equation).skip(column) {
+                        *element -= factor * pivot;
+                    }
+                }
+            }
+        }
+
+        let mut coefficients = [0.0; CALIBRATION_COEFFICIENTS];
+        for (row, coefficient) in coefficients.iter_mut().enumerate().take(size) {
+            *coefficient = (matrix[row][size] / matrix[row][row]) as f32;
+        }
+        if coefficients
+            .iter()
+            .any(|coefficient| !coefficient.is_finite())
+        {
+            return Err(CalibrationError::Singular);
+        }
+        Ok(Self { coefficients })
+    }
+}
+
+/// The calibrations of all channels of a battery test unit
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq)]
+pub struct CalibrationSet {
+    /// incremented with every new calibration, the telemetry reports which one was used
+    pub version: u16,
+    pub voltage: Calibration,
+    pub current: Calibration,
+    pub temperature: Calibration,
+}
+
+impl Default for CalibrationSet {
+    fn default() -> Self {
+        Self {
+            version: 0,
+            voltage: Calibration::IDENTITY,
+            current: Calibration::IDENTITY,
+            temperature: Calibration::IDENTITY,
+        }
+    }
+}
+
+impl CalibrationSet {
+    pub fn get(&self, channel: CalibrationChannel) -> &Calibration {
+        match channel {
+            CalibrationChannel::Voltage => &self.voltage,
+            CalibrationChannel::Current => &self.current,
+            CalibrationChannel::Temperature => &self.temperature,
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), CalibrationError> {
+        let coefficients = [self.voltage, self.current, self.temperature]
+            .into_iter()
+            .flat_map(|calibration| calibration.coefficients);
+        for coefficient in coefficients {
+            if !coefficient.is_finite() {
+                return Err(CalibrationError::InvalidCoefficients);
+            }
+        }
+        Ok(())
+    }
+
+    /// Replaces the calibration of the channel and increments the version
+    pub fn set(&mut self, channel: CalibrationChannel, calibration: Calibration) {
+        match channel {
+            CalibrationChannel::Voltage => self.voltage = calibration,
+            CalibrationChannel::Current => self.current = calibration,
+            CalibrationChannel::Temperature => self.temperature = calibration,
+        }
+        self.version = self.version.wrapping_add(1);
+    }
+}
+
+/// A raw value the unit measured while the channel was connected to a known reference
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq)]
+pub struct CalibrationPoint {
+    pub raw: f32,
+    pub reference: f32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq)]
+pub enum CalibrationError {
+    /// A calibration can only be started or replaced while the unit is idle
+    Busy,
+    /// No calibration was started
+    NotCalibrating,
+    /// The degree has to be between 1 and 3
+    InvalidDegree,
+    /// A calibration takes at most `MAX_CALIBRATION_POINTS` points
+    TooManyPoints,
+    /// The fit needs at least one point more than the degree
+    NotEnoughPoints,
+    /// The raw value or the reference isn't a number, e.g. because the sensor couldn't be read
+    InvalidPoint,
+    /// The points don't determine the polynomial, e.g. because they have the same raw value
+    Singular,
+    /// A coefficient of an uploaded calibration isn't a number
+    InvalidCoefficients,
+}
+
+/// Collects the reference points of one channel until the polynomial is fitted through them
+#[derive(Debug, Clone, PartialEq)]
+pub struct CalibrationRun {
+    channel: CalibrationChannel,
+    degree: u8,
+    points: Vec<CalibrationPoint, MAX_CALIBRATION_POINTS>,
+}
+
+impl CalibrationRun {
+    pub fn new(channel: CalibrationChannel, degree: u8) -> Result<Self, CalibrationError> {
+        if degree == 0 || degree as usize >= CALIBRATION_COEFFICIENTS {
+            return Err(CalibrationError::InvalidDegree);
+        }
+
+        Ok(Self {
+            channel,
+            degree,
+            points: Vec::new(),
+        })
+    }
+
+    pub fn get_channel(&self) -> CalibrationChannel {
+        self.channel
+    }
+
+    pub fn get_points(&self) -> &[CalibrationPoint] {
+        &self.points
+    }
+
+    /// Returns the index of the new point
+    pub fn add_point(&mut self, point: CalibrationPoint) -> Result<u8, CalibrationError> {
+        if !point.raw.is_finite() || !point.reference.is_finite() {
+            return Err(CalibrationError::InvalidPoint);
+        }
+        self.points
+            .push(point)
+            .map_err(|_| CalibrationError::TooManyPoints)?;
+        Ok(self.points.len() as u8 - 1)
+    }
+
+    pub fn fit(&self) -> Result<Calibration, CalibrationError> {
+        Calibration::fit(&self.points, self.degree)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn point(raw: f32, reference: f32) -> CalibrationPoint {
+        CalibrationPoint { raw, reference }
+    }
+
+    fn assert_coefficients_eq(calibration: Calibration, expected: [f32; 4]) {
+        for (coefficient, expected) in calibration.coefficients.iter().zip(expected) {
+            assert!(
+                (coefficient - expected).abs() < 1e-4,
+                "{:?} differs from {:?}",
+                calibration.coefficients,
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn test_apply() {
+        assert_eq!(Calibration::IDENTITY.apply(3.3), 3.3);
+
+        let calibration = Calibration {
+            coefficients: [0.5, 2.0, 0.0, 1.0],
+        };
+        assert_eq!(calibration.apply(2.0), 0.5 + 4.0 + 8.0);
+        assert!(calibration.apply(f32::NAN).is_nan());
+    }
+
+    #[test]
+    fn test_two_point() {
+        // gain and offset of a divider that reads 2 % low with 10 mV offset
+        let calibration = Calibration::fit(&[point(0.99, 1.0), point(3.93, 4.0)], 1).unwrap();
+
+        assert_coefficients_eq(calibration, [-0.010204, 1.020408, 0.0, 0.0]);
+        assert!((calibration.apply(2.45) - 2.4898).abs() < 1e-4);
+    }
+
+    #[test]
+    fn test_least_squares() {
+        let raw = [0.0, 1.0, 2.0, 3.0, 4.0];
+        let exact = raw.map(|raw| point(raw, 1.0 + 0.5 * raw - 0.25 * raw * raw));
+        assert_coefficients_eq(Calibration::fit(&exact, 2).unwrap(), [1.0, 0.5, -0.25, 0.0]);
+        assert_coefficients_eq(Calibration::fit(&exact, 3).unwrap(), [1.0, 0.5, -0.25, 0.0]);
+
+        // a line through points that scatter around it
+        let noisy = [
+            point(0.0, 0.1),
+            point(1.0, 0.9),
+            point(2.0, 2.1),
+            point(3.0, 2.9),
+        ];
+        assert_coefficients_eq(Calibration::fit(&noisy, 1).unwrap(), [0.06, 0.96, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn test_fit_errors() {
+        let points = [point(1.0, 1.0), point(2.0, 2.0)];
+
+        assert_eq!(
+            Calibration::fit(&points, 0),
+            Err(CalibrationError::InvalidDegree)
+        );
+        assert_eq!(
+            Calibration::fit(&points, 4),
+            Err(CalibrationError::InvalidDegree)
+        );
+        assert_eq!(
+            Calibration::fit(&points, 2),
+            Err(CalibrationError::NotEnoughPoints)
+        );
+        assert_eq!(
+            Calibration::fit(&[point(1.0, 1.0), point(1.0, 2.0)], 1),
+            Err(CalibrationError::Singular)
+        );
+    }
+
+    #[test]
+    fn test_run() {
+        let mut run = CalibrationRun::new(CalibrationChannel::Current, 1).unwrap();
+
+        assert_eq!(run.add_point(point(0.1, 0.0)), Ok(0));
+        assert_eq!(
+            run.add_point(point(f32::NAN, 1.0)),
+            Err(CalibrationError::InvalidPoint)
+        );
+        assert_eq!(run.fit(), Err(CalibrationError::NotEnoughPoints));
+        assert_eq!(run.add_point(point(1.1, 1.0)), Ok(1));
+        assert_coefficients_eq(run.fit().unwrap(), [-0.1, 1.0, 0.0, 0.0]);
+
+        for _ in 2..MAX_CALIBRATION_POINTS {
+            run.add_point(point(2.1, 2.0)).unwrap();
+        }
+        assert_eq!(
+            run.add_point(point(3.1, 3.0)),
+            Err(CalibrationError::TooManyPoints)
+        );
+
+        assert_eq!(
+            CalibrationRun::new(CalibrationChannel::Voltage, 0),
+            Err(CalibrationError::InvalidDegree)
+        );
+    }
+
+    #[test]
+    fn test_set() {
+        let mut set = CalibrationSet::default();
+        let calibration = Calibration {
+            coefficients: [0.1, 1.0, 0.0, 0.0],
+        };
+
+        set.set(CalibrationChannel::Temperature, calibration);
+        assert_eq!(set.version, 1);
+        assert_eq!(*set.get(CalibrationChannel::Temperature), calibration);
+        assert_eq!(*set.get(CalibrationChannel::Voltage), Calibration::IDENTITY);
+        assert_eq!(set.validate(), Ok(()));
+
+        set.current.coefficients[0] = f32::INFINITY;
+        assert_eq!(set.validate(), Err(CalibrationError::InvalidCoefficients));
+    }
+}
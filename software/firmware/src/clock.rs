@@ -0,0 +1,100 @@
+//! Time of the firmware. The monotonic clock measures the time between the updates, the real
+//! time clock gives the date of the timestamps. Timestamps are Unix time in ms.
+
+use serde::{Deserialize, Serialize};
+use time::{OffsetDateTime, PrimitiveDateTime};
+
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq)]
+pub enum ClockError {
+    /// The time is beyond the dates the firmware can represent
+    InvalidTime,
+    /// The real time clock didn't accept the date, e.g. because it is outside of its range
+    Rtc,
+}
+
+/// Relates the monotonic clock to the Unix time
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Clock {
+    /// Unix time at the start of the monotonic clock, in ms
+    epoch: u64,
+    /// monotonic time of the last update, in µs
+    last_update: u64,
+}
+
+impl Clock {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// `unix_time` in ms is the time at the monotonic time `now` in µs
+    pub fn set_time(&mut self, unix_time: u64, now: u64) {
+        self.epoch = unix_time.saturating_sub(now / 1000);
+    }
+
+    /// The Unix time at the monotonic time `now` in µs, in ms. Counts from the start until the
+    /// time is set.
+    pub fn get_time(&self, now: u64) -> u64 {
+        self.epoch + now / 1000
+    }
+
+    /// Time since the last update at the monotonic time `now`, in µs
+    pub fn get_time_since_update(&self, now: u64) -> u64 {
+        now.saturating_sub(self.last_update)
+    }
+
+    /// Time since the last update, in µs, the first one counts from the start
+    pub fn update(&mut self, now: u64) -> u64 {
+        let delta = now.saturating_sub(self.last_update);
+        self.last_update = now;
+        delta
+    }
+}
+
+/// The date as Unix time in ms, dates before 1970 are 0
+pub fn to_unix_time(datetime: PrimitiveDateTime) -> u64 {
+    let millis = datetime.assume_utc().unix_timestamp_nanos() / 1_000_000;
+    millis.clamp(0, u64::MAX as i128) as u64
+}
+
+/// The Unix time in ms as date
+pub fn to_datetime(unix_time: u64) -> Result<PrimitiveDateTime, ClockError> {
+    let datetime = OffsetDateTime::from_unix_timestamp_nanos(unix_time as i128 * 1_000_000)
+        .map_err(|_| ClockError::InvalidTime)?;
+    Ok(PrimitiveDateTime::new(datetime.date(), datetime.time()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use time::{Date, Month, Time};
+
+    #[test]
+    fn test_datetime() {
+        let datetime = PrimitiveDateTime::new(
+            Date::from_calendar_date(2023, Month::March, 14).unwrap(),
+            Time::from_hms_milli(15, 9, 26, 535).unwrap(),
+        );
+        assert_eq!(to_unix_time(datetime), 1_678_806_566_535);
+        assert_eq!(to_datetime(1_678_806_566_535), Ok(datetime));
+
+        let before_1970 = PrimitiveDateTime::new(
+            Date::from_calendar_date(1969, Month::December, 31).unwrap(),
+            Time::MIDNIGHT,
+        );
+        assert_eq!(to_unix_time(before_1970), 0);
+        assert_eq!(to_datetime(u64::MAX), Err(ClockError::InvalidTime));
+    }
+
+    #[test]
+    fn test_clock() {
+        let mut clock = Clock::new();
+        assert_eq!(clock.update(5_000_000), 5_000_000);
+        assert_eq!(clock.update(5_250_000), 250_000);
+        // counts from the start until the time is set
+        assert_eq!(clock.get_time(5_250_000), 5250);
+
+        clock.set_time(1_678_806_566_000, 6_000_000);
+        assert_eq!(clock.get_time(6_500_000), 1_678_806_566_500);
+        assert_eq!(clock.update(6_500_000), 1_250_000);
+    }
+}
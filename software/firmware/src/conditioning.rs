@@ -0,0 +1,369 @@
+use serde::{Deserialize, Serialize};
+
+use crate::calibration::CalibrationChannel;
+
+/// Maximum number of readings averaged into one sample
+pub const MAX_OVERSAMPLING: u8 = 16;
+/// Maximum number of samples a median filter looks at
+pub const MAX_MEDIAN_WINDOW: usize = 9;
+
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq)]
+pub enum Filter {
+    /// The mean of the readings is used as it is
+    None,
+    /// Median of the given number of latest samples, removes single spikes
+    Median(u8),
+    /// First order low pass, every sample moves the output by the given fraction from 0 to 1
+    /// towards it
+    LowPass(f32),
+}
+
+/// How the readings of a channel are turned into a sample
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq)]
+pub struct ChannelConditioning {
+    /// number of readings averaged into one sample, 1 reads once
+    pub oversampling: u8,
+    /// applied to the averaged samples
+    pub filter: Filter,
+}
+
+impl Default for ChannelConditioning {
+    fn default() -> Self {
+        Self {
+            oversampling: 1,
+            filter: Filter::None,
+        }
+    }
+}
+
+impl ChannelConditioning {
+    pub fn validate(&self) -> Result<(), ConditioningError> {
+        if self.oversampling == 0 || self.oversampling > MAX_OVERSAMPLING {
+            return Err(ConditioningError::InvalidOversampling);
+        }
+        match self.filter {
+            Filter::None => Ok(()),
+            Filter::Median(window) if window == 0 || window as usize > MAX_MEDIAN_WINDOW => {
+                Err(ConditioningError::InvalidWindow)
+            }
+            Filter::Median(_) => Ok(()),
+            Filter::LowPass(factor) if !(factor > 0.0 && factor <= 1.0) => {
+                Err(ConditioningError::InvalidFactor)
+            }
+            Filter::LowPass(_) => Ok(()),
+        }
+    }
+}
+
+/// The conditioning of all channels of a battery test unit
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Default)]
+pub struct ConditioningConfig {
+    pub voltage: ChannelConditioning,
+    pub current: ChannelConditioning,
+    pub temperature: ChannelConditioning,
+}
+
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq)]
+pub enum ConditioningError {
+    /// Between 1 and `MAX_OVERSAMPLING` readings can be averaged
+    InvalidOversampling,
+    /// A median filter looks at 1 to `MAX_MEDIAN_WINDOW` samples
+    InvalidWindow,
+    /// The factor of a low pass has to be above 0 and at most 1
+    InvalidFactor,
+}
+
+/// Spread of the readings of a channel, before the filter and the calibration
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq)]
+pub struct ChannelStats {
+    /// number of readings, those that aren't a number are left out
+    pub count: u16,
+    pub min: f32,
+    pub max: f32,
+    /// standard deviation
+    pub std_dev: f32,
+}
+
+/// Statistics of the readings since the last telemetry
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq)]
+pub struct MeasurementStats {
+    pub voltage: ChannelStats,
+    pub current: ChannelStats,
+    pub temperature: ChannelStats,
+}
+
+/// Welford's algorithm, numerically stable in single precision
+#[derive(Debug, Copy, Clone, PartialEq)]
+struct RunningStats {
+    count: u16,
+    mean: f32,
+    /// sum of the squared differences from the mean
+    m2: f32,
+    min: f32,
+    max: f32,
+}
+
+impl Default for RunningStats {
+    fn default() -> Self {
+        Self {
+            count: 0,
+            mean: 0.0,
+            m2: 0.0,
+            min: f32::NAN,
+            max: f32::NAN,
+        }
+    }
+}
+
+impl RunningStats {
+    fn add(&mut self, value: f32) {
+        if !value.is_finite() || self.count == u16::MAX {
+            return;
+        }
+        self.count += 1;
+        let delta = value - self.mean;
+        self.mean += delta / self.count as f32;
+        self.m2 += delta * (value - self.mean);
+        // `f32::min` ignores the NaN they start with
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+    }
+
+    fn get(&self) -> ChannelStats {
+        ChannelStats {
+            count: self.count,
+            min: self.min,
+            max: self.max,
+            std_dev: match self.count {
+                0 => f32::NAN,
+                count => libm::sqrtf(self.m2 / count as f32),
+            },
+        }
+    }
+}
+
+/// Oversamples, filters and collects the statistics of one channel
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ChannelConditioner {
+    config: ChannelConditioning,
+    /// latest samples of the median filter, a ring buffer
+    history: [f32; MAX_MEDIAN_WINDOW],
+    /// number of valid entries of `history`
+    history_len: usize,
+    /// index the next sample is stored at
+    history_next: usize,
+    /// output of the low pass
+    low_pass: Option<f32>,
+    stats: RunningStats,
+}
+
+impl ChannelConditioner {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Starts the filter over
+    pub fn set_config(&mut self, config: ChannelConditioning) -> Result<(), ConditioningError> {
+        config.validate()?;
+        *self = Self {
+            config,
+            stats: self.stats,
+            ..Self::default()
+        };
+        Ok(())
+    }
+
+    pub fn get_config(&self) -> &ChannelConditioning {
+        &self.config
+    }
+
+    /// Calls `read` `oversampling` times and filters the mean of the readings
+    pub fn sample(&mut self, mut read: impl FnMut() -> f32) -> f32 {
+        let mut sum = 0.0;
+        for _ in 0..self.config.oversampling {
+            let reading = read();
+            self.stats.add(reading);
+            sum += reading;
+        }
+        let mean = sum / self.config.oversampling as f32;
+
+        match self.config.filter {
+            Filter::None => mean,
+            Filter::Median(window) => self.median(mean, window as usize),
+            Filter::LowPass(factor) => {
+                // a sample that isn't a number would stick in the output forever
+                let output = match self.low_pass {
+                    Some(output) if mean.is_finite() => output + factor * (mean - output),
+                    _ => mean,
+                };
+                if output.is_finite() {
+                    self.low_pass = Some(output);
+                }
+                output
+            }
+        }
+    }
+
+    fn median(&mut self, sample: f32, window: usize) -> f32 {
+        self.history[self.history_next] = sample;
+        self.history_next = (self.history_next + 1) % window;
+        self.history_len = (self.history_len + 1).min(window);
+
+        let mut sorted = [0.0; MAX_MEDIAN_WINDOW];
+        let sorted = &mut sorted[..self.history_len];
+        sorted.copy_from_slice(&self.history[..self.history_len]);
+        sorted.sort_unstable_by(|a, b| a.total_cmp(b));
+        let middle = self.history_len / 2;
+        if self.history_len % 2 == 1 {
+            sorted[middle]
+        } else {
+            (sorted[middle - 1] + sorted[middle]) / 2.0
+        }
+    }
+
+    /// The statistics of the readings since the last call
+    pub fn take_stats(&mut self) -> ChannelStats {
+        let stats = self.stats.get();
+        self.stats = RunningStats::default();
+        stats
+    }
+}
+
+/// The conditioners of all channels of a battery test unit
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Conditioner {
+    pub voltage: ChannelConditioner,
+    pub current: ChannelConditioner,
+    pub temperature: ChannelConditioner,
+}
+
+impl Conditioner {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set_config(
+        &mut self,
+        channel: CalibrationChannel,
+        config: ChannelConditioning,
+    ) -> Result<(), ConditioningError> {
+        match channel {
+            CalibrationChannel::Voltage => self.voltage.set_config(config),
+            CalibrationChannel::Current => self.current.set_config(config),
+            CalibrationChannel::Temperature => self.temperature.set_config(config),
+        }
+    }
+
+    pub fn get_config(&self) -> ConditioningConfig {
+        ConditioningConfig {
+            voltage: *self.voltage.get_config(),
+            current: *self.current.get_config(),
+            temperature: *self.temperature.get_config(),
+        }
+    }
+
+    /// The statistics of the readings since the last call
+    pub fn take_stats(&mut self) -> MeasurementStats {
+        MeasurementStats {
+            voltage: self.voltage.take_stats(),
+            current: self.current.take_stats(),
+            temperature: self.temperature.take_stats(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn conditioner(oversampling: u8, filter: Filter) -> ChannelConditioner {
+        let mut conditioner = ChannelConditioner::new();
+        conditioner
+            .set_config(ChannelConditioning {
+                oversampling,
+                filter,
+            })
+            .unwrap();
+        conditioner
+    }
+
+    /// Feeds the values one reading per sample
+    fn samples(conditioner: &mut ChannelConditioner, values: &[f32]) -> Vec<f32> {
+        values
+            .iter()
+            .map(|value| conditioner.sample(|| *value))
+            .collect()
+    }
+
+    #[test]
+    fn test_validate() {
+        let invalid = [
+            (0, Filter::None, ConditioningError::InvalidOversampling),
+            (17, Filter::None, ConditioningError::InvalidOversampling),
+            (1, Filter::Median(0), ConditioningError::InvalidWindow),
+            (1, Filter::Median(10), ConditioningError::InvalidWindow),
+            (1, Filter::LowPass(0.0), ConditioningError::InvalidFactor),
+            (1, Filter::LowPass(1.5), ConditioningError::InvalidFactor),
+            (
+                1,
+                Filter::LowPass(f32::NAN),
+                ConditioningError::InvalidFactor,
+            ),
+        ];
+        for (oversampling, filter, error) in invalid {
+            let config = ChannelConditioning {
+                oversampling,
+                filter,
+            };
+            assert_eq!(config.validate(), Err(error));
+        }
+        assert_eq!(ChannelConditioning::default().validate(), Ok(()));
+    }
+
+    #[test]
+    fn test_oversampling() {
+        let mut conditioner = conditioner(4, Filter::None);
+        let mut readings = [3.0, 3.2, 3.1, 3.3].into_iter();
+
+        let sample = conditioner.sample(|| readings.next().unwrap());
+        assert!((sample - 3.15).abs() < 1e-6);
+
+        let stats = conditioner.take_stats();
+        assert_eq!((stats.count, stats.min, stats.max), (4, 3.0, 3.3));
+        assert!((stats.std_dev - 0.1118).abs() < 1e-4);
+        assert_eq!(conditioner.take_stats().count, 0);
+    }
+
+    #[test]
+    fn test_median() {
+        let mut conditioner = conditioner(1, Filter::Median(3));
+
+        assert_eq!(
+            samples(&mut conditioner, &[1.0, 3.0, 2.0, 9.0, 2.0, 2.5]),
+            [1.0, 2.0, 2.0, 3.0, 2.0, 2.5]
+        );
+    }
+
+    #[test]
+    fn test_low_pass() {
+        let mut conditioner = conditioner(1, Filter::LowPass(0.5));
+
+        assert_eq!(samples(&mut conditioner, &[2.0, 4.0, 4.0]), [2.0, 3.0, 3.5]);
+        // a sample that isn't a number passes through without disturbing the output
+        assert!(samples(&mut conditioner, &[f32::NAN])[0].is_nan());
+        assert_eq!(samples(&mut conditioner, &[4.0]), [3.75]);
+    }
+
+    #[test]
+    fn test_stats_skip_invalid_readings() {
+        let mut conditioner = conditioner(1, Filter::None);
+        samples(&mut conditioner, &[f32::NAN, 1.0, f32::INFINITY, 3.0]);
+
+        let stats = conditioner.take_stats();
+        assert_eq!(
+            (stats.count, stats.min, stats.max, stats.std_dev),
+            (2, 1.0, 3.0, 1.0)
+        );
+        assert!(conditioner.take_stats().std_dev.is_nan());
+    }
+}
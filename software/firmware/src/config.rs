@@ -0,0 +1,372 @@
+use heapless::{String, Vec};
+use serde::{Deserialize, Serialize};
+
+use crate::calibration::CalibrationSet;
+use crate::limits::SafetyLimits;
+use crate::traits::Flash;
+
+/// Every record takes a slot of this size, a sector holds `sector_size / RECORD_SIZE` of them
+pub const RECORD_SIZE: usize = 1024;
+/// Magic number, sequence number, length of the configuration, two unused bytes and the CRC
+const HEADER_SIZE: usize = 16;
+/// Marks a record, has to change together with the layout of `Config` so old records aren't read
+const MAGIC: u32 = u32::from_le_bytes(*b"CFG1");
+/// Maximum number of battery test units whose configuration is stored
+pub const MAX_CONFIG_UNITS: usize = 8;
+/// in ms
+pub const DEFAULT_SAMPLE_PERIOD: u16 = 100;
+/// The watchdog is only fed while the updates keep up with the sample period, in ms
+pub const MAX_SAMPLE_PERIOD: u16 = 1000;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct UnitConfig {
+    pub name: String<16>,
+    pub limits: SafetyLimits,
+    pub calibration: CalibrationSet,
+}
+
+/// Everything that has to survive a reset
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Config {
+    /// longest time between two updates of the battery test units, in ms
+    pub sample_period: u16,
+    /// by id of the unit
+    pub units: Vec<UnitConfig, MAX_CONFIG_UNITS>,
+}
+
+impl Config {
+    /// Units beyond `MAX_CONFIG_UNITS` are left out
+    pub fn new(sample_period: u16, units: impl Iterator<Item = UnitConfig>) -> Self {
+        Self {
+            sample_period,
+            units: units.take(MAX_CONFIG_UNITS).collect(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq)]
+pub enum ConfigError {
+    /// No valid configuration was stored yet
+    Empty,
+    /// The stored configuration has a valid CRC but can't be decoded
+    Invalid,
+    /// Programming or erasing the flash failed
+    Flash,
+    /// The record that was read back differs from the one that was written
+    Verify,
+    /// The configuration doesn't fit into a record
+    TooLarge,
+    /// The sample period has to be between 1 ms and `MAX_SAMPLE_PERIOD`
+    InvalidSamplePeriod,
+}
+
+pub fn check_sample_period(period: u16) -> Result<(), ConfigError> {
+    if period == 0 || period > MAX_SAMPLE_PERIOD {
+        return Err(ConfigError::InvalidSamplePeriod);
+    }
+    Ok(())
+}
+
+/// Position of a valid record
+#[derive(Debug, Copy, Clone, PartialEq)]
+struct RecordLocation {
+    sector: u8,
+    slot: u32,
+    sequence: u32,
+}
+
+/// Reads the configuration of the newest valid record
+pub fn load(flash: &mut impl Flash) -> Result<Config, ConfigError> {
+    let mut buf = [0; RECORD_SIZE];
+    let latest = find_latest(flash, &mut buf).ok_or(ConfigError::Empty)?;
+    let payload = read_record(flash, offset(flash, latest.sector, latest.slot), &mut buf)
+        .ok_or(ConfigError::Empty)?;
+    postcard::from_bytes(payload).map_err(|_| ConfigError::Invalid)
+}
+
+/// Writes the configuration as a new record and returns its sequence number.
+///
+/// Records are appended to the free slots after the newest record, once its sector is full the
+/// other sector is erased and the record goes into its first slot. This spreads the wear over all
+/// slots of both sectors. The previous record stays untouched until the new one is complete and
+/// every record is protected by a CRC, so a power loss in the middle of a write or an erase leaves
+/// the previous configuration readable.
+pub fn save(flash: &mut impl Flash, config: &Config) -> Result<u32, ConfigError> {
+    let mut record = [0xFF; RECORD_SIZE];
+    let length = postcard::to_slice(config, &mut record[HEADER_SIZE..])
+        .map_err(|_| ConfigError::TooLarge)?
+        .len();
+
+    let mut buf = [0; RECORD_SIZE];
+    let latest = find_latest(flash, &mut buf);
+    let sequence = latest.map_or(0, |latest| latest.sequence.wrapping_add(1));
+
+    record[0..4].copy_from_slice(&MAGIC.to_le_bytes());
+    record[4..8].copy_from_slice(&sequence.to_le_bytes());
+    record[8..10].copy_from_slice(&(length as u16).to_le_bytes());
+    record[10..12].copy_from_slice(&[0, 0]);
+    let crc = crc32(&[&record[4..12], &record[HEADER_SIZE..HEADER_SIZE + length]]);
+    record[12..16].copy_from_slice(&crc.to_le_bytes());
+    let record = &record[..HEADER_SIZE + length];
+
+    let free_slot = latest.and_then(|latest| {
+        (latest.slot + 1..slots(flash))
+            .find(|slot| is_erased(flash, offset(flash, latest.sector, *slot)))
+            .map(|slot| (latest.sector, slot))
+    });
+    let (sector, slot) = match free_slot {
+        Some(location) => location,
+        None => {
+            let sector = latest.map_or(0, |latest| 1 - latest.sector);
+            flash.erase(sector).map_err(|_| ConfigError::Flash)?;
+            (sector, 0)
+        }
+    };
+
+    let offset = offset(flash, sector, slot);
+    flash
+        .write(offset, record)
+        .map_err(|_| ConfigError::Flash)?;
+    flash.read(offset, &mut buf[..record.len()]);
+    if buf[..record.len()] != *record {
+        return Err(ConfigError::Verify);
+    }
+    Ok(sequence)
+}
+
+fn slots(flash: &impl Flash) -> u32 {
+    flash.sector_size() / RECORD_SIZE as u32
+}
+
+fn offset(flash: &impl Flash, sector: u8, slot: u32) -> u32 {
+    sector as u32 * flash.sector_size() + slot * RECORD_SIZE as u32
+}
+
+fn find_latest(flash: &mut impl Flash, buf: &mut [u8; RECORD_SIZE]) -> Option<RecordLocation> {
+    let mut latest: Option<RecordLocation> = None;
+    for sector in 0..2 {
+        for slot in 0..slots(flash) {
+            let offset = offset(flash, sector, slot);
+            if read_record(flash, offset, buf).is_none() {
+                continue;
+            }
+
+            let sequence = u32::from_le_bytes(buf[4..8].try_into().unwrap());
+            // the sequence number may wrap around
+            let newer =
+                latest.is_none_or(|latest| sequence.wrapping_sub(latest.sequence) as i32 > 0);
+            if newer {
+                latest = Some(RecordLocation {
+                    sector,
+                    slot,
+                    sequence,
+                });
+            }
+        }
+    }
+    latest
+}
+
+/// Returns the configuration part of the record, `None` if the slot holds no complete record
+fn read_record<'a>(
+    flash: &mut impl Flash,
+    offset: u32,
+    buf: &'a mut [u8; RECORD_SIZE],
+) -> Option<&'a [u8]> {
+    flash.read(offset, &mut buf[..HEADER_SIZE]);
+    let magic = u32::from_le_bytes(buf[0..4].try_into().unwrap());
+    let length = u16::from_le_bytes(buf[8..10].try_into().unwrap()) as usize;
+    if magic != MAGIC || length > RECORD_SIZE - HEADER_SIZE {
+        return None;
+    }
+
+    flash.read(
+        offset + HEADER_SIZE as u32,
+        &mut buf[HEADER_SIZE..HEADER_SIZE + length],
+    );
+    let crc = u32::from_le_bytes(buf[12..16].try_into().unwrap());
+    if crc != crc32(&[&buf[4..12], &buf[HEADER_SIZE..HEADER_SIZE + length]]) {
+        return None;
+    }
+    Some(&buf[HEADER_SIZE..HEADER_SIZE + length])
+}
+
+/// Whether every byte of the slot is erased, a slot with the remains of an interrupted write
+/// can't be used until its sector is erased
+fn is_erased(flash: &mut impl Flash, offset: u32) -> bool {
+    let mut chunk = [0; 64];
+    (0..RECORD_SIZE as u32)
+        .step_by(chunk.len())
+        .all(|position| {
+            flash.read(offset + position, &mut chunk);
+            chunk.iter().all(|byte| *byte == 0xFF)
+        })
+}
+
+/// CRC-32 as used by Ethernet and zip over the concatenated parts
+pub(crate) fn crc32(parts: &[&[u8]]) -> u32 {
+    let mut crc = !0u32;
+    for byte in parts.iter().flat_map(|part| part.iter()) {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::calibration::Calibration;
+    use crate::mocks::MockFlash;
+
+    /// Four slots per sector
+    const SECTOR_SIZE: u32 = 4 * RECORD_SIZE as u32;
+
+    fn config(sample_period: u16) -> Config {
+        let unit = UnitConfig {
+            name: String::from("cell 7"),
+            limits: SafetyLimits::LIFEPO4,
+            calibration: CalibrationSet {
+                version: 2,
+                voltage: Calibration {
+                    coefficients: [-0.01, 1.02, 0.0, 0.0],
+                },
+                ..CalibrationSet::default()
+            },
+        };
+        Config {
+            sample_period,
+            units: Vec::from_slice(&[unit.clone(), unit.clone(), unit.clone(), unit]).unwrap(),
+        }
+    }
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(crc32(&[b"1234", b"56789"]), 0xCBF4_3926);
+        assert_eq!(crc32(&[]), 0);
+    }
+
+    #[test]
+    fn test_save_and_load() {
+        let mut flash = MockFlash::new(SECTOR_SIZE);
+        assert_eq!(load(&mut flash), Err(ConfigError::Empty));
+
+        assert_eq!(save(&mut flash, &config(100)), Ok(0));
+        assert_eq!(load(&mut flash), Ok(config(100)));
+        assert_eq!(save(&mut flash, &config(200)), Ok(1));
+        assert_eq!(load(&mut flash), Ok(config(200)));
+    }
+
+    #[test]
+    fn test_wear_leveling() {
+        let mut flash = MockFlash::new(SECTOR_SIZE);
+
+        // fills the first sector, then erases the second one and continues there
+        for sequence in 0..6 {
+            assert_eq!(save(&mut flash, &config(sequence as u16 + 1)), Ok(sequence));
+        }
+        assert_eq!(load(&mut flash), Ok(config(6)));
+        let second_sector = SECTOR_SIZE as usize;
+        assert_eq!(flash.memory[second_sector..second_sector + 4], *b"CFG1");
+        assert_eq!(
+            flash.memory[second_sector + 2 * RECORD_SIZE],
+            0xFF,
+            "only two records in the second sector"
+        );
+
+        // the first sector is erased once the second one is full
+        for sequence in 6..9 {
+            save(&mut flash, &config(sequence as u16 + 1)).unwrap();
+        }
+        assert_eq!(load(&mut flash), Ok(config(9)));
+        assert_eq!(flash.memory[RECORD_SIZE], 0xFF);
+    }
+
+    #[test]
+    fn test_power_loss_during_write() {
+        let mut flash = MockFlash::new(SECTOR_SIZE);
+        save(&mut flash, &config(100)).unwrap();
+
+        for budget in [0, 5, 15, 100] {
+            flash.power_budget = Some(budget);
+            assert_eq!(save(&mut flash, &config(200)), Err(ConfigError::Flash));
+            flash.power_budget = None;
+            assert_eq!(load(&mut flash), Ok(config(100)));
+        }
+
+        // the slots with the remains of the interrupted writes are skipped
+        assert_eq!(save(&mut flash, &config(300)), Ok(1));
+        assert_eq!(load(&mut flash), Ok(config(300)));
+    }
+
+    #[test]
+    fn test_power_loss_during_erase() {
+        let mut flash = MockFlash::new(SECTOR_SIZE);
+        for sequence in 0..4 {
+            save(&mut flash, &config(sequence + 1)).unwrap();
+        }
+        // leaves garbage in the second sector
+        flash.memory[SECTOR_SIZE as usize..].fill(0x12);
+
+        flash.power_budget = Some(0);
+        assert_eq!(save(&mut flash, &config(5)), Err(ConfigError::Flash));
+        flash.power_budget = None;
+        assert_eq!(load(&mut flash), Ok(config(4)));
+
+        assert_eq!(save(&mut flash, &config(5)), Ok(4));
+        assert_eq!(load(&mut flash), Ok(config(5)));
+    }
+
+    #[test]
+    fn test_corrupted_record() {
+        let mut flash = MockFlash::new(SECTOR_SIZE);
+        save(&mut flash, &config(100)).unwrap();
+        save(&mut flash, &config(200)).unwrap();
+
+        // a flipped bit in the newest record falls back to the one before
+        flash.memory[RECORD_SIZE + HEADER_SIZE + 1] ^= 0x04;
+        assert_eq!(load(&mut flash), Ok(config(100)));
+    }
+
+    #[test]
+    fn test_sequence_wraps_around() {
+        let mut flash = MockFlash::new(SECTOR_SIZE);
+        save(&mut flash, &config(100)).unwrap();
+        // pretends the first record is number u32::MAX
+        let record = &mut flash.memory[..RECORD_SIZE];
+        record[4..8].copy_from_slice(&u32::MAX.to_le_bytes());
+        let length = u16::from_le_bytes([record[8], record[9]]) as usize;
+        let crc = crc32(&[&record[4..12], &record[HEADER_SIZE..HEADER_SIZE + length]]);
+        record[12..16].copy_from_slice(&crc.to_le_bytes());
+
+        assert_eq!(save(&mut flash, &config(200)), Ok(0));
+        assert_eq!(load(&mut flash), Ok(config(200)));
+    }
+
+    #[test]
+    fn test_largest_config_fits() {
+        let mut flash = MockFlash::new(SECTOR_SIZE);
+        let unit = UnitConfig {
+            name: String::from("sixteen chars ok"),
+            limits: SafetyLimits {
+                max_duration: Some(1.0),
+                max_capacity: Some(1.0),
+                ..SafetyLimits::LIFEPO4
+            },
+            calibration: CalibrationSet::default(),
+        };
+        let config = Config {
+            sample_period: 100,
+            units: core::iter::repeat_n(unit, MAX_CONFIG_UNITS).collect(),
+        };
+
+        assert_eq!(save(&mut flash, &config), Ok(0));
+        assert_eq!(load(&mut flash), Ok(config));
+    }
+}
@@ -0,0 +1,91 @@
+/// Proportional gain of the load controllers, in 1/A
+pub const LOAD_CONTROLLER_KP: f32 = 0.05;
+/// Integral gain of the load controllers, in 1/(A s)
+pub const LOAD_CONTROLLER_KI: f32 = 0.5;
+
+/// PI controller with its output limited to 0..=1, e.g. the duty cycle of a load.
+/// The integral is clamped to the same range so it doesn't wind up while the output saturates.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct PiController {
+    pub kp: f32,
+    pub ki: f32,
+    integral: f32,
+}
+
+impl PiController {
+    pub const fn new(kp: f32, ki: f32) -> Self {
+        Self {
+            kp,
+            ki,
+            integral: 0.0,
+        }
+    }
+
+    /// Forgets the accumulated error, e.g. when the setpoint changes to another quantity
+    pub fn reset(&mut self) {
+        self.integral = 0.0;
+    }
+
+    /// Returns the new output, a measurement of NaN leaves the integral untouched and turns the
+    /// output off
+    pub fn update(&mut self, setpoint: f32, measurement: f32, delta_time: f32) -> f32 {
+        let error = setpoint - measurement;
+        if error.is_nan() {
+            return 0.0;
+        }
+
+        if self.ki != 0.0 {
+            self.integral = (self.integral + error * delta_time).clamp(0.0, 1.0 / self.ki);
+        }
+
+        (self.kp * error + self.ki * self.integral).clamp(0.0, 1.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_proportional() {
+        let mut controller = PiController::new(0.5, 0.0);
+
+        assert_eq!(controller.update(1.0, 0.0, 1.0), 0.5);
+        assert_eq!(controller.update(1.0, 0.5, 1.0), 0.25);
+        // the output is limited
+        assert_eq!(controller.update(1.0, 2.0, 1.0), 0.0);
+        assert_eq!(controller.update(5.0, 0.0, 1.0), 1.0);
+    }
+
+    #[test]
+    fn test_integral() {
+        let mut controller = PiController::new(0.0, 0.25);
+
+        assert_eq!(controller.update(1.0, 0.0, 1.0), 0.25);
+        assert_eq!(controller.update(1.0, 0.0, 2.0), 0.75);
+        assert_eq!(controller.update(1.0, 2.0, 1.0), 0.5);
+
+        controller.reset();
+        assert_eq!(controller.update(1.0, 1.0, 1.0), 0.0);
+    }
+
+    #[test]
+    fn test_anti_windup() {
+        let mut controller = PiController::new(0.0, 1.0);
+
+        for _ in 0..100 {
+            assert_eq!(controller.update(10.0, 0.0, 1.0), 1.0);
+        }
+        // a saturated output doesn't keep integrating, the controller reacts immediately
+        assert!(controller.update(0.0, 0.5, 1.0) < 1.0);
+    }
+
+    #[test]
+    fn test_invalid_measurement() {
+        let mut controller = PiController::new(0.5, 0.5);
+
+        controller.update(1.0, 0.0, 1.0);
+        assert_eq!(controller.update(1.0, f32::NAN, 1.0), 0.0);
+        assert_eq!(controller.update(1.0, 1.0, 1.0), 0.5);
+    }
+}
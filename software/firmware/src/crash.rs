@@ -0,0 +1,140 @@
+//! Reports of panics. The panic handler of the board saves the report in memory that survives a
+//! reset, after the next start the firmware sends it to the client once. The report holds the mode
+//! and the test of every unit, the interrupted tests are resumed through `recovery` as usual.
+//!
+//! | bytes    | content                                                 |
+//! |----------|---------------------------------------------------------|
+//! | 0..144   | `CrashReport`, serialized                               |
+//! | 144..148 | CRC-32 over bytes 0..144                                |
+
+use heapless::{String, Vec};
+use serde::{Deserialize, Serialize};
+
+use crate::config::crc32;
+use crate::recovery::MAX_RUN_UNITS;
+use crate::traits::RetainedMemory;
+use crate::unit::TestKind;
+use crate::BatteryTestUnitMode;
+
+/// Longer panic messages are cut off
+pub const CRASH_MESSAGE_SIZE: usize = 96;
+const REPORT_SIZE: usize = 144;
+pub const RECORD_SIZE: usize = REPORT_SIZE + 4;
+
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq)]
+pub enum ResetCause {
+    PowerOn,
+    Brownout,
+    /// The reset pin, e.g. the button on the board
+    Pin,
+    /// The firmware reset the board, e.g. after a panic
+    Software,
+    Watchdog,
+    LowPower,
+}
+
+/// What a battery test unit did when the board panicked
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq)]
+pub struct UnitState {
+    pub mode: BatteryTestUnitMode,
+    /// `None` while the unit is idle or in the fault state
+    pub test: Option<TestKind>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct CrashReport {
+    /// What panicked and where
+    pub message: String<CRASH_MESSAGE_SIZE>,
+    /// time since the start until the panic, in ms
+    pub uptime: u64,
+    /// How the board started before it panicked, the reset after the panic is reported with
+    /// the report
+    pub reset_cause: ResetCause,
+    /// The units at the last update before the panic, by id
+    pub units: Vec<UnitState, MAX_RUN_UNITS>,
+}
+
+/// Saves the report for the next start, overwrites the last one
+pub fn save(memory: &mut impl RetainedMemory, report: &CrashReport) {
+    if memory.capacity() < RECORD_SIZE {
+        return;
+    }
+    let mut record = [0; RECORD_SIZE];
+    // can't fail, a report with the longest message and all units in a fault state fits
+    if postcard::to_slice(report, &mut record[..REPORT_SIZE]).is_err() {
+        return;
+    }
+    let crc = crc32(&[&record[..REPORT_SIZE]]);
+    record[REPORT_SIZE..].copy_from_slice(&crc.to_le_bytes());
+    memory.write(&record);
+}
+
+/// The report saved before the last reset, `None` if the board didn't panic. Clears the report, it
+/// is only returned once.
+pub fn take(memory: &mut impl RetainedMemory) -> Option<CrashReport> {
+    if memory.capacity() < RECORD_SIZE {
+        return None;
+    }
+    let mut record = [0; RECORD_SIZE];
+    memory.read(&mut record);
+    let crc = u32::from_le_bytes(record[REPORT_SIZE..].try_into().unwrap());
+    if crc != crc32(&[&record[..REPORT_SIZE]]) {
+        return None;
+    }
+    memory.write(&[0; RECORD_SIZE]);
+    postcard::from_bytes(&record[..REPORT_SIZE]).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::limits::FaultCause;
+    use crate::mocks::MockRetainedMemory;
+
+    #[test]
+    fn test_save_take() {
+        let mut memory = MockRetainedMemory::new(RECORD_SIZE);
+        // the memory is cleared at power up
+        assert_eq!(take(&mut memory), None);
+
+        let report = CrashReport {
+            message: String::from("panicked at 'index out of bounds', firmware/src/lib.rs:12:5"),
+            uptime: 86_400_000,
+            reset_cause: ResetCause::PowerOn,
+            units: Vec::from_slice(&[
+                UnitState {
+                    mode: BatteryTestUnitMode::DischargingConstantCurrent(1.5),
+                    test: Some(TestKind::Program),
+                },
+                UnitState {
+                    mode: BatteryTestUnitMode::Idle,
+                    test: None,
+                },
+            ])
+            .unwrap(),
+        };
+        save(&mut memory, &report);
+        assert_eq!(take(&mut memory), Some(report.clone()));
+        // only once
+        assert_eq!(take(&mut memory), None);
+
+        let mut full = String::new();
+        while full.push('x').is_ok() {}
+        let fault = UnitState {
+            mode: BatteryTestUnitMode::Fault(FaultCause::UnderVoltage(f32::MAX)),
+            test: Some(TestKind::PulseTest),
+        };
+        let long = CrashReport {
+            message: full,
+            uptime: u64::MAX,
+            reset_cause: ResetCause::LowPower,
+            units: Vec::from_slice(&[fault; MAX_RUN_UNITS]).unwrap(),
+        };
+        save(&mut memory, &long);
+        assert_eq!(take(&mut memory), Some(long));
+
+        save(&mut memory, &report);
+        memory.memory[3] ^= 0x01;
+        assert_eq!(take(&mut memory), None);
+    }
+}
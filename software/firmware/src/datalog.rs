@@ -0,0 +1,538 @@
+//! Append-only measurement log on the external flash. Every record takes a slot of
+//! `RECORD_SIZE` bytes, the record with sequence number `s` is stored in slot `s % slots`, so
+//! the newest records can be found again after a reset by their sequence numbers.
+//!
+//! | bytes  | content                                   |
+//! |--------|-------------------------------------------|
+//! | 0..4   | sequence number                           |
+//! | 4..12  | Unix time in ms                           |
+//! | 12     | unit id                                   |
+//! | 13     | program step, 0xFF without a program      |
+//! | 14..16 | unused, 0                                 |
+//! | 16..28 | voltage, current, temperature (NaN: none) |
+//! | 28..32 | CRC-32 over bytes 0..28                   |
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::crc32;
+use crate::traits::BlockStorage;
+
+pub const RECORD_SIZE: usize = 32;
+/// Stored as step of the records taken while no program ran
+const NO_STEP: u8 = 0xFF;
+
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq)]
+pub enum LogPolicy {
+    /// The oldest records are erased once the storage is full
+    Wrap,
+    /// Logging stops once the storage is full
+    StopWhenFull,
+}
+
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq)]
+pub struct LogConfig {
+    /// time between two records of a unit, in s
+    pub interval: f32,
+    pub policy: LogPolicy,
+}
+
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq)]
+pub struct LogRecord {
+    /// counts up from 0 after the log was cleared
+    pub sequence: u32,
+    /// Unix time in ms
+    pub timestamp: u64,
+    pub unit: u8,
+    /// index of the program step that ran
+    pub step: Option<u8>,
+    /// in V
+    pub voltage: f32,
+    /// in A, positive while discharging
+    pub current: f32,
+    /// in °C
+    pub temperature: Option<f32>,
+}
+
+impl LogRecord {
+    fn encode(&self) -> [u8; RECORD_SIZE] {
+        let mut bytes = [0; RECORD_SIZE];
+        bytes[0..4].copy_from_slice(&self.sequence.to_le_bytes());
+        bytes[4..12].copy_from_slice(&self.timestamp.to_le_bytes());
+        bytes[12] = self.unit;
+        bytes[13] = self.step.unwrap_or(NO_STEP);
+        bytes[16..20].copy_from_slice(&self.voltage.to_le_bytes());
+        bytes[20..24].copy_from_slice(&self.current.to_le_bytes());
+        bytes[24..28].copy_from_slice(&self.temperature.unwrap_or(f32::NAN).to_le_bytes());
+        let crc = crc32(&[&bytes[..28]]);
+        bytes[28..32].copy_from_slice(&crc.to_le_bytes());
+        bytes
+    }
+
+    /// `None` if the slot is erased or the CRC doesn't match
+    fn decode(bytes: &[u8; RECORD_SIZE]) -> Option<Self> {
+        let word = |start: usize| u32::from_le_bytes(bytes[start..start + 4].try_into().unwrap());
+        if crc32(&[&bytes[..28]]) != word(28) {
+            return None;
+        }
+
+        let temperature = f32::from_bits(word(24));
+        Some(Self {
+            sequence: word(0),
+            timestamp: u64::from_le_bytes(bytes[4..12].try_into().unwrap()),
+            unit: bytes[12],
+            step: (bytes[13] != NO_STEP).then_some(bytes[13]),
+            voltage: f32::from_bits(word(16)),
+            current: f32::from_bits(word(20)),
+            temperature: (!temperature.is_nan()).then_some(temperature),
+        })
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq)]
+pub struct LogStatus {
+    pub running: bool,
+    /// sequence number of the oldest record that is still stored
+    pub oldest: u32,
+    /// sequence number the next record gets
+    pub next: u32,
+    /// number of records the storage holds
+    pub capacity: u32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq)]
+pub enum LogError {
+    /// The interval has to be positive
+    InvalidInterval,
+    /// Reading, programming or erasing the storage failed, logging stopped
+    Storage,
+    /// Logging stopped because the storage is full and the policy is `StopWhenFull`
+    Full,
+    /// The log can only be cleared while it is stopped
+    Running,
+}
+
+/// What the download sends next
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum LogDownload {
+    Record(LogRecord),
+    /// All records were sent, with the sequence number the next record gets
+    End(u32),
+}
+
+pub struct MeasurementLog {
+    /// `Some` while logging
+    config: Option<LogConfig>,
+    oldest: u32,
+    next: u32,
+    /// time since the last records were written, in s
+    elapsed: f32,
+    /// sequence number of the next record to send to the client
+    download: Option<u32>,
+}
+
+impl Default for MeasurementLog {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MeasurementLog {
+    pub fn new() -> Self {
+        Self {
+            config: None,
+            oldest: 0,
+            next: 0,
+            elapsed: 0.0,
+            download: None,
+        }
+    }
+
+    /// Finds the records written before a reset, logging isn't resumed. Only the first record
+    /// of every block and the records of the newest block are read.
+    pub fn recover(&mut self, storage: &mut impl BlockStorage) -> Result<(), LogError> {
+        let slots = slots(storage);
+        let slots_per_block = slots_per_block(storage);
+
+        let mut oldest: Option<u32> = None;
+        let mut newest: Option<u32> = None;
+        for block in 0..slots / slots_per_block {
+            let slot = block * slots_per_block;
+            if let Some(record) = read_slot(storage, slot)? {
+                if record.sequence % slots == slot {
+                    oldest =
+                        Some(oldest.map_or(record.sequence, |oldest| oldest.min(record.sequence)));
+                    newest =
+                        Some(newest.map_or(record.sequence, |newest| newest.max(record.sequence)));
+                }
+            }
+        }
+
+        let (Some(oldest), Some(newest)) = (oldest, newest) else {
+            self.oldest = 0;
+            self.next = 0;
+            return Ok(());
+        };
+
+        let mut next = newest + 1;
+        while next % slots_per_block != 0 {
+            match read_slot(storage, next % slots)? {
+                Some(record) if record.sequence == next => next += 1,
+                _ => break,
+            }
+        }
+        // slots with the remains of an interrupted write can't be programmed again until the
+        // block is erased
+        while next % slots_per_block != 0 && !is_erased(storage, next % slots)? {
+            next += 1;
+        }
+
+        self.oldest = oldest;
+        self.next = next;
+        Ok(())
+    }
+
+    pub fn start(&mut self, config: LogConfig) -> Result<(), LogError> {
+        if config.interval.is_nan() || config.interval <= 0.0 {
+            return Err(LogError::InvalidInterval);
+        }
+        self.config = Some(config);
+        // the first records are written right away
+        self.elapsed = config.interval;
+        Ok(())
+    }
+
+    pub fn stop(&mut self) {
+        self.config = None;
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.config.is_some()
+    }
+
+    /// Erases all records, the sequence numbers start at 0 again
+    pub fn clear(&mut self, storage: &mut impl BlockStorage) -> Result<(), LogError> {
+        if self.is_running() {
+            return Err(LogError::Running);
+        }
+        storage.erase_all().map_err(|_| LogError::Storage)?;
+        self.oldest = 0;
+        self.next = 0;
+        self.download = None;
+        Ok(())
+    }
+
+    pub fn get_status(&self, storage: &impl BlockStorage) -> LogStatus {
+        LogStatus {
+            running: self.is_running(),
+            oldest: self.oldest,
+            next: self.next,
+            capacity: slots(storage),
+        }
+    }
+
+    /// Whether the next records are due, every `interval` while logging
+    pub fn is_due(&mut self, delta_time: f32) -> bool {
+        let Some(config) = self.config else {
+            return false;
+        };
+        self.elapsed += delta_time;
+        if self.elapsed >= config.interval {
+            self.elapsed -= config.interval;
+            // it doesn't catch up after a long pause
+            self.elapsed = self.elapsed.min(config.interval);
+            return true;
+        }
+        false
+    }
+
+    /// Stores the record under the next sequence number and returns it. Logging stops on an
+    /// error.
+    pub fn append(
+        &mut self,
+        storage: &mut impl BlockStorage,
+        mut record: LogRecord,
+    ) -> Result<u32, LogError> {
+        let result = self.write(storage, &mut record);
+        if result.is_err() {
+            self.stop();
+        }
+        result
+    }
+
+    fn write(
+        &mut self,
+        storage: &mut impl BlockStorage,
+        record: &mut LogRecord,
+    ) -> Result<u32, LogError> {
+        let slots = slots(storage);
+        let slots_per_block = slots_per_block(storage);
+        let slot = self.next % slots;
+
+        if slot.is_multiple_of(slots_per_block) {
+            // the block still holds the oldest records once the storage wrapped around
+            let first_kept = (self.next + slots_per_block).saturating_sub(slots);
+            if first_kept > self.oldest
+                && self.config.map(|config| config.policy) == Some(LogPolicy::StopWhenFull)
+            {
+                return Err(LogError::Full);
+            }
+            storage
+                .erase_block(slot / slots_per_block)
+                .map_err(|_| LogError::Storage)?;
+            self.oldest = self.oldest.max(first_kept);
+        }
+
+        record.sequence = self.next;
+        let address = slot * RECORD_SIZE as u32;
+        // the slot is used up even if programming failed halfway
+        self.next += 1;
+        storage
+            .write(address, &record.encode())
+            .map_err(|_| LogError::Storage)?;
+        Ok(record.sequence)
+    }
+
+    /// Sends the records starting with the given sequence number, or with the oldest one if it
+    /// was erased already
+    pub fn start_download(&mut self, from: u32) {
+        self.download = Some(from);
+    }
+
+    /// The next part of the download, `None` if no download runs. A record that can't be read
+    /// ends the download with an error.
+    pub fn next_download(
+        &mut self,
+        storage: &mut impl BlockStorage,
+    ) -> Option<Result<LogDownload, LogError>> {
+        let sequence = self.download?.max(self.oldest);
+        if sequence >= self.next {
+            self.download = None;
+            return Some(Ok(LogDownload::End(self.next)));
+        }
+
+        let record = read_slot(storage, sequence % slots(storage));
+        match record {
+            Ok(Some(record)) if record.sequence == sequence => {
+                self.download = Some(sequence + 1);
+                Some(Ok(LogDownload::Record(record)))
+            }
+            // remains of an interrupted write
+            Ok(_) => {
+                self.download = Some(sequence + 1);
+                self.next_download(storage)
+            }
+            Err(error) => {
+                self.download = None;
+                Some(Err(error))
+            }
+        }
+    }
+}
+
+fn slots(storage: &impl BlockStorage) -> u32 {
+    storage.capacity() / RECORD_SIZE as u32
+}
+
+fn slots_per_block(storage: &impl BlockStorage) -> u32 {
+    storage.block_size() / RECORD_SIZE as u32
+}
+
+fn read_slot(storage: &mut impl BlockStorage, slot: u32) -> Result<Option<LogRecord>, LogError> {
+    let mut bytes = [0; RECORD_SIZE];
+    storage
+        .read(slot * RECORD_SIZE as u32, &mut bytes)
+        .map_err(|_| LogError::Storage)?;
+    Ok(LogRecord::decode(&bytes))
+}
+
+fn is_erased(storage: &mut impl BlockStorage, slot: u32) -> Result<bool, LogError> {
+    let mut bytes = [0; RECORD_SIZE];
+    storage
+        .read(slot * RECORD_SIZE as u32, &mut bytes)
+        .map_err(|_| LogError::Storage)?;
+    Ok(bytes.iter().all(|byte| *byte == 0xFF))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mocks::MockBlockStorage;
+
+    const BLOCKS: u32 = 4;
+    const BLOCK_SIZE: u32 = 4 * RECORD_SIZE as u32;
+
+    fn record(timestamp: u64) -> LogRecord {
+        LogRecord {
+            sequence: 0,
+            timestamp,
+            unit: 1,
+            step: Some(2),
+            voltage: 3.3,
+            current: -0.5,
+            temperature: None,
+        }
+    }
+
+    fn start(log: &mut MeasurementLog, policy: LogPolicy) {
+        log.start(LogConfig {
+            interval: 1.0,
+            policy,
+        })
+        .unwrap();
+    }
+
+    fn download(
+        log: &mut MeasurementLog,
+        storage: &mut MockBlockStorage,
+        from: u32,
+    ) -> (Vec<u32>, u32) {
+        log.start_download(from);
+        let mut sequences = Vec::new();
+        loop {
+            match log.next_download(storage).unwrap().unwrap() {
+                LogDownload::Record(record) => sequences.push(record.sequence),
+                LogDownload::End(next) => return (sequences, next),
+            }
+        }
+    }
+
+    #[test]
+    fn test_encoding() {
+        let record = LogRecord {
+            sequence: 7,
+            temperature: Some(25.5),
+            step: None,
+            ..record(123_456_789_000)
+        };
+        assert_eq!(LogRecord::decode(&record.encode()), Some(record));
+        assert_eq!(LogRecord::decode(&[0xFF; RECORD_SIZE]), None);
+
+        let mut bytes = record.encode();
+        bytes[5] ^= 1;
+        assert_eq!(LogRecord::decode(&bytes), None);
+    }
+
+    #[test]
+    fn test_append_and_download() {
+        let mut storage = MockBlockStorage::new(BLOCKS, BLOCK_SIZE);
+        let mut log = MeasurementLog::new();
+        start(&mut log, LogPolicy::Wrap);
+
+        for timestamp in 0..6 {
+            assert_eq!(
+                log.append(&mut storage, record(timestamp)),
+                Ok(timestamp as u32)
+            );
+        }
+        assert_eq!(download(&mut log, &mut storage, 0), ((0..6).collect(), 6));
+        // resumed after the fourth record
+        assert_eq!(download(&mut log, &mut storage, 4), (vec![4, 5], 6));
+        assert_eq!(log.next_download(&mut storage), None);
+    }
+
+    #[test]
+    fn test_wrap() {
+        let mut storage = MockBlockStorage::new(BLOCKS, BLOCK_SIZE);
+        let mut log = MeasurementLog::new();
+        start(&mut log, LogPolicy::Wrap);
+
+        for timestamp in 0..18 {
+            log.append(&mut storage, record(timestamp)).unwrap();
+        }
+        // the first block was erased for records 16 and 17
+        let status = log.get_status(&storage);
+        assert_eq!((status.oldest, status.next, status.capacity), (4, 18, 16));
+        assert_eq!(download(&mut log, &mut storage, 0), ((4..18).collect(), 18));
+    }
+
+    #[test]
+    fn test_stop_when_full() {
+        let mut storage = MockBlockStorage::new(BLOCKS, BLOCK_SIZE);
+        let mut log = MeasurementLog::new();
+        start(&mut log, LogPolicy::StopWhenFull);
+
+        for timestamp in 0..16 {
+            log.append(&mut storage, record(timestamp)).unwrap();
+        }
+        assert_eq!(log.append(&mut storage, record(16)), Err(LogError::Full));
+        assert!(!log.is_running());
+        assert_eq!(download(&mut log, &mut storage, 0), ((0..16).collect(), 16));
+
+        assert_eq!(log.clear(&mut storage), Ok(()));
+        assert_eq!(download(&mut log, &mut storage, 0), (vec![], 0));
+    }
+
+    #[test]
+    fn test_recover() {
+        let mut storage = MockBlockStorage::new(BLOCKS, BLOCK_SIZE);
+        let mut log = MeasurementLog::new();
+        start(&mut log, LogPolicy::Wrap);
+        for timestamp in 0..22 {
+            log.append(&mut storage, record(timestamp)).unwrap();
+        }
+
+        let mut recovered = MeasurementLog::new();
+        recovered.recover(&mut storage).unwrap();
+        assert!(!recovered.is_running());
+        assert_eq!(
+            recovered.get_status(&storage),
+            LogStatus {
+                running: false,
+                ..log.get_status(&storage)
+            }
+        );
+
+        let mut empty = MeasurementLog::new();
+        empty
+            .recover(&mut MockBlockStorage::new(BLOCKS, BLOCK_SIZE))
+            .unwrap();
+        assert_eq!((empty.oldest, empty.next), (0, 0));
+    }
+
+    #[test]
+    fn test_power_loss_during_write() {
+        let mut storage = MockBlockStorage::new(BLOCKS, BLOCK_SIZE);
+        let mut log = MeasurementLog::new();
+        start(&mut log, LogPolicy::Wrap);
+        for timestamp in 0..5 {
+            log.append(&mut storage, record(timestamp)).unwrap();
+        }
+        storage.power_budget = Some(RECORD_SIZE / 2);
+        assert_eq!(log.append(&mut storage, record(5)), Err(LogError::Storage));
+        assert!(!log.is_running());
+
+        storage.power_budget = None;
+        let mut recovered = MeasurementLog::new();
+        recovered.recover(&mut storage).unwrap();
+        // the torn slot is skipped
+        assert_eq!(recovered.next, 6);
+        start(&mut recovered, LogPolicy::Wrap);
+        assert_eq!(recovered.append(&mut storage, record(6)), Ok(6));
+        assert_eq!(
+            download(&mut recovered, &mut storage, 0),
+            (vec![0, 1, 2, 3, 4, 6], 7)
+        );
+    }
+
+    #[test]
+    fn test_interval() {
+        let mut log = MeasurementLog::new();
+        assert!(!log.is_due(1.0));
+        assert_eq!(
+            log.start(LogConfig {
+                interval: 0.0,
+                policy: LogPolicy::Wrap
+            }),
+            Err(LogError::InvalidInterval)
+        );
+
+        log.start(LogConfig {
+            interval: 0.25,
+            policy: LogPolicy::Wrap,
+        })
+        .unwrap();
+        let due = (0..10).filter(|_| log.is_due(0.1)).count();
+        // right away and after 0.3, 0.5 and 0.8 s
+        assert_eq!(due, 4);
+    }
+}
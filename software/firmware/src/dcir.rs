@@ -0,0 +1,408 @@
+use heapless::Vec;
+use serde::{Deserialize, Serialize};
+
+use crate::sequence::StepMeasurement;
+
+/// Maximum number of state of charge points of a `DcirSchedule`
+pub const MAX_SOC_POINTS: usize = 10;
+
+/// A current step from a base to a pulse current, e.g. 0.2 C to 1 C for 10 s
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq)]
+pub struct PulseConfig {
+    /// in A
+    pub base_current: f32,
+    /// in A
+    pub pulse_current: f32,
+    /// how long the base current flows before the pulse, in s
+    pub base_duration: f32,
+    /// in s
+    pub pulse_duration: f32,
+}
+
+impl PulseConfig {
+    pub fn validate(&self) -> Result<(), DcirError> {
+        let valid = self.base_current >= 0.0
+            && self.pulse_current >= 0.0
+            && self.base_current != self.pulse_current
+            && self.base_duration > 0.0
+            && self.pulse_duration > 0.0;
+
+        if valid {
+            Ok(())
+        } else {
+            Err(DcirError::InvalidPulse)
+        }
+    }
+}
+
+/// Measures the internal resistance at the given states of charge while the unit discharges.
+/// The state of charge is counted down from a full battery, a discharge has to start right after
+/// the battery was charged.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct DcirSchedule {
+    /// the base current is ignored, the pulse starts from the discharge current
+    pub pulse: PulseConfig,
+    /// in Ah
+    pub nominal_capacity: f32,
+    /// from 0 to 1 in descending order
+    pub state_of_charge: Vec<f32, MAX_SOC_POINTS>,
+}
+
+impl DcirSchedule {
+    pub fn validate(&self) -> Result<(), DcirError> {
+        // the base current is replaced by the discharge current
+        let pulse = &self.pulse;
+        if !(pulse.pulse_current > 0.0 && pulse.base_duration > 0.0 && pulse.pulse_duration > 0.0) {
+            return Err(DcirError::InvalidPulse);
+        }
+
+        let descending = self
+            .state_of_charge
+            .windows(2)
+            .all(|pair| pair[0] > pair[1]);
+        let in_range = self
+            .state_of_charge
+            .iter()
+            .all(|soc| (0.0..=1.0).contains(soc));
+        if self.nominal_capacity > 0.0 && descending && in_range {
+            Ok(())
+        } else {
+            Err(DcirError::InvalidSchedule)
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq)]
+pub enum DcirError {
+    /// A current is negative, both currents are the same or a duration isn't longer than zero
+    InvalidPulse,
+    /// The capacity isn't positive or the states of charge aren't between 0 and 1 in descending
+    /// order
+    InvalidSchedule,
+    /// A pulse test can only be started while the unit is idle
+    Busy,
+}
+
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq)]
+pub struct Sample {
+    /// in V
+    pub voltage: f32,
+    /// in A
+    pub current: f32,
+}
+
+impl Sample {
+    fn from(measurement: &StepMeasurement) -> Self {
+        Self {
+            voltage: measurement.voltage,
+            current: measurement.current,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq)]
+pub struct DcirResult {
+    /// the last sample before the pulse
+    pub before: Sample,
+    /// the first sample of the pulse
+    pub after: Sample,
+    /// the last sample of the pulse
+    pub end: Sample,
+    /// the immediate voltage step divided by the current step, in Ω
+    pub ohmic_resistance: f32,
+    /// the further voltage change during the pulse divided by the current step, in Ω
+    pub polarization_resistance: f32,
+    /// the point of the `DcirSchedule` the pulse ran at, `None` for a single pulse test
+    pub state_of_charge: Option<f32>,
+}
+
+impl DcirResult {
+    /// The resistances are NaN if the current didn't change
+    fn new(before: Sample, after: Sample, end: Sample, state_of_charge: Option<f32>) -> Self {
+        let ohmic_resistance = (before.voltage - after.voltage) / (after.current - before.current);
+        let total_resistance = (before.voltage - end.voltage) / (end.current - before.current);
+
+        Self {
+            before,
+            after,
+            end,
+            ohmic_resistance,
+            polarization_resistance: total_resistance - ohmic_resistance,
+            state_of_charge,
+        }
+    }
+}
+
+/// Runs a `PulseConfig`: the base current until the base duration is over, then the pulse current
+/// until the pulse duration is over. The resistances are calculated from the measured currents, so
+/// it doesn't matter that the load controller needs a moment to reach the pulse current.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct PulseTest {
+    config: PulseConfig,
+    state_of_charge: Option<f32>,
+    pulsing: bool,
+    /// time since the base or pulse current was set, in s
+    time: f32,
+    before: Option<Sample>,
+    after: Option<Sample>,
+}
+
+impl PulseTest {
+    pub fn new(config: PulseConfig, state_of_charge: Option<f32>) -> Self {
+        Self {
+            config,
+            state_of_charge,
+            pulsing: false,
+            time: 0.0,
+            before: None,
+            after: None,
+        }
+    }
+
+    pub fn get_config(&self) -> &PulseConfig {
+        &self.config
+    }
+
+    /// The current the load has to draw right now, in A
+    pub fn get_current(&self) -> f32 {
+        if self.pulsing {
+            self.config.pulse_current
+        } else {
+            self.config.base_current
+        }
+    }
+
+    /// Returns the result once the pulse is over
+    pub fn update(&mut self, measurement: &StepMeasurement, delta_time: f32) -> Option<DcirResult> {
+        let sample = Sample::from(measurement);
+
+        if !self.pulsing {
+            self.before = Some(sample);
+            self.time += delta_time;
+            if self.time >= self.config.base_duration {
+                self.pulsing = true;
+                self.time = 0.0;
+            }
+            return None;
+        }
+
+        self.time += delta_time;
+        let after = *self.after.get_or_insert(sample);
+        if self.time >= self.config.pulse_duration {
+            self.before
+                .map(|before| DcirResult::new(before, after, sample, self.state_of_charge))
+        } else {
+            None
+        }
+    }
+}
+
+/// Decides when the pulses of a `DcirSchedule` are due
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct DcirScheduler {
+    schedule: Option<DcirSchedule>,
+    /// index of the next state of charge point
+    next_point: usize,
+    /// charge removed since the battery was full, in Ah
+    discharged: f32,
+}
+
+impl DcirScheduler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// `None` turns the measurements off
+    pub fn set_schedule(&mut self, schedule: Option<DcirSchedule>) -> Result<(), DcirError> {
+        if let Some(schedule) = &schedule {
+            schedule.validate()?;
+        }
+        self.schedule = schedule;
+        self.reset();
+        Ok(())
+    }
+
+    pub fn get_schedule(&self) -> Option<&DcirSchedule> {
+        self.schedule.as_ref()
+    }
+
+    /// The battery is full again, e.g. after charging
+    pub fn reset(&mut self) {
+        self.next_point = 0;
+        self.discharged = 0.0;
+    }
+
+    pub fn get_state_of_charge(&self) -> Option<f32> {
+        self.schedule
+            .as_ref()
+            .map(|schedule| 1.0 - self.discharged / schedule.nominal_capacity)
+    }
+
+    /// in Ah
+    pub fn add_discharged(&mut self, charge: f32) {
+        self.discharged += charge;
+    }
+
+    /// Returns the pulse to run and its state of charge point once the battery reached it. Points
+    /// that were passed in the meantime are skipped.
+    pub fn take_due_pulse(&mut self) -> Option<(PulseConfig, f32)> {
+        let state_of_charge = self.get_state_of_charge()?;
+        let schedule = self.schedule.as_ref()?;
+
+        let mut due = None;
+        while let Some(&point) = schedule.state_of_charge.get(self.next_point) {
+            if state_of_charge > point {
+                break;
+            }
+            due = Some((schedule.pulse, point));
+            self.next_point += 1;
+        }
+        due
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PULSE: PulseConfig = PulseConfig {
+        base_current: 0.5,
+        pulse_current: 2.5,
+        base_duration: 2.0,
+        pulse_duration: 10.0,
+    };
+
+    fn measurement(voltage: f32, current: f32) -> StepMeasurement {
+        StepMeasurement {
+            voltage,
+            current,
+            temperature: None,
+            charge_done: false,
+        }
+    }
+
+    #[test]
+    fn test_validate() {
+        assert_eq!(PULSE.validate(), Ok(()));
+        for pulse in [
+            PulseConfig {
+                pulse_current: 0.5,
+                ..PULSE
+            },
+            PulseConfig {
+                base_current: -0.5,
+                ..PULSE
+            },
+            PulseConfig {
+                pulse_duration: 0.0,
+                ..PULSE
+            },
+            PulseConfig {
+                base_duration: f32::NAN,
+                ..PULSE
+            },
+        ] {
+            assert_eq!(pulse.validate(), Err(DcirError::InvalidPulse));
+        }
+
+        let mut schedule = DcirSchedule {
+            pulse: PULSE,
+            nominal_capacity: 3.0,
+            state_of_charge: Vec::from_slice(&[0.9, 0.5, 0.1]).unwrap(),
+        };
+        assert_eq!(schedule.validate(), Ok(()));
+        schedule.state_of_charge = Vec::from_slice(&[0.5, 0.9]).unwrap();
+        assert_eq!(schedule.validate(), Err(DcirError::InvalidSchedule));
+        schedule.state_of_charge = Vec::from_slice(&[1.5]).unwrap();
+        assert_eq!(schedule.validate(), Err(DcirError::InvalidSchedule));
+        schedule.state_of_charge.clear();
+        schedule.nominal_capacity = 0.0;
+        assert_eq!(schedule.validate(), Err(DcirError::InvalidSchedule));
+    }
+
+    #[test]
+    fn test_pulse() {
+        let mut test = PulseTest::new(PULSE, None);
+
+        assert_eq!(test.get_current(), 0.5);
+        assert_eq!(test.update(&measurement(3.3, 0.5), 1.0), None);
+        assert_eq!(test.update(&measurement(3.25, 0.5), 1.0), None);
+        assert_eq!(test.get_current(), 2.5);
+
+        // 50 mΩ ohmic resistance, 25 mΩ polarization at the end of the pulse
+        assert_eq!(test.update(&measurement(3.15, 2.5), 1.0), None);
+        for _ in 0..8 {
+            assert_eq!(test.update(&measurement(3.125, 2.5), 1.0), None);
+        }
+        let result = test.update(&measurement(3.1, 2.5), 1.0).unwrap();
+
+        assert_eq!(
+            result.before,
+            Sample {
+                voltage: 3.25,
+                current: 0.5
+            }
+        );
+        assert_eq!(
+            result.after,
+            Sample {
+                voltage: 3.15,
+                current: 2.5
+            }
+        );
+        assert_eq!(
+            result.end,
+            Sample {
+                voltage: 3.1,
+                current: 2.5
+            }
+        );
+        assert!((result.ohmic_resistance - 0.05).abs() < 1e-6);
+        assert!((result.polarization_resistance - 0.025).abs() < 1e-6);
+        assert_eq!(result.state_of_charge, None);
+    }
+
+    #[test]
+    fn test_pulse_without_current_change() {
+        let mut test = PulseTest::new(PULSE, Some(0.5));
+
+        test.update(&measurement(3.3, 0.0), 2.0);
+        let result = test.update(&measurement(3.3, 0.0), 10.0).unwrap();
+
+        assert!(result.ohmic_resistance.is_nan());
+        assert_eq!(result.state_of_charge, Some(0.5));
+    }
+
+    #[test]
+    fn test_schedule() {
+        let mut scheduler = DcirScheduler::new();
+        scheduler.add_discharged(1.0);
+        assert_eq!(scheduler.take_due_pulse(), None);
+
+        let schedule = DcirSchedule {
+            pulse: PULSE,
+            nominal_capacity: 2.0,
+            state_of_charge: Vec::from_slice(&[1.0, 0.75, 0.5, 0.25]).unwrap(),
+        };
+        assert_eq!(scheduler.set_schedule(Some(schedule)), Ok(()));
+        assert_eq!(scheduler.get_state_of_charge(), Some(1.0));
+        assert_eq!(scheduler.take_due_pulse(), Some((PULSE, 1.0)));
+        assert_eq!(scheduler.take_due_pulse(), None);
+
+        scheduler.add_discharged(0.25);
+        assert_eq!(scheduler.take_due_pulse(), None);
+        scheduler.add_discharged(0.25);
+        assert_eq!(scheduler.take_due_pulse(), Some((PULSE, 0.75)));
+
+        // passed points are skipped
+        scheduler.add_discharged(1.0);
+        assert_eq!(scheduler.take_due_pulse(), Some((PULSE, 0.25)));
+        assert_eq!(scheduler.take_due_pulse(), None);
+
+        scheduler.reset();
+        assert_eq!(scheduler.take_due_pulse(), Some((PULSE, 1.0)));
+
+        assert_eq!(scheduler.set_schedule(None), Ok(()));
+        assert_eq!(scheduler.get_state_of_charge(), None);
+    }
+}
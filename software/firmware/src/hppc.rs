@@ -0,0 +1,506 @@
+use heapless::Vec;
+use serde::{Deserialize, Serialize};
+
+use crate::sequence::StepMeasurement;
+use crate::BatteryTestUnitMode;
+
+/// Number of samples per `HppcData` message, a message has to fit into a packet
+pub const HPPC_CHUNK_SAMPLES: usize = 12;
+
+/// Hybrid pulse power characterization: starting from a full battery, the unit rests, applies a
+/// discharge and a charge pulse and discharges to the next state of charge step, until the
+/// battery is empty.
+///
+/// The charger can only be switched on and off, the charge pulse draws whatever current it
+/// supplies. The measured current is recorded, so the pulse can still be evaluated.
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq)]
+pub struct HppcConfig {
+    /// in Ah
+    pub nominal_capacity: f32,
+    /// the state of charge between two pulse sets, from 0 to 1, e.g. 0.1
+    pub soc_step: f32,
+    /// current of the discharge to the next state of charge step, in A
+    pub discharge_current: f32,
+    /// rest before the pulses, in s
+    pub rest_duration: f32,
+    /// in A
+    pub discharge_pulse_current: f32,
+    /// in s
+    pub discharge_pulse_duration: f32,
+    /// rest after each pulse, in s
+    pub pulse_rest_duration: f32,
+    /// in s
+    pub charge_pulse_duration: f32,
+    /// the test ends early if the voltage drops below this during a discharge, in V
+    pub min_voltage: f32,
+    /// how much of the rest before the pulses is recorded, in s
+    pub window: f32,
+    /// time between recorded samples, zero records every update, in s
+    pub sample_interval: f32,
+}
+
+impl HppcConfig {
+    pub fn validate(&self) -> Result<(), HppcError> {
+        let positive = [
+            self.nominal_capacity,
+            self.soc_step,
+            self.discharge_current,
+            self.rest_duration,
+            self.discharge_pulse_current,
+            self.discharge_pulse_duration,
+            self.pulse_rest_duration,
+            self.charge_pulse_duration,
+        ];
+        let valid = positive.iter().all(|value| *value > 0.0)
+            && self.soc_step <= 1.0
+            && self.window >= 0.0
+            && self.window <= self.rest_duration
+            && self.sample_interval >= 0.0
+            && self.min_voltage.is_finite();
+
+        if valid {
+            Ok(())
+        } else {
+            Err(HppcError::InvalidConfig)
+        }
+    }
+
+    /// Number of pulse sets, the last one is one step above an empty battery
+    fn steps(&self) -> u8 {
+        libm::roundf(1.0 / self.soc_step).clamp(1.0, u8::MAX as f32) as u8
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq)]
+pub enum HppcError {
+    /// A value is out of range, see `HppcConfig::validate`
+    InvalidConfig,
+    /// The procedure can only be started while the unit is idle
+    Busy,
+}
+
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq)]
+pub enum HppcEndReason {
+    /// The battery was discharged through all steps
+    Completed,
+    /// The voltage dropped below the minimum voltage during a discharge
+    MinVoltage,
+    /// Stopped by the client or a fault
+    Stopped,
+}
+
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq)]
+pub struct HppcEnd {
+    /// number of completed pulse sets
+    pub steps: u8,
+    pub reason: HppcEndReason,
+}
+
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq)]
+pub struct HppcSample {
+    /// since the start of the discharge pulse, negative during the rest before, in s
+    pub time: f32,
+    /// in V
+    pub voltage: f32,
+    /// in A
+    pub current: f32,
+}
+
+/// A part of the recorded window around the pulses of one step. The window is sent while it's
+/// recorded, `first` is the index of the first sample in the window.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct HppcData {
+    /// index of the state of charge step, 0 is the full battery
+    pub step: u8,
+    pub first: u16,
+    pub samples: Vec<HppcSample, HPPC_CHUNK_SAMPLES>,
+    /// whether this is the end of the window
+    pub last: bool,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Phase {
+    Rest,
+    DischargePulse,
+    DischargePulseRest,
+    ChargePulse,
+    ChargePulseRest,
+    Discharge,
+}
+
+/// The result of `HppcProcedure::update`
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct HppcUpdate {
+    /// whether another phase started, the mode of the battery test unit has to follow
+    pub phase_changed: bool,
+    pub data: Option<HppcData>,
+    pub end: Option<HppcEnd>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct HppcState {
+    config: HppcConfig,
+    step: u8,
+    phase: Phase,
+    /// time since the phase started, in s
+    phase_time: f32,
+    /// charge removed during the discharge phase, in Ah
+    discharged: f32,
+    /// time since the discharge pulse started, in s
+    window_time: f32,
+    next_sample_time: f32,
+    chunk: HppcData,
+}
+
+/// Runs the HPPC procedure. Like the `Sequencer` it only decides what runs, the battery test unit
+/// sets the mode of the phase and controls the load.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct HppcProcedure {
+    state: Option<HppcState>,
+}
+
+impl HppcProcedure {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.state.is_some()
+    }
+
+    /// The current state of charge step, `None` if the procedure doesn't run
+    pub fn get_step(&self) -> Option<u8> {
+        self.state.as_ref().map(|state| state.step)
+    }
+
+    /// The mode of the running phase, idle if the procedure doesn't run
+    pub fn get_mode(&self) -> BatteryTestUnitMode {
+        let Some(state) = &self.state else {
+            return BatteryTestUnitMode::Idle;
+        };
+
+        match state.phase {
+            Phase::Rest | Phase::DischargePulseRest | Phase::ChargePulseRest => {
+                BatteryTestUnitMode::Resting
+            }
+            Phase::DischargePulse => BatteryTestUnitMode::DischargingConstantCurrent(
+                state.config.discharge_pulse_current,
+            ),
+            Phase::ChargePulse => BatteryTestUnitMode::Charging,
+            Phase::Discharge => {
+                BatteryTestUnitMode::DischargingConstantCurrent(state.config.discharge_current)
+            }
+        }
+    }
+
+    /// Starts with the rest before the first pulses, the battery has to be full
+    pub fn start(&mut self, config: HppcConfig) -> Result<(), HppcError> {
+        if self.is_running() {
+            return Err(HppcError::Busy);
+        }
+        config.validate()?;
+
+        self.state = Some(HppcState {
+            config,
+            step: 0,
+            phase: Phase::Rest,
+            phase_time: 0.0,
+            discharged: 0.0,
+            window_time: 0.0,
+            next_sample_time: 0.0,
+            chunk: HppcData {
+                step: 0,
+                first: 0,
+                samples: Vec::new(),
+                last: false,
+            },
+        });
+        Ok(())
+    }
+
+    /// Returns how far the procedure got if it was running, the window being recorded is dropped
+    pub fn stop(&mut self) -> Option<HppcEnd> {
+        let state = self.state.take()?;
+        Some(HppcEnd {
+            steps: state.step,
+            reason: HppcEndReason::Stopped,
+        })
+    }
+
+    pub fn update(&mut self, measurement: &StepMeasurement, delta_time: f32) -> HppcUpdate {
+        let mut update = HppcUpdate::default();
+        let Some(state) = self.state.as_mut() else {
+            return update;
+        };
+        let config = state.config;
+        state.phase_time += delta_time;
+
+        // the window starts `window` s before the end of the rest and ends with the rest after
+        // the charge pulse
+        let recording = match state.phase {
+            Phase::Rest => state.phase_time >= config.rest_duration - config.window,
+            Phase::Discharge => false,
+            _ => true,
+        };
+        if recording {
+            state.window_time = match state.phase {
+                Phase::Rest => state.phase_time - config.rest_duration,
+                _ => state.window_time + delta_time,
+            };
+            if state.chunk.samples.is_empty() && state.chunk.first == 0 {
+                state.next_sample_time = state.window_time;
+            }
+            if state.window_time >= state.next_sample_time {
+                state.next_sample_time += config.sample_interval.max(f32::MIN_POSITIVE);
+                // can't fail, full chunks are sent right away
+                let _ = state.chunk.samples.push(HppcSample {
+                    time: state.window_time,
+                    voltage: measurement.voltage,
+                    current: measurement.current,
+                });
+            }
+        }
+
+        let phase_duration = match state.phase {
+            Phase::Rest => config.rest_duration,
+            Phase::DischargePulse => config.discharge_pulse_duration,
+            Phase::DischargePulseRest | Phase::ChargePulseRest => config.pulse_rest_duration,
+            Phase::ChargePulse => config.charge_pulse_duration,
+            Phase::Discharge => f32::INFINITY,
+        };
+
+        let next_phase = match state.phase {
+            Phase::Discharge => {
+                state.discharged += measurement.current * delta_time / 3600.0;
+                if measurement.voltage < config.min_voltage {
+                    self.finish(HppcEndReason::MinVoltage, &mut update);
+                    return update;
+                }
+                if state.discharged < config.soc_step * config.nominal_capacity {
+                    None
+                } else if state.step + 1 >= config.steps() {
+                    state.step += 1;
+                    self.finish(HppcEndReason::Completed, &mut update);
+                    return update;
+                } else {
+                    state.step += 1;
+                    Some(Phase::Rest)
+                }
+            }
+            _ if state.phase_time < phase_duration => None,
+            Phase::Rest => Some(Phase::DischargePulse),
+            Phase::DischargePulse => Some(Phase::DischargePulseRest),
+            Phase::DischargePulseRest => Some(Phase::ChargePulse),
+            Phase::ChargePulse => Some(Phase::ChargePulseRest),
+            Phase::ChargePulseRest => Some(Phase::Discharge),
+        };
+
+        let window_done = next_phase == Some(Phase::Discharge);
+        if state.chunk.samples.is_full() || (window_done && recording) {
+            let next = HppcData {
+                step: state.step,
+                first: state.chunk.first + state.chunk.samples.len() as u16,
+                samples: Vec::new(),
+                last: false,
+            };
+            let mut data = core::mem::replace(&mut state.chunk, next);
+            data.last = window_done;
+            update.data = Some(data);
+        }
+
+        if let Some(phase) = next_phase {
+            state.phase = phase;
+            state.phase_time = 0.0;
+            if phase == Phase::Discharge {
+                state.discharged = 0.0;
+            }
+            if window_done || phase == Phase::Rest {
+                state.chunk = HppcData {
+                    step: state.step,
+                    first: 0,
+                    samples: Vec::new(),
+                    last: false,
+                };
+            }
+            update.phase_changed = true;
+        }
+        update
+    }
+
+    fn finish(&mut self, reason: HppcEndReason, update: &mut HppcUpdate) {
+        if let Some(state) = self.state.take() {
+            update.phase_changed = true;
+            update.end = Some(HppcEnd {
+                steps: state.step,
+                reason,
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: HppcConfig = HppcConfig {
+        nominal_capacity: 1.0,
+        soc_step: 0.5,
+        discharge_current: 1.0,
+        rest_duration: 10.0,
+        discharge_pulse_current: 2.0,
+        discharge_pulse_duration: 2.0,
+        pulse_rest_duration: 4.0,
+        charge_pulse_duration: 2.0,
+        min_voltage: 2.5,
+        window: 2.0,
+        sample_interval: 1.0,
+    };
+
+    fn measurement(voltage: f32, current: f32) -> StepMeasurement {
+        StepMeasurement {
+            voltage,
+            current,
+            temperature: None,
+            charge_done: false,
+        }
+    }
+
+    /// Runs the procedure with 1 s updates and the current following the mode
+    fn run(procedure: &mut HppcProcedure, seconds: u32, data: &mut std::vec::Vec<HppcData>) {
+        for _ in 0..seconds {
+            let current = match procedure.get_mode() {
+                BatteryTestUnitMode::DischargingConstantCurrent(current) => current,
+                BatteryTestUnitMode::Charging => -0.5,
+                _ => 0.0,
+            };
+            let update = procedure.update(&measurement(3.3, current), 1.0);
+            data.extend(update.data);
+        }
+    }
+
+    #[test]
+    fn test_validate() {
+        assert_eq!(CONFIG.validate(), Ok(()));
+        assert_eq!(CONFIG.steps(), 2);
+        for config in [
+            HppcConfig {
+                soc_step: 0.0,
+                ..CONFIG
+            },
+            HppcConfig {
+                soc_step: 1.5,
+                ..CONFIG
+            },
+            HppcConfig {
+                window: 20.0,
+                ..CONFIG
+            },
+            HppcConfig {
+                discharge_pulse_current: f32::NAN,
+                ..CONFIG
+            },
+        ] {
+            assert_eq!(config.validate(), Err(HppcError::InvalidConfig));
+        }
+    }
+
+    #[test]
+    fn test_phases() {
+        let mut procedure = HppcProcedure::new();
+        let mut data = std::vec::Vec::new();
+        assert_eq!(procedure.start(CONFIG), Ok(()));
+        assert_eq!(procedure.start(CONFIG), Err(HppcError::Busy));
+        assert_eq!(procedure.get_mode(), BatteryTestUnitMode::Resting);
+
+        run(&mut procedure, 10, &mut data);
+        assert_eq!(
+            procedure.get_mode(),
+            BatteryTestUnitMode::DischargingConstantCurrent(2.0)
+        );
+        run(&mut procedure, 2, &mut data);
+        assert_eq!(procedure.get_mode(), BatteryTestUnitMode::Resting);
+        run(&mut procedure, 4, &mut data);
+        assert_eq!(procedure.get_mode(), BatteryTestUnitMode::Charging);
+        run(&mut procedure, 2, &mut data);
+        assert_eq!(procedure.get_mode(), BatteryTestUnitMode::Resting);
+        run(&mut procedure, 4, &mut data);
+        assert_eq!(
+            procedure.get_mode(),
+            BatteryTestUnitMode::DischargingConstantCurrent(1.0)
+        );
+
+        // half of the capacity with 1 A
+        run(&mut procedure, 1801, &mut data);
+        assert_eq!(procedure.get_step(), Some(1));
+        assert_eq!(procedure.get_mode(), BatteryTestUnitMode::Resting);
+
+        // the second discharge empties the battery
+        let mut end = None;
+        for _ in 0..10000 {
+            let current = match procedure.get_mode() {
+                BatteryTestUnitMode::DischargingConstantCurrent(current) => current,
+                _ => 0.0,
+            };
+            let update = procedure.update(&measurement(3.3, current), 1.0);
+            if update.end.is_some() {
+                end = update.end;
+                break;
+            }
+        }
+        assert_eq!(
+            end,
+            Some(HppcEnd {
+                steps: 2,
+                reason: HppcEndReason::Completed
+            })
+        );
+        assert_eq!(procedure.get_mode(), BatteryTestUnitMode::Idle);
+    }
+
+    #[test]
+    fn test_window() {
+        let mut procedure = HppcProcedure::new();
+        let mut data = std::vec::Vec::new();
+        procedure.start(CONFIG).unwrap();
+
+        run(&mut procedure, 22 + 100, &mut data);
+
+        // 2 s before the pulse and 12 s of pulses and rests, sampled every second
+        let samples = data
+            .iter()
+            .flat_map(|data| data.samples.iter())
+            .collect::<std::vec::Vec<_>>();
+        assert_eq!(samples.len(), 15);
+        assert_eq!(samples[0].time, -2.0);
+        assert_eq!(samples[14].time, 12.0);
+        // the last sample of the rest and the first of the pulse
+        assert_eq!(samples[2].current, 0.0);
+        assert_eq!(samples[3].current, 2.0);
+
+        assert_eq!(data.len(), 2);
+        assert_eq!(data[0].samples.len(), HPPC_CHUNK_SAMPLES);
+        assert!(!data[0].last);
+        assert_eq!(data[1].first, HPPC_CHUNK_SAMPLES as u16);
+        assert!(data[1].last);
+        assert!(data.iter().all(|data| data.step == 0));
+    }
+
+    #[test]
+    fn test_min_voltage() {
+        let mut procedure = HppcProcedure::new();
+        procedure.start(CONFIG).unwrap();
+        run(&mut procedure, 22, &mut std::vec::Vec::new());
+
+        let update = procedure.update(&measurement(2.4, 1.0), 1.0);
+        assert_eq!(
+            update.end,
+            Some(HppcEnd {
+                steps: 0,
+                reason: HppcEndReason::MinVoltage
+            })
+        );
+        assert!(update.phase_changed);
+        assert!(!procedure.is_running());
+        assert_eq!(procedure.stop(), None);
+    }
+}
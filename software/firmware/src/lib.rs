@@ -1,27 +1,75 @@
 #![cfg_attr(not(test), no_std)]
 
+use calibration::{CalibrationChannel, CalibrationError, CalibrationPoint, CalibrationRun, CalibrationSet};
+use clock::{Clock, ClockError};
+use crash::{ResetCause, UnitState};
+use conditioning::{ChannelConditioning, Conditioner, ConditioningConfig, ConditioningError};
+use config::{Config, ConfigError, UnitConfig};
+use datalog::{LogDownload, LogRecord, MeasurementLog};
+use control::{PiController, LOAD_CONTROLLER_KI, LOAD_CONTROLLER_KP};
+use dcir::{DcirError, DcirSchedule, DcirScheduler, PulseConfig, PulseTest};
+use heapless::{String, Vec};
+use hppc::{HppcConfig, HppcEnd, HppcError, HppcProcedure};
 use libm;
-use msg_types::MsgTypes;
+use limits::{FaultCause, Measurement, SafetyLimits};
+use msg_types::{MsgTypes, Telemetry};
+use ocv::{OcvConfig, OcvEnd, OcvError, OcvProcedure};
+use orbit::{OrbitError, OrbitPlayer, Segment};
+use recovery::{Recovery, RecoveryError, UnitRun, MAX_RUN_UNITS, WATCHDOG_TOLERANCE};
+use sampling::{SampleScheduler, SamplingConfig, SamplingError};
+use serde::{Deserialize, Serialize};
+use sequence::{ProgramError, Sequencer, Step, StepMeasurement, StepTransition};
+use soc::{OcvCurve, SocEstimator, SocEstimatorConfig, SocEstimatorError};
 use traits::{AdcInput, PwmOutput};
+use unit::{TestKind, TestSummary, UnitError, UnitStatus};
 
 use crate::traits::*;
 
+pub mod ads7828;
+pub mod calibration;
+pub mod clock;
+pub mod conditioning;
+pub mod config;
+pub mod control;
+pub mod crash;
+pub mod datalog;
+pub mod dcir;
+pub mod hppc;
+pub mod limits;
 #[cfg(test)]
 mod mocks;
 pub mod msg_types;
+pub mod ocv;
+pub mod orbit;
+pub mod recovery;
+pub mod sampling;
+pub mod sensors;
+pub mod sequence;
+pub mod sim;
+pub mod soc;
 mod test;
 pub mod traits;
+pub mod unit;
 
 macro_rules! generate_firmware {
     ( $( ($field_name:ident ; $type_name:ident : $trait:path) ),+ ;
-      $( ( $obj_field_name:ident ; ( $($obj_type_name:ident : $obj_trait:path),+ ) ; $obj_type:ident ) ),+ ) => {
+      $( ( $obj_field_name:ident ; ( $($obj_type_name:ident : $obj_trait:path),+ ) ; [$obj_type:ident; $obj_count:ident] ) ),+ ) => {
 
-        pub struct Firmware<$( $type_name:  $trait, )+ $( $( $obj_type_name: $obj_trait, )+ )+> {
+        pub struct Firmware<$( $type_name:  $trait, )+ $( $( $obj_type_name: $obj_trait, )+ )+ $( const $obj_count: usize, )+> {
             $( pub $field_name: $type_name, )+
-            $( pub $obj_field_name: $obj_type<$( $obj_type_name, )+>, )+
+            /// longest time between two updates of the battery test units, in ms, part of the
+            /// configuration. The units are sampled with their own periods.
+            pub sample_period: u16,
+            /// writes the measurements of the battery test units to `log_storage`
+            pub log: MeasurementLog,
+            /// relates `monotonic` to the date of `rtc`
+            pub clock: Clock,
+            /// the tests the last reset interrupted
+            pub recovery: Recovery,
+            $( pub $obj_field_name: [$obj_type<$( $obj_type_name, )+>; $obj_count], )+
         }
 
-        impl <$( $type_name:  $trait, )+ $( $( $obj_type_name: $obj_trait, )+ )+> Firmware<$( $type_name, )+ $($($obj_type_name,)+)+> {
+        impl <$( $type_name:  $trait, )+ $( $( $obj_type_name: $obj_trait, )+ )+ $( const $obj_count: usize, )+> Firmware<$( $type_name, )+ $($($obj_type_name,)+)+ $( $obj_count, )+> {
             fn setup(&mut self) {
                 self.on_board_led.set_output(false);
             }
@@ -30,91 +78,1466 @@ macro_rules! generate_firmware {
                 self.on_board_led.toggle();
             }
 
+            /// Handles the commands the client sent
             pub fn update_serial(&mut self) {
-                self.serial_receiver.receive(|val| match val {
-                    MsgTypes::Ping(value) => {
+                while let Some(val) = self.serial_receiver.receive() {
+                    self.handle_msg(val);
+                }
+            }
+
+            fn handle_msg(&mut self, val: Result<MsgTypes, InvalidPacket>) {
+                match val {
+                    Err(_) => {
+                        self.serial_transmitter.transmit(MsgTypes::Msg(String::from("Board dropped an invalid packet")));
+                    }
+                    Ok(MsgTypes::Ping(value)) => {
                         self.serial_transmitter.transmit(MsgTypes::Ping(value + 1));
                     }
-                    _ => {
-                        unimplemented!();
+                    Ok(MsgTypes::SaveConfig) => {
+                        match self.save_config() {
+                            Ok(sequence) => self.serial_transmitter.transmit(MsgTypes::ConfigSaved(sequence)),
+                            Err(error) => self.serial_transmitter.transmit(MsgTypes::ConfigError(error)),
+                        }
+                    }
+                    Ok(MsgTypes::SetSamplePeriod(period)) => {
+                        match self.set_sample_period(period) {
+                            Ok(()) => self.serial_transmitter.transmit(MsgTypes::Ack),
+                            Err(error) => self.serial_transmitter.transmit(MsgTypes::ConfigError(error)),
+                        }
+                    }
+                    Ok(MsgTypes::GetSamplePeriod) => {
+                        self.serial_transmitter.transmit(MsgTypes::SamplePeriod(self.sample_period));
+                    }
+                    Ok(MsgTypes::ListUnits) => {
+                        for btu in self.battery_units.iter() {
+                            self.serial_transmitter.transmit(MsgTypes::Status(btu.get_id(), btu.get_status()));
+                        }
+                        self.serial_transmitter.transmit(MsgTypes::UnitCount(N as u8));
+                    }
+                    Ok(MsgTypes::SetTime(unix_time)) => {
+                        match self.set_time(unix_time) {
+                            Ok(()) => self.serial_transmitter.transmit(MsgTypes::Ack),
+                            Err(error) => self.serial_transmitter.transmit(MsgTypes::ClockError(error)),
+                        }
+                    }
+                    Ok(MsgTypes::GetTime) => {
+                        let time = self.get_time();
+                        self.serial_transmitter.transmit(MsgTypes::Time(time));
+                    }
+                    Ok(MsgTypes::ResumeTest(unit)) => self.resume_test(unit),
+                    Ok(MsgTypes::StartLog(config)) => {
+                        match self.log.start(config) {
+                            Ok(()) => self.serial_transmitter.transmit(MsgTypes::Ack),
+                            Err(error) => self.serial_transmitter.transmit(MsgTypes::LogError(error)),
+                        }
+                    }
+                    Ok(MsgTypes::StopLog) => {
+                        self.log.stop();
+                        self.serial_transmitter.transmit(MsgTypes::Ack);
+                    }
+                    Ok(MsgTypes::ClearLog) => {
+                        match self.log.clear(&mut self.log_storage) {
+                            Ok(()) => self.serial_transmitter.transmit(MsgTypes::Ack),
+                            Err(error) => self.serial_transmitter.transmit(MsgTypes::LogError(error)),
+                        }
+                    }
+                    Ok(MsgTypes::GetLogStatus) => {
+                        self.serial_transmitter.transmit(MsgTypes::LogStatus(self.log.get_status(&self.log_storage)));
+                    }
+                    Ok(MsgTypes::GetLog(from)) => self.log.start_download(from),
+                    Ok(MsgTypes::SetMode(unit, mode)) => {
+                        match self.battery_units.get_mut(unit as usize) {
+                            Some(btu) => match btu.select_mode(mode) {
+                                Ok(()) => self.serial_transmitter.transmit(MsgTypes::Ack),
+                                Err(error) => self.serial_transmitter.transmit(MsgTypes::UnitError(unit, error)),
+                            },
+                            None => self.serial_transmitter.transmit(unknown_battery_unit(unit)),
+                        }
+                    }
+                    Ok(MsgTypes::GetMode(unit)) => {
+                        match self.battery_units.get_mut(unit as usize) {
+                            Some(btu) => self.serial_transmitter.transmit(MsgTypes::Mode(unit, btu.get_mode())),
+                            None => self.serial_transmitter.transmit(unknown_battery_unit(unit)),
+                        }
+                    }
+                    Ok(MsgTypes::PauseTest(unit)) => {
+                        match self.battery_units.get_mut(unit as usize) {
+                            Some(btu) => match btu.pause_test() {
+                                Ok(()) => self.serial_transmitter.transmit(MsgTypes::Ack),
+                                Err(error) => self.serial_transmitter.transmit(MsgTypes::UnitError(unit, error)),
+                            },
+                            None => self.serial_transmitter.transmit(unknown_battery_unit(unit)),
+                        }
+                    }
+                    Ok(MsgTypes::AbortTest(unit)) => {
+                        match self.battery_units.get_mut(unit as usize) {
+                            Some(btu) => {
+                                let serial_transmitter = &mut self.serial_transmitter;
+                                if let Err(error) = btu.abort_test(|msg| serial_transmitter.transmit(msg)) {
+                                    serial_transmitter.transmit(MsgTypes::UnitError(unit, error));
+                                }
+                            }
+                            None => self.serial_transmitter.transmit(unknown_battery_unit(unit)),
+                        }
+                    }
+                    Ok(MsgTypes::GetStatus(unit)) => {
+                        match self.battery_units.get_mut(unit as usize) {
+                            Some(btu) => self.serial_transmitter.transmit(MsgTypes::Status(unit, btu.get_status())),
+                            None => self.serial_transmitter.transmit(unknown_battery_unit(unit)),
+                        }
+                    }
+                    Ok(MsgTypes::SetUnitName(unit, name)) => {
+                        match self.battery_units.get_mut(unit as usize) {
+                            Some(btu) => {
+                                btu.set_name(name);
+                                self.serial_transmitter.transmit(MsgTypes::Ack);
+                            }
+                            None => self.serial_transmitter.transmit(unknown_battery_unit(unit)),
+                        }
+                    }
+                    Ok(MsgTypes::GetUnitName(unit)) => {
+                        match self.battery_units.get_mut(unit as usize) {
+                            Some(btu) => self.serial_transmitter.transmit(MsgTypes::UnitName(unit, btu.get_name().clone())),
+                            None => self.serial_transmitter.transmit(unknown_battery_unit(unit)),
+                        }
+                    }
+                    Ok(MsgTypes::SetSampling(unit, config)) => {
+                        match self.battery_units.get_mut(unit as usize) {
+                            Some(btu) => match btu.set_sampling(config) {
+                                Ok(()) => self.serial_transmitter.transmit(MsgTypes::Ack),
+                                Err(error) => self.serial_transmitter.transmit(MsgTypes::SamplingError(unit, error)),
+                            },
+                            None => self.serial_transmitter.transmit(unknown_battery_unit(unit)),
+                        }
+                    }
+                    Ok(MsgTypes::GetSampling(unit)) => {
+                        match self.battery_units.get_mut(unit as usize) {
+                            Some(btu) => self.serial_transmitter.transmit(MsgTypes::Sampling(unit, *btu.get_sampling())),
+                            None => self.serial_transmitter.transmit(unknown_battery_unit(unit)),
+                        }
+                    }
+                    Ok(MsgTypes::SetConditioning(unit, channel, config)) => {
+                        match self.battery_units.get_mut(unit as usize) {
+                            Some(btu) => match btu.set_conditioning(channel, config) {
+                                Ok(()) => self.serial_transmitter.transmit(MsgTypes::Ack),
+                                Err(error) => self.serial_transmitter.transmit(MsgTypes::ConditioningError(unit, error)),
+                            },
+                            None => self.serial_transmitter.transmit(unknown_battery_unit(unit)),
+                        }
+                    }
+                    Ok(MsgTypes::GetConditioning(unit)) => {
+                        match self.battery_units.get_mut(unit as usize) {
+                            Some(btu) => self.serial_transmitter.transmit(MsgTypes::Conditioning(unit, btu.get_conditioning())),
+                            None => self.serial_transmitter.transmit(unknown_battery_unit(unit)),
+                        }
+                    }
+                    Ok(MsgTypes::SetLimits(unit, limits)) => {
+                        match self.battery_units.get_mut(unit as usize) {
+                            Some(btu) => {
+                                btu.set_limits(limits);
+                                self.serial_transmitter.transmit(MsgTypes::Ack);
+                            }
+                            None => self.serial_transmitter.transmit(unknown_battery_unit(unit)),
+                        }
+                    }
+                    Ok(MsgTypes::GetLimits(unit)) => {
+                        match self.battery_units.get_mut(unit as usize) {
+                            Some(btu) => self.serial_transmitter.transmit(MsgTypes::Limits(unit, btu.get_limits())),
+                            None => self.serial_transmitter.transmit(unknown_battery_unit(unit)),
+                        }
+                    }
+                    Ok(MsgTypes::ClearFault(unit)) => {
+                        match self.battery_units.get_mut(unit as usize) {
+                            Some(btu) => {
+                                btu.clear_fault();
+                                self.serial_transmitter.transmit(MsgTypes::Ack);
+                            }
+                            None => self.serial_transmitter.transmit(unknown_battery_unit(unit)),
+                        }
+                    }
+                    Ok(MsgTypes::SetProgramStep(unit, index, step)) => {
+                        match self.battery_units.get_mut(unit as usize) {
+                            Some(btu) => match btu.set_program_step(index, step) {
+                                Ok(()) => self.serial_transmitter.transmit(MsgTypes::Ack),
+                                Err(error) => self.serial_transmitter.transmit(MsgTypes::ProgramError(unit, error)),
+                            },
+                            None => self.serial_transmitter.transmit(unknown_battery_unit(unit)),
+                        }
+                    }
+                    Ok(MsgTypes::ClearProgram(unit)) => {
+                        match self.battery_units.get_mut(unit as usize) {
+                            Some(btu) => match btu.clear_program() {
+                                Ok(()) => self.serial_transmitter.transmit(MsgTypes::Ack),
+                                Err(error) => self.serial_transmitter.transmit(MsgTypes::ProgramError(unit, error)),
+                            },
+                            None => self.serial_transmitter.transmit(unknown_battery_unit(unit)),
+                        }
+                    }
+                    Ok(MsgTypes::StartProgram(unit)) => {
+                        match self.battery_units.get_mut(unit as usize) {
+                            Some(btu) => match btu.start_program() {
+                                Ok(transition) => self.serial_transmitter.transmit(MsgTypes::StepTransition(unit, transition)),
+                                Err(error) => self.serial_transmitter.transmit(MsgTypes::ProgramError(unit, error)),
+                            },
+                            None => self.serial_transmitter.transmit(unknown_battery_unit(unit)),
+                        }
+                    }
+                    Ok(MsgTypes::StopProgram(unit)) => {
+                        match self.battery_units.get_mut(unit as usize) {
+                            Some(btu) => match btu.stop_program() {
+                                Some(transition) => self.serial_transmitter.transmit(MsgTypes::StepTransition(unit, transition)),
+                                None => self.serial_transmitter.transmit(MsgTypes::Ack),
+                            },
+                            None => self.serial_transmitter.transmit(unknown_battery_unit(unit)),
+                        }
+                    }
+                    Ok(MsgTypes::StartPulseTest(unit, config)) => {
+                        match self.battery_units.get_mut(unit as usize) {
+                            Some(btu) => match btu.start_pulse_test(config) {
+                                Ok(()) => self.serial_transmitter.transmit(MsgTypes::Ack),
+                                Err(error) => self.serial_transmitter.transmit(MsgTypes::DcirError(unit, error)),
+                            },
+                            None => self.serial_transmitter.transmit(unknown_battery_unit(unit)),
+                        }
+                    }
+                    Ok(MsgTypes::SetDcirSchedule(unit, schedule)) => {
+                        match self.battery_units.get_mut(unit as usize) {
+                            Some(btu) => match btu.set_dcir_schedule(schedule) {
+                                Ok(()) => self.serial_transmitter.transmit(MsgTypes::Ack),
+                                Err(error) => self.serial_transmitter.transmit(MsgTypes::DcirError(unit, error)),
+                            },
+                            None => self.serial_transmitter.transmit(unknown_battery_unit(unit)),
+                        }
+                    }
+                    Ok(MsgTypes::StartHppc(unit, config)) => {
+                        match self.battery_units.get_mut(unit as usize) {
+                            Some(btu) => match btu.start_hppc(config) {
+                                Ok(()) => self.serial_transmitter.transmit(MsgTypes::Ack),
+                                Err(error) => self.serial_transmitter.transmit(MsgTypes::HppcError(unit, error)),
+                            },
+                            None => self.serial_transmitter.transmit(unknown_battery_unit(unit)),
+                        }
+                    }
+                    Ok(MsgTypes::StopHppc(unit)) => {
+                        match self.battery_units.get_mut(unit as usize) {
+                            Some(btu) => match btu.stop_hppc() {
+                                Some(end) => self.serial_transmitter.transmit(MsgTypes::HppcFinished(unit, end)),
+                                None => self.serial_transmitter.transmit(MsgTypes::Ack),
+                            },
+                            None => self.serial_transmitter.transmit(unknown_battery_unit(unit)),
+                        }
+                    }
+                    Ok(MsgTypes::StartOcv(unit, config)) => {
+                        match self.battery_units.get_mut(unit as usize) {
+                            Some(btu) => match btu.start_ocv(config) {
+                                Ok(()) => self.serial_transmitter.transmit(MsgTypes::Ack),
+                                Err(error) => self.serial_transmitter.transmit(MsgTypes::OcvError(unit, error)),
+                            },
+                            None => self.serial_transmitter.transmit(unknown_battery_unit(unit)),
+                        }
+                    }
+                    Ok(MsgTypes::StopOcv(unit)) => {
+                        match self.battery_units.get_mut(unit as usize) {
+                            Some(btu) => match btu.stop_ocv() {
+                                Some(end) => self.serial_transmitter.transmit(MsgTypes::OcvFinished(unit, end)),
+                                None => self.serial_transmitter.transmit(MsgTypes::Ack),
+                            },
+                            None => self.serial_transmitter.transmit(unknown_battery_unit(unit)),
+                        }
+                    }
+                    Ok(MsgTypes::GetOcvTable(unit)) => {
+                        match self.battery_units.get_mut(unit as usize) {
+                            Some(btu) => btu.download_ocv_table(),
+                            None => self.serial_transmitter.transmit(unknown_battery_unit(unit)),
+                        }
+                    }
+                    Ok(MsgTypes::SetSocEstimator(unit, config)) => {
+                        match self.battery_units.get_mut(unit as usize) {
+                            Some(btu) => match btu.set_soc_estimator(config) {
+                                Ok(()) => self.serial_transmitter.transmit(MsgTypes::Ack),
+                                Err(error) => self.serial_transmitter.transmit(MsgTypes::SocEstimatorError(unit, error)),
+                            },
+                            None => self.serial_transmitter.transmit(unknown_battery_unit(unit)),
+                        }
+                    }
+                    Ok(MsgTypes::StartCalibration(unit, channel, degree)) => {
+                        match self.battery_units.get_mut(unit as usize) {
+                            Some(btu) => match btu.start_calibration(channel, degree) {
+                                Ok(()) => self.serial_transmitter.transmit(MsgTypes::Ack),
+                                Err(error) => self.serial_transmitter.transmit(MsgTypes::CalibrationError(unit, error)),
+                            },
+                            None => self.serial_transmitter.transmit(unknown_battery_unit(unit)),
+                        }
                     }
-                });
+                    Ok(MsgTypes::AddCalibrationPoint(unit, reference)) => {
+                        match self.battery_units.get_mut(unit as usize) {
+                            Some(btu) => match btu.add_calibration_point(reference) {
+                                Ok((index, point)) => self.serial_transmitter.transmit(MsgTypes::CalibrationPoint(unit, index, point)),
+                                Err(error) => self.serial_transmitter.transmit(MsgTypes::CalibrationError(unit, error)),
+                            },
+                            None => self.serial_transmitter.transmit(unknown_battery_unit(unit)),
+                        }
+                    }
+                    Ok(MsgTypes::FinishCalibration(unit)) => {
+                        match self.battery_units.get_mut(unit as usize) {
+                            Some(btu) => match btu.finish_calibration() {
+                                Ok(calibration) => self.serial_transmitter.transmit(MsgTypes::Calibration(unit, calibration)),
+                                Err(error) => self.serial_transmitter.transmit(MsgTypes::CalibrationError(unit, error)),
+                            },
+                            None => self.serial_transmitter.transmit(unknown_battery_unit(unit)),
+                        }
+                    }
+                    Ok(MsgTypes::CancelCalibration(unit)) => {
+                        match self.battery_units.get_mut(unit as usize) {
+                            Some(btu) => {
+                                btu.cancel_calibration();
+                                self.serial_transmitter.transmit(MsgTypes::Ack);
+                            }
+                            None => self.serial_transmitter.transmit(unknown_battery_unit(unit)),
+                        }
+                    }
+                    Ok(MsgTypes::SetCalibration(unit, calibration)) => {
+                        match self.battery_units.get_mut(unit as usize) {
+                            Some(btu) => match btu.set_calibration(calibration) {
+                                Ok(()) => self.serial_transmitter.transmit(MsgTypes::Ack),
+                                Err(error) => self.serial_transmitter.transmit(MsgTypes::CalibrationError(unit, error)),
+                            },
+                            None => self.serial_transmitter.transmit(unknown_battery_unit(unit)),
+                        }
+                    }
+                    Ok(MsgTypes::GetCalibration(unit)) => {
+                        match self.battery_units.get_mut(unit as usize) {
+                            Some(btu) => self.serial_transmitter.transmit(MsgTypes::Calibration(unit, *btu.get_calibration())),
+                            None => self.serial_transmitter.transmit(unknown_battery_unit(unit)),
+                        }
+                    }
+                    Ok(MsgTypes::SetOrbitSegment(unit, index, segment)) => {
+                        match self.battery_units.get_mut(unit as usize) {
+                            Some(btu) => match btu.set_orbit_segment(index, segment) {
+                                Ok(()) => self.serial_transmitter.transmit(MsgTypes::Ack),
+                                Err(error) => self.serial_transmitter.transmit(MsgTypes::OrbitError(unit, error)),
+                            },
+                            None => self.serial_transmitter.transmit(unknown_battery_unit(unit)),
+                        }
+                    }
+                    Ok(MsgTypes::ClearOrbitProfile(unit)) => {
+                        match self.battery_units.get_mut(unit as usize) {
+                            Some(btu) => match btu.clear_orbit_profile() {
+                                Ok(()) => self.serial_transmitter.transmit(MsgTypes::Ack),
+                                Err(error) => self.serial_transmitter.transmit(MsgTypes::OrbitError(unit, error)),
+                            },
+                            None => self.serial_transmitter.transmit(unknown_battery_unit(unit)),
+                        }
+                    }
+                    Ok(MsgTypes::StartOrbits(unit, orbits)) => {
+                        match self.battery_units.get_mut(unit as usize) {
+                            Some(btu) => match btu.start_orbits(orbits) {
+                                Ok(()) => self.serial_transmitter.transmit(MsgTypes::Ack),
+                                Err(error) => self.serial_transmitter.transmit(MsgTypes::OrbitError(unit, error)),
+                            },
+                            None => self.serial_transmitter.transmit(unknown_battery_unit(unit)),
+                        }
+                    }
+                    Ok(MsgTypes::StopOrbits(unit)) => {
+                        match self.battery_units.get_mut(unit as usize) {
+                            Some(btu) => {
+                                btu.stop_orbits();
+                                self.serial_transmitter.transmit(MsgTypes::Ack);
+                            }
+                            None => self.serial_transmitter.transmit(unknown_battery_unit(unit)),
+                        }
+                    }
+                    _ => self.serial_transmitter.transmit(MsgTypes::Msg(String::from("Unsupported message"))),
+                }
+            }
+
+            pub fn set_sample_period(&mut self, period: u16) -> Result<(), ConfigError> {
+                config::check_sample_period(period)?;
+                self.sample_period = period;
+                Ok(())
+            }
+
+            /// Applies the stored configuration, the battery test units keep their settings if
+            /// none was stored yet. Meant to be called once after the start, when the units are
+            /// still idle.
+            pub fn load_config(&mut self) -> Result<(), ConfigError> {
+                let config = config::load(&mut self.flash)?;
+                self.set_sample_period(config.sample_period)?;
+                for (btu, unit) in self.battery_units.iter_mut().zip(config.units) {
+                    btu.set_name(unit.name);
+                    btu.set_limits(unit.limits);
+                    btu.set_calibration(unit.calibration).map_err(|_| ConfigError::Invalid)?;
+                }
+                Ok(())
+            }
+
+            /// Stores the names, limits and calibrations of the battery test units together with
+            /// the sample period, returns the sequence number of the record
+            pub fn save_config(&mut self) -> Result<u32, ConfigError> {
+                let config = Config::new(self.sample_period, self.battery_units.iter().map(|btu| btu.get_config()));
+                config::save(&mut self.flash, &config)
+            }
+
+            /// Takes the date of the real time clock for the timestamps, once at the start
+            pub fn load_time(&mut self) {
+                let datetime = self.rtc.get_datetime();
+                self.clock.set_time(clock::to_unix_time(datetime), self.monotonic.get_micros());
+            }
+
+            /// Sets the real time clock, `unix_time` in ms
+            pub fn set_time(&mut self, unix_time: u64) -> Result<(), ClockError> {
+                let datetime = clock::to_datetime(unix_time)?;
+                self.rtc.set_datetime(datetime).map_err(|_| ClockError::Rtc)?;
+                self.clock.set_time(unix_time, self.monotonic.get_micros());
+                Ok(())
+            }
+
+            /// Unix time in ms
+            pub fn get_time(&mut self) -> u64 {
+                self.clock.get_time(self.monotonic.get_micros())
+            }
+
+            /// Updates the battery test units with the time that passed on the monotonic clock
+            /// since the last call
+            pub fn update(&mut self) {
+                let now = self.monotonic.get_micros();
+                let delta_time = self.clock.update(now);
+                self.update_battery_units(self.clock.get_time(now), delta_time as f32 / 1e6);
+            }
+
+            /// `timestamp` is the Unix time in ms, `delta_time` the time since the last update in s
+            pub fn update_battery_units(&mut self, timestamp: u64, delta_time: f32) {
+                let serial_transmitter = &mut self.serial_transmitter;
+                for btu in self.battery_units.iter_mut() {
+                    btu.update(timestamp, delta_time, |msg| serial_transmitter.transmit(msg));
+                }
+                self.update_log(timestamp, delta_time);
+                recovery::save(&mut self.retained, self.battery_units.iter().map(|btu| btu.get_run()));
+            }
+
+            /// What the units do, the board keeps it for the crash report after every update
+            pub fn get_unit_states(&self) -> Vec<UnitState, MAX_RUN_UNITS> {
+                self.battery_units
+                    .iter()
+                    .take(MAX_RUN_UNITS)
+                    .map(|btu| UnitState { mode: btu.current_mode, test: btu.get_test_kind() })
+                    .collect()
             }
 
-            pub fn update_battery_units(&mut self, time: f32, delta_time: f32) {
-                self.btu1.update(time, delta_time);
+            /// Reports the panic before the last reset, once at the start before
+            /// `check_interrupted_tests`
+            pub fn check_crash_report(&mut self, reset_cause: ResetCause) {
+                if let Some(report) = crash::take(&mut self.crash_storage) {
+                    self.serial_transmitter.transmit(MsgTypes::CrashReport(report, reset_cause));
+                }
+            }
+
+            /// Reports the tests the reset interrupted, once at the start before the first
+            /// update. The units start idle with their loads off.
+            pub fn check_interrupted_tests(&mut self) {
+                if self.watchdog.caused_reset() {
+                    self.serial_transmitter.transmit(MsgTypes::WatchdogReset);
+                }
+                let Some(runs) = recovery::load(&mut self.retained) else {
+                    return;
+                };
+                for (unit, run) in runs.iter().enumerate() {
+                    if let Some(run) = run {
+                        self.serial_transmitter.transmit(MsgTypes::TestInterrupted(unit as u8, *run));
+                    }
+                }
+                self.recovery.set_interrupted(runs);
+            }
+
+            /// Continues the paused test of the unit or the one the reset interrupted, sends `Ack`
+            /// or the error to the client
+            pub fn resume_test(&mut self, unit: u8) {
+                let msg = match self.battery_units.get_mut(unit as usize) {
+                    Some(btu) if btu.is_paused() => match btu.resume_paused() {
+                        Ok(()) => MsgTypes::Ack,
+                        Err(error) => MsgTypes::UnitError(unit, error),
+                    },
+                    Some(btu) if btu.is_active() => MsgTypes::RecoveryError(unit, RecoveryError::Busy),
+                    Some(btu) => match self.recovery.take_interrupted(unit) {
+                        Ok(run) => match btu.resume(&run) {
+                            Ok(()) => MsgTypes::Ack,
+                            Err(error) => MsgTypes::UnitError(unit, error),
+                        },
+                        Err(error) => MsgTypes::RecoveryError(unit, error),
+                    },
+                    None => unknown_battery_unit(unit),
+                };
+                self.serial_transmitter.transmit(msg);
+            }
+
+            /// Feeds the watchdog as long as the battery test units are updated in time, called
+            /// more often than the update
+            pub fn update_watchdog(&mut self) {
+                let since_update = self.clock.get_time_since_update(self.monotonic.get_micros()) / 1000;
+                if since_update <= self.sample_period as u64 + WATCHDOG_TOLERANCE {
+                    self.watchdog.feed();
+                }
+            }
+
+            /// Time until the next battery test unit has to be sampled, in ms, at most the sample
+            /// period
+            pub fn get_update_period(&self) -> u16 {
+                let next_sample = self.battery_units.iter().map(|btu| btu.get_time_to_sample()).min();
+                next_sample.map_or(self.sample_period, |time| time.clamp(1, self.sample_period as u32) as u16)
+            }
+
+            fn update_log(&mut self, timestamp: u64, delta_time: f32) {
+                if self.log.is_due(delta_time) {
+                    for btu in self.battery_units.iter() {
+                        let Some(record) = btu.get_log_record(timestamp) else {
+                            continue;
+                        };
+                        if let Err(error) = self.log.append(&mut self.log_storage, record) {
+                            self.serial_transmitter.transmit(MsgTypes::LogError(error));
+                            break;
+                        }
+                    }
+                }
+
+                // the download doesn't fit into the transmit buffer at once, a few records per
+                // update
+                for _ in 0..LOG_DOWNLOAD_BATCH {
+                    match self.log.next_download(&mut self.log_storage) {
+                        Some(Ok(LogDownload::Record(record))) => {
+                            self.serial_transmitter.transmit(MsgTypes::LogRecord(record));
+                        }
+                        Some(Ok(LogDownload::End(next))) => {
+                            self.serial_transmitter.transmit(MsgTypes::LogEnd(next));
+                            break;
+                        }
+                        Some(Err(error)) => {
+                            self.serial_transmitter.transmit(MsgTypes::LogError(error));
+                            break;
+                        }
+                        None => break,
+                    }
+                }
             }
         }
     };
 }
 
+/// Records of the measurement log sent per update while it is downloaded
+const LOG_DOWNLOAD_BATCH: usize = 8;
+
+fn unknown_battery_unit(unit: u8) -> MsgTypes {
+    MsgTypes::UnitError(unit, UnitError::UnknownUnit)
+}
+
 generate_firmware!(
    (serial_receiver; TSerialRx: SerialReceiver),
    (serial_transmitter; TSerialTx: SerialTransmitter),
-   (on_board_led; TLed: GpioOutput);
-   (btu1; (TAdcInput1: AdcInput, TPwmOutput1: PwmOutput); BatteryTestUnit)
+   (on_board_led; TLed: GpioOutput),
+   (flash; TFlash: Flash),
+   (log_storage; TLogStorage: BlockStorage),
+   (monotonic; TMonotonic: SystemTime),
+   (rtc; TRtc: RealTimeClock),
+   (watchdog; TWatchdog: Watchdog),
+   (retained; TRetained: RetainedMemory),
+   (crash_storage; TCrashStorage: RetainedMemory);
+   (battery_units; (TAdcInput: AdcInput, TCurrentInput: CurrentInput, TTemperatureInput: TemperatureInput, TPwmOutput: PwmOutput, TChargerEnable: GpioOutput, TChargeDone: GpioInput); [BatteryTestUnit; N])
 );
 
-#[derive(Debug, Copy, Clone, PartialEq)]
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq)]
 pub enum BatteryTestUnitMode {
     Idle,
+    /// The charger is enabled and charges on its own
     Charging,
     Discharging(f32),
+    /// Discharging with the given current, in A
+    DischargingConstantCurrent(f32),
+    /// Discharging with the given power, in W
+    DischargingConstantPower(f32),
+    /// Discharging like the given resistance, in Ω
+    DischargingConstantResistance(f32),
+    /// Load and charger are off like in idle, but the unit is part of a running test
+    Resting,
+    /// Load and charger are off and the test holds its counters and program step until it's
+    /// resumed, the limits aren't checked meanwhile
+    Paused,
+    /// Running a current pulse to measure the internal resistance, the load follows the
+    /// `PulseTest` of the unit
+    DcirPulse,
+    /// Latched after a safety limit was violated, only `clear_fault` leaves this mode
+    Fault(FaultCause),
+}
+
+impl BatteryTestUnitMode {
+    /// Whether the unit runs a test in this mode, i.e. is neither idle nor in the fault state
+    pub fn is_active(&self) -> bool {
+        !matches!(
+            self,
+            BatteryTestUnitMode::Idle | BatteryTestUnitMode::Fault(_)
+        )
+    }
+
+    /// The discharge current the load is controlled to at the given battery voltage, `None` in
+    /// modes without a load controller. No current flows at a voltage that isn't positive.
+    pub fn get_load_current(&self, voltage: f32) -> Option<f32> {
+        let current = match *self {
+            BatteryTestUnitMode::DischargingConstantCurrent(current) => current,
+            BatteryTestUnitMode::DischargingConstantPower(power) => power / voltage,
+            BatteryTestUnitMode::DischargingConstantResistance(resistance) => voltage / resistance,
+            _ => return None,
+        };
+
+        if voltage > 0.0 && current.is_finite() {
+            Some(current.max(0.0))
+        } else {
+            Some(0.0)
+        }
+    }
 }
 
 macro_rules! generate_battery_test_unit {
     ( $( ($field_name:ident ; $type_name:ident : $trait:path) ),+ ) => {
 
         pub struct BatteryTestUnit<$( $type_name: $trait, )+> {
+            /// index of the unit, every message concerning the unit is tagged with it
+            id: u8,
+            /// given by the user, e.g. to tell which cell is connected
+            name: String<16>,
             current_mode: BatteryTestUnitMode,
+            /// the mode to continue with while the test is paused
+            paused: Option<BatteryTestUnitMode>,
+            limits: SafetyLimits,
+            /// time since the unit left idle, in s
+            duration: f32,
+            /// charge moved since the unit left idle, in Ah
+            capacity: f32,
+            /// energy moved since the unit left idle, in Wh
+            energy: f32,
+            /// decides which updates sample the unit and send telemetry
+            sampling: SampleScheduler,
+            /// sets the load duty cycle in the modes with a setpoint
+            load_controller: PiController,
+            sequencer: Sequencer,
+            orbit_player: OrbitPlayer,
+            /// the running pulse and the mode to go back to afterwards
+            pulse_test: Option<(PulseTest, BatteryTestUnitMode)>,
+            dcir_scheduler: DcirScheduler,
+            hppc: HppcProcedure,
+            ocv: OcvProcedure,
+            /// index of the next point of the OCV table to send to the client
+            ocv_download: Option<u8>,
+            soc_estimator: Option<SocEstimator>,
+            /// corrects the raw readings of the voltage, current and temperature channels
+            calibration: CalibrationSet,
+            /// collects reference points while a channel is calibrated
+            calibration_run: Option<CalibrationRun>,
+            /// oversamples and filters the channels before the calibration
+            conditioner: Conditioner,
+            /// measured in the latest update, written to the measurement log
+            last_measurement: Option<Measurement>,
             $( pub $field_name: $type_name, )+
         }
 
         impl<$( $type_name: $trait, )+> BatteryTestUnit<$( $type_name, )+> {
-            pub fn new( $( $field_name: $type_name, )+ ) -> Self {
+            pub fn new(id: u8, $( $field_name: $type_name, )+ ) -> Self {
                 let mut res = Self {
+                    id,
+                    name: String::new(),
                     current_mode: BatteryTestUnitMode::Idle,
+                    paused: None,
+                    limits: SafetyLimits::default(),
+                    duration: 0.0,
+                    capacity: 0.0,
+                    energy: 0.0,
+                    sampling: SampleScheduler::new(),
+                    load_controller: PiController::new(LOAD_CONTROLLER_KP, LOAD_CONTROLLER_KI),
+                    sequencer: Sequencer::new(),
+                    orbit_player: OrbitPlayer::new(),
+                    pulse_test: None,
+                    dcir_scheduler: DcirScheduler::new(),
+                    hppc: HppcProcedure::new(),
+                    ocv: OcvProcedure::new(),
+                    ocv_download: None,
+                    soc_estimator: None,
+                    calibration: CalibrationSet::default(),
+                    calibration_run: None,
+                    conditioner: Conditioner::new(),
+                    last_measurement: None,
                     $( $field_name, )+
                 };
                 res.set_mode(BatteryTestUnitMode::Idle);
                 res
             }
 
-            pub fn update(&mut self, time: f32, delta_time: f32) {
+            /// `timestamp` is the Unix time in ms and `delta_time` the time since the last call in s.
+            /// `transmit` is called for every message the unit wants to send to the client.
+            ///
+            /// The unit is only sampled once its sample period passed.
+            pub fn update(&mut self, timestamp: u64, delta_time: f32, mut transmit: impl FnMut(MsgTypes)) {
+                // the µs of the monotonic clock are kept until the next sample
+                let Some(interval) = self.sampling.update(libm::roundf(delta_time * 1e6) as u32) else {
+                    // the limits are checked at every update, between the samples with the
+                    // unfiltered readings
+                    let measurement = Measurement {
+                        voltage: self.get_voltage(),
+                        current: self.get_current(),
+                        temperature: Some(self.get_temperature()),
+                        duration: self.duration,
+                        capacity: self.capacity,
+                    };
+                    self.check_limits(&measurement, &mut transmit);
+                    return;
+                };
+                let delta_time = interval as f32 / 1e6;
+
+                let measurement = self.measure(delta_time);
+                self.last_measurement = Some(measurement);
+                let send_telemetry = self.sampling.add_sample(measurement.voltage, interval);
+                let step_measurement = self.step_measurement(&measurement);
+
+                if let Some(estimator) = &mut self.soc_estimator {
+                    if self.current_mode == BatteryTestUnitMode::Charging && step_measurement.charge_done {
+                        estimator.set_state_of_charge(1.0);
+                    } else {
+                        estimator.update(measurement.voltage, measurement.current, delta_time);
+                    }
+                }
+
+                self.check_limits(&measurement, &mut transmit);
+
+                if send_telemetry {
+                    transmit(MsgTypes::Telemetry(self.id, Telemetry {
+                        timestamp,
+                        mode: self.current_mode,
+                        step: self.sequencer.get_step(),
+                        capacity: self.capacity,
+                        energy: self.energy,
+                        voltage: measurement.voltage,
+                        current: measurement.current,
+                        temperature: measurement.temperature,
+                        state_of_charge: self.get_state_of_charge(),
+                        remaining_time: self
+                            .soc_estimator
+                            .as_ref()
+                            .and_then(|estimator| estimator.get_remaining_time(measurement.current)),
+                        calibration: self.calibration.version,
+                        stats: self.conditioner.take_stats(),
+                    }));
+                }
+
+                if let Some(transition) = self.sequencer.update(&step_measurement, delta_time) {
+                    self.set_mode(self.sequencer.get_mode());
+                    transmit(MsgTypes::StepTransition(self.id, transition));
+                }
+
+                let orbit_update = self.orbit_player.update(&step_measurement, delta_time);
+                if let Some(stats) = orbit_update.stats {
+                    transmit(MsgTypes::OrbitStats(self.id, stats));
+                }
+                if orbit_update.segment_changed {
+                    self.set_mode(self.orbit_player.get_mode());
+                }
+
+                let hppc_update = self.hppc.update(&step_measurement, delta_time);
+                if let Some(data) = hppc_update.data {
+                    transmit(MsgTypes::HppcData(self.id, data));
+                }
+                if let Some(end) = hppc_update.end {
+                    transmit(MsgTypes::HppcFinished(self.id, end));
+                }
+                if hppc_update.phase_changed {
+                    self.set_mode(self.hppc.get_mode());
+                }
+
+                let ocv_update = self.ocv.update(&step_measurement, delta_time);
+                if let Some((index, point)) = ocv_update.point {
+                    transmit(MsgTypes::OcvPoint(self.id, index, point));
+                }
+                if let Some(end) = ocv_update.end {
+                    transmit(MsgTypes::OcvFinished(self.id, end));
+                }
+                if ocv_update.phase_changed {
+                    self.set_mode(self.ocv.get_mode());
+                }
+
+                // the table doesn't fit into the transmit buffer at once, one point per update
+                if let Some(index) = self.ocv_download {
+                    match self.ocv.get_table().get(index as usize) {
+                        Some(point) => {
+                            transmit(MsgTypes::OcvPoint(self.id, index, *point));
+                            self.ocv_download = Some(index + 1);
+                        }
+                        None => {
+                            transmit(MsgTypes::OcvTableEnd(self.id, index));
+                            self.ocv_download = None;
+                        }
+                    }
+                }
+
+                self.update_dcir_schedule(&measurement, delta_time);
+
                 match self.current_mode {
                     BatteryTestUnitMode::Idle => {}
+                    BatteryTestUnitMode::Fault(_) => {}
                     BatteryTestUnitMode::Charging => {}
-                    BatteryTestUnitMode::Discharging(target_voltage) => {
+                    BatteryTestUnitMode::Resting => {}
+                    BatteryTestUnitMode::Paused => {}
+                    BatteryTestUnitMode::Discharging(_) => {
+                        // wraps every 2000 s to stay precise as f32
+                        let time = (timestamp % 2_000_000) as f32 / 1000.0;
+                        // not π, the duty cycles of the test pattern are truncated from this
+                        #[allow(clippy::approx_constant)]
                         let output = libm::sinf(time * 3.1415) * 0.5 + 0.5;
                         let output = output * self.load_pwm.get_max_duty_cycle() as f32;
                         self.load_pwm.set_duty_cycle(output as u16);
                     }
+                    BatteryTestUnitMode::DischargingConstantCurrent(_)
+                    | BatteryTestUnitMode::DischargingConstantPower(_)
+                    | BatteryTestUnitMode::DischargingConstantResistance(_) => {
+                        // constant power and resistance are both a current that follows the voltage
+                        let current = self.current_mode.get_load_current(measurement.voltage).unwrap_or(0.0);
+                        let output = self.load_controller.update(current, measurement.current, delta_time);
+                        self.set_load(output);
+                    }
+                    BatteryTestUnitMode::DcirPulse => {
+                        let Some((pulse_test, return_mode)) = self.pulse_test.as_mut() else {
+                            return;
+                        };
+
+                        match pulse_test.update(&step_measurement, delta_time) {
+                            Some(result) => {
+                                transmit(MsgTypes::DcirResult(self.id, result));
+                                let return_mode = *return_mode;
+                                self.set_mode(return_mode);
+                            }
+                            None => {
+                                let current = pulse_test.get_current();
+                                let output = self.load_controller.update(current, measurement.current, delta_time);
+                                self.set_load(output);
+                            }
+                        }
+                    }
                 }
             }
 
-            pub fn set_mode(&mut self, new_mode: BatteryTestUnitMode) {
-                match (self.current_mode, new_mode) {
-                    (_, BatteryTestUnitMode::Idle) => {
-                        let min = self.load_pwm.get_min_duty_cycle();
-                        self.load_pwm.set_duty_cycle(min);
-                    }
-                    (BatteryTestUnitMode::Idle, BatteryTestUnitMode::Discharging(_)) => {
-                        let min = self.load_pwm.get_min_duty_cycle();
-                        self.load_pwm.set_duty_cycle(min);
+            /// Latches the fault state if the measurement violates a limit, the program and the
+            /// procedures are stopped
+            fn check_limits(&mut self, measurement: &Measurement, transmit: &mut impl FnMut(MsgTypes)) {
+                // e.g. a thermistor is re-seated during a pause, `resume_paused` checks the limits
+                if !self.is_active() || self.is_paused() {
+                    return;
+                }
+                let Some(cause) = self.limits.check(measurement) else {
+                    return;
+                };
+
+                self.set_mode(BatteryTestUnitMode::Fault(cause));
+                transmit(MsgTypes::Fault(self.id, cause));
+                if let Some(transition) = self.sequencer.stop() {
+                    transmit(MsgTypes::StepTransition(self.id, transition));
+                }
+                self.orbit_player.stop();
+                if let Some(end) = self.hppc.stop() {
+                    transmit(MsgTypes::HppcFinished(self.id, end));
+                }
+                if let Some(end) = self.ocv.stop() {
+                    transmit(MsgTypes::OcvFinished(self.id, end));
+                }
+            }
+
+            /// Counts the discharged charge and interrupts a discharge with a pulse once the next
+            /// state of charge point of the schedule is reached
+            fn update_dcir_schedule(&mut self, measurement: &Measurement, delta_time: f32) {
+                match self.current_mode {
+                    BatteryTestUnitMode::Charging => self.dcir_scheduler.reset(),
+                    BatteryTestUnitMode::DcirPulse => {
+                        self.dcir_scheduler.add_discharged(measurement.current * delta_time / 3600.0);
                     }
-                    _ => {
-                        unimplemented!();
+                    mode @ (BatteryTestUnitMode::DischargingConstantCurrent(_)
+                    | BatteryTestUnitMode::DischargingConstantPower(_)
+                    | BatteryTestUnitMode::DischargingConstantResistance(_)) => {
+                        self.dcir_scheduler.add_discharged(measurement.current * delta_time / 3600.0);
+
+                        if let Some((pulse, state_of_charge)) = self.dcir_scheduler.take_due_pulse() {
+                            // the pulse starts from the current of the discharge
+                            let config = PulseConfig {
+                                base_current: mode.get_load_current(measurement.voltage).unwrap_or(0.0),
+                                ..pulse
+                            };
+                            self.set_mode(BatteryTestUnitMode::DcirPulse);
+                            self.pulse_test = Some((PulseTest::new(config, Some(state_of_charge)), mode));
+                        }
                     }
+                    _ => {}
+                }
+            }
+
+            /// Does nothing while the unit is in the fault state. Every mode starts with the load
+            /// off, the control loop of the mode takes over with the next update.
+            pub fn set_mode(&mut self, new_mode: BatteryTestUnitMode) {
+                if let BatteryTestUnitMode::Fault(_) = self.current_mode {
+                    return;
+                }
+
+                self.set_load(0.0);
+                self.load_controller.reset();
+                self.charger_enable.set_output(new_mode == BatteryTestUnitMode::Charging);
+                // any other mode interrupts a running pulse, and ends a pause
+                self.pulse_test = None;
+                self.paused = None;
+
+                // the duration and capacity limits apply to the whole test, not to a single mode
+                if !self.is_active() && new_mode.is_active() {
+                    self.duration = 0.0;
+                    self.capacity = 0.0;
+                    self.energy = 0.0;
+                }
+                // e.g. the start and the end of a pulse
+                if new_mode != self.current_mode {
+                    self.sampling.trigger();
                 }
                 self.current_mode = new_mode;
             }
 
+            /// Sets the mode on its own, e.g. a constant current discharge that runs until the
+            /// client sets idle or a limit is reached. Not possible while a program or procedure
+            /// sets the modes.
+            pub fn select_mode(&mut self, mode: BatteryTestUnitMode) -> Result<(), UnitError> {
+                if let BatteryTestUnitMode::Fault(_) = self.current_mode {
+                    return Err(UnitError::Faulted);
+                }
+                if self.get_test_kind().is_some_and(|kind| kind != TestKind::Mode) {
+                    return Err(UnitError::Busy);
+                }
+                unit::check_mode(mode)?;
+
+                self.set_mode(mode);
+                Ok(())
+            }
+
+            /// Turns the load off and holds the counters until `resume_paused`, a running program
+            /// stays in its step. A scheduled pulse is cut short. Only a mode that was set on its
+            /// own and a program can be paused.
+            pub fn pause_test(&mut self) -> Result<(), UnitError> {
+                if self.is_paused() {
+                    return Ok(());
+                }
+                match self.get_test_kind() {
+                    Some(TestKind::Mode | TestKind::Program) => {
+                        let mode = match self.pulse_test {
+                            Some((_, return_mode)) => return_mode,
+                            None => self.current_mode,
+                        };
+                        self.sequencer.pause();
+                        self.set_mode(BatteryTestUnitMode::Paused);
+                        self.paused = Some(mode);
+                        Ok(())
+                    }
+                    Some(_) => Err(UnitError::NotPausable),
+                    None => Err(UnitError::NotRunning),
+                }
+            }
+
+            /// Continues the paused test where it stopped, unless the present measurement
+            /// violates a limit. Does nothing if the unit isn't paused.
+            pub fn resume_paused(&mut self) -> Result<(), UnitError> {
+                let Some(mode) = self.paused else {
+                    return Ok(());
+                };
+                let measurement = self.measure(0.0);
+                if let Some(cause) = self.limits.check(&measurement) {
+                    return Err(UnitError::LimitViolated(cause));
+                }
+
+                self.sequencer.resume();
+                self.set_mode(mode);
+                Ok(())
+            }
+
+            pub fn is_paused(&self) -> bool {
+                self.paused.is_some()
+            }
+
+            /// Stops whatever runs on the unit, also while it's paused, and goes idle. The program
+            /// and the procedures report how far they got, followed by `TestAborted` with the
+            /// partial result of the test.
+            pub fn abort_test(&mut self, mut transmit: impl FnMut(MsgTypes)) -> Result<(), UnitError> {
+                let Some(test) = self.get_test_kind() else {
+                    return Err(UnitError::NotRunning);
+                };
+                let summary = TestSummary {
+                    test,
+                    step: self.sequencer.get_step(),
+                    duration: self.duration,
+                    capacity: self.capacity,
+                    energy: self.energy,
+                };
+
+                if let Some(transition) = self.sequencer.abort() {
+                    transmit(MsgTypes::StepTransition(self.id, transition));
+                }
+                self.orbit_player.stop();
+                if let Some(end) = self.hppc.stop() {
+                    transmit(MsgTypes::HppcFinished(self.id, end));
+                }
+                if let Some(end) = self.ocv.stop() {
+                    transmit(MsgTypes::OcvFinished(self.id, end));
+                }
+                self.set_mode(BatteryTestUnitMode::Idle);
+                transmit(MsgTypes::TestAborted(self.id, summary));
+                Ok(())
+            }
+
+            /// What runs on the unit, `None` while it's idle or in the fault state
+            pub fn get_test_kind(&self) -> Option<TestKind> {
+                if !self.is_active() {
+                    return None;
+                }
+                let kind = if self.sequencer.is_running() {
+                    TestKind::Program
+                } else if self.orbit_player.is_running() {
+                    TestKind::Orbits
+                } else if self.hppc.is_running() {
+                    TestKind::Hppc
+                } else if self.ocv.is_running() {
+                    TestKind::Ocv
+                } else if let Some((_, BatteryTestUnitMode::Idle)) = self.pulse_test {
+                    TestKind::PulseTest
+                } else {
+                    // a scheduled pulse returns to the discharge it interrupted
+                    TestKind::Mode
+                };
+                Some(kind)
+            }
+
+            pub fn get_status(&self) -> UnitStatus {
+                UnitStatus {
+                    name: self.name.clone(),
+                    mode: self.current_mode,
+                    test: self.get_test_kind(),
+                    paused: self.paused,
+                    step: self.sequencer.get_step(),
+                    duration: self.duration,
+                    capacity: self.capacity,
+                    energy: self.energy,
+                    calibrating: self.calibration_run.is_some(),
+                }
+            }
+
+            /// Replaces or appends a step of the program, see `Program::set_step`
+            pub fn set_program_step(&mut self, index: u8, step: Step) -> Result<(), ProgramError> {
+                self.sequencer.set_step(index, step)
+            }
+
+            pub fn clear_program(&mut self) -> Result<(), ProgramError> {
+                self.sequencer.clear_program()
+            }
+
+            pub fn get_sequencer(&self) -> &Sequencer {
+                &self.sequencer
+            }
+
+            /// Starts the program from its first step, only possible while the unit is idle
+            pub fn start_program(&mut self) -> Result<StepTransition, ProgramError> {
+                if self.current_mode != BatteryTestUnitMode::Idle {
+                    return Err(ProgramError::Busy);
+                }
+
+                let measurement = self.measure(0.0);
+                let step_measurement = self.step_measurement(&measurement);
+                let transition = self.sequencer.start(&step_measurement)?;
+                self.set_mode(self.sequencer.get_mode());
+                Ok(transition)
+            }
+
+            /// Replaces or appends a segment of the orbit profile, see `OrbitPlayer::set_segment`
+            pub fn set_orbit_segment(&mut self, index: u8, segment: Segment) -> Result<(), OrbitError> {
+                self.orbit_player.set_segment(index, segment)
+            }
+
+            pub fn clear_orbit_profile(&mut self) -> Result<(), OrbitError> {
+                self.orbit_player.clear()
+            }
+
+            pub fn get_orbit_player(&self) -> &OrbitPlayer {
+                &self.orbit_player
+            }
+
+            /// Replays the orbit profile `orbits` times, only possible while the unit is idle
+            pub fn start_orbits(&mut self, orbits: u16) -> Result<(), OrbitError> {
+                if self.current_mode != BatteryTestUnitMode::Idle {
+                    return Err(OrbitError::Busy);
+                }
+
+                self.orbit_player.start(orbits)?;
+                self.set_mode(self.orbit_player.get_mode());
+                Ok(())
+            }
+
+            /// Stops replaying orbits and goes idle
+            pub fn stop_orbits(&mut self) {
+                if self.orbit_player.is_running() {
+                    self.orbit_player.stop();
+                    self.set_mode(BatteryTestUnitMode::Idle);
+                }
+            }
+
+            /// Runs a single current pulse and goes back to idle once the result was sent, only
+            /// possible while the unit is idle
+            pub fn start_pulse_test(&mut self, config: PulseConfig) -> Result<(), DcirError> {
+                if self.current_mode != BatteryTestUnitMode::Idle {
+                    return Err(DcirError::Busy);
+                }
+                config.validate()?;
+
+                self.set_mode(BatteryTestUnitMode::DcirPulse);
+                self.pulse_test = Some((PulseTest::new(config, None), BatteryTestUnitMode::Idle));
+                Ok(())
+            }
+
+            /// Measures the internal resistance at the given states of charge during every
+            /// discharge, `None` turns it off
+            pub fn set_dcir_schedule(&mut self, schedule: Option<DcirSchedule>) -> Result<(), DcirError> {
+                self.dcir_scheduler.set_schedule(schedule)
+            }
+
+            pub fn get_dcir_scheduler(&self) -> &DcirScheduler {
+                &self.dcir_scheduler
+            }
+
+            /// Starts the HPPC procedure, only possible while the unit is idle
+            pub fn start_hppc(&mut self, config: HppcConfig) -> Result<(), HppcError> {
+                if self.current_mode != BatteryTestUnitMode::Idle {
+                    return Err(HppcError::Busy);
+                }
+
+                self.hppc.start(config)?;
+                self.set_mode(self.hppc.get_mode());
+                Ok(())
+            }
+
+            /// Stops the HPPC procedure and goes idle, returns how far it got if it was running
+            pub fn stop_hppc(&mut self) -> Option<HppcEnd> {
+                let end = self.hppc.stop()?;
+                self.set_mode(BatteryTestUnitMode::Idle);
+                Some(end)
+            }
+
+            pub fn get_hppc(&self) -> &HppcProcedure {
+                &self.hppc
+            }
+
+            /// Starts the OCV procedure, only possible while the unit is idle
+            pub fn start_ocv(&mut self, config: OcvConfig) -> Result<(), OcvError> {
+                if self.current_mode != BatteryTestUnitMode::Idle {
+                    return Err(OcvError::Busy);
+                }
+
+                self.ocv.start(config)?;
+                self.set_mode(self.ocv.get_mode());
+                Ok(())
+            }
+
+            /// Stops the OCV procedure and goes idle, returns the state of the table if it was
+            /// running
+            pub fn stop_ocv(&mut self) -> Option<OcvEnd> {
+                let end = self.ocv.stop()?;
+                self.set_mode(BatteryTestUnitMode::Idle);
+                Some(end)
+            }
+
+            pub fn get_ocv(&self) -> &OcvProcedure {
+                &self.ocv
+            }
+
+            /// Sends the OCV table of the last run to the client, starting with the next update
+            pub fn download_ocv_table(&mut self) {
+                self.ocv_download = Some(0);
+            }
+
+            /// Estimates the state of charge with the given battery model, `None` turns it off.
+            /// The OCV table of the last OCV procedure is used if there is one, otherwise a typical
+            /// LiFePO4 curve. The estimate starts from the voltage at the next update.
+            pub fn set_soc_estimator(&mut self, config: Option<SocEstimatorConfig>) -> Result<(), SocEstimatorError> {
+                self.soc_estimator = match config {
+                    Some(config) => {
+                        config.model.validate()?;
+                        let curve = OcvCurve::from_table(self.ocv.get_table()).unwrap_or_else(OcvCurve::lifepo4);
+                        Some(SocEstimator::new(curve, config))
+                    }
+                    None => None,
+                };
+                Ok(())
+            }
+
+            /// from 0 to 1, `None` without an estimator
+            pub fn get_state_of_charge(&self) -> Option<f32> {
+                self.soc_estimator.as_ref().and_then(|estimator| estimator.get_state_of_charge())
+            }
+
+            /// Starts collecting reference points for the channel, a calibration that was already
+            /// started is discarded
+            pub fn start_calibration(&mut self, channel: CalibrationChannel, degree: u8) -> Result<(), CalibrationError> {
+                self.calibration_run = Some(CalibrationRun::new(channel, degree)?);
+                Ok(())
+            }
+
+            /// Samples the raw value of the channel while it's connected to the known reference,
+            /// returns the index of the point and the point
+            pub fn add_calibration_point(&mut self, reference: f32) -> Result<(u8, CalibrationPoint), CalibrationError> {
+                let channel = self.calibration_run.as_ref().ok_or(CalibrationError::NotCalibrating)?.get_channel();
+                let point = CalibrationPoint {
+                    raw: self.get_raw(channel),
+                    reference,
+                };
+                let run = self.calibration_run.as_mut().ok_or(CalibrationError::NotCalibrating)?;
+                Ok((run.add_point(point)?, point))
+            }
+
+            /// Fits the polynomial through the reference points and uses it from now on, only
+            /// possible while the unit isn't running a test. If the fit fails the points are kept,
+            /// so more of them can be added.
+            pub fn finish_calibration(&mut self) -> Result<CalibrationSet, CalibrationError> {
+                if self.is_active() {
+                    return Err(CalibrationError::Busy);
+                }
+                let run = self.calibration_run.as_ref().ok_or(CalibrationError::NotCalibrating)?;
+
+                let calibration = run.fit()?;
+                self.calibration.set(run.get_channel(), calibration);
+                self.calibration_run = None;
+                Ok(self.calibration)
+            }
+
+            pub fn cancel_calibration(&mut self) {
+                self.calibration_run = None;
+            }
+
+            pub fn get_calibration_run(&self) -> Option<&CalibrationRun> {
+                self.calibration_run.as_ref()
+            }
+
+            /// Replaces the calibrations of all channels including the version, e.g. with ones the
+            /// client saved earlier. Only possible while the unit isn't running a test.
+            pub fn set_calibration(&mut self, calibration: CalibrationSet) -> Result<(), CalibrationError> {
+                if self.is_active() {
+                    return Err(CalibrationError::Busy);
+                }
+                calibration.validate()?;
+
+                self.calibration = calibration;
+                Ok(())
+            }
+
+            pub fn get_calibration(&self) -> &CalibrationSet {
+                &self.calibration
+            }
+
+            /// Stops the program and goes idle, returns the transition to report if a program ran
+            pub fn stop_program(&mut self) -> Option<StepTransition> {
+                let transition = self.sequencer.stop()?;
+                self.set_mode(BatteryTestUnitMode::Idle);
+                Some(transition)
+            }
+
+            pub fn get_id(&self) -> u8 {
+                self.id
+            }
+
+            pub fn set_name(&mut self, name: String<16>) {
+                self.name = name;
+            }
+
+            pub fn get_name(&self) -> &String<16> {
+                &self.name
+            }
+
+            /// The settings of the unit that are stored in flash
+            pub fn get_config(&self) -> UnitConfig {
+                UnitConfig {
+                    name: self.name.clone(),
+                    limits: self.limits,
+                    calibration: self.calibration,
+                }
+            }
+
             pub fn get_mode(&self) -> BatteryTestUnitMode {
                 self.current_mode
             }
 
+            /// in V, calibrated
             pub fn get_voltage(&mut self) -> f32 {
-                self.voltage_adc.get_voltage()
+                let raw = self.get_raw(CalibrationChannel::Voltage);
+                self.calibration.voltage.apply(raw)
+            }
+
+            /// in A, calibrated
+            pub fn get_current(&mut self) -> f32 {
+                let raw = self.get_raw(CalibrationChannel::Current);
+                self.calibration.current.apply(raw)
+            }
+
+            /// in °C, calibrated
+            pub fn get_temperature(&mut self) -> f32 {
+                let raw = self.get_raw(CalibrationChannel::Temperature);
+                self.calibration.temperature.apply(raw)
+            }
+
+            /// The reading of the channel without calibration
+            pub fn get_raw(&mut self, channel: CalibrationChannel) -> f32 {
+                match channel {
+                    CalibrationChannel::Voltage => self.voltage_adc.get_voltage(),
+                    CalibrationChannel::Current => self.current_sensor.get_current(),
+                    CalibrationChannel::Temperature => self.temperature_sensor.get_temperature(),
+                }
+            }
+
+            /// Reads the channel through its oversampling and filter, without calibration
+            fn sample_raw(&mut self, channel: CalibrationChannel) -> f32 {
+                match channel {
+                    CalibrationChannel::Voltage => self.conditioner.voltage.sample(|| self.voltage_adc.get_voltage()),
+                    CalibrationChannel::Current => self.conditioner.current.sample(|| self.current_sensor.get_current()),
+                    CalibrationChannel::Temperature => {
+                        self.conditioner.temperature.sample(|| self.temperature_sensor.get_temperature())
+                    }
+                }
+            }
+
+            pub fn set_conditioning(&mut self, channel: CalibrationChannel, config: ChannelConditioning) -> Result<(), ConditioningError> {
+                self.conditioner.set_config(channel, config)
+            }
+
+            pub fn get_conditioning(&self) -> ConditioningConfig {
+                self.conditioner.get_config()
+            }
+
+            /// Whether the unit is running a test, i.e. is neither idle nor in the fault state
+            pub fn is_active(&self) -> bool {
+                self.current_mode.is_active()
+            }
+
+            pub fn set_limits(&mut self, limits: SafetyLimits) {
+                self.limits = limits;
+            }
+
+            pub fn get_limits(&self) -> SafetyLimits {
+                self.limits
+            }
+
+            /// What the unit does for the recovery after a reset, `None` while it doesn't run a test.
+            /// A paused test is reported with the mode it continues with.
+            pub fn get_run(&self) -> Option<UnitRun> {
+                if !self.is_active() {
+                    return None;
+                }
+                let procedure = self.sequencer.is_running()
+                    || self.orbit_player.is_running()
+                    || self.hppc.is_running()
+                    || self.ocv.is_running()
+                    || self.pulse_test.is_some();
+                Some(UnitRun {
+                    mode: self.paused.unwrap_or(self.current_mode),
+                    resumable: !procedure,
+                    duration: self.duration,
+                    capacity: self.capacity,
+                    energy: self.energy,
+                })
+            }
+
+            /// Continues a run the reset interrupted, with the duration and the counters where
+            /// they stopped. The cell may have left the limits while the board was down, the
+            /// unit stays idle then.
+            pub fn resume(&mut self, run: &UnitRun) -> Result<(), UnitError> {
+                unit::check_mode(run.mode)?;
+                let measurement = Measurement {
+                    duration: run.duration,
+                    capacity: run.capacity,
+                    ..self.measure(0.0)
+                };
+                if let Some(cause) = self.limits.check(&measurement) {
+                    return Err(UnitError::LimitViolated(cause));
+                }
+
+                self.set_mode(run.mode);
+                self.duration = run.duration;
+                self.capacity = run.capacity;
+                self.energy = run.energy;
+                Ok(())
+            }
+
+            /// Leaves the fault state, the unit stays idle afterwards
+            pub fn clear_fault(&mut self) {
+                if let BatteryTestUnitMode::Fault(_) = self.current_mode {
+                    self.current_mode = BatteryTestUnitMode::Idle;
+                    self.set_mode(BatteryTestUnitMode::Idle);
+                }
+            }
+
+            /// Sample rate of the unit and how many samples are sent as telemetry
+            pub fn set_sampling(&mut self, config: SamplingConfig) -> Result<(), SamplingError> {
+                self.sampling.set_config(config)
+            }
+
+            pub fn get_sampling(&self) -> &SamplingConfig {
+                self.sampling.get_config()
+            }
+
+            /// in ms
+            pub fn get_time_to_sample(&self) -> u32 {
+                self.sampling.get_time_to_sample()
+            }
+
+            /// The latest measurement as record of the measurement log, `None` before the first
+            /// update
+            pub fn get_log_record(&self, timestamp: u64) -> Option<LogRecord> {
+                self.last_measurement.map(|measurement| LogRecord {
+                    sequence: 0,
+                    timestamp,
+                    unit: self.id,
+                    step: self.sequencer.get_step(),
+                    voltage: measurement.voltage,
+                    current: measurement.current,
+                    temperature: measurement.temperature,
+                })
+            }
+
+            /// Samples all channels and, while the unit is active, integrates the moved charge
+            fn measure(&mut self, delta_time: f32) -> Measurement {
+                let voltage = self.sample_raw(CalibrationChannel::Voltage);
+                let voltage = self.calibration.voltage.apply(voltage);
+                let current = self.sample_raw(CalibrationChannel::Current);
+                let current = self.calibration.current.apply(current);
+                let temperature = self.sample_raw(CalibrationChannel::Temperature);
+                let temperature = self.calibration.temperature.apply(temperature);
+
+                if self.is_active() && !self.is_paused() {
+                    self.duration += delta_time;
+                    self.capacity += libm::fabsf(current) * delta_time / 3600.0;
+                    self.energy += libm::fabsf(voltage * current) * delta_time / 3600.0;
+                }
+
+                Measurement {
+                    voltage,
+                    current,
+                    temperature: Some(temperature),
+                    duration: self.duration,
+                    capacity: self.capacity,
+                }
+            }
+
+            fn step_measurement(&mut self, measurement: &Measurement) -> StepMeasurement {
+                StepMeasurement {
+                    voltage: measurement.voltage,
+                    current: measurement.current,
+                    temperature: measurement.temperature,
+                    charge_done: self.charge_done.get_input(),
+                }
+            }
+
+            /// `output` is the load from 0 (off) to 1 (maximum duty cycle)
+            fn set_load(&mut self, output: f32) {
+                let min = self.load_pwm.get_min_duty_cycle() as f32;
+                let max = self.load_pwm.get_max_duty_cycle() as f32;
+                self.load_pwm.set_duty_cycle((min + output * (max - min)) as u16);
             }
         }
     };
@@ -122,5 +1545,9 @@ macro_rules! generate_battery_test_unit {
 
 generate_battery_test_unit!(
     (voltage_adc; TAdcVoltage: AdcInput),
-    (load_pwm; TLoad: PwmOutput)
+    (current_sensor; TCurrent: CurrentInput),
+    (temperature_sensor; TTemperature: TemperatureInput),
+    (load_pwm; TLoad: PwmOutput),
+    (charger_enable; TChargerEnable: GpioOutput),
+    (charge_done; TChargeDone: GpioInput)
 );
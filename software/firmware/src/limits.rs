@@ -0,0 +1,215 @@
+use serde::{Deserialize, Serialize};
+
+/// Limits a battery test unit has to stay within while it is not idle.
+/// Limits that are `None` are not checked.
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Default)]
+pub struct SafetyLimits {
+    /// in V
+    pub min_voltage: Option<f32>,
+    /// in V
+    pub max_voltage: Option<f32>,
+    /// in A, applies to charge and discharge current
+    pub max_current: Option<f32>,
+    /// in °C
+    pub max_temperature: Option<f32>,
+    /// in s, counted from the moment the unit left idle
+    pub max_duration: Option<f32>,
+    /// in Ah, charged or discharged since the unit left idle
+    pub max_capacity: Option<f32>,
+}
+
+impl SafetyLimits {
+    /// Conservative limits for a single LiFePO4 cell
+    pub const LIFEPO4: SafetyLimits = SafetyLimits {
+        min_voltage: Some(2.0),
+        max_voltage: Some(3.65),
+        max_current: Some(5.0),
+        max_temperature: Some(60.0),
+        max_duration: None,
+        max_capacity: None,
+    };
+
+    /// Returns the first limit the measurement violates
+    pub fn check(&self, measurement: &Measurement) -> Option<FaultCause> {
+        // sensors report NaN if they couldn't be read, none of the limits can be checked then
+        if measurement.voltage.is_nan()
+            || measurement.current.is_nan()
+            || measurement.temperature.is_some_and(f32::is_nan)
+        {
+            return Some(FaultCause::InvalidMeasurement);
+        }
+
+        let below = |limit: Option<f32>, value: f32| limit.is_some_and(|limit| value < limit);
+        let above = |limit: Option<f32>, value: f32| limit.is_some_and(|limit| value > limit);
+
+        if below(self.min_voltage, measurement.voltage) {
+            return Some(FaultCause::UnderVoltage(measurement.voltage));
+        }
+        if above(self.max_voltage, measurement.voltage) {
+            return Some(FaultCause::OverVoltage(measurement.voltage));
+        }
+        if above(self.max_current, libm::fabsf(measurement.current)) {
+            return Some(FaultCause::OverCurrent(measurement.current));
+        }
+        if let Some(temperature) = measurement.temperature {
+            if above(self.max_temperature, temperature) {
+                return Some(FaultCause::OverTemperature(temperature));
+            }
+        }
+        if above(self.max_duration, measurement.duration) {
+            return Some(FaultCause::DurationExceeded(measurement.duration));
+        }
+        if above(self.max_capacity, measurement.capacity) {
+            return Some(FaultCause::CapacityExceeded(measurement.capacity));
+        }
+        None
+    }
+}
+
+/// The reason a battery test unit went into the fault state, together with the offending value
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq)]
+pub enum FaultCause {
+    InvalidMeasurement,
+    UnderVoltage(f32),
+    OverVoltage(f32),
+    OverCurrent(f32),
+    OverTemperature(f32),
+    DurationExceeded(f32),
+    CapacityExceeded(f32),
+}
+
+/// Everything the safety limits are checked against
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub struct Measurement {
+    /// in V
+    pub voltage: f32,
+    /// in A, positive while discharging
+    pub current: f32,
+    /// in °C, `None` if the unit has no temperature sensor
+    pub temperature: Option<f32>,
+    /// in s
+    pub duration: f32,
+    /// in Ah
+    pub capacity: f32,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn nominal() -> Measurement {
+        Measurement {
+            voltage: 3.2,
+            current: 1.0,
+            temperature: Some(25.0),
+            duration: 60.0,
+            capacity: 0.1,
+        }
+    }
+
+    #[test]
+    fn test_no_limits() {
+        let limits = SafetyLimits::default();
+        let measurement = Measurement {
+            voltage: -1.0,
+            current: 100.0,
+            temperature: Some(200.0),
+            duration: 1e6,
+            capacity: 1e3,
+        };
+
+        assert_eq!(limits.check(&measurement), None);
+    }
+
+    #[test]
+    fn test_lifepo4_limits() {
+        macro_rules! t {
+            ($measurement:expr, $result:expr) => {
+                assert_eq!(SafetyLimits::LIFEPO4.check(&$measurement), $result);
+            };
+        }
+
+        t!(nominal(), None);
+        t!(
+            Measurement {
+                voltage: 1.9,
+                ..nominal()
+            },
+            Some(FaultCause::UnderVoltage(1.9))
+        );
+        t!(
+            Measurement {
+                voltage: 3.7,
+                ..nominal()
+            },
+            Some(FaultCause::OverVoltage(3.7))
+        );
+        t!(
+            Measurement {
+                current: 5.5,
+                ..nominal()
+            },
+            Some(FaultCause::OverCurrent(5.5))
+        );
+        t!(
+            Measurement {
+                current: -5.5,
+                ..nominal()
+            },
+            Some(FaultCause::OverCurrent(-5.5))
+        );
+        t!(
+            Measurement {
+                temperature: Some(61.0),
+                ..nominal()
+            },
+            Some(FaultCause::OverTemperature(61.0))
+        );
+        t!(
+            Measurement {
+                temperature: None,
+                ..nominal()
+            },
+            None
+        );
+        t!(
+            Measurement {
+                voltage: f32::NAN,
+                ..nominal()
+            },
+            Some(FaultCause::InvalidMeasurement)
+        );
+        t!(
+            Measurement {
+                temperature: Some(f32::NAN),
+                ..nominal()
+            },
+            Some(FaultCause::InvalidMeasurement)
+        );
+    }
+
+    #[test]
+    fn test_duration_and_capacity_limits() {
+        let limits = SafetyLimits {
+            max_duration: Some(3600.0),
+            max_capacity: Some(1.5),
+            ..SafetyLimits::default()
+        };
+
+        assert_eq!(limits.check(&nominal()), None);
+        assert_eq!(
+            limits.check(&Measurement {
+                duration: 3601.0,
+                ..nominal()
+            }),
+            Some(FaultCause::DurationExceeded(3601.0))
+        );
+        assert_eq!(
+            limits.check(&Measurement {
+                capacity: 1.6,
+                ..nominal()
+            }),
+            Some(FaultCause::CapacityExceeded(1.6))
+        );
+    }
+}
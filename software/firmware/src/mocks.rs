@@ -1,6 +1,8 @@
 use crate::msg_types::MsgTypes;
 use crate::traits::*;
+use embedded_hal::blocking::i2c::WriteRead;
 use std::collections::VecDeque;
+use time::{Date, Month, PrimitiveDateTime, Time};
 
 // +--------------------------------------------------------------------------+
 // |                               GPIO Output                                |
@@ -21,6 +23,20 @@ impl GpioOutput for MockGpioOutput {
     }
 }
 
+// +--------------------------------------------------------------------------+
+// |                                GPIO Input                                |
+// +--------------------------------------------------------------------------+
+
+pub struct MockGpioInput {
+    pub value: bool,
+}
+
+impl GpioInput for MockGpioInput {
+    fn get_input(&mut self) -> bool {
+        self.value
+    }
+}
+
 // +--------------------------------------------------------------------------+
 // |                                ADC Input                                 |
 // +--------------------------------------------------------------------------+
@@ -49,6 +65,54 @@ impl AdcInput for MockAdcInput {
     }
 }
 
+// +--------------------------------------------------------------------------+
+// |                              Current Input                               |
+// +--------------------------------------------------------------------------+
+
+pub struct MockCurrentInput {
+    pub current: f32,
+}
+
+impl MockCurrentInput {
+    pub fn new() -> Self {
+        MockCurrentInput { current: 0.0 }
+    }
+
+    pub fn set_current(&mut self, current: f32) {
+        self.current = current;
+    }
+}
+
+impl CurrentInput for MockCurrentInput {
+    fn get_current(&mut self) -> f32 {
+        self.current
+    }
+}
+
+// +--------------------------------------------------------------------------+
+// |                            Temperature Input                             |
+// +--------------------------------------------------------------------------+
+
+pub struct MockTemperatureInput {
+    pub temperature: f32,
+}
+
+impl MockTemperatureInput {
+    pub fn new() -> Self {
+        MockTemperatureInput { temperature: 25.0 }
+    }
+
+    pub fn set_temperature(&mut self, temperature: f32) {
+        self.temperature = temperature;
+    }
+}
+
+impl TemperatureInput for MockTemperatureInput {
+    fn get_temperature(&mut self) -> f32 {
+        self.temperature
+    }
+}
+
 // +--------------------------------------------------------------------------+
 // |                                PWM Output                                |
 // +--------------------------------------------------------------------------+
@@ -81,21 +145,31 @@ impl PwmOutput for MockPwmOutput {
 // |                             Serial Receiver                              |
 // +--------------------------------------------------------------------------+
 
+/// Hands out one message per `update_serial`, as if the client sent them one after the other
 pub struct MockSerialReceiver {
     pub msg_queue: VecDeque<MsgTypes>,
+    /// whether the message of this `update_serial` was handed out
+    received: bool,
 }
 
 impl MockSerialReceiver {
     pub fn new(msg_queue: Vec<MsgTypes>) -> Self {
         MockSerialReceiver {
             msg_queue: VecDeque::from(msg_queue),
+            received: false,
         }
     }
 }
 
 impl SerialReceiver for MockSerialReceiver {
-    fn receive(&mut self, mut cb: impl FnMut(MsgTypes)) {
-        cb(self.msg_queue.pop_front().unwrap());
+    fn receive(&mut self) -> Option<Result<MsgTypes, InvalidPacket>> {
+        if self.received {
+            self.received = false;
+            return None;
+        }
+        let msg = self.msg_queue.pop_front()?;
+        self.received = true;
+        Some(Ok(msg))
     }
 }
 
@@ -120,3 +194,313 @@ impl SerialTransmitter for MockSerialTransmitter {
         self.msg_queue.push_back(msg);
     }
 }
+
+// +--------------------------------------------------------------------------+
+// |                                  Clocks                                  |
+// +--------------------------------------------------------------------------+
+
+pub struct MockSystemTime {
+    /// in µs
+    pub micros: u64,
+}
+
+impl MockSystemTime {
+    pub fn new() -> Self {
+        MockSystemTime { micros: 0 }
+    }
+
+    pub fn advance_millis(&mut self, millis: u64) {
+        self.micros += millis * 1000;
+    }
+}
+
+impl SystemTime for MockSystemTime {
+    fn get_micros(&mut self) -> u64 {
+        self.micros
+    }
+}
+
+pub struct MockRtc {
+    pub datetime: PrimitiveDateTime,
+}
+
+impl MockRtc {
+    pub fn new() -> Self {
+        MockRtc {
+            datetime: PrimitiveDateTime::new(
+                Date::from_calendar_date(2000, Month::January, 1).unwrap(),
+                Time::MIDNIGHT,
+            ),
+        }
+    }
+}
+
+impl RealTimeClock for MockRtc {
+    /// Like the RTC of the board it only counts the years from 1970 to 2069
+    fn set_datetime(&mut self, datetime: PrimitiveDateTime) -> Result<(), RtcError> {
+        if !(1970..=2069).contains(&datetime.year()) {
+            return Err(RtcError);
+        }
+        self.datetime = datetime;
+        Ok(())
+    }
+
+    fn get_datetime(&mut self) -> PrimitiveDateTime {
+        self.datetime
+    }
+}
+
+// +--------------------------------------------------------------------------+
+// |                                 Watchdog                                 |
+// +--------------------------------------------------------------------------+
+
+pub struct MockWatchdog {
+    /// number of times it was fed
+    pub fed: u32,
+    pub caused_reset: bool,
+}
+
+impl MockWatchdog {
+    pub fn new() -> Self {
+        MockWatchdog {
+            fed: 0,
+            caused_reset: false,
+        }
+    }
+}
+
+impl Watchdog for MockWatchdog {
+    fn feed(&mut self) {
+        self.fed += 1;
+    }
+
+    fn caused_reset(&self) -> bool {
+        self.caused_reset
+    }
+}
+
+/// Cleared to 0 like the backup registers after the board was powered up
+pub struct MockRetainedMemory {
+    pub memory: Vec<u8>,
+}
+
+impl MockRetainedMemory {
+    pub fn new(size: usize) -> Self {
+        MockRetainedMemory {
+            memory: vec![0; size],
+        }
+    }
+}
+
+impl RetainedMemory for MockRetainedMemory {
+    fn capacity(&self) -> usize {
+        self.memory.len()
+    }
+
+    fn read(&mut self, buf: &mut [u8]) {
+        buf.copy_from_slice(&self.memory[..buf.len()]);
+    }
+
+    fn write(&mut self, data: &[u8]) {
+        self.memory[..data.len()].copy_from_slice(data);
+    }
+}
+
+// +--------------------------------------------------------------------------+
+// |                                  Flash                                   |
+// +--------------------------------------------------------------------------+
+
+pub struct MockFlash {
+    pub memory: Vec<u8>,
+    sector_size: u32,
+    /// bytes that can still be programmed before the power fails, `None` never fails
+    pub power_budget: Option<usize>,
+}
+
+impl MockFlash {
+    pub fn new(sector_size: u32) -> Self {
+        MockFlash {
+            memory: vec![0xFF; 2 * sector_size as usize],
+            sector_size,
+            power_budget: None,
+        }
+    }
+}
+
+impl Flash for MockFlash {
+    fn sector_size(&self) -> u32 {
+        self.sector_size
+    }
+
+    fn read(&mut self, offset: u32, buf: &mut [u8]) {
+        let offset = offset as usize;
+        buf.copy_from_slice(&self.memory[offset..offset + buf.len()]);
+    }
+
+    fn write(&mut self, offset: u32, data: &[u8]) -> Result<(), FlashError> {
+        for (index, byte) in data.iter().enumerate() {
+            match &mut self.power_budget {
+                Some(0) => return Err(FlashError),
+                Some(budget) => *budget -= 1,
+                None => {}
+            }
+            // programming can only clear bits
+            self.memory[offset as usize + index] &= byte;
+        }
+        Ok(())
+    }
+
+    fn erase(&mut self, sector: u8) -> Result<(), FlashError> {
+        let start = sector as usize * self.sector_size as usize;
+        let end = start + self.sector_size as usize;
+        if self.power_budget == Some(0) {
+            // the power failed halfway through
+            self.memory[start..(start + end) / 2].fill(0xFF);
+            return Err(FlashError);
+        }
+        self.memory[start..end].fill(0xFF);
+        Ok(())
+    }
+}
+
+// +--------------------------------------------------------------------------+
+// |                              Block Storage                               |
+// +--------------------------------------------------------------------------+
+
+pub struct MockBlockStorage {
+    pub memory: Vec<u8>,
+    block_size: u32,
+    /// bytes that can still be programmed before the power fails, `None` never fails
+    pub power_budget: Option<usize>,
+}
+
+impl MockBlockStorage {
+    pub fn new(blocks: u32, block_size: u32) -> Self {
+        MockBlockStorage {
+            memory: vec![0xFF; (blocks * block_size) as usize],
+            block_size,
+            power_budget: None,
+        }
+    }
+}
+
+impl BlockStorage for MockBlockStorage {
+    fn capacity(&self) -> u32 {
+        self.memory.len() as u32
+    }
+
+    fn block_size(&self) -> u32 {
+        self.block_size
+    }
+
+    fn read(&mut self, address: u32, buf: &mut [u8]) -> Result<(), FlashError> {
+        let address = address as usize;
+        buf.copy_from_slice(&self.memory[address..address + buf.len()]);
+        Ok(())
+    }
+
+    fn write(&mut self, address: u32, data: &[u8]) -> Result<(), FlashError> {
+        for (index, byte) in data.iter().enumerate() {
+            match &mut self.power_budget {
+                Some(0) => return Err(FlashError),
+                Some(budget) => *budget -= 1,
+                None => {}
+            }
+            // programming can only clear bits
+            self.memory[address as usize + index] &= byte;
+        }
+        Ok(())
+    }
+
+    fn erase_block(&mut self, block: u32) -> Result<(), FlashError> {
+        let start = (block * self.block_size) as usize;
+        self.memory[start..start + self.block_size as usize].fill(0xFF);
+        Ok(())
+    }
+
+    fn erase_all(&mut self) -> Result<(), FlashError> {
+        self.memory.fill(0xFF);
+        Ok(())
+    }
+}
+
+// +--------------------------------------------------------------------------+
+// |                                   I2C                                    |
+// +--------------------------------------------------------------------------+
+
+#[derive(Debug, PartialEq)]
+pub struct MockI2cTransaction {
+    pub address: u8,
+    pub write: Vec<u8>,
+    /// `None` makes the transaction fail
+    pub read: Option<Vec<u8>>,
+}
+
+impl MockI2cTransaction {
+    pub fn write_read(address: u8, write: Vec<u8>, read: Vec<u8>) -> Self {
+        MockI2cTransaction {
+            address,
+            write,
+            read: Some(read),
+        }
+    }
+
+    pub fn error(address: u8) -> Self {
+        MockI2cTransaction {
+            address,
+            write: Vec::new(),
+            read: None,
+        }
+    }
+}
+
+/// Checks that the expected transactions happen in the given order
+pub struct MockI2c {
+    pub expected: VecDeque<MockI2cTransaction>,
+}
+
+impl MockI2c {
+    pub fn new(expected: Vec<MockI2cTransaction>) -> Self {
+        MockI2c {
+            expected: VecDeque::from(expected),
+        }
+    }
+
+    /// Asserts that all expected transactions happened
+    pub fn done(&self) {
+        assert!(
+            self.expected.is_empty(),
+            "transactions left: {:?}",
+            self.expected
+        );
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct MockI2cError;
+
+impl WriteRead for MockI2c {
+    type Error = MockI2cError;
+
+    fn write_read(
+        &mut self,
+        address: u8,
+        bytes: &[u8],
+        buffer: &mut [u8],
+    ) -> Result<(), Self::Error> {
+        let transaction = self
+            .expected
+            .pop_front()
+            .expect("no more transactions expected");
+        assert_eq!(transaction.address, address);
+
+        match transaction.read {
+            Some(read) => {
+                assert_eq!(transaction.write, bytes);
+                buffer.copy_from_slice(&read);
+                Ok(())
+            }
+            None => Err(MockI2cError),
+        }
+    }
+}
@@ -1,13 +1,195 @@
 use heapless::String;
 use serde::{Deserialize, Serialize};
 
+use crate::calibration::{CalibrationChannel, CalibrationError, CalibrationPoint, CalibrationSet};
+use crate::clock::ClockError;
+use crate::conditioning::{
+    ChannelConditioning, ConditioningConfig, ConditioningError, MeasurementStats,
+};
+use crate::config::ConfigError;
+use crate::crash::{CrashReport, ResetCause};
+use crate::datalog::{LogConfig, LogError, LogRecord, LogStatus};
+use crate::dcir::{DcirError, DcirResult, DcirSchedule, PulseConfig};
+use crate::hppc::{HppcConfig, HppcData, HppcEnd, HppcError};
+use crate::limits::{FaultCause, SafetyLimits};
+use crate::ocv::{OcvConfig, OcvEnd, OcvError, OcvPoint};
+use crate::orbit::{OrbitError, OrbitStats, Segment};
+use crate::recovery::{RecoveryError, UnitRun};
+use crate::sampling::{SamplingConfig, SamplingError};
+use crate::sequence::{ProgramError, Step, StepTransition};
+use crate::soc::{SocEstimatorConfig, SocEstimatorError};
+use crate::unit::{TestSummary, UnitError, UnitStatus};
+use crate::BatteryTestUnitMode;
+
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq)]
+pub struct Telemetry {
+    /// time of the sample, Unix time in ms
+    pub timestamp: u64,
+    pub mode: BatteryTestUnitMode,
+    /// index of the program step that runs
+    pub step: Option<u8>,
+    /// charge moved since the unit left idle, in Ah
+    pub capacity: f32,
+    /// energy moved since the unit left idle, in Wh
+    pub energy: f32,
+    /// in V
+    pub voltage: f32,
+    /// in A, positive while discharging
+    pub current: f32,
+    /// in °C
+    pub temperature: Option<f32>,
+    /// from 0 to 1, only with a state of charge estimator
+    pub state_of_charge: Option<f32>,
+    /// time until the battery is empty at the present discharge current, in s
+    pub remaining_time: Option<f32>,
+    /// version of the calibration the values were corrected with
+    pub calibration: u16,
+    /// spread of the readings since the last telemetry
+    pub stats: MeasurementStats,
+}
+
 #[derive(Serialize, Deserialize, Debug, PartialEq)]
 pub enum MsgTypes {
     Msg(String<128>),
     Ping(u16),
+    /// the command was carried out, sent for the commands that have no other response
+    Ack,
     Test1(u32),
     Test2(f32, u8),
 
     SampleAdc(u8),
     SampleAdcResult(u16),
+
+    /// stores the names, limits and calibrations of all units and the sample period in flash
+    SaveConfig,
+    /// sequence number of the stored record
+    ConfigSaved(u32),
+    ConfigError(ConfigError),
+    /// longest time between two updates of the units, in ms, see `SetSampling` for the
+    /// sample rates of the units
+    SetSamplePeriod(u16),
+    GetSamplePeriod,
+    /// in ms
+    SamplePeriod(u16),
+    /// sends the `Status` of every unit, followed by `UnitCount`
+    ListUnits,
+    /// number of battery test units of the board
+    UnitCount(u8),
+    /// sets the real time clock to the Unix time in ms
+    SetTime(u64),
+    GetTime,
+    /// Unix time of the board in ms
+    Time(u64),
+    ClockError(ClockError),
+    /// sent at the start if the watchdog reset the board
+    WatchdogReset,
+    /// sent at the start for every unit that ran a test when the board was reset
+    TestInterrupted(u8, UnitRun),
+    /// continues the paused test of the unit, or the test the reset interrupted
+    ResumeTest(u8),
+    RecoveryError(u8, RecoveryError),
+    /// sent at the start if the board panicked before the reset, with the cause of the reset
+    CrashReport(CrashReport, ResetCause),
+    /// starts writing the measurements of all units to the external flash
+    StartLog(LogConfig),
+    StopLog,
+    /// erases all records, only while the log is stopped
+    ClearLog,
+    GetLogStatus,
+    LogStatus(LogStatus),
+    /// sends the records starting with the given sequence number, followed by `LogEnd`
+    GetLog(u32),
+    LogRecord(LogRecord),
+    /// sequence number the next record gets
+    LogEnd(u32),
+    LogError(LogError),
+
+    // the first field of the following messages is the id of the battery test unit
+    /// sets the mode on its own, only while no program or procedure runs
+    SetMode(u8, BatteryTestUnitMode),
+    GetMode(u8),
+    Mode(u8, BatteryTestUnitMode),
+    /// turns the load off and holds the counters and the program step until `ResumeTest`
+    PauseTest(u8),
+    /// stops whatever runs on the unit and goes idle, answered with `TestAborted`
+    AbortTest(u8),
+    /// the partial result, after the end messages of the program or procedure
+    TestAborted(u8, TestSummary),
+    GetStatus(u8),
+    Status(u8, UnitStatus),
+    /// the errors of the messages above, and of any message for a unit that doesn't exist
+    UnitError(u8, UnitError),
+    SetUnitName(u8, String<16>),
+    GetUnitName(u8),
+    UnitName(u8, String<16>),
+    /// sample rate of the unit and how many samples are sent as telemetry
+    SetSampling(u8, SamplingConfig),
+    GetSampling(u8),
+    Sampling(u8, SamplingConfig),
+    SamplingError(u8, SamplingError),
+    /// oversampling and filter of a channel of the unit
+    SetConditioning(u8, CalibrationChannel, ChannelConditioning),
+    GetConditioning(u8),
+    Conditioning(u8, ConditioningConfig),
+    ConditioningError(u8, ConditioningError),
+    SetLimits(u8, SafetyLimits),
+    GetLimits(u8),
+    Limits(u8, SafetyLimits),
+    ClearFault(u8),
+    Fault(u8, FaultCause),
+    Telemetry(u8, Telemetry),
+    /// index of the step and the step, a program is uploaded one step at a time
+    SetProgramStep(u8, u8, Step),
+    ClearProgram(u8),
+    StartProgram(u8),
+    StopProgram(u8),
+    StepTransition(u8, StepTransition),
+    ProgramError(u8, ProgramError),
+    /// index of the segment and the segment, a profile is uploaded one segment at a time
+    SetOrbitSegment(u8, u8, Segment),
+    ClearOrbitProfile(u8),
+    /// number of orbits to replay
+    StartOrbits(u8, u16),
+    StopOrbits(u8),
+    OrbitStats(u8, OrbitStats),
+    OrbitError(u8, OrbitError),
+    /// runs a single pulse while the unit is idle
+    StartPulseTest(u8, PulseConfig),
+    /// `None` turns the measurements during discharges off
+    SetDcirSchedule(u8, Option<DcirSchedule>),
+    DcirResult(u8, DcirResult),
+    DcirError(u8, DcirError),
+    StartHppc(u8, HppcConfig),
+    StopHppc(u8),
+    /// a part of the recorded window around the pulses of a state of charge step
+    HppcData(u8, HppcData),
+    HppcFinished(u8, HppcEnd),
+    HppcError(u8, HppcError),
+    StartOcv(u8, OcvConfig),
+    StopOcv(u8),
+    /// sends the OCV table of the last run point by point, followed by `OcvTableEnd`
+    GetOcvTable(u8),
+    /// index of the point in the table and the point
+    OcvPoint(u8, u8, OcvPoint),
+    /// number of points in the table
+    OcvTableEnd(u8, u8),
+    OcvFinished(u8, OcvEnd),
+    OcvError(u8, OcvError),
+    /// `None` turns the estimator off
+    SetSocEstimator(u8, Option<SocEstimatorConfig>),
+    SocEstimatorError(u8, SocEstimatorError),
+    /// channel and degree of the polynomial, 1 is a two-point calibration
+    StartCalibration(u8, CalibrationChannel, u8),
+    /// the reference value the channel is connected to at the moment
+    AddCalibrationPoint(u8, f32),
+    /// index of the point and the point
+    CalibrationPoint(u8, u8, CalibrationPoint),
+    /// fits the polynomial through the points and uses it from then on
+    FinishCalibration(u8),
+    CancelCalibration(u8),
+    SetCalibration(u8, CalibrationSet),
+    GetCalibration(u8),
+    /// the calibrations in use, the answer to `FinishCalibration` and `GetCalibration`
+    Calibration(u8, CalibrationSet),
+    CalibrationError(u8, CalibrationError),
 }
@@ -0,0 +1,491 @@
+use heapless::Vec;
+use serde::{Deserialize, Serialize};
+
+use crate::sequence::StepMeasurement;
+use crate::BatteryTestUnitMode;
+
+/// Maximum number of points of an OCV table
+pub const MAX_OCV_POINTS: usize = 64;
+
+/// Open circuit voltage measurement: starting from a full battery, the unit discharges in small
+/// increments and records the relaxed voltage after each rest, until the battery is empty. It
+/// optionally charges back the same way until the charger reports that it's done.
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq)]
+pub struct OcvConfig {
+    /// in Ah
+    pub nominal_capacity: f32,
+    /// charge moved between two points, in Ah
+    pub increment: f32,
+    /// in A
+    pub discharge_current: f32,
+    /// the rest ends after this time at the latest, in s
+    pub rest_duration: f32,
+    /// the rest takes at least this long, in s
+    pub min_rest_duration: f32,
+    /// the rest ends early once the voltage changes slower than this, zero waits for the whole
+    /// rest duration, in V/s
+    pub dvdt_threshold: f32,
+    /// time over which the voltage change is measured, in s
+    pub dvdt_interval: f32,
+    /// the battery counts as empty below this voltage, in V
+    pub min_voltage: f32,
+    /// whether to charge back in increments after the discharge
+    pub charge: bool,
+}
+
+impl OcvConfig {
+    pub fn validate(&self) -> Result<(), OcvError> {
+        let positive = [
+            self.nominal_capacity,
+            self.increment,
+            self.discharge_current,
+            self.rest_duration,
+            self.dvdt_interval,
+        ];
+        let valid = positive.iter().all(|value| *value > 0.0)
+            && self.min_rest_duration >= 0.0
+            && self.min_rest_duration <= self.rest_duration
+            && self.dvdt_threshold >= 0.0
+            && self.min_voltage.is_finite();
+
+        if valid {
+            Ok(())
+        } else {
+            Err(OcvError::InvalidConfig)
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq)]
+pub enum OcvError {
+    /// A value is out of range, see `OcvConfig::validate`
+    InvalidConfig,
+    /// The procedure can only be started while the unit is idle
+    Busy,
+}
+
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq)]
+pub enum OcvDirection {
+    Discharge,
+    Charge,
+}
+
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq)]
+pub struct OcvPoint {
+    /// from the nominal capacity, 1 is the full battery at the start
+    pub state_of_charge: f32,
+    /// charge removed since the start, in Ah
+    pub discharged: f32,
+    /// the relaxed voltage at the end of the rest, in V
+    pub voltage: f32,
+    /// how long the battery rested, in s
+    pub rest_duration: f32,
+    /// whether the point was reached by discharging or by charging
+    pub direction: OcvDirection,
+}
+
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq)]
+pub enum OcvEndReason {
+    /// The battery was discharged and, if configured, charged again
+    Completed,
+    /// The table has `MAX_OCV_POINTS` points, the increment is too small for the battery
+    TableFull,
+    /// Stopped by the client or a fault
+    Stopped,
+}
+
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq)]
+pub struct OcvEnd {
+    /// number of points in the table
+    pub points: u8,
+    pub reason: OcvEndReason,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Phase {
+    Rest,
+    Discharge,
+    Charge,
+}
+
+/// The result of `OcvProcedure::update`
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub struct OcvUpdate {
+    /// whether another phase started, the mode of the battery test unit has to follow
+    pub phase_changed: bool,
+    /// the index and the point that was just recorded
+    pub point: Option<(u8, OcvPoint)>,
+    pub end: Option<OcvEnd>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+struct OcvState {
+    config: OcvConfig,
+    direction: OcvDirection,
+    phase: Phase,
+    /// time since the phase started, in s
+    phase_time: f32,
+    /// charge removed since the start, in Ah
+    discharged: f32,
+    /// charge moved during the current increment, in Ah
+    moved: f32,
+    /// the end of the current direction was reached, the next rest is the last one
+    last_rest: bool,
+    /// time into the rest and voltage the voltage change is measured against
+    reference: (f32, f32),
+}
+
+/// Runs the OCV procedure and keeps the table of the last run. Like the `Sequencer` it only
+/// decides what runs, the battery test unit sets the mode of the phase.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct OcvProcedure {
+    table: Vec<OcvPoint, MAX_OCV_POINTS>,
+    state: Option<OcvState>,
+}
+
+impl OcvProcedure {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.state.is_some()
+    }
+
+    /// The points recorded so far, they stay available after the procedure ended
+    pub fn get_table(&self) -> &[OcvPoint] {
+        &self.table
+    }
+
+    /// The mode of the running phase, idle if the procedure doesn't run
+    pub fn get_mode(&self) -> BatteryTestUnitMode {
+        match self.state {
+            Some(state) => match state.phase {
+                Phase::Rest => BatteryTestUnitMode::Resting,
+                Phase::Discharge => {
+                    BatteryTestUnitMode::DischargingConstantCurrent(state.config.discharge_current)
+                }
+                Phase::Charge => BatteryTestUnitMode::Charging,
+            },
+            None => BatteryTestUnitMode::Idle,
+        }
+    }
+
+    /// Clears the table and starts with a rest, the battery has to be full
+    pub fn start(&mut self, config: OcvConfig) -> Result<(), OcvError> {
+        if self.is_running() {
+            return Err(OcvError::Busy);
+        }
+        config.validate()?;
+
+        self.table.clear();
+        self.state = Some(OcvState {
+            config,
+            direction: OcvDirection::Discharge,
+            phase: Phase::Rest,
+            phase_time: 0.0,
+            discharged: 0.0,
+            moved: 0.0,
+            last_rest: false,
+            reference: (0.0, f32::NAN),
+        });
+        Ok(())
+    }
+
+    /// Returns the state of the table if the procedure was running, the table is kept
+    pub fn stop(&mut self) -> Option<OcvEnd> {
+        self.state.take()?;
+        Some(self.end(OcvEndReason::Stopped))
+    }
+
+    pub fn update(&mut self, measurement: &StepMeasurement, delta_time: f32) -> OcvUpdate {
+        let mut update = OcvUpdate::default();
+        let Some(state) = self.state.as_mut() else {
+            return update;
+        };
+        let config = state.config;
+        state.phase_time += delta_time;
+
+        let next_phase = match state.phase {
+            Phase::Rest => {
+                if state.reference.1.is_nan() {
+                    state.reference = (state.phase_time, measurement.voltage);
+                }
+
+                let (reference_time, reference_voltage) = state.reference;
+                let mut relaxed = false;
+                if state.phase_time - reference_time >= config.dvdt_interval {
+                    let dvdt = (measurement.voltage - reference_voltage)
+                        / (state.phase_time - reference_time);
+                    relaxed = libm::fabsf(dvdt) < config.dvdt_threshold;
+                    state.reference = (state.phase_time, measurement.voltage);
+                }
+
+                let rest_over = state.phase_time >= config.rest_duration
+                    || (relaxed && state.phase_time >= config.min_rest_duration);
+                if !rest_over {
+                    None
+                } else {
+                    let point = OcvPoint {
+                        state_of_charge: 1.0 - state.discharged / config.nominal_capacity,
+                        discharged: state.discharged,
+                        voltage: measurement.voltage,
+                        rest_duration: state.phase_time,
+                        direction: state.direction,
+                    };
+                    // can't fail, the procedure ends once the table is full
+                    let _ = self.table.push(point);
+                    update.point = Some((self.table.len() as u8 - 1, point));
+
+                    if state.last_rest
+                        && state.direction == OcvDirection::Discharge
+                        && config.charge
+                    {
+                        state.direction = OcvDirection::Charge;
+                        state.last_rest = false;
+                    } else if state.last_rest {
+                        self.finish(OcvEndReason::Completed, &mut update);
+                        return update;
+                    }
+
+                    if self.table.is_full() {
+                        self.finish(OcvEndReason::TableFull, &mut update);
+                        return update;
+                    }
+                    match state.direction {
+                        OcvDirection::Discharge => Some(Phase::Discharge),
+                        OcvDirection::Charge => Some(Phase::Charge),
+                    }
+                }
+            }
+            Phase::Discharge => {
+                let charge = measurement.current * delta_time / 3600.0;
+                state.discharged += charge;
+                state.moved += charge;
+
+                state.last_rest = measurement.voltage < config.min_voltage;
+                if state.last_rest || state.moved >= config.increment {
+                    Some(Phase::Rest)
+                } else {
+                    None
+                }
+            }
+            Phase::Charge => {
+                let charge = measurement.current * delta_time / 3600.0;
+                state.discharged += charge;
+                state.moved -= charge;
+
+                state.last_rest = measurement.charge_done;
+                if state.last_rest || state.moved >= config.increment {
+                    Some(Phase::Rest)
+                } else {
+                    None
+                }
+            }
+        };
+
+        if let Some(phase) = next_phase {
+            state.phase = phase;
+            state.phase_time = 0.0;
+            state.moved = 0.0;
+            state.reference = (0.0, f32::NAN);
+            update.phase_changed = true;
+        }
+        update
+    }
+
+    fn end(&self, reason: OcvEndReason) -> OcvEnd {
+        OcvEnd {
+            points: self.table.len() as u8,
+            reason,
+        }
+    }
+
+    fn finish(&mut self, reason: OcvEndReason, update: &mut OcvUpdate) {
+        self.state = None;
+        update.phase_changed = true;
+        update.end = Some(self.end(reason));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: OcvConfig = OcvConfig {
+        nominal_capacity: 1.0,
+        increment: 0.25,
+        discharge_current: 1.0,
+        rest_duration: 100.0,
+        min_rest_duration: 10.0,
+        dvdt_threshold: 0.001,
+        dvdt_interval: 5.0,
+        min_voltage: 2.5,
+        charge: false,
+    };
+
+    /// A battery whose voltage follows the state of charge and relaxes towards it while resting
+    struct Battery {
+        soc: f32,
+        voltage: f32,
+        charge_done: bool,
+    }
+
+    impl Battery {
+        fn ocv(&self) -> f32 {
+            2.5 + self.soc
+        }
+
+        fn update(&mut self, mode: BatteryTestUnitMode, delta_time: f32) -> StepMeasurement {
+            let current = match mode {
+                BatteryTestUnitMode::DischargingConstantCurrent(current) => current,
+                BatteryTestUnitMode::Charging if self.soc < 1.0 => -1.0,
+                _ => 0.0,
+            };
+            self.soc -= current * delta_time / 3600.0;
+            self.charge_done = self.soc >= 1.0;
+            // 100 mΩ and a voltage that settles within seconds once the current stops
+            self.voltage = self.ocv() - 0.1 * current + (self.voltage - self.ocv()) * 0.5;
+
+            StepMeasurement {
+                voltage: self.voltage,
+                current,
+                temperature: None,
+                charge_done: self.charge_done,
+            }
+        }
+    }
+
+    fn run(procedure: &mut OcvProcedure, battery: &mut Battery) -> (Vec<OcvPoint, 16>, OcvEnd) {
+        let mut points = Vec::new();
+        for _ in 0..100000 {
+            let measurement = battery.update(procedure.get_mode(), 1.0);
+            let update = procedure.update(&measurement, 1.0);
+            if let Some((index, point)) = update.point {
+                assert_eq!(index as usize, points.len());
+                points.push(point).unwrap();
+            }
+            if let Some(end) = update.end {
+                return (points, end);
+            }
+        }
+        panic!("the procedure didn't end");
+    }
+
+    #[test]
+    fn test_validate() {
+        assert_eq!(CONFIG.validate(), Ok(()));
+        for config in [
+            OcvConfig {
+                increment: 0.0,
+                ..CONFIG
+            },
+            OcvConfig {
+                min_rest_duration: 200.0,
+                ..CONFIG
+            },
+            OcvConfig {
+                dvdt_threshold: -1.0,
+                ..CONFIG
+            },
+        ] {
+            assert_eq!(config.validate(), Err(OcvError::InvalidConfig));
+        }
+    }
+
+    #[test]
+    fn test_discharge() {
+        let mut procedure = OcvProcedure::new();
+        let mut battery = Battery {
+            soc: 1.0,
+            voltage: 3.5,
+            charge_done: true,
+        };
+        procedure.start(CONFIG).unwrap();
+        assert_eq!(procedure.start(CONFIG), Err(OcvError::Busy));
+
+        let (points, end) = run(&mut procedure, &mut battery);
+
+        // 1, 0.75, 0.5, 0.25 and the empty battery
+        assert_eq!(
+            end,
+            OcvEnd {
+                points: 5,
+                reason: OcvEndReason::Completed
+            }
+        );
+        assert_eq!(procedure.get_table(), points.as_slice());
+        for (point, soc) in points.iter().zip([1.0, 0.75, 0.5, 0.25]) {
+            assert!((point.state_of_charge - soc).abs() < 0.01);
+        }
+        // the voltage under load dropped below the minimum before the last increment was done
+        assert!(points[4].state_of_charge < 0.25);
+        for point in &points {
+            assert!((point.voltage - (2.5 + point.state_of_charge)).abs() < 0.005);
+            // the voltage settled long before the rest duration
+            assert!(point.rest_duration < 20.0);
+            assert_eq!(point.direction, OcvDirection::Discharge);
+        }
+        assert_eq!(procedure.get_mode(), BatteryTestUnitMode::Idle);
+    }
+
+    #[test]
+    fn test_discharge_and_charge() {
+        let mut procedure = OcvProcedure::new();
+        let mut battery = Battery {
+            soc: 1.0,
+            voltage: 3.5,
+            charge_done: true,
+        };
+        procedure
+            .start(OcvConfig {
+                charge: true,
+                dvdt_threshold: 0.0,
+                ..CONFIG
+            })
+            .unwrap();
+
+        let (points, end) = run(&mut procedure, &mut battery);
+
+        assert_eq!(end.reason, OcvEndReason::Completed);
+        assert_eq!(points.len(), 9);
+        // without a threshold every rest takes the whole duration
+        assert!(points.iter().all(|point| point.rest_duration == 100.0));
+        assert_eq!(points[4].direction, OcvDirection::Discharge);
+        assert_eq!(points[5].direction, OcvDirection::Charge);
+        assert!((points[5].state_of_charge - points[4].state_of_charge - 0.25).abs() < 0.01);
+        assert!((points[8].state_of_charge - 1.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn test_table_full() {
+        let mut procedure = OcvProcedure::new();
+        let mut battery = Battery {
+            soc: 1.0,
+            voltage: 3.5,
+            charge_done: true,
+        };
+        procedure
+            .start(OcvConfig {
+                increment: 0.001,
+                ..CONFIG
+            })
+            .unwrap();
+
+        let mut end = None;
+        while end.is_none() {
+            let measurement = battery.update(procedure.get_mode(), 1.0);
+            end = procedure.update(&measurement, 1.0).end;
+        }
+
+        assert_eq!(
+            end,
+            Some(OcvEnd {
+                points: MAX_OCV_POINTS as u8,
+                reason: OcvEndReason::TableFull
+            })
+        );
+        assert_eq!(procedure.stop(), None, "the procedure ended on its own");
+        assert_eq!(procedure.get_table().len(), MAX_OCV_POINTS);
+    }
+}
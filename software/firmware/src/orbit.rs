@@ -0,0 +1,419 @@
+use heapless::Vec;
+use serde::{Deserialize, Serialize};
+
+use crate::sequence::StepMeasurement;
+use crate::BatteryTestUnitMode;
+
+/// Maximum number of segments of an orbit profile
+pub const MAX_SEGMENTS: usize = 32;
+
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq)]
+pub enum SegmentLoad {
+    /// in W
+    Power(f32),
+    /// in A
+    Current(f32),
+}
+
+/// A part of an orbit during which the load of the satellite bus stays the same
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq)]
+pub struct Segment {
+    /// in s
+    pub duration: f32,
+    /// the discharge load, zero for a segment without load
+    pub load: SegmentLoad,
+    /// Whether the charger is on, like in sunlight. The hat only measures the battery current, so
+    /// a load during charging would fight the charger and segments can't do both.
+    pub charge: bool,
+}
+
+impl Segment {
+    fn get_mode(&self) -> BatteryTestUnitMode {
+        match self.load {
+            _ if self.charge => BatteryTestUnitMode::Charging,
+            SegmentLoad::Power(power) if power > 0.0 => {
+                BatteryTestUnitMode::DischargingConstantPower(power)
+            }
+            SegmentLoad::Current(current) if current > 0.0 => {
+                BatteryTestUnitMode::DischargingConstantCurrent(current)
+            }
+            _ => BatteryTestUnitMode::Resting,
+        }
+    }
+
+    fn has_load(&self) -> bool {
+        match self.load {
+            SegmentLoad::Power(value) | SegmentLoad::Current(value) => value != 0.0,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq)]
+pub enum OrbitError {
+    /// The profile has no segments
+    Empty,
+    /// Segments have to be uploaded in order, the given index is past the end of the profile
+    InvalidIndex(u8),
+    /// The profile already has `MAX_SEGMENTS` segments
+    TooManySegments,
+    /// The segment with the given index isn't longer than zero
+    InvalidDuration(u8),
+    /// The segment with the given index charges and has a load
+    LoadWhileCharging(u8),
+    /// The profile can't be changed or started while the unit is busy
+    Busy,
+}
+
+/// Statistics of one complete orbit
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq)]
+pub struct OrbitStats {
+    /// number of the orbit, starting at 0
+    pub orbit: u16,
+    /// the most charge removed since the start of the orbit, in Ah
+    pub depth_of_discharge: f32,
+    /// the lowest voltage at the end of an eclipse, i.e. before a charging segment starts, in V
+    pub end_of_eclipse_voltage: Option<f32>,
+    /// in V
+    pub min_voltage: f32,
+    /// in V
+    pub max_voltage: f32,
+    /// in Ah
+    pub discharged: f32,
+    /// in Ah
+    pub charged: f32,
+}
+
+impl OrbitStats {
+    fn new(orbit: u16) -> Self {
+        Self {
+            orbit,
+            depth_of_discharge: 0.0,
+            end_of_eclipse_voltage: None,
+            min_voltage: f32::INFINITY,
+            max_voltage: f32::NEG_INFINITY,
+            discharged: 0.0,
+            charged: 0.0,
+        }
+    }
+}
+
+/// The result of `OrbitPlayer::update`
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub struct OrbitUpdate {
+    /// whether another segment started, the mode of the battery test unit has to follow
+    pub segment_changed: bool,
+    /// the statistics of the orbit that just ended
+    pub stats: Option<OrbitStats>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+struct OrbitState {
+    orbits: u16,
+    segment: u8,
+    /// time since the segment started, in s
+    segment_time: f32,
+    /// charge removed since the start of the orbit, negative after charging, in Ah
+    removed: f32,
+    stats: OrbitStats,
+}
+
+/// Replays an orbit profile a given number of times. Like the `Sequencer` it only decides what
+/// runs, the battery test unit sets the mode of the segment and controls the load.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct OrbitPlayer {
+    segments: Vec<Segment, MAX_SEGMENTS>,
+    state: Option<OrbitState>,
+}
+
+impl OrbitPlayer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Replaces the segment with the given index or appends it if `index` is the number of
+    /// segments
+    pub fn set_segment(&mut self, index: u8, segment: Segment) -> Result<(), OrbitError> {
+        if self.is_running() {
+            return Err(OrbitError::Busy);
+        }
+
+        let position = index as usize;
+        if position < self.segments.len() {
+            self.segments[position] = segment;
+            Ok(())
+        } else if position == self.segments.len() {
+            self.segments
+                .push(segment)
+                .map_err(|_| OrbitError::TooManySegments)
+        } else {
+            Err(OrbitError::InvalidIndex(index))
+        }
+    }
+
+    pub fn get_segments(&self) -> &[Segment] {
+        &self.segments
+    }
+
+    pub fn clear(&mut self) -> Result<(), OrbitError> {
+        if self.is_running() {
+            return Err(OrbitError::Busy);
+        }
+        self.segments.clear();
+        Ok(())
+    }
+
+    pub fn validate(&self) -> Result<(), OrbitError> {
+        if self.segments.is_empty() {
+            return Err(OrbitError::Empty);
+        }
+        for (index, segment) in self.segments.iter().enumerate() {
+            if segment.duration.is_nan() || segment.duration <= 0.0 {
+                return Err(OrbitError::InvalidDuration(index as u8));
+            }
+            if segment.charge && segment.has_load() {
+                return Err(OrbitError::LoadWhileCharging(index as u8));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.state.is_some()
+    }
+
+    /// The number of the running orbit, starting at 0
+    pub fn get_orbit(&self) -> Option<u16> {
+        self.state.map(|state| state.stats.orbit)
+    }
+
+    /// The mode of the running segment, idle if no orbits are replayed
+    pub fn get_mode(&self) -> BatteryTestUnitMode {
+        match self.state {
+            Some(state) => self.segments[state.segment as usize].get_mode(),
+            None => BatteryTestUnitMode::Idle,
+        }
+    }
+
+    /// Replays the profile `orbits` times
+    pub fn start(&mut self, orbits: u16) -> Result<(), OrbitError> {
+        if self.is_running() {
+            return Err(OrbitError::Busy);
+        }
+        self.validate()?;
+
+        if orbits > 0 {
+            self.state = Some(OrbitState {
+                orbits,
+                segment: 0,
+                segment_time: 0.0,
+                removed: 0.0,
+                stats: OrbitStats::new(0),
+            });
+        }
+        Ok(())
+    }
+
+    /// Stops in the middle of an orbit, no statistics are reported for it
+    pub fn stop(&mut self) {
+        self.state = None;
+    }
+
+    pub fn update(&mut self, measurement: &StepMeasurement, delta_time: f32) -> OrbitUpdate {
+        let mut update = OrbitUpdate::default();
+        let Some(state) = self.state.as_mut() else {
+            return update;
+        };
+
+        let charge = measurement.current * delta_time / 3600.0;
+        state.removed += charge;
+        let stats = &mut state.stats;
+        stats.depth_of_discharge = stats.depth_of_discharge.max(state.removed);
+        if charge > 0.0 {
+            stats.discharged += charge;
+        } else {
+            stats.charged -= charge;
+        }
+        stats.min_voltage = stats.min_voltage.min(measurement.voltage);
+        stats.max_voltage = stats.max_voltage.max(measurement.voltage);
+
+        state.segment_time += delta_time;
+        loop {
+            let segment = self.segments[state.segment as usize];
+            if state.segment_time < segment.duration {
+                break;
+            }
+            state.segment_time -= segment.duration;
+            update.segment_changed = true;
+
+            let next = (state.segment as usize + 1) % self.segments.len();
+            if !segment.charge && self.segments[next].charge {
+                let voltage = state
+                    .stats
+                    .end_of_eclipse_voltage
+                    .map_or(measurement.voltage, |v| v.min(measurement.voltage));
+                state.stats.end_of_eclipse_voltage = Some(voltage);
+            }
+            state.segment = next as u8;
+
+            if next == 0 {
+                let orbit = state.stats.orbit + 1;
+                update.stats = Some(state.stats);
+                state.removed = 0.0;
+                state.stats = OrbitStats::new(orbit);
+
+                if orbit == state.orbits {
+                    self.state = None;
+                    break;
+                }
+            }
+        }
+        update
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn segment(duration: f32, load: SegmentLoad, charge: bool) -> Segment {
+        Segment {
+            duration,
+            load,
+            charge,
+        }
+    }
+
+    /// 60 s of sunlight, a transmitter burst of 10 s and 30 s of eclipse
+    fn player() -> OrbitPlayer {
+        let mut player = OrbitPlayer::new();
+        player
+            .set_segment(0, segment(60.0, SegmentLoad::Power(0.0), true))
+            .unwrap();
+        player
+            .set_segment(1, segment(10.0, SegmentLoad::Current(2.0), false))
+            .unwrap();
+        player
+            .set_segment(2, segment(30.0, SegmentLoad::Power(1.0), false))
+            .unwrap();
+        player
+    }
+
+    fn measurement(voltage: f32, current: f32) -> StepMeasurement {
+        StepMeasurement {
+            voltage,
+            current,
+            temperature: None,
+            charge_done: false,
+        }
+    }
+
+    #[test]
+    fn test_upload() {
+        let mut player = player();
+        let idle = segment(1.0, SegmentLoad::Current(0.0), false);
+
+        assert_eq!(
+            player.set_segment(4, idle),
+            Err(OrbitError::InvalidIndex(4))
+        );
+        assert_eq!(player.set_segment(3, idle), Ok(()));
+        assert_eq!(player.get_segments().len(), 4);
+        assert_eq!(player.validate(), Ok(()));
+
+        assert_eq!(
+            player.set_segment(3, segment(0.0, SegmentLoad::Current(0.0), false)),
+            Ok(())
+        );
+        assert_eq!(player.validate(), Err(OrbitError::InvalidDuration(3)));
+        assert_eq!(
+            player.set_segment(3, segment(1.0, SegmentLoad::Current(1.0), true)),
+            Ok(())
+        );
+        assert_eq!(player.validate(), Err(OrbitError::LoadWhileCharging(3)));
+
+        assert_eq!(player.clear(), Ok(()));
+        assert_eq!(player.start(1), Err(OrbitError::Empty));
+    }
+
+    #[test]
+    fn test_segments() {
+        let mut player = player();
+
+        player.start(2).unwrap();
+        assert_eq!(player.start(2), Err(OrbitError::Busy));
+        assert_eq!(player.get_mode(), BatteryTestUnitMode::Charging);
+
+        let update = player.update(&measurement(3.4, -1.0), 59.0);
+        assert!(!update.segment_changed);
+        let update = player.update(&measurement(3.4, -1.0), 1.0);
+        assert!(update.segment_changed);
+        assert_eq!(
+            player.get_mode(),
+            BatteryTestUnitMode::DischargingConstantCurrent(2.0)
+        );
+
+        // an update can skip a segment
+        player.update(&measurement(3.3, 2.0), 15.0);
+        assert_eq!(
+            player.get_mode(),
+            BatteryTestUnitMode::DischargingConstantPower(1.0)
+        );
+        assert_eq!(player.get_orbit(), Some(0));
+    }
+
+    #[test]
+    fn test_orbit_stats() {
+        let mut player = player();
+        player.start(2).unwrap();
+
+        // sunlight charges 0.5 Ah
+        player.update(&measurement(3.4, -30.0), 60.0);
+        // the burst and the eclipse remove 0.25 Ah each
+        player.update(&measurement(3.1, 90.0), 10.0);
+        player.update(&measurement(3.2, 30.0), 15.0);
+        let update = player.update(&measurement(3.0, 30.0), 15.0);
+
+        assert!(update.segment_changed);
+        assert_eq!(
+            update.stats,
+            Some(OrbitStats {
+                orbit: 0,
+                depth_of_discharge: 0.0,
+                end_of_eclipse_voltage: Some(3.0),
+                min_voltage: 3.0,
+                max_voltage: 3.4,
+                discharged: 0.5,
+                charged: 0.5,
+            })
+        );
+        assert_eq!(player.get_orbit(), Some(1));
+        assert_eq!(player.get_mode(), BatteryTestUnitMode::Charging);
+
+        // the depth of discharge is counted from the start of the orbit
+        let stats = player.update(&measurement(3.3, 9.0), 100.0).stats.unwrap();
+        assert_eq!(stats.orbit, 1);
+        assert_eq!(stats.depth_of_discharge, 0.25);
+        assert_eq!(stats.end_of_eclipse_voltage, Some(3.3));
+        assert!(!player.is_running());
+    }
+
+    #[test]
+    fn test_orbit_count() {
+        let mut player = player();
+
+        player.start(0).unwrap();
+        assert!(!player.is_running());
+
+        player.start(3).unwrap();
+        let mut orbits = vec![];
+        for _ in 0..10 {
+            if let Some(stats) = player.update(&measurement(3.3, 0.0), 50.0).stats {
+                orbits.push(stats.orbit);
+            }
+        }
+
+        assert_eq!(orbits, [0, 1, 2]);
+        assert!(!player.is_running());
+        assert_eq!(player.get_mode(), BatteryTestUnitMode::Idle);
+    }
+}
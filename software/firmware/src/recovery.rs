@@ -0,0 +1,214 @@
+//! Finds the tests a reset interrupted. After every update the firmware stores what the battery
+//! test units do in memory that survives a reset, after the next start it reports the units
+//! that ran a test and can resume them.
+//!
+//! | bytes  | content                                                   |
+//! |--------|-----------------------------------------------------------|
+//! | 0..76  | one slot of `RUN_SIZE` bytes per unit, see `UnitRun`      |
+//! | 76..80 | CRC-32 over bytes 0..76                                   |
+//!
+//! | bytes  | content of a slot                                         |
+//! |--------|-----------------------------------------------------------|
+//! | 0      | bit 0: the unit ran a test, bit 1: the test is resumable  |
+//! | 1..7   | mode, serialized                                          |
+//! | 7..19  | duration, capacity and energy                             |
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::crc32;
+use crate::traits::RetainedMemory;
+use crate::BatteryTestUnitMode;
+
+/// The 80 bytes of the backup registers of the board hold this many units
+pub const MAX_RUN_UNITS: usize = 4;
+const RUN_SIZE: usize = 19;
+pub const RECORD_SIZE: usize = MAX_RUN_UNITS * RUN_SIZE + 4;
+/// How much longer than the sample period an update may take before the watchdog isn't fed
+/// anymore, in ms
+pub const WATCHDOG_TOLERANCE: u64 = 500;
+
+const FLAG_RUNNING: u8 = 0x01;
+const FLAG_RESUMABLE: u8 = 0x02;
+
+/// The test a battery test unit ran
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq)]
+pub struct UnitRun {
+    pub mode: BatteryTestUnitMode,
+    /// Only a mode that was set on its own can be resumed, a program or procedure can't
+    /// continue where it stopped
+    pub resumable: bool,
+    /// time since the unit left idle, in s
+    pub duration: f32,
+    /// in Ah
+    pub capacity: f32,
+    /// in Wh
+    pub energy: f32,
+}
+
+impl UnitRun {
+    fn encode(run: Option<&UnitRun>) -> [u8; RUN_SIZE] {
+        let mut bytes = [0; RUN_SIZE];
+        let Some(run) = run else {
+            return bytes;
+        };
+        bytes[0] = FLAG_RUNNING | if run.resumable { FLAG_RESUMABLE } else { 0 };
+        // every mode but the fault fits, a unit in the fault state doesn't run a test
+        if postcard::to_slice(&run.mode, &mut bytes[1..7]).is_err() {
+            return [0; RUN_SIZE];
+        }
+        bytes[7..11].copy_from_slice(&run.duration.to_le_bytes());
+        bytes[11..15].copy_from_slice(&run.capacity.to_le_bytes());
+        bytes[15..19].copy_from_slice(&run.energy.to_le_bytes());
+        bytes
+    }
+
+    fn decode(bytes: &[u8]) -> Option<UnitRun> {
+        if bytes[0] & FLAG_RUNNING == 0 {
+            return None;
+        }
+        let float = |start: usize| f32::from_le_bytes(bytes[start..start + 4].try_into().unwrap());
+        Some(UnitRun {
+            mode: postcard::from_bytes(&bytes[1..7]).ok()?,
+            resumable: bytes[0] & FLAG_RESUMABLE != 0,
+            duration: float(7),
+            capacity: float(11),
+            energy: float(15),
+        })
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq)]
+pub enum RecoveryError {
+    /// The unit didn't run a test when the board was reset, or it was resumed already
+    NotInterrupted,
+    /// The unit ran a program or procedure
+    NotResumable,
+    /// The unit runs a test again
+    Busy,
+}
+
+/// Stores the runs of the units, those beyond `MAX_RUN_UNITS` are left out. Does nothing if the
+/// memory is too small.
+pub fn save(memory: &mut impl RetainedMemory, runs: impl Iterator<Item = Option<UnitRun>>) {
+    if memory.capacity() < RECORD_SIZE {
+        return;
+    }
+    let mut record = [0; RECORD_SIZE];
+    for (slot, run) in record.chunks_exact_mut(RUN_SIZE).zip(runs) {
+        slot.copy_from_slice(&UnitRun::encode(run.as_ref()));
+    }
+    let crc = crc32(&[&record[..RECORD_SIZE - 4]]);
+    record[RECORD_SIZE - 4..].copy_from_slice(&crc.to_le_bytes());
+    memory.write(&record);
+}
+
+/// The runs that were stored last by unit, `None` if nothing valid was stored, e.g. after the
+/// board was powered up
+pub fn load(memory: &mut impl RetainedMemory) -> Option<[Option<UnitRun>; MAX_RUN_UNITS]> {
+    if memory.capacity() < RECORD_SIZE {
+        return None;
+    }
+    let mut record = [0; RECORD_SIZE];
+    memory.read(&mut record);
+    let crc = u32::from_le_bytes(record[RECORD_SIZE - 4..].try_into().unwrap());
+    if crc != crc32(&[&record[..RECORD_SIZE - 4]]) {
+        return None;
+    }
+    let mut slots = record.chunks_exact(RUN_SIZE);
+    Some(core::array::from_fn(|_| {
+        UnitRun::decode(slots.next().unwrap())
+    }))
+}
+
+/// The tests the last reset interrupted
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Recovery {
+    interrupted: [Option<UnitRun>; MAX_RUN_UNITS],
+}
+
+impl Recovery {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set_interrupted(&mut self, runs: [Option<UnitRun>; MAX_RUN_UNITS]) {
+        self.interrupted = runs;
+    }
+
+    /// The run to resume, it can only be resumed once
+    pub fn take_interrupted(&mut self, unit: u8) -> Result<UnitRun, RecoveryError> {
+        let run = self
+            .interrupted
+            .get_mut(unit as usize)
+            .and_then(|run| run.take())
+            .ok_or(RecoveryError::NotInterrupted)?;
+        if !run.resumable {
+            return Err(RecoveryError::NotResumable);
+        }
+        Ok(run)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mocks::MockRetainedMemory;
+
+    const RUN: UnitRun = UnitRun {
+        mode: BatteryTestUnitMode::DischargingConstantCurrent(1.5),
+        resumable: true,
+        duration: 120.0,
+        capacity: 0.05,
+        energy: 0.16,
+    };
+
+    #[test]
+    fn test_save_load() {
+        let mut memory = MockRetainedMemory::new(RECORD_SIZE);
+        // the memory is cleared at power up
+        assert_eq!(load(&mut memory), None);
+
+        let program = UnitRun {
+            mode: BatteryTestUnitMode::Resting,
+            resumable: false,
+            ..RUN
+        };
+        let runs = [Some(RUN), None, Some(program), None, Some(RUN)];
+        save(&mut memory, runs.into_iter());
+        assert_eq!(
+            load(&mut memory),
+            Some([Some(RUN), None, Some(program), None])
+        );
+
+        memory.memory[8] ^= 0x01;
+        assert_eq!(load(&mut memory), None);
+    }
+
+    #[test]
+    fn test_take_interrupted() {
+        let mut recovery = Recovery::new();
+        recovery.set_interrupted([
+            Some(RUN),
+            Some(UnitRun {
+                resumable: false,
+                ..RUN
+            }),
+            None,
+            None,
+        ]);
+
+        assert_eq!(recovery.take_interrupted(0), Ok(RUN));
+        assert_eq!(
+            recovery.take_interrupted(0),
+            Err(RecoveryError::NotInterrupted)
+        );
+        assert_eq!(
+            recovery.take_interrupted(1),
+            Err(RecoveryError::NotResumable)
+        );
+        assert_eq!(
+            recovery.take_interrupted(7),
+            Err(RecoveryError::NotInterrupted)
+        );
+    }
+}
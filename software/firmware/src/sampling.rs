@@ -0,0 +1,264 @@
+use serde::{Deserialize, Serialize};
+
+use crate::config::{DEFAULT_SAMPLE_PERIOD, MAX_SAMPLE_PERIOD};
+
+/// How often a battery test unit is sampled and how many of the samples are sent as telemetry
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq)]
+pub struct SamplingConfig {
+    /// time between two samples, in ms
+    pub period: u16,
+    /// every `decimation`-th sample is sent as telemetry, 1 sends all of them
+    pub decimation: u16,
+    /// time between two samples after the mode changed or at a voltage knee, in ms
+    pub fast_period: u16,
+    /// how long it samples with the fast period after the last of these events, in ms, 0
+    /// never samples fast
+    pub fast_duration: u32,
+    /// rate of change of the voltage that counts as a knee, in V/s, 0 ignores the voltage
+    pub knee_slope: f32,
+}
+
+impl Default for SamplingConfig {
+    fn default() -> Self {
+        Self {
+            period: DEFAULT_SAMPLE_PERIOD,
+            decimation: 1,
+            fast_period: DEFAULT_SAMPLE_PERIOD,
+            fast_duration: 0,
+            knee_slope: 0.0,
+        }
+    }
+}
+
+impl SamplingConfig {
+    pub fn validate(&self) -> Result<(), SamplingError> {
+        if self.period == 0
+            || self.period > MAX_SAMPLE_PERIOD
+            || self.fast_period == 0
+            || self.decimation == 0
+        {
+            return Err(SamplingError::InvalidPeriod);
+        }
+        if self.fast_period > self.period {
+            return Err(SamplingError::InvalidFastPeriod);
+        }
+        if self.knee_slope.is_nan() || self.knee_slope < 0.0 {
+            return Err(SamplingError::InvalidKneeSlope);
+        }
+        Ok(())
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq)]
+pub enum SamplingError {
+    /// The periods and the decimation have to be at least 1, the period at most
+    /// `MAX_SAMPLE_PERIOD`
+    InvalidPeriod,
+    /// The fast period can't be longer than the period
+    InvalidFastPeriod,
+    /// The knee slope can't be negative
+    InvalidKneeSlope,
+}
+
+/// Decides when a unit is sampled and which samples are sent as telemetry
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct SampleScheduler {
+    config: SamplingConfig,
+    /// time since the last sample, in µs
+    elapsed: u32,
+    /// samples since the last one that was sent
+    samples: u16,
+    /// time it still samples with the fast period, in µs
+    fast_remaining: u64,
+    /// voltage of the last sample, in V
+    last_voltage: Option<f32>,
+}
+
+impl SampleScheduler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set_config(&mut self, config: SamplingConfig) -> Result<(), SamplingError> {
+        config.validate()?;
+        self.config = config;
+        self.samples = 0;
+        self.fast_remaining = self.fast_remaining.min(config.fast_duration as u64 * 1000);
+        Ok(())
+    }
+
+    pub fn get_config(&self) -> &SamplingConfig {
+        &self.config
+    }
+
+    /// The period it samples with at the moment, in ms
+    pub fn get_period(&self) -> u16 {
+        if self.fast_remaining > 0 {
+            self.config.fast_period
+        } else {
+            self.config.period
+        }
+    }
+
+    /// in ms, rounded up
+    pub fn get_time_to_sample(&self) -> u32 {
+        (self.get_period() as u32 * 1000)
+            .saturating_sub(self.elapsed)
+            .div_ceil(1000)
+    }
+
+    /// Samples with the fast period for the fast duration, e.g. because the mode changed
+    pub fn trigger(&mut self) {
+        self.fast_remaining = self.config.fast_duration as u64 * 1000;
+    }
+
+    /// Advances the time by `delta_time` in µs. Once the next sample is due it returns the
+    /// time since the last one, in µs.
+    pub fn update(&mut self, delta_time: u32) -> Option<u32> {
+        self.elapsed = self.elapsed.saturating_add(delta_time);
+        if self.elapsed < self.get_period() as u32 * 1000 {
+            return None;
+        }
+
+        self.fast_remaining = self.fast_remaining.saturating_sub(self.elapsed as u64);
+        Some(core::mem::take(&mut self.elapsed))
+    }
+
+    /// Checks the sample for a voltage knee and returns whether it is sent as telemetry.
+    /// `interval` is the time since the last sample, in µs.
+    pub fn add_sample(&mut self, voltage: f32, interval: u32) -> bool {
+        if let Some(last_voltage) = self.last_voltage {
+            let slope = libm::fabsf(voltage - last_voltage) * 1e6 / interval.max(1) as f32;
+            if self.config.knee_slope > 0.0 && slope >= self.config.knee_slope {
+                self.trigger();
+            }
+        }
+        self.last_voltage = Some(voltage);
+
+        self.samples += 1;
+        if self.samples < self.config.decimation {
+            return false;
+        }
+        self.samples = 0;
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FAST: SamplingConfig = SamplingConfig {
+        period: 1000,
+        decimation: 2,
+        fast_period: 100,
+        fast_duration: 500,
+        knee_slope: 0.01,
+    };
+
+    /// Updates every 100 ms for the given time in ms and collects the intervals of the samples
+    fn run(scheduler: &mut SampleScheduler, duration: u32) -> Vec<u32> {
+        (0..duration / 100)
+            .filter_map(|_| scheduler.update(100_000))
+            .collect()
+    }
+
+    #[test]
+    fn test_validate() {
+        assert_eq!(SamplingConfig::default().validate(), Ok(()));
+        assert_eq!(FAST.validate(), Ok(()));
+
+        let invalid = [
+            (
+                SamplingConfig { period: 0, ..FAST },
+                SamplingError::InvalidPeriod,
+            ),
+            (
+                SamplingConfig {
+                    period: MAX_SAMPLE_PERIOD + 1,
+                    ..FAST
+                },
+                SamplingError::InvalidPeriod,
+            ),
+            (
+                SamplingConfig {
+                    decimation: 0,
+                    ..FAST
+                },
+                SamplingError::InvalidPeriod,
+            ),
+            (
+                SamplingConfig {
+                    fast_period: 2000,
+                    ..FAST
+                },
+                SamplingError::InvalidFastPeriod,
+            ),
+            (
+                SamplingConfig {
+                    knee_slope: -1.0,
+                    ..FAST
+                },
+                SamplingError::InvalidKneeSlope,
+            ),
+            (
+                SamplingConfig {
+                    knee_slope: f32::NAN,
+                    ..FAST
+                },
+                SamplingError::InvalidKneeSlope,
+            ),
+        ];
+        for (config, error) in invalid {
+            assert_eq!(SampleScheduler::new().set_config(config), Err(error));
+        }
+    }
+
+    #[test]
+    fn test_period() {
+        let mut scheduler = SampleScheduler::new();
+        scheduler.set_config(FAST).unwrap();
+
+        assert_eq!(run(&mut scheduler, 3000), [1_000_000; 3]);
+        scheduler.update(300_000);
+        assert_eq!(scheduler.get_time_to_sample(), 700);
+        // the part of a ms counts as a whole one
+        scheduler.update(200);
+        assert_eq!(scheduler.get_time_to_sample(), 700);
+    }
+
+    #[test]
+    fn test_fast_sampling() {
+        let mut scheduler = SampleScheduler::new();
+        scheduler.set_config(FAST).unwrap();
+
+        scheduler.trigger();
+        assert_eq!(scheduler.get_period(), 100);
+        assert_eq!(run(&mut scheduler, 500), [100_000; 5]);
+        assert_eq!(scheduler.get_period(), 1000);
+
+        // 5 mV/s is below the knee slope
+        scheduler.add_sample(3.300, 1_000_000);
+        scheduler.add_sample(3.295, 1_000_000);
+        assert_eq!(scheduler.get_period(), 1000);
+        // 20 mV/s isn't
+        scheduler.add_sample(3.275, 1_000_000);
+        assert_eq!(scheduler.get_period(), 100);
+    }
+
+    #[test]
+    fn test_decimation() {
+        let mut scheduler = SampleScheduler::new();
+        scheduler
+            .set_config(SamplingConfig {
+                knee_slope: 0.0,
+                ..FAST
+            })
+            .unwrap();
+
+        let sent = (0..6)
+            .map(|_| scheduler.add_sample(3.3, 1_000_000))
+            .collect::<Vec<_>>();
+        assert_eq!(sent, [false, true, false, true, false, true]);
+    }
+}
@@ -0,0 +1,325 @@
+use crate::traits::{AdcInput, CurrentInput, GpioInput, TemperatureInput};
+
+/// Gain of the INA181A2 current sense amplifier on the hat, in V/V
+pub const INA181A2_GAIN: f32 = 50.0;
+
+/// Resistance of the shunt on the hat, in Ω
+pub const SHUNT_RESISTANCE: f32 = 0.005;
+
+/// Measures the current through a shunt resistor with a current sense amplifier,
+/// whose output is sampled by an `AdcInput`.
+pub struct ShuntCurrentSensor<T: AdcInput> {
+    pub adc: T,
+    /// in V/V
+    pub gain: f32,
+    /// in Ω
+    pub shunt_resistance: f32,
+    /// amplifier output at zero current, in V
+    pub offset_voltage: f32,
+}
+
+impl<T: AdcInput> ShuntCurrentSensor<T> {
+    pub fn new(adc: T, gain: f32, shunt_resistance: f32, offset_voltage: f32) -> Self {
+        Self {
+            adc,
+            gain,
+            shunt_resistance,
+            offset_voltage,
+        }
+    }
+}
+
+impl<T: AdcInput> CurrentInput for ShuntCurrentSensor<T> {
+    fn get_current(&mut self) -> f32 {
+        (self.adc.get_voltage() - self.offset_voltage) / (self.gain * self.shunt_resistance)
+    }
+}
+
+/// 0 °C in K
+const ZERO_CELSIUS: f32 = 273.15;
+
+/// Relates the resistance of an NTC thermistor to its temperature
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum NtcModel {
+    /// R = R0 * exp(beta * (1/T - 1/T0))
+    Beta {
+        /// in Ω
+        r0: f32,
+        /// in °C
+        t0: f32,
+        /// in K
+        beta: f32,
+    },
+    /// 1/T = a + b * ln(R) + c * ln(R)^3
+    SteinhartHart { a: f32, b: f32, c: f32 },
+}
+
+impl NtcModel {
+    /// Beta model of a 10 kΩ NTC (R25 = 10 kΩ)
+    pub fn ntc_10k(beta: f32) -> Self {
+        NtcModel::Beta {
+            r0: 10_000.0,
+            t0: 25.0,
+            beta,
+        }
+    }
+
+    /// Fits the Steinhart–Hart coefficients through three (temperature in °C, resistance in Ω)
+    /// points, e.g. taken from the datasheet of the thermistor
+    pub fn steinhart_hart_from_points(points: [(f32, f32); 3]) -> Self {
+        // the coefficients differ by orders of magnitude, so they are solved in f64
+        let [(t1, r1), (t2, r2), (t3, r3)] =
+            points.map(|(t, r)| (t as f64 + ZERO_CELSIUS as f64, r as f64));
+        let (l1, l2, l3) = (libm::log(r1), libm::log(r2), libm::log(r3));
+        let (y1, y2, y3) = (1.0 / t1, 1.0 / t2, 1.0 / t3);
+
+        let g2 = (y2 - y1) / (l2 - l1);
+        let g3 = (y3 - y1) / (l3 - l1);
+
+        let c = (g3 - g2) / (l3 - l2) / (l1 + l2 + l3);
+        let b = g2 - c * (l1 * l1 + l1 * l2 + l2 * l2);
+        let a = y1 - (b + l1 * l1 * c) * l1;
+
+        NtcModel::SteinhartHart {
+            a: a as f32,
+            b: b as f32,
+            c: c as f32,
+        }
+    }
+
+    /// Returns the temperature in °C for a resistance in Ω, NaN if the resistance isn't positive
+    /// and finite
+    pub fn temperature(&self, resistance: f32) -> f32 {
+        if !resistance.is_finite() || resistance <= 0.0 {
+            return f32::NAN;
+        }
+        let inverse_temperature = match *self {
+            NtcModel::Beta { r0, t0, beta } => {
+                1.0 / (t0 + ZERO_CELSIUS) + libm::logf(resistance / r0) / beta
+            }
+            NtcModel::SteinhartHart { a, b, c } => {
+                let ln_r = libm::logf(resistance);
+                a + b * ln_r + c * ln_r * ln_r * ln_r
+            }
+        };
+
+        let temperature = 1.0 / inverse_temperature - ZERO_CELSIUS;
+        if temperature.is_finite() {
+            temperature
+        } else {
+            f32::NAN
+        }
+    }
+}
+
+/// Measures the temperature with an NTC thermistor that forms a voltage divider with a fixed
+/// resistor. The thermistor sits between the ADC input and ground, the divider resistor between
+/// the reference voltage and the ADC input.
+pub struct NtcThermistor<T: AdcInput> {
+    pub adc: T,
+    pub model: NtcModel,
+    /// in Ω
+    pub divider_resistance: f32,
+    /// voltage the divider is supplied with, in V
+    pub reference_voltage: f32,
+}
+
+impl<T: AdcInput> NtcThermistor<T> {
+    pub fn new(adc: T, model: NtcModel, divider_resistance: f32, reference_voltage: f32) -> Self {
+        Self {
+            adc,
+            model,
+            divider_resistance,
+            reference_voltage,
+        }
+    }
+
+    /// Returns the resistance of the thermistor in Ω. NaN if the thermistor is open, the input
+    /// reads the reference voltage, or shorted, the input reads 0 V, the limits fault on it.
+    pub fn get_resistance(&mut self) -> f32 {
+        let voltage = self.adc.get_voltage();
+        if !(voltage > 0.0 && voltage < self.reference_voltage) {
+            return f32::NAN;
+        }
+        self.divider_resistance * voltage / (self.reference_voltage - voltage)
+    }
+}
+
+impl<T: AdcInput> TemperatureInput for NtcThermistor<T> {
+    fn get_temperature(&mut self) -> f32 {
+        let resistance = self.get_resistance();
+        self.model.temperature(resistance)
+    }
+}
+
+/// Reads a logic signal through an `AdcInput`, like the open-drain ~CHRG_DONE output of the
+/// TP5000 charger that is routed to one of the converters on the hat.
+pub struct AdcDigitalInput<T: AdcInput> {
+    pub adc: T,
+    /// in V
+    pub threshold: f32,
+    /// whether the signal is asserted while it is below the threshold
+    pub active_low: bool,
+}
+
+impl<T: AdcInput> AdcDigitalInput<T> {
+    pub fn new(adc: T, threshold: f32, active_low: bool) -> Self {
+        Self {
+            adc,
+            threshold,
+            active_low,
+        }
+    }
+}
+
+impl<T: AdcInput> GpioInput for AdcDigitalInput<T> {
+    /// A failed conversion reads as not asserted
+    fn get_input(&mut self) -> bool {
+        let voltage = self.adc.get_voltage();
+        if voltage.is_nan() {
+            return false;
+        }
+        (voltage > self.threshold) != self.active_low
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mocks::MockAdcInput;
+
+    #[test]
+    fn test_shunt_current_sensor() {
+        let mut sensor =
+            ShuntCurrentSensor::new(MockAdcInput::new(), INA181A2_GAIN, SHUNT_RESISTANCE, 0.0);
+
+        assert_eq!(sensor.get_current(), 0.0);
+
+        sensor.adc.set_voltage(0.25);
+        assert_eq!(sensor.get_current(), 1.0);
+
+        sensor.adc.set_voltage(1.25);
+        assert_eq!(sensor.get_current(), 5.0);
+
+        sensor.offset_voltage = 1.25;
+        sensor.adc.set_voltage(1.0);
+        assert_eq!(sensor.get_current(), -1.0);
+    }
+
+    // resistance table of the EPCOS B57861S0103F040 (R/T characteristic 8016)
+    const DATASHEET_POINTS: [(f32, f32); 5] = [
+        (-20.0, 96_358.0),
+        (0.0, 32_575.0),
+        (25.0, 10_000.0),
+        (50.0, 3_592.6),
+        (100.0, 680.65),
+    ];
+
+    macro_rules! assert_temperature_eq {
+        ($temperature:expr, $expected:expr, $tolerance:expr) => {
+            let (temperature, expected): (f32, f32) = ($temperature, $expected);
+            assert!(
+                (temperature - expected).abs() <= $tolerance,
+                "{} °C differs from the expected {} °C",
+                temperature,
+                expected
+            );
+        };
+    }
+
+    #[test]
+    fn test_beta_model() {
+        // B25/100 from the datasheet
+        let model = NtcModel::ntc_10k(3988.0);
+
+        assert_temperature_eq!(model.temperature(10_000.0), 25.0, 1e-4);
+        // the beta model is exact at T0 and B25/100 is fitted at 100 °C
+        assert_temperature_eq!(model.temperature(680.65), 100.0, 0.1);
+
+        // the beta model is only accurate around the range it was fitted for
+        for (t, r) in &DATASHEET_POINTS[1..] {
+            assert_temperature_eq!(model.temperature(*r), *t, 1.0);
+        }
+        assert_temperature_eq!(model.temperature(DATASHEET_POINTS[0].1), -20.0, 2.0);
+    }
+
+    #[test]
+    fn test_steinhart_hart_model() {
+        let model = NtcModel::steinhart_hart_from_points([
+            DATASHEET_POINTS[1],
+            DATASHEET_POINTS[2],
+            DATASHEET_POINTS[4],
+        ]);
+
+        for (t, r) in DATASHEET_POINTS {
+            assert_temperature_eq!(model.temperature(r), t, 0.3);
+        }
+    }
+
+    #[test]
+    fn test_ntc_thermistor() {
+        let mut sensor = NtcThermistor::new(
+            MockAdcInput::new(),
+            NtcModel::ntc_10k(3988.0),
+            10_000.0,
+            3.3,
+        );
+
+        // at 25 °C both resistors of the divider are equal
+        sensor.adc.set_voltage(1.65);
+        assert_eq!(sensor.get_resistance(), 10_000.0);
+        assert_temperature_eq!(sensor.get_temperature(), 25.0, 1e-4);
+
+        // 3592.6 Ω at 50 °C
+        sensor.adc.set_voltage(3.3 * 3_592.6 / 13_592.6);
+        assert_temperature_eq!(sensor.get_temperature(), 50.0, 0.5);
+
+        // a higher temperature means a lower resistance and voltage
+        sensor.adc.set_voltage(0.3);
+        assert!(sensor.get_temperature() > 50.0);
+    }
+
+    #[test]
+    fn test_ntc_thermistor_open_and_shorted() {
+        let mut sensor = NtcThermistor::new(
+            MockAdcInput::new(),
+            NtcModel::ntc_10k(3988.0),
+            10_000.0,
+            3.3,
+        );
+
+        // open, the divider resistor pulls the input up to the reference voltage
+        for voltage in [3.3, 3.4] {
+            sensor.adc.set_voltage(voltage);
+            assert!(sensor.get_resistance().is_nan());
+            assert!(sensor.get_temperature().is_nan());
+        }
+        // shorted
+        for voltage in [0.0, -0.01] {
+            sensor.adc.set_voltage(voltage);
+            assert!(sensor.get_resistance().is_nan());
+            assert!(sensor.get_temperature().is_nan());
+        }
+
+        let model = NtcModel::ntc_10k(3988.0);
+        assert!(model.temperature(0.0).is_nan());
+        assert!(model.temperature(f32::INFINITY).is_nan());
+    }
+
+    #[test]
+    fn test_adc_digital_input() {
+        let mut input = AdcDigitalInput::new(MockAdcInput::from(2.5), 1.25, true);
+
+        assert!(!input.get_input());
+        input.adc.set_voltage(0.1);
+        assert!(input.get_input());
+        input.adc.set_voltage(f32::NAN);
+        assert!(!input.get_input());
+
+        input.active_low = false;
+        input.adc.set_voltage(2.5);
+        assert!(input.get_input());
+        input.adc.set_voltage(0.1);
+        assert!(!input.get_input());
+    }
+}
@@ -0,0 +1,847 @@
+use heapless::Vec;
+use serde::{Deserialize, Serialize};
+
+use crate::BatteryTestUnitMode;
+
+/// Maximum number of steps of a program
+pub const MAX_STEPS: usize = 16;
+/// Maximum number of end conditions of a single step
+pub const MAX_END_CONDITIONS: usize = 4;
+/// Maximum number of `Loop` and `GotoIf` steps evaluated in a row before the program is
+/// considered to be stuck
+const MAX_JUMPS: usize = 4 * MAX_STEPS;
+
+/// A step ends as soon as any of its end conditions is met
+pub type EndConditions = Vec<Condition, MAX_END_CONDITIONS>;
+
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq)]
+pub enum Condition {
+    /// in V
+    VoltageBelow(f32),
+    /// in V
+    VoltageAbove(f32),
+    /// magnitude of the current, in A
+    CurrentBelow(f32),
+    /// magnitude of the current, in A
+    CurrentAbove(f32),
+    /// in °C, never met if the unit has no temperature sensor
+    TemperatureBelow(f32),
+    /// in °C, never met if the unit has no temperature sensor
+    TemperatureAbove(f32),
+    /// time since the step started, in s
+    Duration(f32),
+    /// charge moved since the step started, in Ah
+    Capacity(f32),
+    /// the charger finished its constant voltage phase
+    ChargeDone,
+}
+
+impl Condition {
+    fn is_met(&self, measurement: &StepMeasurement, progress: &StepProgress) -> bool {
+        let current = libm::fabsf(measurement.current);
+
+        match *self {
+            Condition::VoltageBelow(voltage) => measurement.voltage < voltage,
+            Condition::VoltageAbove(voltage) => measurement.voltage > voltage,
+            Condition::CurrentBelow(limit) => current < limit,
+            Condition::CurrentAbove(limit) => current > limit,
+            Condition::TemperatureBelow(limit) => {
+                measurement.temperature.is_some_and(|t| t < limit)
+            }
+            Condition::TemperatureAbove(limit) => {
+                measurement.temperature.is_some_and(|t| t > limit)
+            }
+            Condition::Duration(duration) => progress.duration >= duration,
+            Condition::Capacity(capacity) => progress.capacity >= capacity,
+            Condition::ChargeDone => measurement.charge_done,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum Step {
+    /// Enables the charger, which charges with constant current and then constant voltage on its
+    /// own
+    Charge(EndConditions),
+    /// Discharges with the given current, in A
+    DischargeConstantCurrent(f32, EndConditions),
+    /// Discharges with the given power, in W
+    DischargeConstantPower(f32, EndConditions),
+    /// Discharges like the given resistance, in Ω
+    DischargeConstantResistance(f32, EndConditions),
+    /// Turns the load and the charger off, the safety limits are still checked
+    Rest(EndConditions),
+    /// Jumps back to the step with the given index `count` times, then continues with the next
+    /// step
+    Loop { target: u8, count: u16 },
+    /// Jumps to the step with the given index if the condition is met, otherwise continues with
+    /// the next step. Duration and capacity refer to the step that ended last.
+    GotoIf { condition: Condition, target: u8 },
+}
+
+impl Step {
+    /// The mode the battery test unit runs in during the step, `None` for steps that only jump
+    pub fn get_mode(&self) -> Option<BatteryTestUnitMode> {
+        match *self {
+            Step::Charge(_) => Some(BatteryTestUnitMode::Charging),
+            Step::DischargeConstantCurrent(current, _) => {
+                Some(BatteryTestUnitMode::DischargingConstantCurrent(current))
+            }
+            Step::DischargeConstantPower(power, _) => {
+                Some(BatteryTestUnitMode::DischargingConstantPower(power))
+            }
+            Step::DischargeConstantResistance(resistance, _) => Some(
+                BatteryTestUnitMode::DischargingConstantResistance(resistance),
+            ),
+            Step::Rest(_) => Some(BatteryTestUnitMode::Resting),
+            Step::Loop { .. } | Step::GotoIf { .. } => None,
+        }
+    }
+
+    fn get_end_conditions(&self) -> &[Condition] {
+        match self {
+            Step::Charge(end)
+            | Step::DischargeConstantCurrent(_, end)
+            | Step::DischargeConstantPower(_, end)
+            | Step::DischargeConstantResistance(_, end)
+            | Step::Rest(end) => end,
+            Step::Loop { .. } | Step::GotoIf { .. } => &[],
+        }
+    }
+
+    fn get_target(&self) -> Option<u8> {
+        match *self {
+            Step::Loop { target, .. } | Step::GotoIf { target, .. } => Some(target),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq)]
+pub enum ProgramError {
+    /// The program has no steps
+    Empty,
+    /// Steps have to be uploaded in order, the given index is past the end of the program
+    InvalidIndex(u8),
+    /// The program already has `MAX_STEPS` steps
+    TooManySteps,
+    /// The step with the given index jumps past the end of the program
+    InvalidTarget(u8),
+    /// The step with the given index would never end
+    MissingEndCondition(u8),
+    /// The step with the given index jumps back without running any step in between
+    EmptyLoop(u8),
+    /// The program can't be changed or started while the unit is busy
+    Busy,
+}
+
+/// The steps of a test, executed by a `Sequencer`
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Program {
+    steps: Vec<Step, MAX_STEPS>,
+}
+
+impl Program {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Replaces the step with the given index or appends it if `index` is the number of steps
+    pub fn set_step(&mut self, index: u8, step: Step) -> Result<(), ProgramError> {
+        let index = index as usize;
+
+        if index < self.steps.len() {
+            self.steps[index] = step;
+            Ok(())
+        } else if index == self.steps.len() {
+            self.steps
+                .push(step)
+                .map_err(|_| ProgramError::TooManySteps)
+        } else {
+            Err(ProgramError::InvalidIndex(index as u8))
+        }
+    }
+
+    pub fn get_steps(&self) -> &[Step] {
+        &self.steps
+    }
+
+    pub fn clear(&mut self) {
+        self.steps.clear();
+    }
+
+    /// Checks everything that can be checked before the program runs
+    pub fn validate(&self) -> Result<(), ProgramError> {
+        if self.steps.is_empty() {
+            return Err(ProgramError::Empty);
+        }
+
+        for (index, step) in self.steps.iter().enumerate() {
+            let index = index as u8;
+
+            if step.get_mode().is_some() && step.get_end_conditions().is_empty() {
+                return Err(ProgramError::MissingEndCondition(index));
+            }
+            if let Some(target) = step.get_target() {
+                if target as usize >= self.steps.len() {
+                    return Err(ProgramError::InvalidTarget(index));
+                }
+                if target <= index
+                    && !self.steps[target as usize..index as usize]
+                        .iter()
+                        .any(|step| step.get_mode().is_some())
+                {
+                    return Err(ProgramError::EmptyLoop(index));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Everything the end conditions of a step are checked against
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub struct StepMeasurement {
+    /// in V
+    pub voltage: f32,
+    /// in A, positive while discharging
+    pub current: f32,
+    /// in °C, `None` if the unit has no temperature sensor
+    pub temperature: Option<f32>,
+    pub charge_done: bool,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+struct StepProgress {
+    /// in s
+    duration: f32,
+    /// in Ah
+    capacity: f32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq)]
+pub enum TransitionReason {
+    /// The program was started
+    Started,
+    /// The given end condition of the step was met
+    EndCondition(Condition),
+    /// The program was stopped before it finished, e.g. because of a fault
+    Stopped,
+    /// The client aborted the program, the transition holds the partial step
+    Aborted,
+    /// The program kept jumping without reaching a step that does something
+    EndlessJumps,
+}
+
+/// Sent to the client whenever a program moves to another step
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq)]
+pub struct StepTransition {
+    /// the step that ended, `None` if the program just started
+    pub from: Option<u8>,
+    /// the step that starts, `None` if the program ended
+    pub to: Option<u8>,
+    pub reason: TransitionReason,
+    /// duration of the step that ended, in s
+    pub duration: f32,
+    /// charge moved during the step that ended, in Ah
+    pub capacity: f32,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct RunState {
+    step: u8,
+    progress: StepProgress,
+    /// how often each `Loop` step already jumped back
+    loop_counters: [u16; MAX_STEPS],
+    /// the step neither advances nor ends until the program is resumed
+    paused: bool,
+}
+
+/// Runs a program step by step. It only decides which step runs, the battery test unit sets the
+/// mode of the step and controls the load.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Sequencer {
+    program: Program,
+    state: Option<RunState>,
+}
+
+impl Sequencer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get_program(&self) -> &Program {
+        &self.program
+    }
+
+    pub fn set_step(&mut self, index: u8, step: Step) -> Result<(), ProgramError> {
+        if self.is_running() {
+            return Err(ProgramError::Busy);
+        }
+        self.program.set_step(index, step)
+    }
+
+    pub fn clear_program(&mut self) -> Result<(), ProgramError> {
+        if self.is_running() {
+            return Err(ProgramError::Busy);
+        }
+        self.program.clear();
+        Ok(())
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.state.is_some()
+    }
+
+    /// Index of the step that is running
+    pub fn get_step(&self) -> Option<u8> {
+        self.state.as_ref().map(|state| state.step)
+    }
+
+    /// The mode of the step that is running, idle if no program runs
+    pub fn get_mode(&self) -> BatteryTestUnitMode {
+        self.state
+            .as_ref()
+            .and_then(|state| self.program.steps[state.step as usize].get_mode())
+            .unwrap_or(BatteryTestUnitMode::Idle)
+    }
+
+    /// Starts the program from its first step, the measurement is used by `GotoIf` steps at the
+    /// start of the program
+    pub fn start(&mut self, measurement: &StepMeasurement) -> Result<StepTransition, ProgramError> {
+        if self.is_running() {
+            return Err(ProgramError::Busy);
+        }
+        self.program.validate()?;
+
+        self.state = Some(RunState {
+            step: 0,
+            progress: StepProgress::default(),
+            loop_counters: [0; MAX_STEPS],
+            paused: false,
+        });
+        Ok(self.enter(None, 0, TransitionReason::Started, measurement))
+    }
+
+    /// Stops the program, returns the transition to report if it was running
+    pub fn stop(&mut self) -> Option<StepTransition> {
+        self.end(TransitionReason::Stopped)
+    }
+
+    /// Like `stop`, but the transition is reported as aborted
+    pub fn abort(&mut self) -> Option<StepTransition> {
+        self.end(TransitionReason::Aborted)
+    }
+
+    /// Holds the running step with its duration, capacity and loop counters until `resume`
+    pub fn pause(&mut self) {
+        if let Some(state) = self.state.as_mut() {
+            state.paused = true;
+        }
+    }
+
+    pub fn resume(&mut self) {
+        if let Some(state) = self.state.as_mut() {
+            state.paused = false;
+        }
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.state.as_ref().is_some_and(|state| state.paused)
+    }
+
+    /// Advances the running step by `delta_time` and moves on if one of its end conditions is met
+    pub fn update(
+        &mut self,
+        measurement: &StepMeasurement,
+        delta_time: f32,
+    ) -> Option<StepTransition> {
+        let state = self.state.as_mut()?;
+        if state.paused {
+            return None;
+        }
+        state.progress.duration += delta_time;
+        state.progress.capacity += libm::fabsf(measurement.current) * delta_time / 3600.0;
+
+        let step = state.step;
+        let condition = *self.program.steps[step as usize]
+            .get_end_conditions()
+            .iter()
+            .find(|condition| condition.is_met(measurement, &state.progress))?;
+
+        Some(self.enter(
+            Some(step),
+            step as usize + 1,
+            TransitionReason::EndCondition(condition),
+            measurement,
+        ))
+    }
+
+    fn end(&mut self, reason: TransitionReason) -> Option<StepTransition> {
+        let state = self.state.take()?;
+
+        Some(StepTransition {
+            from: Some(state.step),
+            to: None,
+            reason,
+            duration: state.progress.duration,
+            capacity: state.progress.capacity,
+        })
+    }
+
+    /// Follows all jumps starting at `index` until a step that runs is reached or the program
+    /// ends
+    fn enter(
+        &mut self,
+        from: Option<u8>,
+        mut index: usize,
+        reason: TransitionReason,
+        measurement: &StepMeasurement,
+    ) -> StepTransition {
+        let Some(state) = self.state.as_mut() else {
+            unreachable!("only running programs enter steps");
+        };
+        let finished = state.progress;
+        let transition = |to, reason| StepTransition {
+            from,
+            to,
+            reason,
+            duration: finished.duration,
+            capacity: finished.capacity,
+        };
+
+        for _ in 0..MAX_JUMPS {
+            match self.program.steps.get(index) {
+                None => {
+                    self.state = None;
+                    return transition(None, reason);
+                }
+                Some(&Step::Loop { target, count }) => {
+                    if state.loop_counters[index] < count {
+                        state.loop_counters[index] += 1;
+                        index = target as usize;
+                    } else {
+                        // nested loops start counting again the next time they are reached
+                        state.loop_counters[index] = 0;
+                        index += 1;
+                    }
+                }
+                Some(&Step::GotoIf { condition, target }) => {
+                    if condition.is_met(measurement, &finished) {
+                        index = target as usize;
+                    } else {
+                        index += 1;
+                    }
+                }
+                Some(_) => {
+                    state.step = index as u8;
+                    state.progress = StepProgress::default();
+                    return transition(Some(index as u8), reason);
+                }
+            }
+        }
+
+        self.state = None;
+        transition(None, TransitionReason::EndlessJumps)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn end(conditions: &[Condition]) -> EndConditions {
+        Vec::from_slice(conditions).unwrap()
+    }
+
+    fn program(steps: &[Step]) -> Program {
+        let mut program = Program::new();
+        for (index, step) in steps.iter().enumerate() {
+            program.set_step(index as u8, step.clone()).unwrap();
+        }
+        program
+    }
+
+    fn sequencer(steps: &[Step]) -> Sequencer {
+        Sequencer {
+            program: program(steps),
+            state: None,
+        }
+    }
+
+    fn at_voltage(voltage: f32) -> StepMeasurement {
+        StepMeasurement {
+            voltage,
+            current: 1.0,
+            temperature: Some(25.0),
+            charge_done: false,
+        }
+    }
+
+    #[test]
+    fn test_upload() {
+        let mut program = Program::new();
+        let rest = Step::Rest(end(&[Condition::Duration(1.0)]));
+
+        assert_eq!(
+            program.set_step(1, rest.clone()),
+            Err(ProgramError::InvalidIndex(1))
+        );
+        assert_eq!(program.set_step(0, rest.clone()), Ok(()));
+        assert_eq!(program.set_step(1, rest.clone()), Ok(()));
+        assert_eq!(
+            program.set_step(0, Step::Charge(end(&[Condition::ChargeDone]))),
+            Ok(())
+        );
+        assert_eq!(program.get_steps().len(), 2);
+        assert_eq!(program.get_steps()[1], rest);
+
+        for index in 2..MAX_STEPS {
+            program.set_step(index as u8, rest.clone()).unwrap();
+        }
+        assert_eq!(
+            program.set_step(MAX_STEPS as u8, rest),
+            Err(ProgramError::TooManySteps)
+        );
+    }
+
+    #[test]
+    fn test_validate() {
+        let rest = Step::Rest(end(&[Condition::Duration(1.0)]));
+
+        assert_eq!(program(&[]).validate(), Err(ProgramError::Empty));
+        assert_eq!(program(core::slice::from_ref(&rest)).validate(), Ok(()));
+        assert_eq!(
+            program(&[rest.clone(), Step::Rest(end(&[]))]).validate(),
+            Err(ProgramError::MissingEndCondition(1))
+        );
+        assert_eq!(
+            program(&[
+                rest.clone(),
+                Step::Loop {
+                    target: 2,
+                    count: 1
+                }
+            ])
+            .validate(),
+            Err(ProgramError::InvalidTarget(1))
+        );
+        assert_eq!(
+            program(&[
+                rest.clone(),
+                Step::Loop {
+                    target: 0,
+                    count: 1
+                }
+            ])
+            .validate(),
+            Ok(())
+        );
+        assert_eq!(
+            program(&[
+                rest,
+                Step::GotoIf {
+                    condition: Condition::ChargeDone,
+                    target: 1
+                }
+            ])
+            .validate(),
+            Err(ProgramError::EmptyLoop(1))
+        );
+    }
+
+    #[test]
+    fn test_step_modes() {
+        let end = end(&[Condition::Duration(1.0)]);
+
+        assert_eq!(
+            Step::DischargeConstantPower(2.0, end.clone()).get_mode(),
+            Some(BatteryTestUnitMode::DischargingConstantPower(2.0))
+        );
+        assert_eq!(
+            Step::DischargeConstantResistance(4.0, end.clone()).get_mode(),
+            Some(BatteryTestUnitMode::DischargingConstantResistance(4.0))
+        );
+        assert_eq!(
+            Step::DischargeConstantResistance(4.0, end.clone()).get_end_conditions(),
+            end
+        );
+        assert_eq!(
+            Step::Loop {
+                target: 0,
+                count: 1
+            }
+            .get_mode(),
+            None
+        );
+    }
+
+    #[test]
+    fn test_end_conditions() {
+        let mut sequencer = sequencer(&[
+            Step::DischargeConstantCurrent(
+                1.0,
+                end(&[Condition::VoltageBelow(2.5), Condition::Duration(7200.0)]),
+            ),
+            Step::Rest(end(&[Condition::Duration(10.0)])),
+        ]);
+
+        let transition = sequencer.start(&at_voltage(3.3)).unwrap();
+        assert_eq!(transition.from, None);
+        assert_eq!(transition.to, Some(0));
+        assert_eq!(transition.reason, TransitionReason::Started);
+        assert_eq!(
+            sequencer.get_mode(),
+            BatteryTestUnitMode::DischargingConstantCurrent(1.0)
+        );
+
+        assert_eq!(sequencer.update(&at_voltage(3.0), 1800.0), None);
+        assert_eq!(
+            sequencer.update(&at_voltage(2.4), 1800.0),
+            Some(StepTransition {
+                from: Some(0),
+                to: Some(1),
+                reason: TransitionReason::EndCondition(Condition::VoltageBelow(2.5)),
+                duration: 3600.0,
+                capacity: 1.0,
+            })
+        );
+        assert_eq!(sequencer.get_mode(), BatteryTestUnitMode::Resting);
+
+        // the duration is counted from the start of the step
+        assert_eq!(sequencer.update(&at_voltage(3.0), 9.0), None);
+        let transition = sequencer.update(&at_voltage(3.0), 1.0).unwrap();
+        assert_eq!(transition.to, None);
+        assert_eq!(
+            transition.reason,
+            TransitionReason::EndCondition(Condition::Duration(10.0))
+        );
+        assert!(!sequencer.is_running());
+        assert_eq!(sequencer.get_mode(), BatteryTestUnitMode::Idle);
+    }
+
+    #[test]
+    fn test_conditions() {
+        let progress = StepProgress {
+            duration: 60.0,
+            capacity: 0.5,
+        };
+        let measurement = StepMeasurement {
+            voltage: 3.3,
+            current: -0.5,
+            temperature: Some(30.0),
+            charge_done: true,
+        };
+
+        macro_rules! t {
+            ($measurement:expr, $condition:expr, $met:expr) => {
+                assert_eq!($condition.is_met(&$measurement, &progress), $met);
+            };
+        }
+
+        t!(measurement, Condition::VoltageBelow(3.3), false);
+        t!(measurement, Condition::VoltageAbove(3.2), true);
+        // the current is compared by magnitude, it is negative while charging
+        t!(measurement, Condition::CurrentBelow(0.6), true);
+        t!(measurement, Condition::CurrentAbove(0.6), false);
+        t!(measurement, Condition::TemperatureBelow(30.0), false);
+        t!(measurement, Condition::TemperatureAbove(29.0), true);
+        t!(measurement, Condition::Duration(60.0), true);
+        t!(measurement, Condition::Capacity(0.6), false);
+        t!(measurement, Condition::ChargeDone, true);
+
+        let without_temperature = StepMeasurement {
+            temperature: None,
+            ..measurement
+        };
+        t!(
+            without_temperature,
+            Condition::TemperatureBelow(100.0),
+            false
+        );
+        t!(
+            without_temperature,
+            Condition::TemperatureAbove(-100.0),
+            false
+        );
+    }
+
+    #[test]
+    fn test_loop() {
+        let mut sequencer = sequencer(&[
+            Step::Charge(end(&[Condition::ChargeDone])),
+            Step::DischargeConstantCurrent(1.0, end(&[Condition::VoltageBelow(2.5)])),
+            Step::Loop {
+                target: 0,
+                count: 2,
+            },
+            Step::Rest(end(&[Condition::Duration(1.0)])),
+        ]);
+        let charged = StepMeasurement {
+            charge_done: true,
+            ..at_voltage(3.6)
+        };
+        let discharged = at_voltage(2.4);
+
+        sequencer.start(&charged).unwrap();
+        let mut steps = vec![];
+        while sequencer.is_running() {
+            let measurement = match sequencer.get_mode() {
+                BatteryTestUnitMode::Charging => charged,
+                _ => discharged,
+            };
+            steps.push(sequencer.update(&measurement, 1.0).unwrap().to);
+        }
+
+        // the loop jumps back twice, so the cycle runs three times
+        assert_eq!(
+            steps,
+            [Some(1), Some(0), Some(1), Some(0), Some(1), Some(3), None]
+        );
+    }
+
+    #[test]
+    fn test_nested_loops() {
+        let rest = Step::Rest(end(&[Condition::Duration(1.0)]));
+        let mut sequencer = sequencer(&[
+            rest.clone(),
+            rest,
+            Step::Loop {
+                target: 1,
+                count: 1,
+            },
+            Step::Loop {
+                target: 0,
+                count: 1,
+            },
+        ]);
+
+        let mut steps = vec![sequencer.start(&at_voltage(3.3)).unwrap().to];
+        while sequencer.is_running() {
+            steps.push(sequencer.update(&at_voltage(3.3), 1.0).unwrap().to);
+        }
+
+        assert_eq!(
+            steps,
+            [Some(0), Some(1), Some(1), Some(0), Some(1), Some(1), None]
+        );
+    }
+
+    #[test]
+    fn test_goto_if() {
+        let mut sequencer = sequencer(&[
+            Step::DischargeConstantCurrent(1.0, end(&[Condition::VoltageBelow(2.5)])),
+            // skip the rest after discharges that took longer than an hour
+            Step::GotoIf {
+                condition: Condition::Duration(3600.0),
+                target: 3,
+            },
+            Step::Rest(end(&[Condition::Duration(60.0)])),
+            Step::Charge(end(&[Condition::ChargeDone])),
+        ]);
+
+        sequencer.start(&at_voltage(3.3)).unwrap();
+        sequencer.update(&at_voltage(3.0), 3600.0);
+        assert_eq!(sequencer.update(&at_voltage(2.4), 1.0).unwrap().to, Some(3));
+
+        sequencer.stop();
+        sequencer.start(&at_voltage(3.3)).unwrap();
+        assert_eq!(sequencer.update(&at_voltage(2.4), 1.0).unwrap().to, Some(2));
+    }
+
+    #[test]
+    fn test_endless_jumps() {
+        let mut sequencer = sequencer(&[
+            Step::GotoIf {
+                condition: Condition::ChargeDone,
+                target: 2,
+            },
+            Step::Rest(end(&[Condition::Duration(1.0)])),
+            Step::GotoIf {
+                condition: Condition::ChargeDone,
+                target: 0,
+            },
+        ]);
+        let charged = StepMeasurement {
+            charge_done: true,
+            ..at_voltage(3.6)
+        };
+
+        let transition = sequencer.start(&charged).unwrap();
+        assert_eq!(transition.to, None);
+        assert_eq!(transition.reason, TransitionReason::EndlessJumps);
+        assert!(!sequencer.is_running());
+    }
+
+    #[test]
+    fn test_stop() {
+        let rest = Step::Rest(end(&[Condition::Duration(10.0)]));
+        let mut sequencer = sequencer(core::slice::from_ref(&rest));
+
+        assert_eq!(sequencer.stop(), None);
+
+        sequencer.start(&at_voltage(3.3)).unwrap();
+        assert_eq!(sequencer.start(&at_voltage(3.3)), Err(ProgramError::Busy));
+        assert_eq!(sequencer.set_step(0, rest), Err(ProgramError::Busy));
+        assert_eq!(sequencer.clear_program(), Err(ProgramError::Busy));
+
+        sequencer.update(&at_voltage(3.3), 2.0);
+        assert_eq!(
+            sequencer.stop(),
+            Some(StepTransition {
+                from: Some(0),
+                to: None,
+                reason: TransitionReason::Stopped,
+                duration: 2.0,
+                capacity: 2.0 / 3600.0,
+            })
+        );
+        assert_eq!(sequencer.update(&at_voltage(3.3), 2.0), None);
+        assert_eq!(sequencer.clear_program(), Ok(()));
+    }
+
+    #[test]
+    fn test_pause_and_abort() {
+        let rest = Step::Rest(end(&[Condition::Duration(10.0)]));
+        let mut sequencer = sequencer(&[rest.clone(), rest]);
+
+        sequencer.pause();
+        assert!(!sequencer.is_paused());
+        assert_eq!(sequencer.abort(), None);
+
+        sequencer.start(&at_voltage(3.3)).unwrap();
+        sequencer.update(&at_voltage(3.3), 6.0);
+        sequencer.pause();
+        assert!(sequencer.is_paused());
+        // the step keeps its progress and doesn't end while paused
+        assert_eq!(sequencer.update(&at_voltage(3.3), 60.0), None);
+        assert_eq!(sequencer.get_step(), Some(0));
+
+        sequencer.resume();
+        assert!(!sequencer.is_paused());
+        assert_eq!(sequencer.update(&at_voltage(3.3), 3.0), None);
+        assert!(matches!(
+            sequencer.update(&at_voltage(3.3), 1.0),
+            Some(StepTransition {
+                from: Some(0),
+                to: Some(1),
+                ..
+            })
+        ));
+
+        sequencer.update(&at_voltage(3.3), 2.0);
+        sequencer.pause();
+        assert_eq!(
+            sequencer.abort(),
+            Some(StepTransition {
+                from: Some(1),
+                to: None,
+                reason: TransitionReason::Aborted,
+                duration: 2.0,
+                capacity: 2.0 / 3600.0,
+            })
+        );
+        assert!(!sequencer.is_running());
+        assert!(!sequencer.is_paused());
+    }
+}
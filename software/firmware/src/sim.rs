@@ -0,0 +1,483 @@
+use crate::sensors::SHUNT_RESISTANCE;
+use crate::soc::{EcmParameters, OcvCurve};
+use crate::traits::{
+    AdcInput, CurrentInput, GpioInput, GpioOutput, PwmOutput, SharedResource, TemperatureInput,
+};
+
+/// Maximum duty cycle of the simulated load PWM
+pub const SIM_MAX_DUTY_CYCLE: u16 = 1000;
+
+/// Heat exchange of the cell with its surroundings
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct ThermalParameters {
+    /// in J/K
+    pub heat_capacity: f32,
+    /// between the cell and the ambient, in K/W
+    pub thermal_resistance: f32,
+    /// in °C
+    pub ambient_temperature: f32,
+}
+
+impl ThermalParameters {
+    /// An 18650 cell in still air
+    pub const CELL_18650: ThermalParameters = ThermalParameters {
+        heat_capacity: 40.0,
+        thermal_resistance: 15.0,
+        ambient_temperature: 25.0,
+    };
+}
+
+/// A cell modelled by its equivalent circuit: the open circuit voltage in series with a resistance
+/// and one RC element. The losses in both resistances heat the cell up.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SimulatedCell {
+    curve: OcvCurve,
+    model: EcmParameters,
+    thermal: ThermalParameters,
+    /// from 0 to 1
+    soc: f32,
+    /// voltage over the RC element, in V
+    rc_voltage: f32,
+    /// in °C
+    temperature: f32,
+}
+
+impl SimulatedCell {
+    /// The cell starts relaxed at the ambient temperature
+    pub fn new(
+        curve: OcvCurve,
+        model: EcmParameters,
+        thermal: ThermalParameters,
+        soc: f32,
+    ) -> Self {
+        Self {
+            curve,
+            model,
+            thermal,
+            soc: soc.clamp(0.0, 1.0),
+            rc_voltage: 0.0,
+            temperature: thermal.ambient_temperature,
+        }
+    }
+
+    /// A LiFePO4 18650 cell with the given capacity in Ah. Its voltage rises steeply at the end
+    /// of the charge, so the charger reaches its charge voltage.
+    pub fn lifepo4(capacity: f32, soc: f32) -> Self {
+        let curve = OcvCurve::new(&[
+            (0.0, 2.5),
+            (0.05, 3.0),
+            (0.1, 3.2),
+            (0.2, 3.25),
+            (0.3, 3.28),
+            (0.5, 3.3),
+            (0.7, 3.32),
+            (0.9, 3.34),
+            (0.95, 3.36),
+            (1.0, 3.6),
+        ])
+        .unwrap();
+
+        Self::new(
+            curve,
+            EcmParameters {
+                capacity,
+                r0: 0.04,
+                r1: 0.02,
+                c1: 2000.0,
+            },
+            ThermalParameters::CELL_18650,
+            soc,
+        )
+    }
+
+    pub fn get_model(&self) -> &EcmParameters {
+        &self.model
+    }
+
+    pub fn get_state_of_charge(&self) -> f32 {
+        self.soc
+    }
+
+    pub fn set_state_of_charge(&mut self, soc: f32) {
+        self.soc = soc.clamp(0.0, 1.0);
+    }
+
+    /// in °C
+    pub fn get_temperature(&self) -> f32 {
+        self.temperature
+    }
+
+    pub fn set_temperature(&mut self, temperature: f32) {
+        self.temperature = temperature;
+    }
+
+    /// The voltage behind the series resistance, in V
+    pub fn get_source_voltage(&self) -> f32 {
+        self.curve.get_voltage(self.soc) - self.rc_voltage
+    }
+
+    /// The voltage at the terminals while the given current flows, positive while discharging,
+    /// in V
+    pub fn get_voltage(&self, current: f32) -> f32 {
+        self.get_source_voltage() - self.model.r0 * current
+    }
+
+    /// Lets the given current flow for `delta_time` seconds, positive while discharging
+    pub fn update(&mut self, current: f32, delta_time: f32) {
+        let model = self.model;
+        self.soc = (self.soc - current * delta_time / 3600.0 / model.capacity).clamp(0.0, 1.0);
+
+        let decay = if model.r1 > 0.0 {
+            libm::expf(-delta_time / (model.r1 * model.c1))
+        } else {
+            0.0
+        };
+        self.rc_voltage = decay * self.rc_voltage + (1.0 - decay) * model.r1 * current;
+
+        let mut heat = current * current * model.r0;
+        if model.r1 > 0.0 {
+            heat += self.rc_voltage * self.rc_voltage / model.r1;
+        }
+        // exact for a constant heat flow, so large steps stay stable
+        let thermal = self.thermal;
+        let steady_state = thermal.ambient_temperature + heat * thermal.thermal_resistance;
+        let decay = libm::expf(-delta_time / (thermal.thermal_resistance * thermal.heat_capacity));
+        self.temperature = steady_state + (self.temperature - steady_state) * decay;
+    }
+}
+
+/// The MOSFET load of the hat. The PWM is filtered to the gate voltage, so the MOSFET conducts
+/// like a current source that is set by the duty cycle until it is fully on.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct MosfetLoad {
+    /// gate voltage at the maximum duty cycle, in V
+    pub gate_drive_voltage: f32,
+    /// in V
+    pub threshold_voltage: f32,
+    /// I = k * (Vgs - Vth)² while saturated, in A/V²
+    pub transconductance: f32,
+    /// on-resistance of the MOSFET with the shunt and wiring, in Ω
+    pub resistance: f32,
+}
+
+impl MosfetLoad {
+    /// The DMT6009LCT with the 5 mΩ shunt of the hat. The 680 Ω / 10 kΩ gate filter divides
+    /// the 3.3 V PWM down to about 3.1 V, where the MOSFET has about 15 mΩ.
+    pub const HAT: MosfetLoad = MosfetLoad {
+        gate_drive_voltage: 3.1,
+        threshold_voltage: 1.5,
+        transconductance: 2.0,
+        resistance: 0.015 + SHUNT_RESISTANCE,
+    };
+
+    /// The current drawn from a source with the given voltage and series resistance, `duty` is
+    /// from 0 to 1
+    pub fn get_current(&self, duty: f32, source_voltage: f32, source_resistance: f32) -> f32 {
+        let overdrive =
+            (duty.clamp(0.0, 1.0) * self.gate_drive_voltage - self.threshold_voltage).max(0.0);
+        let saturation_current = self.transconductance * overdrive * overdrive;
+        let resistive_current = source_voltage.max(0.0) / (self.resistance + source_resistance);
+        saturation_current.min(resistive_current)
+    }
+}
+
+/// The TP5000 charger of the hat: constant current until the charge voltage is reached, then
+/// constant voltage until the current dropped to the termination current
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Charger {
+    /// in A
+    pub charge_current: f32,
+    /// in V
+    pub charge_voltage: f32,
+    /// in A
+    pub termination_current: f32,
+}
+
+impl Charger {
+    /// The TP5000 configured for a single LiFePO4 cell
+    pub const LIFEPO4: Charger = Charger {
+        charge_current: 1.0,
+        charge_voltage: 3.6,
+        termination_current: 0.1,
+    };
+
+    /// The charge current into a source with the given voltage and series resistance, in A
+    pub fn get_current(&self, source_voltage: f32, source_resistance: f32) -> f32 {
+        let headroom = self.charge_voltage - source_voltage;
+        if headroom <= 0.0 {
+            0.0
+        } else if source_resistance > 0.0 {
+            (headroom / source_resistance).min(self.charge_current)
+        } else {
+            self.charge_current
+        }
+    }
+}
+
+/// One battery test unit of the hat with its cell. The firmware reads and drives it through the
+/// handles returned by `channels`, the test advances it with `update`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BatterySimulation {
+    pub cell: SimulatedCell,
+    pub load: MosfetLoad,
+    pub charger: Charger,
+    duty_cycle: u16,
+    charger_enabled: bool,
+    /// latched once the charge terminated, until the charger is disabled
+    charge_done: bool,
+}
+
+impl BatterySimulation {
+    pub fn new(cell: SimulatedCell, load: MosfetLoad, charger: Charger) -> Self {
+        Self {
+            cell,
+            load,
+            charger,
+            duty_cycle: 0,
+            charger_enabled: false,
+            charge_done: false,
+        }
+    }
+
+    /// A LiFePO4 cell with the given capacity in Ah on the hat
+    pub fn lifepo4(capacity: f32, soc: f32) -> Self {
+        Self::new(
+            SimulatedCell::lifepo4(capacity, soc),
+            MosfetLoad::HAT,
+            Charger::LIFEPO4,
+        )
+    }
+
+    /// The current through the cell with the present outputs, positive while discharging, in A
+    pub fn get_current(&self) -> f32 {
+        let source_voltage = self.cell.get_source_voltage();
+        let source_resistance = self.cell.get_model().r0;
+        let duty = self.duty_cycle as f32 / SIM_MAX_DUTY_CYCLE as f32;
+
+        let mut current = self
+            .load
+            .get_current(duty, source_voltage, source_resistance);
+        if self.charger_enabled && !self.charge_done {
+            current -= self.charger.get_current(source_voltage, source_resistance);
+        }
+        current
+    }
+
+    /// The voltage at the cell terminals, in V
+    pub fn get_voltage(&self) -> f32 {
+        self.cell.get_voltage(self.get_current())
+    }
+
+    pub fn is_charge_done(&self) -> bool {
+        self.charge_done
+    }
+
+    /// Advances the simulation by `delta_time` seconds with the present outputs
+    pub fn update(&mut self, delta_time: f32) {
+        let current = self.get_current();
+        self.cell.update(current, delta_time);
+
+        if self.charger_enabled && !self.charge_done {
+            let source_voltage = self.cell.get_source_voltage();
+            let charge_current = self
+                .charger
+                .get_current(source_voltage, self.cell.get_model().r0);
+            self.charge_done = charge_current < self.charger.termination_current;
+        }
+    }
+}
+
+pub struct SimulatedVoltage<'a, S: SharedResource>(&'a S);
+pub struct SimulatedCurrent<'a, S: SharedResource>(&'a S);
+pub struct SimulatedTemperature<'a, S: SharedResource>(&'a S);
+pub struct SimulatedLoad<'a, S: SharedResource>(&'a S);
+pub struct SimulatedChargerEnable<'a, S: SharedResource>(&'a S);
+pub struct SimulatedChargeDone<'a, S: SharedResource>(&'a S);
+
+/// The inputs and outputs of a battery test unit, in the order `BatteryTestUnit::new` takes them
+pub type SimulatedChannels<'a, S> = (
+    SimulatedVoltage<'a, S>,
+    SimulatedCurrent<'a, S>,
+    SimulatedTemperature<'a, S>,
+    SimulatedLoad<'a, S>,
+    SimulatedChargerEnable<'a, S>,
+    SimulatedChargeDone<'a, S>,
+);
+
+pub fn channels<S: SharedResource<Target = BatterySimulation>>(
+    simulation: &S,
+) -> SimulatedChannels<'_, S> {
+    (
+        SimulatedVoltage(simulation),
+        SimulatedCurrent(simulation),
+        SimulatedTemperature(simulation),
+        SimulatedLoad(simulation),
+        SimulatedChargerEnable(simulation),
+        SimulatedChargeDone(simulation),
+    )
+}
+
+impl<S: SharedResource<Target = BatterySimulation>> AdcInput for SimulatedVoltage<'_, S> {
+    fn get_voltage(&mut self) -> f32 {
+        self.0.lock(|simulation| simulation.get_voltage())
+    }
+}
+
+impl<S: SharedResource<Target = BatterySimulation>> CurrentInput for SimulatedCurrent<'_, S> {
+    fn get_current(&mut self) -> f32 {
+        self.0.lock(|simulation| simulation.get_current())
+    }
+}
+
+impl<S: SharedResource<Target = BatterySimulation>> TemperatureInput
+    for SimulatedTemperature<'_, S>
+{
+    fn get_temperature(&mut self) -> f32 {
+        self.0.lock(|simulation| simulation.cell.get_temperature())
+    }
+}
+
+impl<S: SharedResource<Target = BatterySimulation>> PwmOutput for SimulatedLoad<'_, S> {
+    fn set_duty_cycle(&mut self, duty_cycle: u16) {
+        self.0
+            .lock(|simulation| simulation.duty_cycle = duty_cycle.min(SIM_MAX_DUTY_CYCLE));
+    }
+
+    fn get_duty_cycle(&mut self) -> u16 {
+        self.0.lock(|simulation| simulation.duty_cycle)
+    }
+
+    fn get_max_duty_cycle(&mut self) -> u16 {
+        SIM_MAX_DUTY_CYCLE
+    }
+}
+
+impl<S: SharedResource<Target = BatterySimulation>> GpioOutput for SimulatedChargerEnable<'_, S> {
+    fn set_output(&mut self, value: bool) {
+        self.0.lock(|simulation| {
+            simulation.charger_enabled = value;
+            if !value {
+                simulation.charge_done = false;
+            }
+        });
+    }
+
+    fn get_output(&mut self) -> bool {
+        self.0.lock(|simulation| simulation.charger_enabled)
+    }
+}
+
+impl<S: SharedResource<Target = BatterySimulation>> GpioInput for SimulatedChargeDone<'_, S> {
+    fn get_input(&mut self) -> bool {
+        self.0.lock(|simulation| simulation.charge_done)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::cell::RefCell;
+
+    #[test]
+    fn test_cell_relaxed() {
+        let cell = SimulatedCell::lifepo4(1.0, 0.4);
+
+        assert!((cell.get_voltage(0.0) - 3.29).abs() < 1e-6);
+        assert_eq!(cell.get_temperature(), 25.0);
+        // the series resistance drops the voltage immediately
+        assert!((cell.get_voltage(1.0) - 3.25).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_cell_discharge() {
+        let mut cell = SimulatedCell::lifepo4(1.0, 0.5);
+
+        for _ in 0..360 {
+            cell.update(1.0, 1.0);
+        }
+        assert!((cell.get_state_of_charge() - 0.4).abs() < 1e-4);
+        // the RC element charged up and the losses heat the cell
+        let loaded = cell.get_voltage(1.0);
+        assert!(loaded < 3.29 - 0.04 - 0.015);
+        assert!(cell.get_temperature() > 25.0);
+
+        // and both relax back afterwards
+        for _ in 0..3600 {
+            cell.update(0.0, 1.0);
+        }
+        assert!((cell.get_voltage(0.0) - 3.29).abs() < 1e-4);
+        assert!((cell.get_temperature() - 25.0).abs() < 0.01);
+
+        cell.update(1.0, 36_000.0);
+        assert_eq!(cell.get_state_of_charge(), 0.0);
+    }
+
+    #[test]
+    fn test_cell_heating() {
+        let mut cell = SimulatedCell::lifepo4(100.0, 1.0);
+
+        // 1.5 W of losses warm the cell by the thermal resistance in steady state
+        for _ in 0..100 {
+            cell.update(5.0, 60.0);
+        }
+        let heat = 25.0 * 0.04 + 0.1 * 0.1 / 0.02;
+        assert!((cell.get_temperature() - (25.0 + heat * 15.0)).abs() < 0.1);
+    }
+
+    #[test]
+    fn test_mosfet_load() {
+        let load = MosfetLoad::HAT;
+
+        // below the threshold voltage nothing flows
+        assert_eq!(load.get_current(0.0, 3.3, 0.04), 0.0);
+        assert_eq!(load.get_current(0.48, 3.3, 0.04), 0.0);
+
+        let currents = [0.6, 0.7, 0.8, 0.9].map(|duty| load.get_current(duty, 3.3, 0.04));
+        assert!(currents.windows(2).all(|pair| pair[0] < pair[1]));
+        assert!((currents[1] - 0.8978).abs() < 1e-4);
+
+        // fully on the resistances limit the current
+        assert!((load.get_current(1.0, 0.24, 0.04) - 4.0).abs() < 1e-4);
+        assert_eq!(load.get_current(1.0, -0.1, 0.04), 0.0);
+    }
+
+    #[test]
+    fn test_charger() {
+        let charger = Charger::LIFEPO4;
+
+        assert_eq!(charger.get_current(3.3, 0.04), 1.0);
+        assert!((charger.get_current(3.58, 0.04) - 0.5).abs() < 1e-4);
+        assert_eq!(charger.get_current(3.6, 0.04), 0.0);
+        assert_eq!(charger.get_current(3.3, 0.0), 1.0);
+    }
+
+    #[test]
+    fn test_channels() {
+        let simulation = RefCell::new(BatterySimulation::lifepo4(1.0, 0.4));
+        let (mut voltage, mut current, mut temperature, mut load, mut charger, mut done) =
+            channels(&simulation);
+
+        assert!((voltage.get_voltage() - 3.29).abs() < 1e-6);
+        assert_eq!(current.get_current(), 0.0);
+        assert_eq!(temperature.get_temperature(), 25.0);
+
+        load.set_duty_cycle(SIM_MAX_DUTY_CYCLE);
+        assert_eq!(load.get_duty_cycle(), SIM_MAX_DUTY_CYCLE);
+        assert!((current.get_current() - 5.12).abs() < 1e-4);
+        assert!((voltage.get_voltage() - (3.29 - 5.12 * 0.04)).abs() < 1e-4);
+        load.set_duty_cycle(0);
+
+        // charging is a negative current, the charger terminates on its own
+        charger.set_output(true);
+        assert_eq!(current.get_current(), -1.0);
+        for _ in 0..8000 {
+            simulation.borrow_mut().update(1.0);
+        }
+        assert!(done.get_input());
+        assert_eq!(current.get_current(), 0.0);
+        assert!(simulation.borrow().cell.get_state_of_charge() > 0.99);
+
+        charger.set_output(false);
+        assert!(!done.get_input());
+    }
+}
@@ -0,0 +1,450 @@
+use heapless::Vec;
+use serde::{Deserialize, Serialize};
+
+use crate::ocv::{OcvDirection, OcvPoint, MAX_OCV_POINTS};
+
+/// Process noise of the state of charge per update
+const EKF_SOC_NOISE: f32 = 1e-7;
+/// Process noise of the voltage over the RC element per update, in V²
+const EKF_RC_VOLTAGE_NOISE: f32 = 1e-6;
+/// Noise of the voltage measurement, in V²
+const EKF_MEASUREMENT_NOISE: f32 = 1e-4;
+/// Uncertainty of the state of charge looked up from the open circuit voltage
+const EKF_INITIAL_SOC_VARIANCE: f32 = 0.01;
+
+/// Open circuit voltage over the state of charge, with both ascending. Between the points the
+/// voltage is interpolated linearly.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OcvCurve {
+    /// state of charge from 0 to 1 and voltage in V
+    points: Vec<(f32, f32), MAX_OCV_POINTS>,
+}
+
+impl OcvCurve {
+    /// A typical LiFePO4 cell, flat between 20 % and 90 %
+    pub fn lifepo4() -> Self {
+        Self::new(&[
+            (0.0, 2.5),
+            (0.05, 3.0),
+            (0.1, 3.2),
+            (0.2, 3.25),
+            (0.3, 3.28),
+            (0.5, 3.3),
+            (0.7, 3.32),
+            (0.9, 3.34),
+            (0.95, 3.36),
+            (1.0, 3.45),
+        ])
+        .unwrap()
+    }
+
+    /// `None` for fewer than two points or points that aren't ascending
+    pub fn new(points: &[(f32, f32)]) -> Option<Self> {
+        let ascending = points
+            .windows(2)
+            .all(|pair| pair[0].0 < pair[1].0 && pair[0].1 <= pair[1].1);
+        if points.len() < 2 || !ascending {
+            return None;
+        }
+
+        Some(Self {
+            points: Vec::from_slice(points).ok()?,
+        })
+    }
+
+    /// Uses the discharge points of a table recorded by the `OcvProcedure`
+    pub fn from_table(table: &[OcvPoint]) -> Option<Self> {
+        let mut points = table
+            .iter()
+            .filter(|point| point.direction == OcvDirection::Discharge)
+            .map(|point| (point.state_of_charge, point.voltage))
+            .collect::<Vec<_, MAX_OCV_POINTS>>();
+        // the discharge goes from full to empty
+        points.reverse();
+        Self::new(&points)
+    }
+
+    /// The segment the state of charge is in, the first or last one outside of the curve
+    fn segment(&self, soc: f32) -> ((f32, f32), (f32, f32)) {
+        let index = self
+            .points
+            .iter()
+            .position(|point| point.0 > soc)
+            .unwrap_or(self.points.len())
+            .clamp(1, self.points.len() - 1);
+        (self.points[index - 1], self.points[index])
+    }
+
+    /// in V
+    pub fn get_voltage(&self, soc: f32) -> f32 {
+        let ((soc0, voltage0), (soc1, voltage1)) = self.segment(soc.clamp(0.0, 1.0));
+        voltage0 + (voltage1 - voltage0) * (soc.clamp(soc0, soc1) - soc0) / (soc1 - soc0)
+    }
+
+    /// The derivative of the voltage, in V per state of charge
+    pub fn get_slope(&self, soc: f32) -> f32 {
+        let ((soc0, voltage0), (soc1, voltage1)) = self.segment(soc);
+        (voltage1 - voltage0) / (soc1 - soc0)
+    }
+
+    /// The state of charge of a relaxed battery, limited to the curve. In flat parts of the curve
+    /// the lowest matching state of charge is returned.
+    pub fn get_state_of_charge(&self, voltage: f32) -> f32 {
+        let first = self.points[0];
+        let last = self.points[self.points.len() - 1];
+        if voltage <= first.1 {
+            return first.0;
+        }
+        if voltage >= last.1 {
+            return last.0;
+        }
+
+        let index = self
+            .points
+            .iter()
+            .position(|point| point.1 >= voltage)
+            .unwrap();
+        let (soc0, voltage0) = self.points[index - 1];
+        let (soc1, voltage1) = self.points[index];
+        soc0 + (soc1 - soc0) * (voltage - voltage0) / (voltage1 - voltage0)
+    }
+}
+
+/// Equivalent circuit of the battery: the open circuit voltage in series with a resistance and
+/// one RC element
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq)]
+pub struct EcmParameters {
+    /// in Ah
+    pub capacity: f32,
+    /// series resistance, in Ω
+    pub r0: f32,
+    /// resistance of the RC element, in Ω
+    pub r1: f32,
+    /// capacitance of the RC element, in F
+    pub c1: f32,
+}
+
+impl EcmParameters {
+    pub fn validate(&self) -> Result<(), SocEstimatorError> {
+        if self.capacity > 0.0 && self.r0 >= 0.0 && self.r1 >= 0.0 && self.c1 > 0.0 {
+            Ok(())
+        } else {
+            Err(SocEstimatorError::InvalidModel)
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq)]
+pub enum SocEstimatorError {
+    /// The capacity or the capacitance isn't positive or a resistance is negative
+    InvalidModel,
+}
+
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq)]
+pub struct SocEstimatorConfig {
+    pub model: EcmParameters,
+    /// whether the extended Kalman filter corrects the integrated current with the voltage
+    pub ekf: bool,
+}
+
+/// Estimates the state of charge by integrating the current, starting from the open circuit
+/// voltage of the relaxed battery. The extended Kalman filter additionally compares the measured
+/// voltage to the one of the equivalent circuit and corrects the estimate, which works best where
+/// the OCV curve isn't flat.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SocEstimator {
+    curve: OcvCurve,
+    config: SocEstimatorConfig,
+    /// from 0 to 1, `None` until the first voltage is known
+    soc: Option<f32>,
+    /// voltage over the RC element, in V
+    rc_voltage: f32,
+    /// covariance of the state of charge and the RC voltage
+    covariance: [[f32; 2]; 2],
+}
+
+impl SocEstimator {
+    pub fn new(curve: OcvCurve, config: SocEstimatorConfig) -> Self {
+        Self {
+            curve,
+            config,
+            soc: None,
+            rc_voltage: 0.0,
+            covariance: [[0.0; 2]; 2],
+        }
+    }
+
+    pub fn get_config(&self) -> &SocEstimatorConfig {
+        &self.config
+    }
+
+    pub fn get_state_of_charge(&self) -> Option<f32> {
+        self.soc
+    }
+
+    /// Time until the battery is empty at the given discharge current, `None` while not
+    /// discharging, in s
+    pub fn get_remaining_time(&self, current: f32) -> Option<f32> {
+        match self.soc {
+            Some(soc) if current > 0.0 => Some(soc * self.config.model.capacity * 3600.0 / current),
+            _ => None,
+        }
+    }
+
+    /// Starts over from the open circuit voltage, the battery has to be relaxed
+    pub fn reset(&mut self, voltage: f32) {
+        self.set_state_of_charge(self.curve.get_state_of_charge(voltage));
+        self.covariance[0][0] = EKF_INITIAL_SOC_VARIANCE;
+    }
+
+    /// Sets a known state of charge, e.g. 1 once the charger is done
+    pub fn set_state_of_charge(&mut self, soc: f32) {
+        self.soc = Some(soc.clamp(0.0, 1.0));
+        self.rc_voltage = 0.0;
+        self.covariance = [[0.0; 2]; 2];
+    }
+
+    /// `current` is positive while discharging. The first update starts from the voltage,
+    /// invalid measurements are skipped.
+    pub fn update(&mut self, voltage: f32, current: f32, delta_time: f32) -> Option<f32> {
+        if voltage.is_nan() || current.is_nan() {
+            return self.soc;
+        }
+        let Some(soc) = self.soc else {
+            self.reset(voltage);
+            return self.soc;
+        };
+
+        let model = self.config.model;
+        let decay = if model.r1 > 0.0 {
+            libm::expf(-delta_time / (model.r1 * model.c1))
+        } else {
+            0.0
+        };
+
+        // prediction with the equivalent circuit
+        let mut soc = soc - current * delta_time / 3600.0 / model.capacity;
+        self.rc_voltage = decay * self.rc_voltage + (1.0 - decay) * model.r1 * current;
+
+        if self.config.ekf {
+            let p = &mut self.covariance;
+            p[0][0] += EKF_SOC_NOISE;
+            p[0][1] *= decay;
+            p[1][0] *= decay;
+            p[1][1] = decay * decay * p[1][1] + EKF_RC_VOLTAGE_NOISE;
+
+            // correction with the measured voltage, H = [dOCV/dSoC, -1]
+            let expected = self.curve.get_voltage(soc) - self.rc_voltage - model.r0 * current;
+            let h = [self.curve.get_slope(soc), -1.0];
+            let ph = [
+                p[0][0] * h[0] + p[0][1] * h[1],
+                p[1][0] * h[0] + p[1][1] * h[1],
+            ];
+            let innovation_variance = h[0] * ph[0] + h[1] * ph[1] + EKF_MEASUREMENT_NOISE;
+            let gain = [ph[0] / innovation_variance, ph[1] / innovation_variance];
+            let innovation = voltage - expected;
+
+            soc += gain[0] * innovation;
+            self.rc_voltage += gain[1] * innovation;
+            let hp = [
+                h[0] * p[0][0] + h[1] * p[1][0],
+                h[0] * p[0][1] + h[1] * p[1][1],
+            ];
+            for (row, gain) in gain.iter().enumerate() {
+                for (column, hp) in hp.iter().enumerate() {
+                    p[row][column] -= gain * hp;
+                }
+            }
+        }
+
+        self.soc = Some(soc.clamp(0.0, 1.0));
+        self.soc
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MODEL: EcmParameters = EcmParameters {
+        capacity: 1.0,
+        r0: 0.05,
+        r1: 0.02,
+        c1: 1000.0,
+    };
+
+    /// An OCV curve without flat parts, the filter can correct the estimate everywhere
+    fn sloped_curve() -> OcvCurve {
+        OcvCurve::new(&[(0.0, 3.0), (0.5, 3.6), (1.0, 4.2)]).unwrap()
+    }
+
+    /// Synthetic battery with the same equivalent circuit as the estimator and noisy sensors
+    struct Battery {
+        curve: OcvCurve,
+        soc: f32,
+        rc_voltage: f32,
+        noise: u32,
+    }
+
+    impl Battery {
+        fn new(curve: OcvCurve, soc: f32) -> Self {
+            Self {
+                curve,
+                soc,
+                rc_voltage: 0.0,
+                noise: 1,
+            }
+        }
+
+        /// Uniform noise in -amplitude..amplitude
+        fn noise(&mut self, amplitude: f32) -> f32 {
+            self.noise = self.noise.wrapping_mul(1664525).wrapping_add(1013904223);
+            ((self.noise >> 8) as f32 / (1 << 24) as f32 * 2.0 - 1.0) * amplitude
+        }
+
+        /// Returns the measured voltage and current
+        fn update(&mut self, current: f32, delta_time: f32) -> (f32, f32) {
+            self.soc -= current * delta_time / 3600.0 / MODEL.capacity;
+            let decay = libm::expf(-delta_time / (MODEL.r1 * MODEL.c1));
+            self.rc_voltage = decay * self.rc_voltage + (1.0 - decay) * MODEL.r1 * current;
+            let voltage = self.curve.get_voltage(self.soc) - self.rc_voltage - MODEL.r0 * current;
+            (voltage + self.noise(0.005), current + self.noise(0.01))
+        }
+    }
+
+    /// 1 A with a pause every ten minutes
+    fn load(time: u32) -> f32 {
+        if time % 600 < 540 {
+            1.0
+        } else {
+            0.0
+        }
+    }
+
+    #[test]
+    fn test_curve() {
+        let curve = OcvCurve::lifepo4();
+
+        assert_eq!(curve.get_voltage(0.0), 2.5);
+        assert_eq!(curve.get_voltage(1.5), 3.45);
+        assert!((curve.get_voltage(0.4) - 3.29).abs() < 1e-6);
+        assert!((curve.get_slope(0.4) - 0.1).abs() < 1e-4);
+        assert!((curve.get_state_of_charge(3.29) - 0.4).abs() < 1e-4);
+        assert_eq!(curve.get_state_of_charge(2.0), 0.0);
+        assert_eq!(curve.get_state_of_charge(4.0), 1.0);
+
+        assert_eq!(OcvCurve::new(&[(0.0, 3.0)]), None);
+        assert_eq!(OcvCurve::new(&[(0.5, 3.0), (0.0, 3.3)]), None);
+    }
+
+    #[test]
+    fn test_curve_from_table() {
+        let point = |state_of_charge, voltage, direction| OcvPoint {
+            state_of_charge,
+            discharged: 1.0 - state_of_charge,
+            voltage,
+            rest_duration: 3600.0,
+            direction,
+        };
+        let table = [
+            point(1.0, 3.4, OcvDirection::Discharge),
+            point(0.5, 3.3, OcvDirection::Discharge),
+            point(0.0, 2.6, OcvDirection::Discharge),
+            point(0.5, 3.32, OcvDirection::Charge),
+        ];
+
+        let curve = OcvCurve::from_table(&table).unwrap();
+        assert_eq!(curve.get_voltage(0.5), 3.3);
+        assert_eq!(curve.get_voltage(0.0), 2.6);
+        assert_eq!(OcvCurve::from_table(&table[..1]), None);
+    }
+
+    #[test]
+    fn test_coulomb_counting() {
+        let mut battery = Battery::new(OcvCurve::lifepo4(), 0.4);
+        let mut estimator = SocEstimator::new(
+            OcvCurve::lifepo4(),
+            SocEstimatorConfig {
+                model: MODEL,
+                ekf: false,
+            },
+        );
+
+        // the first update looks the relaxed voltage up
+        let (voltage, _) = battery.update(0.0, 1.0);
+        assert!((estimator.update(voltage, 0.0, 1.0).unwrap() - 0.4).abs() < 0.1);
+        assert_eq!(
+            estimator.update(f32::NAN, 0.0, 1.0),
+            estimator.get_state_of_charge()
+        );
+
+        estimator.set_state_of_charge(0.4);
+        for time in 0..3600 {
+            let (voltage, current) = battery.update(load(time) * 0.3, 1.0);
+            estimator.update(voltage, current, 1.0);
+        }
+        let soc = estimator.get_state_of_charge().unwrap();
+        assert!((soc - battery.soc).abs() < 0.005, "{} {}", soc, battery.soc);
+        assert!((estimator.get_remaining_time(1.0).unwrap() - soc * 3600.0).abs() < 1.0);
+        assert_eq!(estimator.get_remaining_time(0.0), None);
+    }
+
+    #[test]
+    fn test_ekf_corrects_initial_error() {
+        let mut battery = Battery::new(sloped_curve(), 0.9);
+        let mut with_ekf = SocEstimator::new(
+            sloped_curve(),
+            SocEstimatorConfig {
+                model: MODEL,
+                ekf: true,
+            },
+        );
+        let mut without_ekf = SocEstimator::new(
+            sloped_curve(),
+            SocEstimatorConfig {
+                model: MODEL,
+                ekf: false,
+            },
+        );
+        with_ekf.set_state_of_charge(0.5);
+        with_ekf.covariance[0][0] = EKF_INITIAL_SOC_VARIANCE;
+        without_ekf.set_state_of_charge(0.5);
+
+        for time in 0..1800 {
+            let (voltage, current) = battery.update(load(time), 1.0);
+            with_ekf.update(voltage, current, 1.0);
+            without_ekf.update(voltage, current, 1.0);
+        }
+
+        let error = |estimator: &SocEstimator| {
+            libm::fabsf(estimator.get_state_of_charge().unwrap() - battery.soc)
+        };
+        assert!(error(&with_ekf) < 0.02, "{}", error(&with_ekf));
+        assert!(error(&without_ekf) > 0.35);
+    }
+
+    #[test]
+    fn test_ekf_follows_discharge() {
+        let mut battery = Battery::new(OcvCurve::lifepo4(), 1.0);
+        let mut estimator = SocEstimator::new(
+            OcvCurve::lifepo4(),
+            SocEstimatorConfig {
+                model: MODEL,
+                ekf: true,
+            },
+        );
+        estimator.set_state_of_charge(1.0);
+
+        for time in 0..3400 {
+            let (voltage, current) = battery.update(load(time), 1.0);
+            let soc = estimator.update(voltage, current, 1.0).unwrap();
+            assert!(
+                (soc - battery.soc).abs() < 0.03,
+                "{} {} at {} s",
+                soc,
+                battery.soc,
+                time
+            );
+        }
+    }
+}
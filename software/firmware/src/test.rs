@@ -1,9 +1,32 @@
 #[cfg(test)]
 mod tests {
+    use crate::calibration::{Calibration, CalibrationChannel, CalibrationError, CalibrationSet};
+    use crate::clock::{self, Clock, ClockError};
+    use crate::conditioning::{
+        ChannelConditioning, ChannelStats, ConditioningConfig, ConditioningError, Filter,
+        MeasurementStats,
+    };
+    use crate::config::{ConfigError, DEFAULT_SAMPLE_PERIOD};
+    use crate::crash::{self, CrashReport, ResetCause, UnitState};
+    use crate::datalog::MeasurementLog;
+    use crate::datalog::{LogConfig, LogError, LogPolicy, LogStatus};
+    use crate::dcir::{DcirError, DcirSchedule, PulseConfig, Sample};
+    use crate::hppc::{HppcConfig, HppcEnd, HppcEndReason};
+    use crate::limits::{FaultCause, SafetyLimits};
     use crate::mocks::*;
-    use crate::msg_types::MsgTypes;
+    use crate::msg_types::{MsgTypes, Telemetry};
+    use crate::ocv::{OcvConfig, OcvDirection, OcvEndReason};
+    use crate::orbit::{OrbitError, Segment, SegmentLoad};
+    use crate::recovery::{Recovery, RecoveryError, UnitRun};
+    use crate::sampling::{SamplingConfig, SamplingError};
+    use crate::sensors::{NtcModel, NtcThermistor};
+    use crate::sequence::{Condition, ProgramError, Step, StepTransition, TransitionReason};
+    use crate::sim::{self, BatterySimulation};
+    use crate::soc::{EcmParameters, SocEstimatorConfig, SocEstimatorError};
     use crate::traits::PwmOutput;
+    use crate::unit::{TestKind, TestSummary, UnitError, UnitStatus};
     use crate::{BatteryTestUnit, BatteryTestUnitMode, Firmware};
+    use std::cell::RefCell;
 
     macro_rules! new_mock_firmware {
         () => {
@@ -12,13 +35,54 @@ mod tests {
         ($serial_rx_queue: expr) => {
             Firmware {
                 on_board_led: MockGpioOutput { value: false },
+                flash: MockFlash::new(4096),
+                sample_period: DEFAULT_SAMPLE_PERIOD,
+                log_storage: MockBlockStorage::new(4, 1024),
+                log: MeasurementLog::new(),
+                monotonic: MockSystemTime::new(),
+                rtc: MockRtc::new(),
+                clock: Clock::new(),
+                watchdog: MockWatchdog::new(),
+                retained: MockRetainedMemory::new(80),
+                crash_storage: MockRetainedMemory::new(crash::RECORD_SIZE),
+                recovery: Recovery::new(),
                 serial_receiver: MockSerialReceiver::new($serial_rx_queue),
                 serial_transmitter: MockSerialTransmitter::new(),
-                btu1: BatteryTestUnit::new(MockAdcInput::new(), MockPwmOutput::new()),
+                battery_units: [0, 1, 2].map(|id| test_unit(id, 0.0)),
             }
         };
     }
 
+    type MockBatteryTestUnit = BatteryTestUnit<
+        MockAdcInput,
+        MockCurrentInput,
+        MockTemperatureInput,
+        MockPwmOutput,
+        MockGpioOutput,
+        MockGpioInput,
+    >;
+
+    /// A unit with mocks for all its inputs and outputs, `voltage` is read by its ADC
+    fn test_unit(id: u8, voltage: f32) -> MockBatteryTestUnit {
+        BatteryTestUnit::new(
+            id,
+            MockAdcInput::from(voltage),
+            MockCurrentInput::new(),
+            MockTemperatureInput::new(),
+            MockPwmOutput::new(),
+            MockGpioOutput { value: false },
+            MockGpioInput { value: false },
+        )
+    }
+
+    fn only_telemetry(msg: MsgTypes) {
+        assert!(
+            matches!(msg, MsgTypes::Telemetry(_, _)),
+            "unexpected message {:?}",
+            msg
+        );
+    }
+
     #[test]
     fn test_setup() {
         let mut firmware = new_mock_firmware!();
@@ -53,7 +117,7 @@ mod tests {
 
     #[test]
     fn test_battery_unit_discharge_state_transition() {
-        let mut btu = BatteryTestUnit::new(MockAdcInput::new(), MockPwmOutput::new());
+        let mut btu = test_unit(0, 0.0);
 
         let MIN_DC = btu.load_pwm.get_min_duty_cycle();
         let MAX_DC = btu.load_pwm.get_max_duty_cycle();
@@ -78,7 +142,7 @@ mod tests {
 
     #[test]
     fn test_battery_unit_discharge() {
-        let mut btu = BatteryTestUnit::new(MockAdcInput::new(), MockPwmOutput::new());
+        let mut btu = test_unit(0, 0.0);
 
         let MIN_DC = btu.load_pwm.get_min_duty_cycle();
 
@@ -87,22 +151,1807 @@ mod tests {
         // btu.voltage_adc.set_voltage(3.3);
         assert_eq!(btu.load_pwm.duty_cycle, MIN_DC);
 
-        btu.update(0.0, 0.5);
+        btu.update(0, 0.5, |_| {});
         assert_eq!(btu.load_pwm.duty_cycle, 50);
 
-        btu.update(0.5, 0.5);
+        btu.update(500, 0.5, |_| {});
         assert_eq!(btu.load_pwm.duty_cycle, 100);
 
-        btu.update(0.75, 0.5);
+        btu.update(750, 0.5, |_| {});
         assert_eq!(btu.load_pwm.duty_cycle, 85);
 
-        btu.update(1.0, 0.5);
+        btu.update(1000, 0.5, |_| {});
         assert_eq!(btu.load_pwm.duty_cycle, 50);
 
-        btu.update(1.5, 0.5);
+        btu.update(1500, 0.5, |_| {});
         assert_eq!(btu.load_pwm.duty_cycle, 0);
 
-        btu.update(2.0, 0.5);
+        btu.update(2000, 0.5, |_| {});
         assert_eq!(btu.load_pwm.duty_cycle, 49);
     }
+
+    #[test]
+    fn test_battery_unit_limits() {
+        let mut btu = test_unit(0, 3.3);
+        btu.set_limits(SafetyLimits::LIFEPO4);
+
+        // limits are not checked while idle
+        btu.voltage_adc.set_voltage(0.0);
+        btu.update(0, 0.5, only_telemetry);
+        assert_eq!(btu.get_mode(), BatteryTestUnitMode::Idle);
+
+        btu.voltage_adc.set_voltage(3.3);
+        btu.current_sensor.set_current(1.0);
+        btu.set_mode(BatteryTestUnitMode::Discharging(2.5));
+        btu.update(0, 0.5, only_telemetry);
+        assert_eq!(btu.get_mode(), BatteryTestUnitMode::Discharging(2.5));
+
+        btu.current_sensor.set_current(6.0);
+        let mut messages = Vec::new();
+        btu.update(500, 0.5, |msg| messages.push(msg));
+
+        let fault = BatteryTestUnitMode::Fault(FaultCause::OverCurrent(6.0));
+        let min_dc = btu.load_pwm.get_min_duty_cycle();
+        assert_eq!(btu.get_mode(), fault);
+        assert_eq!(btu.load_pwm.duty_cycle, min_dc);
+        assert_eq!(
+            messages,
+            vec![
+                MsgTypes::Fault(0, FaultCause::OverCurrent(6.0)),
+                MsgTypes::Telemetry(
+                    0,
+                    Telemetry {
+                        timestamp: 500,
+                        mode: fault,
+                        step: None,
+                        // sampled twice for 0.5 s, at 1 A and at 6 A
+                        capacity: 1.0f32 * 0.5 / 3600.0 + 6.0f32 * 0.5 / 3600.0,
+                        energy: 3.3f32 * 1.0 * 0.5 / 3600.0 + 3.3f32 * 6.0 * 0.5 / 3600.0,
+                        voltage: 3.3,
+                        current: 6.0,
+                        temperature: Some(25.0),
+                        state_of_charge: None,
+                        remaining_time: None,
+                        calibration: 0,
+                        // one reading since the telemetry of the update before
+                        stats: MeasurementStats {
+                            voltage: single_reading(3.3),
+                            current: single_reading(6.0),
+                            temperature: single_reading(25.0),
+                        },
+                    }
+                )
+            ]
+        );
+    }
+
+    #[test]
+    fn test_battery_unit_thermistor_disconnected() {
+        // an open or a shorted thermistor faults the unit instead of reading about -273 °C
+        for voltage in [3.3, 0.0] {
+            let mut btu = BatteryTestUnit::new(
+                0,
+                MockAdcInput::from(3.3),
+                MockCurrentInput::new(),
+                NtcThermistor::new(
+                    MockAdcInput::from(1.65),
+                    NtcModel::ntc_10k(3988.0),
+                    10_000.0,
+                    3.3,
+                ),
+                MockPwmOutput::new(),
+                MockGpioOutput { value: false },
+                MockGpioInput { value: false },
+            );
+            btu.set_limits(SafetyLimits::LIFEPO4);
+            btu.set_mode(BatteryTestUnitMode::Charging);
+            btu.update(0, 0.5, only_telemetry);
+            assert_eq!(btu.get_mode(), BatteryTestUnitMode::Charging);
+
+            btu.temperature_sensor.adc.set_voltage(voltage);
+            btu.update(500, 0.5, |_| {});
+            assert_eq!(
+                btu.get_mode(),
+                BatteryTestUnitMode::Fault(FaultCause::InvalidMeasurement)
+            );
+        }
+    }
+
+    fn single_reading(value: f32) -> ChannelStats {
+        ChannelStats {
+            count: 1,
+            min: value,
+            max: value,
+            std_dev: 0.0,
+        }
+    }
+
+    #[test]
+    fn test_battery_unit_limits_between_samples() {
+        let mut btu = test_unit(0, 3.3);
+        btu.set_limits(SafetyLimits::LIFEPO4);
+        btu.set_sampling(SamplingConfig {
+            period: 1000,
+            fast_period: 1000,
+            ..SamplingConfig::default()
+        })
+        .unwrap();
+        btu.set_mode(BatteryTestUnitMode::DischargingConstantCurrent(1.0));
+        btu.update(0, 1.0, only_telemetry);
+
+        // a short pulls the voltage down right after the sample
+        btu.voltage_adc.set_voltage(0.5);
+        let mut messages = Vec::new();
+        btu.update(100, 0.1, |msg| messages.push(msg));
+
+        assert_eq!(
+            messages,
+            [MsgTypes::Fault(0, FaultCause::UnderVoltage(0.5))]
+        );
+        assert_eq!(
+            btu.get_mode(),
+            BatteryTestUnitMode::Fault(FaultCause::UnderVoltage(0.5))
+        );
+    }
+
+    #[test]
+    fn test_battery_unit_over_temperature() {
+        let mut btu = test_unit(0, 3.3);
+        btu.set_limits(SafetyLimits::LIFEPO4);
+        btu.set_mode(BatteryTestUnitMode::Discharging(2.5));
+
+        btu.temperature_sensor.set_temperature(59.0);
+        btu.update(0, 0.5, only_telemetry);
+        assert_eq!(btu.get_mode(), BatteryTestUnitMode::Discharging(2.5));
+
+        btu.temperature_sensor.set_temperature(61.0);
+        btu.update(500, 0.5, |_| {});
+        assert_eq!(
+            btu.get_mode(),
+            BatteryTestUnitMode::Fault(FaultCause::OverTemperature(61.0))
+        );
+    }
+
+    #[test]
+    fn test_battery_unit_fault_is_latched() {
+        let mut btu = test_unit(0, 3.3);
+        btu.set_limits(SafetyLimits {
+            max_duration: Some(1.0),
+            ..SafetyLimits::default()
+        });
+
+        btu.set_mode(BatteryTestUnitMode::Discharging(2.5));
+        btu.update(0, 0.5, |_| {});
+        btu.update(500, 0.5, |_| {});
+        assert_eq!(btu.get_mode(), BatteryTestUnitMode::Discharging(2.5));
+        btu.update(1000, 0.5, |_| {});
+        let fault = BatteryTestUnitMode::Fault(FaultCause::DurationExceeded(1.5));
+        assert_eq!(btu.get_mode(), fault);
+
+        // neither new modes nor further updates leave the fault state
+        btu.set_mode(BatteryTestUnitMode::Idle);
+        assert_eq!(btu.get_mode(), fault);
+        btu.set_mode(BatteryTestUnitMode::Discharging(2.5));
+        btu.update(1500, 0.5, only_telemetry);
+        assert_eq!(btu.get_mode(), fault);
+
+        btu.clear_fault();
+        assert_eq!(btu.get_mode(), BatteryTestUnitMode::Idle);
+
+        // the duration is counted from the start of the new discharge
+        btu.set_mode(BatteryTestUnitMode::Discharging(2.5));
+        btu.update(2000, 0.5, |_| {});
+        assert_eq!(btu.get_mode(), BatteryTestUnitMode::Discharging(2.5));
+    }
+
+    #[test]
+    fn test_serial_fault_handling() {
+        let mut firmware = new_mock_firmware!(vec![
+            MsgTypes::SetLimits(1, SafetyLimits::LIFEPO4),
+            MsgTypes::ClearFault(1)
+        ]);
+
+        firmware.update_serial();
+        assert_eq!(
+            firmware.battery_units[0].get_limits(),
+            SafetyLimits::default()
+        );
+        assert_eq!(
+            firmware.battery_units[1].get_limits(),
+            SafetyLimits::LIFEPO4
+        );
+
+        for btu in firmware.battery_units.iter_mut() {
+            btu.voltage_adc.set_voltage(1.5);
+            btu.set_mode(BatteryTestUnitMode::Discharging(2.5));
+        }
+        firmware.update_battery_units(0, 0.1);
+
+        // only unit 1 has limits
+        let fault = FaultCause::UnderVoltage(1.5);
+        assert_eq!(
+            firmware.battery_units[1].get_mode(),
+            BatteryTestUnitMode::Fault(fault)
+        );
+        assert!(firmware
+            .serial_transmitter
+            .msg_queue
+            .contains(&MsgTypes::Fault(1, fault)));
+
+        firmware.update_serial();
+        assert_eq!(
+            firmware.battery_units[1].get_mode(),
+            BatteryTestUnitMode::Idle
+        );
+    }
+
+    #[test]
+    fn test_serial_unsupported_message() {
+        let mut firmware = new_mock_firmware!(vec![MsgTypes::SampleAdc(0)]);
+
+        firmware.update_serial();
+
+        assert_eq!(
+            firmware.serial_transmitter.msg_queue.pop_front(),
+            Some(MsgTypes::Msg(heapless::String::from("Unsupported message")))
+        );
+    }
+
+    #[test]
+    fn test_serial_unknown_battery_unit() {
+        let mut firmware = new_mock_firmware!(vec![MsgTypes::ClearFault(3)]);
+
+        firmware.update_serial();
+
+        assert_eq!(
+            firmware.serial_transmitter.msg_queue.pop_front(),
+            Some(MsgTypes::UnitError(3, UnitError::UnknownUnit))
+        );
+    }
+
+    #[test]
+    fn test_battery_units_are_independent() {
+        let mut firmware = new_mock_firmware!();
+
+        for (btu, voltage) in firmware.battery_units.iter_mut().zip([3.0, 3.1, 3.2]) {
+            btu.voltage_adc.set_voltage(voltage);
+            btu.set_limits(SafetyLimits {
+                max_duration: Some(1.0),
+                ..SafetyLimits::default()
+            });
+        }
+        firmware.battery_units[0].set_mode(BatteryTestUnitMode::Discharging(2.5));
+        firmware.battery_units[2].set_mode(BatteryTestUnitMode::Discharging(2.5));
+
+        firmware.update_battery_units(0, 0.75);
+        firmware.battery_units[2].set_mode(BatteryTestUnitMode::Idle);
+        firmware.update_battery_units(750, 0.75);
+
+        assert_eq!(
+            firmware.battery_units[0].get_mode(),
+            BatteryTestUnitMode::Fault(FaultCause::DurationExceeded(1.5))
+        );
+        assert_eq!(
+            firmware.battery_units[1].get_mode(),
+            BatteryTestUnitMode::Idle
+        );
+        assert_eq!(
+            firmware.battery_units[2].get_mode(),
+            BatteryTestUnitMode::Idle
+        );
+
+        // every unit reports its own telemetry, tagged with its id
+        let telemetry = firmware
+            .serial_transmitter
+            .msg_queue
+            .iter()
+            .filter_map(|msg| match msg {
+                MsgTypes::Telemetry(unit, telemetry) => Some((*unit, telemetry.voltage)),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            telemetry,
+            [(0, 3.0), (1, 3.1), (2, 3.2), (0, 3.0), (1, 3.1), (2, 3.2)]
+        );
+    }
+
+    #[test]
+    fn test_battery_unit_constant_current() {
+        let mut btu = test_unit(0, 3.3);
+
+        btu.set_mode(BatteryTestUnitMode::DischargingConstantCurrent(1.0));
+        btu.update(0, 0.1, only_telemetry);
+        let first = btu.load_pwm.duty_cycle;
+        assert!(first > 0);
+
+        // the load is increased until the current is reached
+        btu.update(100, 0.1, only_telemetry);
+        assert!(btu.load_pwm.duty_cycle > first);
+
+        // and decreased if the current is too high
+        let before = btu.load_pwm.duty_cycle;
+        btu.current_sensor.set_current(2.0);
+        btu.update(200, 0.1, only_telemetry);
+        assert!(btu.load_pwm.duty_cycle < before);
+
+        btu.set_mode(BatteryTestUnitMode::Idle);
+        assert_eq!(btu.load_pwm.duty_cycle, 0);
+    }
+
+    #[test]
+    fn test_load_current() {
+        macro_rules! t {
+            ($mode:expr, $voltage:expr, $current:expr) => {
+                assert_eq!($mode.get_load_current($voltage), $current);
+            };
+        }
+
+        t!(
+            BatteryTestUnitMode::DischargingConstantCurrent(1.5),
+            3.0,
+            Some(1.5)
+        );
+        t!(
+            BatteryTestUnitMode::DischargingConstantPower(6.0),
+            3.0,
+            Some(2.0)
+        );
+        t!(
+            BatteryTestUnitMode::DischargingConstantPower(6.0),
+            2.0,
+            Some(3.0)
+        );
+        t!(
+            BatteryTestUnitMode::DischargingConstantResistance(2.0),
+            3.0,
+            Some(1.5)
+        );
+        t!(
+            BatteryTestUnitMode::DischargingConstantResistance(2.0),
+            2.0,
+            Some(1.0)
+        );
+        // a dead or disconnected cell is not loaded
+        t!(
+            BatteryTestUnitMode::DischargingConstantPower(6.0),
+            0.0,
+            Some(0.0)
+        );
+        t!(
+            BatteryTestUnitMode::DischargingConstantPower(6.0),
+            -0.1,
+            Some(0.0)
+        );
+        t!(
+            BatteryTestUnitMode::DischargingConstantResistance(2.0),
+            f32::NAN,
+            Some(0.0)
+        );
+        t!(
+            BatteryTestUnitMode::DischargingConstantResistance(0.0),
+            3.0,
+            Some(0.0)
+        );
+        t!(BatteryTestUnitMode::Resting, 3.0, None);
+        t!(BatteryTestUnitMode::Idle, 3.0, None);
+    }
+
+    #[test]
+    fn test_battery_unit_constant_power_and_resistance() {
+        macro_rules! new_btu {
+            ($mode:expr) => {{
+                let mut btu = test_unit(0, 2.0);
+                btu.set_limits(SafetyLimits::LIFEPO4);
+                btu.set_mode($mode);
+                btu
+            }};
+        }
+
+        // at 2 V, 4 W and 1 Ω are the same load as 2 A
+        let mut units = [
+            new_btu!(BatteryTestUnitMode::DischargingConstantCurrent(2.0)),
+            new_btu!(BatteryTestUnitMode::DischargingConstantPower(4.0)),
+            new_btu!(BatteryTestUnitMode::DischargingConstantResistance(1.0)),
+        ];
+        for (step, current) in [0.0, 1.0, 1.5, 2.5].into_iter().enumerate() {
+            for btu in units.iter_mut() {
+                btu.current_sensor.set_current(current);
+                btu.update(step as u64 * 1000, 1.0, only_telemetry);
+            }
+            assert_eq!(units[0].load_pwm.duty_cycle, units[1].load_pwm.duty_cycle);
+            assert_eq!(units[0].load_pwm.duty_cycle, units[2].load_pwm.duty_cycle);
+        }
+
+        // at a higher voltage constant power needs less and constant resistance more current
+        for btu in units.iter_mut() {
+            btu.voltage_adc.set_voltage(3.2);
+            btu.current_sensor.set_current(2.0);
+            btu.update(4000, 1.0, only_telemetry);
+        }
+        assert!(units[1].load_pwm.duty_cycle < units[0].load_pwm.duty_cycle);
+        assert!(units[2].load_pwm.duty_cycle > units[0].load_pwm.duty_cycle);
+
+        // the safety limits apply like in every other mode
+        units[1].voltage_adc.set_voltage(1.9);
+        units[1].update(5000, 1.0, |_| {});
+        assert_eq!(
+            units[1].get_mode(),
+            BatteryTestUnitMode::Fault(FaultCause::UnderVoltage(1.9))
+        );
+        assert_eq!(units[1].load_pwm.duty_cycle, 0);
+    }
+
+    #[test]
+    fn test_battery_unit_program() {
+        let mut btu = test_unit(0, 3.3);
+        let end = |condition| heapless::Vec::from_slice(&[condition]).unwrap();
+
+        btu.set_program_step(0, Step::Charge(end(Condition::ChargeDone)))
+            .unwrap();
+        btu.set_program_step(1, Step::Rest(end(Condition::Duration(1.0))))
+            .unwrap();
+        btu.set_program_step(
+            2,
+            Step::DischargeConstantCurrent(0.5, end(Condition::VoltageBelow(2.5))),
+        )
+        .unwrap();
+
+        assert_eq!(
+            btu.start_program(),
+            Ok(StepTransition {
+                from: None,
+                to: Some(0),
+                reason: TransitionReason::Started,
+                duration: 0.0,
+                capacity: 0.0,
+            })
+        );
+        assert_eq!(btu.get_mode(), BatteryTestUnitMode::Charging);
+        assert!(btu.charger_enable.value);
+        assert_eq!(btu.start_program(), Err(ProgramError::Busy));
+
+        btu.current_sensor.set_current(-1.0);
+        btu.update(0, 1.0, only_telemetry);
+        assert_eq!(btu.get_sequencer().get_step(), Some(0));
+
+        let mut messages = Vec::new();
+        btu.charge_done.value = true;
+        btu.update(1000, 1.0, |msg| messages.push(msg));
+        assert_eq!(btu.get_mode(), BatteryTestUnitMode::Resting);
+        assert!(!btu.charger_enable.value);
+        assert_eq!(
+            messages[1],
+            MsgTypes::StepTransition(
+                0,
+                StepTransition {
+                    from: Some(0),
+                    to: Some(1),
+                    reason: TransitionReason::EndCondition(Condition::ChargeDone),
+                    duration: 2.0,
+                    capacity: 2.0 / 3600.0,
+                }
+            )
+        );
+
+        btu.current_sensor.set_current(0.0);
+        btu.update(2000, 1.0, |_| {});
+        assert_eq!(
+            btu.get_mode(),
+            BatteryTestUnitMode::DischargingConstantCurrent(0.5)
+        );
+
+        btu.update(3000, 1.0, only_telemetry);
+        assert!(btu.load_pwm.duty_cycle > 0);
+
+        btu.voltage_adc.set_voltage(2.4);
+        btu.update(4000, 1.0, |_| {});
+        assert_eq!(btu.get_mode(), BatteryTestUnitMode::Idle);
+        assert_eq!(btu.load_pwm.duty_cycle, 0);
+        assert!(!btu.get_sequencer().is_running());
+    }
+
+    #[test]
+    fn test_battery_unit_fault_stops_program() {
+        let mut btu = test_unit(0, 3.3);
+        btu.set_limits(SafetyLimits::LIFEPO4);
+        btu.set_program_step(
+            0,
+            Step::Charge(heapless::Vec::from_slice(&[Condition::ChargeDone]).unwrap()),
+        )
+        .unwrap();
+        btu.start_program().unwrap();
+
+        btu.voltage_adc.set_voltage(3.7);
+        let mut messages = Vec::new();
+        btu.update(0, 1.0, |msg| messages.push(msg));
+
+        assert_eq!(
+            btu.get_mode(),
+            BatteryTestUnitMode::Fault(FaultCause::OverVoltage(3.7))
+        );
+        assert!(!btu.charger_enable.value);
+        assert!(!btu.get_sequencer().is_running());
+        assert!(messages.iter().any(|msg| matches!(
+            msg,
+            MsgTypes::StepTransition(
+                0,
+                StepTransition {
+                    to: None,
+                    reason: TransitionReason::Stopped,
+                    ..
+                }
+            )
+        )));
+    }
+
+    #[test]
+    fn test_battery_unit_orbits() {
+        let mut btu = test_unit(0, 3.3);
+        btu.set_limits(SafetyLimits::LIFEPO4);
+        btu.set_orbit_segment(
+            0,
+            Segment {
+                duration: 2.0,
+                load: SegmentLoad::Current(0.0),
+                charge: true,
+            },
+        )
+        .unwrap();
+        btu.set_orbit_segment(
+            1,
+            Segment {
+                duration: 2.0,
+                load: SegmentLoad::Current(1.0),
+                charge: false,
+            },
+        )
+        .unwrap();
+
+        btu.set_mode(BatteryTestUnitMode::Charging);
+        assert_eq!(btu.start_orbits(1), Err(OrbitError::Busy));
+        btu.set_mode(BatteryTestUnitMode::Idle);
+
+        assert_eq!(btu.start_orbits(1), Ok(()));
+        assert_eq!(btu.get_mode(), BatteryTestUnitMode::Charging);
+        assert!(btu.charger_enable.value);
+
+        let mut messages = Vec::new();
+        btu.update(0, 2.0, |msg| messages.push(msg));
+        assert_eq!(
+            btu.get_mode(),
+            BatteryTestUnitMode::DischargingConstantCurrent(1.0)
+        );
+        assert!(!btu.charger_enable.value);
+
+        btu.current_sensor.set_current(1.0);
+        btu.update(2000, 2.0, |msg| messages.push(msg));
+        // the only orbit ended
+        assert_eq!(btu.get_mode(), BatteryTestUnitMode::Idle);
+        assert!(!btu.get_orbit_player().is_running());
+        assert!(messages
+            .iter()
+            .any(|msg| matches!(msg, MsgTypes::OrbitStats(0, stats) if stats.orbit == 0)));
+
+        // a fault stops the orbits
+        btu.current_sensor.set_current(0.0);
+        btu.start_orbits(2).unwrap();
+        btu.voltage_adc.set_voltage(3.7);
+        btu.update(4000, 1.0, |_| {});
+        assert!(matches!(btu.get_mode(), BatteryTestUnitMode::Fault(_)));
+        assert!(!btu.get_orbit_player().is_running());
+    }
+
+    #[test]
+    fn test_battery_unit_pulse_test() {
+        let mut btu = test_unit(0, 3.3);
+        btu.set_limits(SafetyLimits::LIFEPO4);
+        let config = PulseConfig {
+            base_current: 0.0,
+            pulse_current: 2.0,
+            base_duration: 1.0,
+            pulse_duration: 2.0,
+        };
+
+        assert_eq!(
+            btu.start_pulse_test(PulseConfig {
+                pulse_current: 0.0,
+                ..config
+            }),
+            Err(DcirError::InvalidPulse)
+        );
+        assert_eq!(btu.start_pulse_test(config), Ok(()));
+        assert_eq!(btu.get_mode(), BatteryTestUnitMode::DcirPulse);
+        assert_eq!(btu.start_pulse_test(config), Err(DcirError::Busy));
+
+        let mut messages = Vec::new();
+        btu.update(0, 1.0, |msg| messages.push(msg));
+        // the load is turned on for the pulse
+        assert!(btu.load_pwm.duty_cycle > 0);
+
+        btu.voltage_adc.set_voltage(3.2);
+        btu.current_sensor.set_current(2.0);
+        btu.update(1000, 1.0, |msg| messages.push(msg));
+        btu.voltage_adc.set_voltage(3.1);
+        btu.update(2000, 1.0, |msg| messages.push(msg));
+
+        let result = messages
+            .iter()
+            .find_map(|msg| match msg {
+                MsgTypes::DcirResult(0, result) => Some(*result),
+                _ => None,
+            })
+            .unwrap();
+        assert_eq!(
+            result.before,
+            Sample {
+                voltage: 3.3,
+                current: 0.0
+            }
+        );
+        assert!((result.ohmic_resistance - 0.05).abs() < 1e-6);
+        assert!((result.polarization_resistance - 0.05).abs() < 1e-6);
+        assert_eq!(btu.get_mode(), BatteryTestUnitMode::Idle);
+        assert_eq!(btu.load_pwm.duty_cycle, 0);
+    }
+
+    #[test]
+    fn test_battery_unit_dcir_schedule() {
+        let mut btu = test_unit(0, 3.3);
+        btu.set_limits(SafetyLimits::LIFEPO4);
+        let pulse = PulseConfig {
+            base_current: 0.0,
+            pulse_current: 2.0,
+            base_duration: 1.0,
+            pulse_duration: 1.0,
+        };
+        btu.set_dcir_schedule(Some(DcirSchedule {
+            pulse,
+            nominal_capacity: 1.0,
+            state_of_charge: heapless::Vec::from_slice(&[0.5]).unwrap(),
+        }))
+        .unwrap();
+
+        btu.set_mode(BatteryTestUnitMode::DischargingConstantCurrent(1.0));
+        btu.current_sensor.set_current(1.0);
+        btu.update(0, 1790.0, |_| {});
+        assert_eq!(
+            btu.get_mode(),
+            BatteryTestUnitMode::DischargingConstantCurrent(1.0)
+        );
+
+        btu.update(1790000, 20.0, |_| {});
+        assert_eq!(btu.get_mode(), BatteryTestUnitMode::DcirPulse);
+        assert!(btu.get_dcir_scheduler().get_state_of_charge().unwrap() < 0.5);
+
+        let mut messages = Vec::new();
+        for _ in 0..3 {
+            btu.update(1810000, 1.0, |msg| messages.push(msg));
+        }
+        assert!(messages.iter().any(|msg| matches!(
+            msg,
+            MsgTypes::DcirResult(0, result)
+                if result.state_of_charge == Some(0.5) && result.before.current == 1.0
+        )));
+        // the discharge goes on after the pulse
+        assert_eq!(
+            btu.get_mode(),
+            BatteryTestUnitMode::DischargingConstantCurrent(1.0)
+        );
+    }
+
+    #[test]
+    fn test_battery_unit_hppc() {
+        let mut btu = test_unit(0, 3.3);
+        btu.set_limits(SafetyLimits::LIFEPO4);
+        let config = HppcConfig {
+            nominal_capacity: 1.0,
+            soc_step: 0.1,
+            discharge_current: 1.0,
+            rest_duration: 2.0,
+            discharge_pulse_current: 2.0,
+            discharge_pulse_duration: 1.0,
+            pulse_rest_duration: 1.0,
+            charge_pulse_duration: 1.0,
+            min_voltage: 2.5,
+            window: 1.0,
+            sample_interval: 0.0,
+        };
+        btu.start_hppc(config).unwrap();
+        assert_eq!(btu.get_mode(), BatteryTestUnitMode::Resting);
+
+        let mut messages = Vec::new();
+        let mut modes = Vec::new();
+        for i in 0..6 {
+            btu.update(i as u64 * 1000, 1.0, |msg| messages.push(msg));
+            modes.push((btu.get_mode(), btu.charger_enable.value));
+        }
+        assert_eq!(
+            modes,
+            [
+                (BatteryTestUnitMode::Resting, false),
+                (BatteryTestUnitMode::DischargingConstantCurrent(2.0), false),
+                (BatteryTestUnitMode::Resting, false),
+                (BatteryTestUnitMode::Charging, true),
+                (BatteryTestUnitMode::Resting, false),
+                (BatteryTestUnitMode::DischargingConstantCurrent(1.0), false),
+            ]
+        );
+        assert!(messages.iter().any(|msg| matches!(
+            msg,
+            MsgTypes::HppcData(0, data) if data.last && data.samples.len() == 6
+        )));
+
+        // a fault stops the procedure
+        btu.voltage_adc.set_voltage(3.7);
+        messages.clear();
+        btu.update(6000, 1.0, |msg| messages.push(msg));
+        assert!(!btu.get_hppc().is_running());
+        assert!(messages.contains(&MsgTypes::HppcFinished(
+            0,
+            HppcEnd {
+                steps: 0,
+                reason: HppcEndReason::Stopped
+            }
+        )));
+    }
+
+    #[test]
+    fn test_battery_unit_ocv() {
+        let mut btu = test_unit(0, 3.3);
+        btu.set_limits(SafetyLimits::LIFEPO4);
+        btu.start_ocv(OcvConfig {
+            nominal_capacity: 1.0,
+            increment: 0.5,
+            discharge_current: 1.0,
+            rest_duration: 10.0,
+            min_rest_duration: 0.0,
+            dvdt_threshold: 0.0,
+            dvdt_interval: 1.0,
+            min_voltage: 2.5,
+            charge: false,
+        })
+        .unwrap();
+        assert_eq!(btu.get_mode(), BatteryTestUnitMode::Resting);
+
+        let mut messages = Vec::new();
+        btu.update(0, 10.0, |msg| messages.push(msg));
+        assert_eq!(
+            btu.get_mode(),
+            BatteryTestUnitMode::DischargingConstantCurrent(1.0)
+        );
+
+        btu.current_sensor.set_current(1.0);
+        btu.update(10000, 1800.0, |msg| messages.push(msg));
+        btu.current_sensor.set_current(0.0);
+        btu.voltage_adc.set_voltage(3.2);
+        btu.update(1810000, 10.0, |msg| messages.push(msg));
+        // the voltage drops below the minimum during the second increment
+        btu.voltage_adc.set_voltage(2.4);
+        btu.update(1820000, 1.0, |msg| messages.push(msg));
+        btu.voltage_adc.set_voltage(3.0);
+        btu.update(1821000, 10.0, |msg| messages.push(msg));
+
+        let points = messages
+            .iter()
+            .filter_map(|msg| match msg {
+                MsgTypes::OcvPoint(0, index, point) => Some((*index, point.voltage)),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(points, [(0, 3.3), (1, 3.2), (2, 3.0)]);
+        assert!(messages.iter().any(|msg| matches!(
+            msg,
+            MsgTypes::OcvFinished(0, end) if end.points == 3 && end.reason == OcvEndReason::Completed
+        )));
+        assert_eq!(btu.get_mode(), BatteryTestUnitMode::Idle);
+
+        // the table stays available for the download
+        btu.download_ocv_table();
+        messages.clear();
+        for i in 0..5 {
+            btu.update(1_831_000 + i * 1000, 1.0, |msg| messages.push(msg));
+        }
+        let download = messages
+            .iter()
+            .filter(|msg| !matches!(msg, MsgTypes::Telemetry(..)))
+            .collect::<Vec<_>>();
+        assert_eq!(download.len(), 4);
+        assert!(matches!(
+            download[1],
+            MsgTypes::OcvPoint(0, 1, point)
+                if point.direction == OcvDirection::Discharge && point.state_of_charge == 0.5
+        ));
+        assert_eq!(*download[3], MsgTypes::OcvTableEnd(0, 3));
+    }
+
+    #[test]
+    fn test_battery_unit_state_of_charge() {
+        let mut btu = test_unit(0, 3.29);
+        let config = SocEstimatorConfig {
+            model: EcmParameters {
+                capacity: 1.0,
+                r0: 0.05,
+                r1: 0.0,
+                c1: 1.0,
+            },
+            ekf: false,
+        };
+
+        assert_eq!(
+            btu.set_soc_estimator(Some(SocEstimatorConfig {
+                model: EcmParameters {
+                    capacity: 0.0,
+                    ..config.model
+                },
+                ..config
+            })),
+            Err(SocEstimatorError::InvalidModel)
+        );
+        btu.set_soc_estimator(Some(config)).unwrap();
+        assert_eq!(btu.get_state_of_charge(), None);
+
+        // starts from the open circuit voltage
+        btu.update(0, 1.0, |_| {});
+        let soc = btu.get_state_of_charge().unwrap();
+        assert!((soc - 0.4).abs() < 0.001);
+
+        btu.set_mode(BatteryTestUnitMode::DischargingConstantCurrent(1.0));
+        btu.current_sensor.set_current(1.0);
+        let mut messages = Vec::new();
+        btu.update(1000, 360.0, |msg| messages.push(msg));
+        let telemetry = messages
+            .iter()
+            .find_map(|msg| match msg {
+                MsgTypes::Telemetry(0, telemetry) => Some(*telemetry),
+                _ => None,
+            })
+            .unwrap();
+        assert!((telemetry.state_of_charge.unwrap() - 0.3).abs() < 0.001);
+        assert!((telemetry.remaining_time.unwrap() - 1080.0).abs() < 1.0);
+
+        // a full charge is a known state of charge
+        btu.set_mode(BatteryTestUnitMode::Charging);
+        btu.current_sensor.set_current(-1.0);
+        btu.charge_done.value = true;
+        btu.update(361000, 1.0, |_| {});
+        assert_eq!(btu.get_state_of_charge(), Some(1.0));
+
+        btu.set_soc_estimator(None).unwrap();
+        assert_eq!(btu.get_state_of_charge(), None);
+    }
+
+    #[test]
+    fn test_serial_calibration() {
+        let invalid = CalibrationSet {
+            voltage: Calibration {
+                coefficients: [f32::NAN, 1.0, 0.0, 0.0],
+            },
+            ..CalibrationSet::default()
+        };
+        let mut firmware = new_mock_firmware!(vec![
+            MsgTypes::AddCalibrationPoint(1, 1.0),
+            MsgTypes::StartCalibration(1, CalibrationChannel::Voltage, 1),
+            MsgTypes::AddCalibrationPoint(1, 1.0),
+            MsgTypes::FinishCalibration(1),
+            MsgTypes::AddCalibrationPoint(1, 4.0),
+            MsgTypes::FinishCalibration(1),
+            MsgTypes::SetCalibration(1, invalid),
+            MsgTypes::GetCalibration(1),
+        ]);
+        let btu = &mut firmware.battery_units[1];
+
+        btu.voltage_adc.set_voltage(0.99);
+        firmware.update_serial();
+        firmware.update_serial();
+        firmware.update_serial();
+        firmware.update_serial();
+        firmware.battery_units[1].voltage_adc.set_voltage(3.93);
+        for _ in 0..4 {
+            firmware.update_serial();
+        }
+
+        let queue = &firmware.serial_transmitter.msg_queue;
+        assert_eq!(
+            queue[0],
+            MsgTypes::CalibrationError(1, CalibrationError::NotCalibrating)
+        );
+        assert_eq!(queue[1], MsgTypes::Ack);
+        assert!(matches!(queue[2], MsgTypes::CalibrationPoint(1, 0, point) if point.raw == 0.99));
+        assert_eq!(
+            queue[3],
+            MsgTypes::CalibrationError(1, CalibrationError::NotEnoughPoints)
+        );
+        assert!(matches!(queue[4], MsgTypes::CalibrationPoint(1, 1, point) if point.raw == 3.93));
+        let MsgTypes::Calibration(1, calibration) = queue[5] else {
+            panic!("expected the calibration, got {:?}", queue[5]);
+        };
+        assert_eq!(calibration.version, 1);
+        assert_eq!(
+            queue[6],
+            MsgTypes::CalibrationError(1, CalibrationError::InvalidCoefficients)
+        );
+        assert_eq!(queue[7], MsgTypes::Calibration(1, calibration));
+        assert_eq!(queue.len(), 8);
+
+        // the telemetry is corrected and tells which calibration was used
+        let btu = &mut firmware.battery_units[1];
+        btu.voltage_adc.set_voltage(2.45);
+        let mut messages = Vec::new();
+        btu.update(0, 1.0, |msg| messages.push(msg));
+        let MsgTypes::Telemetry(1, telemetry) = messages[0] else {
+            panic!("expected telemetry, got {:?}", messages[0]);
+        };
+        assert!((telemetry.voltage - 2.4898).abs() < 1e-4);
+        assert_eq!(telemetry.calibration, 1);
+        assert_eq!(btu.get_raw(CalibrationChannel::Voltage), 2.45);
+        assert!(btu.get_calibration_run().is_none());
+
+        // a calibration can't change during a test
+        btu.set_mode(BatteryTestUnitMode::Resting);
+        assert_eq!(
+            btu.set_calibration(CalibrationSet::default()),
+            Err(CalibrationError::Busy)
+        );
+        btu.set_mode(BatteryTestUnitMode::Idle);
+        btu.set_calibration(CalibrationSet::default()).unwrap();
+        assert_eq!(btu.get_voltage(), 2.45);
+    }
+
+    #[test]
+    fn test_serial_config() {
+        let mut firmware = new_mock_firmware!(vec![
+            MsgTypes::SaveConfig,
+            MsgTypes::SetUnitName(1, heapless::String::from("cell A")),
+            MsgTypes::SetSamplePeriod(0),
+            MsgTypes::SetSamplePeriod(250),
+            MsgTypes::SaveConfig,
+            MsgTypes::GetUnitName(1),
+        ]);
+        firmware.battery_units[2].set_limits(SafetyLimits::LIFEPO4);
+        let calibration = CalibrationSet {
+            version: 4,
+            ..CalibrationSet::default()
+        };
+        firmware.battery_units[2]
+            .set_calibration(calibration)
+            .unwrap();
+
+        for _ in 0..6 {
+            firmware.update_serial();
+        }
+
+        let queue = &firmware.serial_transmitter.msg_queue;
+        assert_eq!(queue[0], MsgTypes::ConfigSaved(0));
+        assert_eq!(queue[1], MsgTypes::Ack);
+        assert_eq!(
+            queue[2],
+            MsgTypes::ConfigError(ConfigError::InvalidSamplePeriod)
+        );
+        assert_eq!(queue[3], MsgTypes::Ack);
+        assert_eq!(queue[4], MsgTypes::ConfigSaved(1));
+        assert_eq!(
+            queue[5],
+            MsgTypes::UnitName(1, heapless::String::from("cell A"))
+        );
+
+        // after a reset
+        let mut restarted = new_mock_firmware!();
+        assert_eq!(restarted.load_config(), Err(ConfigError::Empty));
+        restarted.flash = firmware.flash;
+        restarted.load_config().unwrap();
+        assert_eq!(restarted.sample_period, 250);
+        assert_eq!(restarted.battery_units[1].get_name().as_str(), "cell A");
+        assert_eq!(
+            restarted.battery_units[2].get_limits(),
+            SafetyLimits::LIFEPO4
+        );
+        assert_eq!(*restarted.battery_units[2].get_calibration(), calibration);
+        assert_eq!(
+            restarted.battery_units[0].get_limits(),
+            SafetyLimits::default()
+        );
+    }
+
+    #[test]
+    fn test_serial_log() {
+        let config = LogConfig {
+            interval: 0.5,
+            policy: LogPolicy::Wrap,
+        };
+        let mut firmware = new_mock_firmware!(vec![
+            MsgTypes::StartLog(LogConfig {
+                interval: -1.0,
+                ..config
+            }),
+            MsgTypes::StartLog(config),
+            MsgTypes::ClearLog,
+            MsgTypes::StopLog,
+            MsgTypes::GetLogStatus,
+            MsgTypes::GetLog(4),
+        ]);
+        firmware.battery_units[1].voltage_adc.set_voltage(3.3);
+
+        for _ in 0..3 {
+            firmware.update_serial();
+        }
+        for update in 0..10 {
+            firmware.update_battery_units(update * 100, 0.1);
+        }
+        for _ in 0..3 {
+            firmware.update_serial();
+        }
+
+        let queue = &mut firmware.serial_transmitter.msg_queue;
+        queue.retain(|msg| !matches!(msg, MsgTypes::Telemetry(_, _)));
+        assert_eq!(
+            queue.pop_front(),
+            Some(MsgTypes::LogError(LogError::InvalidInterval))
+        );
+        assert_eq!(queue.pop_front(), Some(MsgTypes::Ack));
+        assert_eq!(
+            queue.pop_front(),
+            Some(MsgTypes::LogError(LogError::Running))
+        );
+        assert_eq!(queue.pop_front(), Some(MsgTypes::Ack));
+        // three units logged every 0.5 s, the first time right away
+        assert_eq!(
+            queue.pop_front(),
+            Some(MsgTypes::LogStatus(LogStatus {
+                running: false,
+                oldest: 0,
+                next: 9,
+                capacity: 128,
+            }))
+        );
+
+        firmware.update_battery_units(1000, 0.1);
+        let queue = &mut firmware.serial_transmitter.msg_queue;
+        queue.retain(|msg| !matches!(msg, MsgTypes::Telemetry(_, _)));
+        let records = queue
+            .iter()
+            .filter_map(|msg| match msg {
+                MsgTypes::LogRecord(record) => Some(*record),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            records
+                .iter()
+                .map(|record| record.sequence)
+                .collect::<Vec<_>>(),
+            [4, 5, 6, 7, 8]
+        );
+        assert_eq!(records[0].unit, 1);
+        assert_eq!(records[0].timestamp, 400);
+        assert_eq!(records[0].voltage, 3.3);
+        assert_eq!(records[0].step, None);
+        assert_eq!(queue.back(), Some(&MsgTypes::LogEnd(9)));
+    }
+
+    #[test]
+    fn test_serial_sampling() {
+        let config = SamplingConfig {
+            period: 1000,
+            decimation: 2,
+            fast_period: 50,
+            fast_duration: 200,
+            knee_slope: 0.0,
+        };
+        let mut firmware = new_mock_firmware!(vec![
+            MsgTypes::SetSampling(
+                1,
+                SamplingConfig {
+                    decimation: 0,
+                    ..config
+                }
+            ),
+            MsgTypes::SetSampling(1, config),
+            MsgTypes::GetSampling(1),
+        ]);
+        for _ in 0..3 {
+            firmware.update_serial();
+        }
+        let queue = &mut firmware.serial_transmitter.msg_queue;
+        assert_eq!(
+            queue.pop_front(),
+            Some(MsgTypes::SamplingError(1, SamplingError::InvalidPeriod))
+        );
+        assert_eq!(queue.pop_front(), Some(MsgTypes::Ack));
+        assert_eq!(queue.pop_front(), Some(MsgTypes::Sampling(1, config)));
+
+        // the other units are sampled every 100 ms, unit 1 every second and sends every second
+        // sample
+        for update in 0..40 {
+            firmware.update_battery_units(update * 100, 0.1);
+        }
+        // takes the telemetry of the unit from the queue
+        let telemetry = |queue: &mut std::collections::VecDeque<MsgTypes>, unit: u8| {
+            let is_telemetry =
+                |msg: &MsgTypes| matches!(msg, MsgTypes::Telemetry(id, _) if *id == unit);
+            let count = queue.iter().filter(|msg| is_telemetry(msg)).count();
+            queue.retain(|msg| !is_telemetry(msg));
+            count
+        };
+        assert_eq!(telemetry(&mut firmware.serial_transmitter.msg_queue, 0), 40);
+        assert_eq!(telemetry(&mut firmware.serial_transmitter.msg_queue, 1), 2);
+        assert_eq!(firmware.get_update_period(), 100);
+
+        // a new mode samples fast for a while
+        firmware.battery_units[1].set_mode(BatteryTestUnitMode::Resting);
+        assert_eq!(firmware.get_update_period(), 50);
+        for update in 0..10 {
+            firmware.update_battery_units(4000 + update * 50, 0.05);
+        }
+        assert_eq!(telemetry(&mut firmware.serial_transmitter.msg_queue, 1), 2);
+        assert_eq!(firmware.get_update_period(), 100);
+    }
+
+    #[test]
+    fn test_serial_time() {
+        let mut firmware = new_mock_firmware!(vec![
+            MsgTypes::SetTime(1_678_806_566_000),
+            MsgTypes::GetTime,
+            // 2100 is beyond the real time clock
+            MsgTypes::SetTime(4_102_444_800_000),
+        ]);
+        firmware.monotonic.advance_millis(1000);
+        firmware.load_time();
+        assert_eq!(firmware.get_time(), 946_684_800_000);
+
+        firmware.monotonic.advance_millis(500);
+        firmware.update_serial();
+        assert_eq!(
+            clock::to_unix_time(firmware.rtc.datetime),
+            1_678_806_566_000
+        );
+        firmware.monotonic.advance_millis(250);
+        firmware.update_serial();
+        firmware.update_serial();
+        let queue = &mut firmware.serial_transmitter.msg_queue;
+        assert_eq!(queue.pop_front(), Some(MsgTypes::Ack));
+        assert_eq!(queue.pop_front(), Some(MsgTypes::Time(1_678_806_566_250)));
+        assert_eq!(
+            queue.pop_front(),
+            Some(MsgTypes::ClockError(ClockError::Rtc))
+        );
+
+        // more than the sample period passed since the start
+        firmware.update();
+        let timestamp = firmware
+            .serial_transmitter
+            .msg_queue
+            .iter()
+            .find_map(|msg| match msg {
+                MsgTypes::Telemetry(0, telemetry) => Some(telemetry.timestamp),
+                _ => None,
+            });
+        assert_eq!(timestamp, Some(1_678_806_566_250));
+    }
+
+    #[test]
+    fn test_watchdog() {
+        let mut firmware = new_mock_firmware!();
+        firmware.sample_period = 200;
+
+        // fed while the updates keep up with the sample period and the tolerance
+        for _ in 0..5 {
+            firmware.monotonic.advance_millis(200);
+            firmware.update();
+            firmware.monotonic.advance_millis(500);
+            firmware.update_watchdog();
+        }
+        assert_eq!(firmware.watchdog.fed, 5);
+
+        // the update hangs
+        firmware.monotonic.advance_millis(201);
+        firmware.update_watchdog();
+        assert_eq!(firmware.watchdog.fed, 5);
+    }
+
+    #[test]
+    fn test_serial_recover_interrupted_tests() {
+        let mut firmware = new_mock_firmware!();
+        let current = BatteryTestUnitMode::DischargingConstantCurrent(1.0);
+        firmware.battery_units[0].current_sensor.set_current(1.0);
+        firmware.battery_units[0].set_mode(current);
+        let rest = Step::Rest(heapless::Vec::from_slice(&[Condition::Duration(60.0)]).unwrap());
+        firmware.battery_units[2].set_program_step(0, rest).unwrap();
+        firmware.battery_units[2].start_program().unwrap();
+        for _ in 0..36 {
+            firmware.monotonic.advance_millis(100);
+            firmware.update();
+        }
+
+        // the watchdog resets the board, the backup registers keep their content
+        let retained = std::mem::replace(&mut firmware.retained, MockRetainedMemory::new(0));
+        let mut firmware = new_mock_firmware!(vec![
+            MsgTypes::ResumeTest(2),
+            MsgTypes::ResumeTest(0),
+            MsgTypes::ResumeTest(0),
+            MsgTypes::ResumeTest(5),
+        ]);
+        firmware.retained = retained;
+        firmware.watchdog.caused_reset = true;
+        firmware.check_interrupted_tests();
+
+        assert!(firmware.battery_units.iter().all(|btu| !btu.is_active()));
+        let queue = &mut firmware.serial_transmitter.msg_queue;
+        assert_eq!(queue.pop_front(), Some(MsgTypes::WatchdogReset));
+        let Some(MsgTypes::TestInterrupted(0, run)) = queue.pop_front() else {
+            panic!("unit 0 wasn't interrupted");
+        };
+        assert_eq!((run.mode, run.resumable), (current, true));
+        assert!((run.capacity - 0.001).abs() < 1e-6);
+        assert!(matches!(
+            queue.pop_front(),
+            Some(MsgTypes::TestInterrupted(
+                2,
+                UnitRun {
+                    resumable: false,
+                    ..
+                }
+            ))
+        ));
+        assert!(queue.is_empty());
+
+        // only the plain mode continues, with its capacity
+        for _ in 0..4 {
+            firmware.update_serial();
+        }
+        let queue = &mut firmware.serial_transmitter.msg_queue;
+        assert_eq!(
+            queue.pop_front(),
+            Some(MsgTypes::RecoveryError(2, RecoveryError::NotResumable))
+        );
+        assert_eq!(queue.pop_front(), Some(MsgTypes::Ack));
+        assert_eq!(
+            queue.pop_front(),
+            Some(MsgTypes::RecoveryError(0, RecoveryError::Busy))
+        );
+        assert_eq!(
+            queue.pop_front(),
+            Some(MsgTypes::UnitError(5, UnitError::UnknownUnit))
+        );
+        assert_eq!(firmware.battery_units[0].get_mode(), current);
+        assert_eq!(
+            firmware.battery_units[0].get_run().unwrap().capacity,
+            run.capacity
+        );
+        assert!(!firmware.battery_units[2].is_active());
+    }
+
+    #[test]
+    fn test_serial_resume_over_limit() {
+        let mut firmware = new_mock_firmware!();
+        let current = BatteryTestUnitMode::DischargingConstantCurrent(1.0);
+        firmware.battery_units[0].voltage_adc.set_voltage(3.3);
+        firmware.battery_units[0].current_sensor.set_current(1.0);
+        firmware.battery_units[0].set_mode(current);
+        for _ in 0..10 {
+            firmware.monotonic.advance_millis(100);
+            firmware.update();
+        }
+
+        // the cell discharged further while the board was down
+        let retained = std::mem::replace(&mut firmware.retained, MockRetainedMemory::new(0));
+        let mut firmware = new_mock_firmware!(vec![MsgTypes::ResumeTest(0)]);
+        firmware.retained = retained;
+        firmware.battery_units[0].set_limits(SafetyLimits::LIFEPO4);
+        firmware.battery_units[0].voltage_adc.set_voltage(1.9);
+        firmware.check_interrupted_tests();
+        firmware.serial_transmitter.msg_queue.clear();
+
+        firmware.update_serial();
+        assert_eq!(
+            firmware.serial_transmitter.msg_queue.pop_front(),
+            Some(MsgTypes::UnitError(
+                0,
+                UnitError::LimitViolated(FaultCause::UnderVoltage(1.9))
+            ))
+        );
+        assert_eq!(
+            firmware.battery_units[0].get_mode(),
+            BatteryTestUnitMode::Idle
+        );
+    }
+
+    #[test]
+    fn test_serial_unit_control() {
+        let current = BatteryTestUnitMode::DischargingConstantCurrent(1.0);
+        let rest = Step::Rest(heapless::Vec::from_slice(&[Condition::Duration(60.0)]).unwrap());
+        let mut firmware = new_mock_firmware!(vec![
+            MsgTypes::SetMode(0, BatteryTestUnitMode::DischargingConstantCurrent(-1.0)),
+            MsgTypes::SetMode(0, current),
+            MsgTypes::GetMode(0),
+            MsgTypes::SetMode(1, BatteryTestUnitMode::Idle),
+            MsgTypes::PauseTest(1),
+            MsgTypes::GetStatus(1),
+            MsgTypes::AbortTest(1),
+            MsgTypes::AbortTest(1),
+            MsgTypes::GetLimits(2),
+            MsgTypes::GetSamplePeriod,
+            MsgTypes::ListUnits,
+        ]);
+        firmware.battery_units[0].current_sensor.set_current(1.0);
+        firmware.battery_units[1].set_program_step(0, rest).unwrap();
+        firmware.battery_units[1].start_program().unwrap();
+        firmware.battery_units[2].set_limits(SafetyLimits::LIFEPO4);
+
+        for _ in 0..11 {
+            firmware.update_serial();
+        }
+
+        let queue = &mut firmware.serial_transmitter.msg_queue;
+        assert_eq!(
+            queue.pop_front(),
+            Some(MsgTypes::UnitError(0, UnitError::InvalidMode))
+        );
+        assert_eq!(queue.pop_front(), Some(MsgTypes::Ack));
+        assert_eq!(queue.pop_front(), Some(MsgTypes::Mode(0, current)));
+        // the program sets the modes itself
+        assert_eq!(
+            queue.pop_front(),
+            Some(MsgTypes::UnitError(1, UnitError::Busy))
+        );
+        assert_eq!(queue.pop_front(), Some(MsgTypes::Ack));
+        let Some(MsgTypes::Status(1, status)) = queue.pop_front() else {
+            panic!("no status of unit 1");
+        };
+        assert_eq!(
+            (status.mode, status.test, status.step, status.paused),
+            (
+                BatteryTestUnitMode::Paused,
+                Some(TestKind::Program),
+                Some(0),
+                Some(BatteryTestUnitMode::Resting)
+            )
+        );
+        // a paused test can be aborted too
+        assert!(matches!(
+            queue.pop_front(),
+            Some(MsgTypes::StepTransition(
+                1,
+                StepTransition {
+                    from: Some(0),
+                    to: None,
+                    reason: TransitionReason::Aborted,
+                    ..
+                }
+            ))
+        ));
+        assert_eq!(
+            queue.pop_front(),
+            Some(MsgTypes::TestAborted(
+                1,
+                TestSummary {
+                    test: TestKind::Program,
+                    step: Some(0),
+                    duration: 0.0,
+                    capacity: 0.0,
+                    energy: 0.0,
+                }
+            ))
+        );
+        assert_eq!(
+            queue.pop_front(),
+            Some(MsgTypes::UnitError(1, UnitError::NotRunning))
+        );
+        assert_eq!(
+            queue.pop_front(),
+            Some(MsgTypes::Limits(2, SafetyLimits::LIFEPO4))
+        );
+        assert_eq!(
+            queue.pop_front(),
+            Some(MsgTypes::SamplePeriod(DEFAULT_SAMPLE_PERIOD))
+        );
+        for unit in 0..3 {
+            let Some(MsgTypes::Status(id, UnitStatus { test, .. })) = queue.pop_front() else {
+                panic!("no status of unit {}", unit);
+            };
+            assert_eq!(id, unit);
+            assert_eq!(test, (unit == 0).then_some(TestKind::Mode));
+        }
+        assert_eq!(queue.pop_front(), Some(MsgTypes::UnitCount(3)));
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn test_serial_pause_and_resume() {
+        let current = BatteryTestUnitMode::DischargingConstantCurrent(1.0);
+        let mut firmware = new_mock_firmware!(vec![
+            MsgTypes::PauseTest(0),
+            MsgTypes::SetMode(0, current),
+            MsgTypes::PauseTest(0),
+            MsgTypes::ResumeTest(0),
+            MsgTypes::ResumeTest(0),
+        ]);
+        let btu = &mut firmware.battery_units[0];
+        btu.current_sensor.set_current(1.0);
+        btu.temperature_sensor.set_temperature(25.0);
+        btu.set_limits(SafetyLimits {
+            max_temperature: Some(60.0),
+            ..SafetyLimits::default()
+        });
+
+        firmware.update_serial();
+        assert_eq!(
+            firmware.serial_transmitter.msg_queue.pop_front(),
+            Some(MsgTypes::UnitError(0, UnitError::NotRunning))
+        );
+        firmware.update_serial();
+        firmware.update_battery_units(0, 3.6);
+
+        // the load is off and the counters hold
+        firmware.update_serial();
+        firmware.update_battery_units(0, 3.6);
+        let btu = &mut firmware.battery_units[0];
+        assert_eq!(btu.get_mode(), BatteryTestUnitMode::Paused);
+        assert_eq!(btu.get_status().paused, Some(current));
+        assert!((btu.get_status().duration - 3.6).abs() < 1e-6);
+        assert!((btu.get_status().capacity - 0.001).abs() < 1e-6);
+        assert_eq!(
+            btu.load_pwm.get_duty_cycle(),
+            btu.load_pwm.get_min_duty_cycle()
+        );
+        // a reset during the pause would continue the discharge
+        assert_eq!(btu.get_run().unwrap().mode, current);
+
+        // e.g. a loose thermistor doesn't fault the paused unit, but it can't resume yet
+        btu.temperature_sensor.set_temperature(150.0);
+        firmware.update_battery_units(0, 3.6);
+        firmware.update_serial();
+        assert_eq!(
+            firmware.battery_units[0].get_mode(),
+            BatteryTestUnitMode::Paused
+        );
+        firmware.battery_units[0]
+            .temperature_sensor
+            .set_temperature(25.0);
+
+        firmware.update_serial();
+        let btu = &firmware.battery_units[0];
+        assert_eq!(btu.get_mode(), current);
+        assert!(!btu.is_paused());
+        // the test goes on with its counters
+        assert!((btu.get_status().capacity - 0.001).abs() < 1e-6);
+        let queue = &firmware.serial_transmitter.msg_queue;
+        let replies = queue
+            .iter()
+            .filter(|msg| !matches!(msg, MsgTypes::Telemetry(_, _)))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            replies,
+            [
+                &MsgTypes::Ack,
+                &MsgTypes::Ack,
+                &MsgTypes::UnitError(
+                    0,
+                    UnitError::LimitViolated(FaultCause::OverTemperature(150.0))
+                ),
+                &MsgTypes::Ack,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_battery_unit_pause_program() {
+        let mut btu = test_unit(0, 0.0);
+        let discharge = Step::DischargeConstantCurrent(
+            1.0,
+            heapless::Vec::from_slice(&[Condition::Duration(10.0)]).unwrap(),
+        );
+        btu.set_program_step(0, discharge).unwrap();
+        btu.current_sensor.set_current(1.0);
+        btu.start_program().unwrap();
+        btu.update(0, 6.0, only_telemetry);
+
+        btu.pause_test().unwrap();
+        // the step would have ended long ago
+        btu.update(6_000, 60.0, only_telemetry);
+        assert_eq!(btu.get_mode(), BatteryTestUnitMode::Paused);
+        assert_eq!(btu.get_sequencer().get_step(), Some(0));
+        assert_eq!(
+            btu.select_mode(BatteryTestUnitMode::Idle),
+            Err(UnitError::Busy)
+        );
+
+        btu.resume_paused().unwrap();
+        assert_eq!(
+            btu.get_mode(),
+            BatteryTestUnitMode::DischargingConstantCurrent(1.0)
+        );
+        btu.update(66_000, 3.0, only_telemetry);
+        assert_eq!(btu.get_sequencer().get_step(), Some(0));
+        let mut transitions = Vec::new();
+        btu.update(69_000, 1.0, |msg| match msg {
+            MsgTypes::StepTransition(_, transition) => transitions.push(transition),
+            msg => only_telemetry(msg),
+        });
+        assert!(matches!(
+            transitions[..],
+            [StepTransition {
+                from: Some(0),
+                to: None,
+                ..
+            }]
+        ));
+        assert!((btu.get_status().duration - 10.0).abs() < 1e-4);
+        assert!(!btu.is_active());
+    }
+
+    #[test]
+    fn test_crash_report() {
+        let mut firmware = new_mock_firmware!();
+        firmware.check_crash_report(ResetCause::PowerOn);
+        assert!(firmware.serial_transmitter.msg_queue.is_empty());
+
+        firmware.battery_units[1].set_mode(BatteryTestUnitMode::Charging);
+        let units = firmware.get_unit_states();
+        let idle = UnitState {
+            mode: BatteryTestUnitMode::Idle,
+            test: None,
+        };
+        let charging = UnitState {
+            mode: BatteryTestUnitMode::Charging,
+            test: Some(TestKind::Mode),
+        };
+        assert_eq!(units, [idle, charging, idle]);
+
+        // saved by the panic handler
+        let report = CrashReport {
+            message: heapless::String::from("panicked at 'explicit panic', board/src/main.rs:9:5"),
+            uptime: 3_600_000,
+            reset_cause: ResetCause::PowerOn,
+            units,
+        };
+        crash::save(&mut firmware.crash_storage, &report);
+        firmware.check_crash_report(ResetCause::Software);
+        firmware.check_crash_report(ResetCause::Software);
+        let queue = &mut firmware.serial_transmitter.msg_queue;
+        assert_eq!(
+            queue.pop_front(),
+            Some(MsgTypes::CrashReport(report, ResetCause::Software))
+        );
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn test_serial_conditioning() {
+        let config = ChannelConditioning {
+            oversampling: 4,
+            filter: Filter::Median(3),
+        };
+        let mut firmware = new_mock_firmware!(vec![
+            MsgTypes::SetConditioning(
+                1,
+                CalibrationChannel::Voltage,
+                ChannelConditioning {
+                    oversampling: 0,
+                    ..config
+                }
+            ),
+            MsgTypes::SetConditioning(1, CalibrationChannel::Voltage, config),
+            MsgTypes::GetConditioning(1),
+        ]);
+        for _ in 0..3 {
+            firmware.update_serial();
+        }
+        let queue = &mut firmware.serial_transmitter.msg_queue;
+        assert_eq!(
+            queue.pop_front(),
+            Some(MsgTypes::ConditioningError(
+                1,
+                ConditioningError::InvalidOversampling
+            ))
+        );
+        assert_eq!(queue.pop_front(), Some(MsgTypes::Ack));
+        assert_eq!(
+            queue.pop_front(),
+            Some(MsgTypes::Conditioning(
+                1,
+                ConditioningConfig {
+                    voltage: config,
+                    ..ConditioningConfig::default()
+                }
+            ))
+        );
+
+        // the telemetry counts every reading of the voltage
+        firmware.update_battery_units(0, 0.1);
+        let stats = firmware
+            .serial_transmitter
+            .msg_queue
+            .iter()
+            .find_map(|msg| match msg {
+                MsgTypes::Telemetry(1, telemetry) => Some(telemetry.stats),
+                _ => None,
+            })
+            .unwrap();
+        assert_eq!(stats.voltage.count, 4);
+        assert_eq!(stats.current.count, 1);
+    }
+
+    #[test]
+    fn test_serial_program_upload() {
+        let rest = Step::Rest(heapless::Vec::from_slice(&[Condition::Duration(1.0)]).unwrap());
+        let mut firmware = new_mock_firmware!(vec![
+            MsgTypes::StartProgram(2),
+            MsgTypes::SetProgramStep(2, 0, rest.clone()),
+            MsgTypes::SetProgramStep(2, 2, rest.clone()),
+            MsgTypes::StartProgram(2),
+            MsgTypes::ClearProgram(2),
+            MsgTypes::StopProgram(2),
+            MsgTypes::StopProgram(2),
+        ]);
+
+        // every update handles one message
+        for _ in 0..7 {
+            firmware.update_serial();
+        }
+
+        let queue = &firmware.serial_transmitter.msg_queue;
+        assert_eq!(queue[0], MsgTypes::ProgramError(2, ProgramError::Empty));
+        assert_eq!(queue[1], MsgTypes::Ack);
+        assert_eq!(
+            queue[2],
+            MsgTypes::ProgramError(2, ProgramError::InvalidIndex(2))
+        );
+        assert!(matches!(
+            queue[3],
+            MsgTypes::StepTransition(
+                2,
+                StepTransition {
+                    to: Some(0),
+                    reason: TransitionReason::Started,
+                    ..
+                }
+            )
+        ));
+        assert_eq!(queue[4], MsgTypes::ProgramError(2, ProgramError::Busy));
+        assert!(matches!(
+            queue[5],
+            MsgTypes::StepTransition(
+                2,
+                StepTransition {
+                    to: None,
+                    reason: TransitionReason::Stopped,
+                    ..
+                }
+            )
+        ));
+        // stopping a unit without a running program is only acknowledged
+        assert_eq!(queue[6], MsgTypes::Ack);
+        assert_eq!(queue.len(), 7);
+        assert_eq!(
+            firmware.battery_units[2]
+                .get_sequencer()
+                .get_program()
+                .get_steps(),
+            [rest]
+        );
+        assert_eq!(
+            firmware.battery_units[2].get_mode(),
+            BatteryTestUnitMode::Idle
+        );
+    }
+
+    macro_rules! new_simulated_btu {
+        ($simulation:expr) => {{
+            let (voltage, current, temperature, load, charger_enable, charge_done) =
+                sim::channels($simulation);
+            let mut btu = BatteryTestUnit::new(
+                0,
+                voltage,
+                current,
+                temperature,
+                load,
+                charger_enable,
+                charge_done,
+            );
+            btu.set_limits(SafetyLimits::LIFEPO4);
+            btu
+        }};
+    }
+
+    #[test]
+    fn test_simulated_load_control() {
+        let simulation = RefCell::new(BatterySimulation::lifepo4(1.0, 0.5));
+        let mut btu = new_simulated_btu!(&simulation);
+
+        for mode in [
+            BatteryTestUnitMode::DischargingConstantCurrent(1.0),
+            BatteryTestUnitMode::DischargingConstantPower(2.0),
+            BatteryTestUnitMode::DischargingConstantResistance(1.6),
+        ] {
+            btu.set_mode(mode);
+            for step in 0..300 {
+                btu.update(step * 100, 0.1, only_telemetry);
+                simulation.borrow_mut().update(0.1);
+            }
+
+            // the load settles where the mode wants it, within one step of the duty cycle
+            let voltage = simulation.borrow().get_voltage();
+            let current = simulation.borrow().get_current();
+            let expected = mode.get_load_current(voltage).unwrap();
+            assert!(
+                (current - expected).abs() < 0.02,
+                "{:?}: {} A instead of {} A",
+                mode,
+                current,
+                expected
+            );
+        }
+
+        btu.set_mode(BatteryTestUnitMode::Idle);
+        assert_eq!(simulation.borrow().get_current(), 0.0);
+    }
+
+    #[test]
+    fn test_simulated_discharge_and_charge() {
+        let capacity = 0.05;
+        let simulation = RefCell::new(BatterySimulation::lifepo4(capacity, 1.0));
+        let mut btu = new_simulated_btu!(&simulation);
+        let end = |condition| heapless::Vec::from_slice(&[condition]).unwrap();
+
+        btu.set_program_step(
+            0,
+            Step::DischargeConstantCurrent(1.0, end(Condition::VoltageBelow(2.8))),
+        )
+        .unwrap();
+        btu.set_program_step(1, Step::Rest(end(Condition::Duration(60.0))))
+            .unwrap();
+        btu.set_program_step(2, Step::Charge(end(Condition::ChargeDone)))
+            .unwrap();
+        btu.set_soc_estimator(Some(SocEstimatorConfig {
+            model: *simulation.borrow().cell.get_model(),
+            ekf: false,
+        }))
+        .unwrap();
+        btu.start_program().unwrap();
+
+        let mut transitions = Vec::new();
+        let mut estimate_when_empty = None;
+        let mut max_temperature: f32 = 0.0;
+        let mut step = 0;
+        while btu.get_sequencer().is_running() {
+            assert!(step < 10_000, "the program didn't finish");
+            btu.update(step * 100, 0.1, |msg| {
+                if let MsgTypes::StepTransition(_, transition) = msg {
+                    transitions.push((transition, simulation.borrow().cell.get_state_of_charge()));
+                }
+            });
+            if transitions.len() == 1 && estimate_when_empty.is_none() {
+                estimate_when_empty = btu.get_state_of_charge();
+            }
+            simulation.borrow_mut().update(0.1);
+            max_temperature = max_temperature.max(simulation.borrow().cell.get_temperature());
+            step += 1;
+        }
+
+        assert_eq!(btu.get_mode(), BatteryTestUnitMode::Idle);
+        let [(discharge, empty), (_, _), (charge, full)] = transitions[..] else {
+            panic!("unexpected transitions {:?}", transitions);
+        };
+
+        // the discharge ends shortly before the cell is empty, and the estimator followed it
+        assert_eq!(
+            discharge.reason,
+            TransitionReason::EndCondition(Condition::VoltageBelow(2.8))
+        );
+        assert!(empty < 0.05, "{}", empty);
+        assert!((estimate_when_empty.unwrap() - empty).abs() < 0.01);
+        assert!((discharge.capacity - capacity * (1.0 - empty)).abs() < 0.001);
+        assert!((discharge.duration - 3600.0 * discharge.capacity).abs() < 2.0);
+        assert!(max_temperature > 25.0);
+
+        assert_eq!(
+            charge.reason,
+            TransitionReason::EndCondition(Condition::ChargeDone)
+        );
+        assert!(full > 0.99, "{}", full);
+        assert_eq!(btu.get_state_of_charge(), Some(1.0));
+        assert!(!simulation.borrow().is_charge_done());
+        assert_eq!(simulation.borrow().get_current(), 0.0);
+    }
 }
@@ -1,3 +1,4 @@
+use core::cell::RefCell;
 use time::PrimitiveDateTime;
 
 use crate::msg_types::MsgTypes;
@@ -19,6 +20,16 @@ pub trait AdcInput {
     fn get_voltage(&mut self) -> f32;
 }
 
+pub trait CurrentInput {
+    /// in A, positive while the battery is discharged
+    fn get_current(&mut self) -> f32;
+}
+
+pub trait TemperatureInput {
+    /// in °C
+    fn get_temperature(&mut self) -> f32;
+}
+
 pub trait PwmOutput {
     fn set_duty_cycle(&mut self, duty_cycle: u16);
     fn get_duty_cycle(&mut self) -> u16;
@@ -29,23 +40,95 @@ pub trait PwmOutput {
     }
 }
 
+/// A received package couldn't be decoded
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct InvalidPacket;
+
 pub trait SerialReceiver {
-    fn receive(&mut self, cb: impl FnMut(MsgTypes));
+    /// The next complete package that was received, `None` if there is none
+    fn receive(&mut self) -> Option<Result<MsgTypes, InvalidPacket>>;
 }
 
 pub trait SerialTransmitter {
     fn transmit(&mut self, msg: MsgTypes);
 }
 
+/// Setting the real time clock failed, e.g. because it can't represent the date
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct RtcError;
+
+/// Wall clock that keeps running while the firmware is reset
 pub trait RealTimeClock {
-    fn set_datetime(&mut self, datetime: PrimitiveDateTime);
+    fn set_datetime(&mut self, datetime: PrimitiveDateTime) -> Result<(), RtcError>;
     fn get_datetime(&mut self) -> PrimitiveDateTime;
 }
 
+/// Gives mutable access to something that is used by several drivers, like a bus
+pub trait SharedResource {
+    type Target;
+    fn lock<R>(&self, f: impl FnOnce(&mut Self::Target) -> R) -> R;
+}
+
+impl<T> SharedResource for RefCell<T> {
+    type Target = T;
+    fn lock<R>(&self, f: impl FnOnce(&mut T) -> R) -> R {
+        f(&mut self.borrow_mut())
+    }
+}
+
+/// Reading, programming or erasing a flash failed
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct FlashError;
+
+/// Two flash sectors reserved for the configuration, erased bytes read as 0xFF
+pub trait Flash {
+    /// in bytes, both sectors have the same size
+    fn sector_size(&self) -> u32;
+    /// `offset` counts from the start of the first sector
+    fn read(&mut self, offset: u32, buf: &mut [u8]);
+    /// Programs bytes that were erased before
+    fn write(&mut self, offset: u32, data: &[u8]) -> Result<(), FlashError>;
+    /// Erases sector 0 or 1
+    fn erase(&mut self, sector: u8) -> Result<(), FlashError>;
+}
+
+/// External flash the measurements are logged to, erased bytes read as 0xFF
+pub trait BlockStorage {
+    /// in bytes
+    fn capacity(&self) -> u32;
+    /// the smallest part that can be erased, in bytes
+    fn block_size(&self) -> u32;
+    fn read(&mut self, address: u32, buf: &mut [u8]) -> Result<(), FlashError>;
+    /// Programs bytes that were erased before
+    fn write(&mut self, address: u32, data: &[u8]) -> Result<(), FlashError>;
+    fn erase_block(&mut self, block: u32) -> Result<(), FlashError>;
+    fn erase_all(&mut self) -> Result<(), FlashError>;
+}
+
+/// Resets the board unless it is fed in time
+pub trait Watchdog {
+    fn feed(&mut self);
+    /// Whether the watchdog caused the last reset
+    fn caused_reset(&self) -> bool;
+}
+
+/// Memory that keeps its content through a reset as long as the board stays powered, like the
+/// backup registers of the RTC
+pub trait RetainedMemory {
+    /// in bytes
+    fn capacity(&self) -> usize;
+    /// Reads from the start of the memory
+    fn read(&mut self, buf: &mut [u8]);
+    /// Writes to the start of the memory
+    fn write(&mut self, data: &[u8]);
+}
+
+/// Monotonic clock, counts from the start and never goes backwards
 pub trait SystemTime {
-    fn get_delta_time(&self) -> f64;
-    fn get_delta_time_micros(&self) -> u32;
-    fn get_delta_time_millis(&self) -> u32 {
-        self.get_delta_time_micros() / 1000
+    /// in µs
+    fn get_micros(&mut self) -> u64;
+    /// in ms
+    fn get_millis(&mut self) -> u64 {
+        self.get_micros() / 1000
     }
 }
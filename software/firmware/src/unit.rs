@@ -0,0 +1,120 @@
+//! What the client can ask about a battery test unit, and the errors of the commands that control
+//! it directly, e.g. setting its mode or pausing its test.
+
+use heapless::String;
+use serde::{Deserialize, Serialize};
+
+use crate::limits::FaultCause;
+use crate::BatteryTestUnitMode;
+
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq)]
+pub enum UnitError {
+    /// There is no unit with the id
+    UnknownUnit,
+    /// A program or procedure runs, it sets the modes itself
+    Busy,
+    /// The unit latched a fault, it has to be cleared first
+    Faulted,
+    /// The mode can't be set directly, or its setpoint isn't positive
+    InvalidMode,
+    /// The unit doesn't run a test
+    NotRunning,
+    /// Only a mode that was set on its own and a program can be paused
+    NotPausable,
+    /// The measurement violates the limit, the test stays paused
+    LimitViolated(FaultCause),
+}
+
+/// What runs on a unit
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq)]
+pub enum TestKind {
+    /// A mode that was set on its own
+    Mode,
+    Program,
+    Orbits,
+    /// A single pulse started by the client
+    PulseTest,
+    Hppc,
+    Ocv,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct UnitStatus {
+    pub name: String<16>,
+    pub mode: BatteryTestUnitMode,
+    /// `None` while the unit is idle or in the fault state
+    pub test: Option<TestKind>,
+    /// the mode the test continues with once it's resumed, `Some` while it's paused
+    pub paused: Option<BatteryTestUnitMode>,
+    /// index of the program step that runs
+    pub step: Option<u8>,
+    /// time since the test started, in s
+    pub duration: f32,
+    /// in Ah
+    pub capacity: f32,
+    /// in Wh
+    pub energy: f32,
+    /// whether a calibration collects reference points
+    pub calibrating: bool,
+}
+
+/// How far an aborted test got
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq)]
+pub struct TestSummary {
+    pub test: TestKind,
+    /// index of the program step that ran
+    pub step: Option<u8>,
+    /// time since the test started without the pauses, in s
+    pub duration: f32,
+    /// in Ah
+    pub capacity: f32,
+    /// in Wh
+    pub energy: f32,
+}
+
+/// Whether the client can set the mode on its own. The pulse and the fault state are entered by
+/// the unit, a pause is started with `PauseTest`, setpoints have to be positive. `Discharging`
+/// drives the load with a test pattern that ignores its setpoint, it's never set on a cell.
+pub fn check_mode(mode: BatteryTestUnitMode) -> Result<(), UnitError> {
+    let setpoint = match mode {
+        BatteryTestUnitMode::Idle
+        | BatteryTestUnitMode::Charging
+        | BatteryTestUnitMode::Resting => return Ok(()),
+        BatteryTestUnitMode::DischargingConstantCurrent(setpoint)
+        | BatteryTestUnitMode::DischargingConstantPower(setpoint)
+        | BatteryTestUnitMode::DischargingConstantResistance(setpoint) => setpoint,
+        BatteryTestUnitMode::Discharging(_)
+        | BatteryTestUnitMode::DcirPulse
+        | BatteryTestUnitMode::Paused
+        | BatteryTestUnitMode::Fault(_) => return Err(UnitError::InvalidMode),
+    };
+    if setpoint.is_finite() && setpoint > 0.0 {
+        Ok(())
+    } else {
+        Err(UnitError::InvalidMode)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_mode() {
+        assert_eq!(check_mode(BatteryTestUnitMode::Idle), Ok(()));
+        assert_eq!(
+            check_mode(BatteryTestUnitMode::DischargingConstantCurrent(1.5)),
+            Ok(())
+        );
+        for mode in [
+            BatteryTestUnitMode::DischargingConstantPower(0.0),
+            BatteryTestUnitMode::DischargingConstantResistance(f32::NAN),
+            BatteryTestUnitMode::Discharging(2.5),
+            BatteryTestUnitMode::DcirPulse,
+            BatteryTestUnitMode::Paused,
+            BatteryTestUnitMode::Fault(FaultCause::UnderVoltage(1.9)),
+        ] {
+            assert_eq!(check_mode(mode), Err(UnitError::InvalidMode), "{:?}", mode);
+        }
+    }
+}
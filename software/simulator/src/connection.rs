@@ -0,0 +1,57 @@
+use std::io::{Read, Write};
+use std::time::Duration;
+
+use bbqueue::Producer;
+use serialport::{SerialPort, TTYPort};
+
+/// The link to the client, it takes the place of the UART of the board. The client opens the
+/// slave side of the pseudo-terminal like the serial port of the board.
+pub struct Connection {
+    master: TTYPort,
+    /// kept open, so writes to the master don't fail before the client opened the port
+    slave: TTYPort,
+}
+
+impl Connection {
+    pub fn pty() -> serialport::Result<Self> {
+        let (mut master, slave) = TTYPort::pair()?;
+        // neither reading nor writing may block the simulation
+        master.set_timeout(Duration::ZERO)?;
+        Ok(Connection { master, slave })
+    }
+
+    /// Where the client connects to
+    pub fn describe(&self) -> String {
+        self.slave.name().unwrap_or_default()
+    }
+
+    /// Moves the received bytes into the buffer, as many as fit
+    pub fn receive<const N: usize>(&mut self, prod_rx: &mut Producer<N>) {
+        loop {
+            let mut grant = match prod_rx.grant_max_remaining(256) {
+                Ok(grant) => grant,
+                Err(_) => return,
+            };
+            let count = self.read(&mut grant);
+            grant.commit(count);
+            if count == 0 {
+                return;
+            }
+        }
+    }
+
+    /// Returns how many bytes were written, the rest has to be sent later
+    pub fn transmit(&mut self, bytes: &[u8]) -> usize {
+        self.master.write(bytes).unwrap_or(0)
+    }
+
+    fn read(&mut self, buf: &mut [u8]) -> usize {
+        match self.master.bytes_to_read() {
+            Ok(0) | Err(_) => 0,
+            Ok(available) => {
+                let count = (available as usize).min(buf.len());
+                self.master.read(&mut buf[..count]).unwrap_or(0)
+            }
+        }
+    }
+}
@@ -0,0 +1,287 @@
+use bbqueue::{Consumer, Producer};
+use firmware::clock;
+use firmware::msg_types::MsgTypes;
+use firmware::traits;
+use time::PrimitiveDateTime;
+
+// +--------------------------------------------------------------------------+
+// |                               GPIO Output                                |
+// +--------------------------------------------------------------------------+
+
+/// The LED of the Nucleo board, nobody looks at it
+pub struct SimulatedLed {
+    value: bool,
+}
+
+impl SimulatedLed {
+    pub fn new() -> Self {
+        Self { value: false }
+    }
+}
+
+impl traits::GpioOutput for SimulatedLed {
+    fn set_output(&mut self, value: bool) {
+        self.value = value;
+    }
+
+    fn get_output(&mut self) -> bool {
+        self.value
+    }
+}
+
+// +--------------------------------------------------------------------------+
+// |                             Serial Receiver                              |
+// +--------------------------------------------------------------------------+
+
+pub struct SerialReceiver<'a, const N: usize> {
+    cons_rx: Consumer<'a, N>,
+}
+
+impl<'a, const N: usize> SerialReceiver<'a, N> {
+    pub fn new(cons_rx: Consumer<'a, N>) -> Self {
+        Self { cons_rx }
+    }
+}
+
+impl<const N: usize> traits::SerialReceiver for SerialReceiver<'_, N> {
+    fn receive(&mut self) -> Option<Result<MsgTypes, traits::InvalidPacket>> {
+        let mut received = None;
+        transmission::receive::receive::<MsgTypes, N>(&mut self.cons_rx, |val| {
+            received = Some(val.map_err(|_| traits::InvalidPacket));
+        });
+        received
+    }
+}
+
+// +--------------------------------------------------------------------------+
+// |                            Serial Transmitter                            |
+// +--------------------------------------------------------------------------+
+
+pub struct SerialTransmitter<'a, const N: usize> {
+    prod_tx: Producer<'a, N>,
+    /// whether telemetry is sent, faster than real time the UART can't keep up with all of it
+    pub send_telemetry: bool,
+}
+
+impl<'a, const N: usize> SerialTransmitter<'a, N> {
+    pub fn new(prod_tx: Producer<'a, N>) -> Self {
+        Self {
+            prod_tx,
+            send_telemetry: true,
+        }
+    }
+}
+
+impl<const N: usize> traits::SerialTransmitter for SerialTransmitter<'_, N> {
+    fn transmit(&mut self, msg: MsgTypes) {
+        if !self.send_telemetry && matches!(msg, MsgTypes::Telemetry(_, _)) {
+            return;
+        }
+        // like on the board, if the client doesn't read fast enough the message is dropped
+        transmission::send::send(&mut self.prod_tx, msg).ok();
+    }
+}
+
+// +--------------------------------------------------------------------------+
+// |                                  Flash                                   |
+// +--------------------------------------------------------------------------+
+
+/// Keeps the configuration while the simulator runs, like the flash of the board across resets
+pub struct SimulatedFlash {
+    memory: Vec<u8>,
+    sector_size: u32,
+}
+
+impl SimulatedFlash {
+    pub fn new(sector_size: u32) -> Self {
+        Self {
+            memory: vec![0xFF; 2 * sector_size as usize],
+            sector_size,
+        }
+    }
+}
+
+impl traits::Flash for SimulatedFlash {
+    fn sector_size(&self) -> u32 {
+        self.sector_size
+    }
+
+    fn read(&mut self, offset: u32, buf: &mut [u8]) {
+        let offset = offset as usize;
+        buf.copy_from_slice(&self.memory[offset..offset + buf.len()]);
+    }
+
+    fn write(&mut self, offset: u32, data: &[u8]) -> Result<(), traits::FlashError> {
+        let memory = &mut self.memory[offset as usize..offset as usize + data.len()];
+        // programming can only clear bits
+        for (byte, value) in memory.iter_mut().zip(data) {
+            *byte &= value;
+        }
+        Ok(())
+    }
+
+    fn erase(&mut self, sector: u8) -> Result<(), traits::FlashError> {
+        let start = sector as usize * self.sector_size as usize;
+        self.memory[start..start + self.sector_size as usize].fill(0xFF);
+        Ok(())
+    }
+}
+
+// +--------------------------------------------------------------------------+
+// |                              Block Storage                               |
+// +--------------------------------------------------------------------------+
+
+/// Holds the measurement log while the simulator runs, like the SPI flash of the board
+pub struct SimulatedBlockStorage {
+    memory: Vec<u8>,
+    block_size: u32,
+}
+
+impl SimulatedBlockStorage {
+    pub fn new(capacity: u32, block_size: u32) -> Self {
+        Self {
+            memory: vec![0xFF; capacity as usize],
+            block_size,
+        }
+    }
+}
+
+impl traits::BlockStorage for SimulatedBlockStorage {
+    fn capacity(&self) -> u32 {
+        self.memory.len() as u32
+    }
+
+    fn block_size(&self) -> u32 {
+        self.block_size
+    }
+
+    fn read(&mut self, address: u32, buf: &mut [u8]) -> Result<(), traits::FlashError> {
+        let address = address as usize;
+        buf.copy_from_slice(&self.memory[address..address + buf.len()]);
+        Ok(())
+    }
+
+    fn write(&mut self, address: u32, data: &[u8]) -> Result<(), traits::FlashError> {
+        let memory = &mut self.memory[address as usize..address as usize + data.len()];
+        // programming can only clear bits
+        for (byte, value) in memory.iter_mut().zip(data) {
+            *byte &= value;
+        }
+        Ok(())
+    }
+
+    fn erase_block(&mut self, block: u32) -> Result<(), traits::FlashError> {
+        let start = (block * self.block_size) as usize;
+        self.memory[start..start + self.block_size as usize].fill(0xFF);
+        Ok(())
+    }
+
+    fn erase_all(&mut self) -> Result<(), traits::FlashError> {
+        self.memory.fill(0xFF);
+        Ok(())
+    }
+}
+
+// +--------------------------------------------------------------------------+
+// |                                  Clocks                                  |
+// +--------------------------------------------------------------------------+
+
+/// Time of the simulation, it advances by the sample period however fast the simulation runs
+pub struct SimulatedClock {
+    /// in µs
+    time: u64,
+}
+
+impl SimulatedClock {
+    pub fn new() -> Self {
+        Self { time: 0 }
+    }
+
+    pub fn tick(&mut self, step_micros: u32) {
+        self.time += step_micros as u64;
+    }
+}
+
+impl traits::SystemTime for SimulatedClock {
+    fn get_micros(&mut self) -> u64 {
+        self.time
+    }
+}
+
+/// Runs with the clock of the host, starts at its time
+pub struct SimulatedRtc {
+    /// difference to the Unix time of the host, in ms
+    offset: i64,
+}
+
+impl SimulatedRtc {
+    pub fn new() -> Self {
+        Self { offset: 0 }
+    }
+
+    /// in ms
+    fn host_time() -> i64 {
+        let time = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH);
+        time.map_or(0, |time| time.as_millis() as i64)
+    }
+}
+
+impl traits::RealTimeClock for SimulatedRtc {
+    fn set_datetime(&mut self, datetime: PrimitiveDateTime) -> Result<(), traits::RtcError> {
+        self.offset = clock::to_unix_time(datetime) as i64 - Self::host_time();
+        Ok(())
+    }
+
+    fn get_datetime(&mut self) -> PrimitiveDateTime {
+        let time = (Self::host_time() + self.offset).max(0) as u64;
+        clock::to_datetime(time).unwrap_or(PrimitiveDateTime::MIN)
+    }
+}
+
+// +--------------------------------------------------------------------------+
+// |                                 Watchdog                                 |
+// +--------------------------------------------------------------------------+
+
+/// The simulation can't reset itself, it is never reset by the watchdog
+pub struct SimulatedWatchdog {}
+
+impl SimulatedWatchdog {
+    pub fn new() -> Self {
+        Self {}
+    }
+}
+
+impl traits::Watchdog for SimulatedWatchdog {
+    fn feed(&mut self) {}
+
+    fn caused_reset(&self) -> bool {
+        false
+    }
+}
+
+/// Lost when the simulator exits, like the backup registers when the board is powered down
+pub struct SimulatedRetainedMemory {
+    memory: Vec<u8>,
+}
+
+impl SimulatedRetainedMemory {
+    pub fn new(size: usize) -> Self {
+        Self {
+            memory: vec![0; size],
+        }
+    }
+}
+
+impl traits::RetainedMemory for SimulatedRetainedMemory {
+    fn capacity(&self) -> usize {
+        self.memory.len()
+    }
+
+    fn read(&mut self, buf: &mut [u8]) {
+        buf.copy_from_slice(&self.memory[..buf.len()]);
+    }
+
+    fn write(&mut self, data: &[u8]) {
+        self.memory[..data.len()].copy_from_slice(data);
+    }
+}
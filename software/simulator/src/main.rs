@@ -0,0 +1,208 @@
+use std::cell::RefCell;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+use bbqueue::BBBuffer;
+use firmware::clock::Clock;
+use firmware::config::DEFAULT_SAMPLE_PERIOD;
+use firmware::crash::{self, ResetCause};
+use firmware::datalog::MeasurementLog;
+use firmware::limits::SafetyLimits;
+use firmware::msg_types::MsgTypes;
+use firmware::recovery::{self, Recovery};
+use firmware::sim::{self, BatterySimulation};
+use firmware::traits::SerialTransmitter as _;
+use firmware::BatteryTestUnit;
+use heapless::String;
+
+use crate::connection::Connection;
+use crate::interfaces::{
+    SerialReceiver, SerialTransmitter, SimulatedBlockStorage, SimulatedClock, SimulatedFlash,
+    SimulatedLed, SimulatedRetainedMemory, SimulatedRtc, SimulatedWatchdog,
+};
+
+mod connection;
+mod interfaces;
+
+/// Same as on the board
+const BATTERY_TEST_UNITS: usize = 4;
+const BUFFER_SIZE: usize = 1024;
+/// Smaller than the 128 KiB sectors of the board, still room for 16 records each
+const FLASH_SECTOR_SIZE: u32 = 16 * 1024;
+/// Same as the SST25VF080B of the board
+const LOG_CAPACITY: u32 = 1024 * 1024;
+const LOG_BLOCK_SIZE: u32 = 4096;
+/// Telemetry is sent at most this often in real time
+const TELEMETRY_PERIOD: Duration = Duration::from_millis(100);
+
+const USAGE: &str = "\
+Runs the firmware with simulated LiFePO4 cells, the client connects to it like to the board
+
+Usage: simulator [OPTIONS]
+
+Options:
+  --speed <FACTOR>    how much faster than real time the simulation runs [default: 1]
+  --baud <RATE>       baud rate of the simulated UART [default: 921600]
+  --capacity <AH>     capacity of the cells [default: 1.5]
+  --soc <SOC>         state of charge the cells start with, from 0 to 1 [default: 0.5]
+  --help              print this help";
+
+struct Options {
+    speed: f64,
+    baud: u32,
+    capacity: f32,
+    soc: f32,
+}
+
+fn parse_value<T: FromStr>(arg: &str, value: &str) -> Result<T, std::string::String> {
+    value
+        .parse()
+        .map_err(|_| format!("invalid value for {}: {}", arg, value))
+}
+
+fn parse_options() -> Result<Options, std::string::String> {
+    let mut options = Options {
+        speed: 1.0,
+        baud: 921_600,
+        capacity: 1.5,
+        soc: 0.5,
+    };
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--help" {
+            println!("{}", USAGE);
+            std::process::exit(0);
+        }
+
+        let value = args
+            .next()
+            .ok_or_else(|| format!("{} needs a value", arg))?;
+        match arg.as_str() {
+            "--speed" => options.speed = parse_value(&arg, &value)?,
+            "--baud" => options.baud = parse_value(&arg, &value)?,
+            "--capacity" => options.capacity = parse_value(&arg, &value)?,
+            "--soc" => options.soc = parse_value(&arg, &value)?,
+            _ => return Err(format!("unknown option {}", arg)),
+        }
+    }
+
+    if !(options.speed > 0.0 && options.capacity > 0.0 && options.baud > 0) {
+        return Err(std::string::String::from(
+            "speed, baud rate and capacity have to be positive",
+        ));
+    }
+    Ok(options)
+}
+
+fn main() {
+    let options = parse_options().unwrap_or_else(|error| {
+        eprintln!("{}\n\n{}", error, USAGE);
+        std::process::exit(2);
+    });
+
+    let mut connection = Connection::pty().unwrap_or_else(|error| {
+        eprintln!("couldn't open the connection: {}", error);
+        std::process::exit(1);
+    });
+    println!("the client can connect to {}", connection.describe());
+
+    let buf_rx: BBBuffer<BUFFER_SIZE> = BBBuffer::new();
+    let buf_tx: BBBuffer<BUFFER_SIZE> = BBBuffer::new();
+    let (mut prod_rx, cons_rx) = buf_rx.try_split().unwrap();
+    let (mut prod_tx, mut cons_tx) = buf_tx.try_split().unwrap();
+    transmission::send::setup(&mut prod_tx);
+
+    let simulations: [RefCell<BatterySimulation>; BATTERY_TEST_UNITS] =
+        core::array::from_fn(|_| {
+            RefCell::new(BatterySimulation::lifepo4(options.capacity, options.soc))
+        });
+    let battery_units: [_; BATTERY_TEST_UNITS] = core::array::from_fn(|id| {
+        let (voltage, current, temperature, load, charger_enable, charge_done) =
+            sim::channels(&simulations[id]);
+        let mut btu = BatteryTestUnit::new(
+            id as u8,
+            voltage,
+            current,
+            temperature,
+            load,
+            charger_enable,
+            charge_done,
+        );
+        btu.set_limits(SafetyLimits::LIFEPO4);
+        btu
+    });
+
+    let mut fm = firmware::Firmware {
+        serial_receiver: SerialReceiver::new(cons_rx),
+        serial_transmitter: SerialTransmitter::new(prod_tx),
+        on_board_led: SimulatedLed::new(),
+        flash: SimulatedFlash::new(FLASH_SECTOR_SIZE),
+        sample_period: DEFAULT_SAMPLE_PERIOD,
+        log_storage: SimulatedBlockStorage::new(LOG_CAPACITY, LOG_BLOCK_SIZE),
+        log: MeasurementLog::new(),
+        monotonic: SimulatedClock::new(),
+        rtc: SimulatedRtc::new(),
+        clock: Clock::new(),
+        watchdog: SimulatedWatchdog::new(),
+        retained: SimulatedRetainedMemory::new(recovery::RECORD_SIZE),
+        crash_storage: SimulatedRetainedMemory::new(crash::RECORD_SIZE),
+        recovery: Recovery::new(),
+        battery_units,
+    };
+    fm.load_time();
+    fm.check_crash_report(ResetCause::PowerOn);
+    fm.check_interrupted_tests();
+    fm.serial_transmitter
+        .transmit(MsgTypes::Msg(String::from("Init done")));
+
+    // bytes the simulated UART may still send, 10 bits per byte
+    let mut uart_budget = 0.0;
+    let mut last_update = Instant::now();
+    let mut last_telemetry = Instant::now();
+    let mut next_update = Instant::now();
+    // time until the next update, in µs
+    let mut period_micros = fm.get_update_period() as u32 * 1000;
+
+    loop {
+        connection.receive(&mut prod_rx);
+        fm.update_serial();
+
+        let now = Instant::now();
+        fm.serial_transmitter.send_telemetry = now - last_telemetry >= TELEMETRY_PERIOD;
+        if fm.serial_transmitter.send_telemetry {
+            last_telemetry = now;
+        }
+        // the battery test units are updated in simulated time whenever the next one has to be
+        // sampled, like on the board
+        for simulation in simulations.iter() {
+            simulation.borrow_mut().update(period_micros as f32 / 1e6);
+        }
+        fm.monotonic.tick(period_micros);
+        fm.update();
+        fm.update_watchdog();
+        let elapsed_micros = period_micros;
+        period_micros = fm.get_update_period() as u32 * 1000;
+
+        uart_budget += (now - last_update).as_secs_f64() * options.baud as f64 / 10.0;
+        uart_budget = uart_budget.min(BUFFER_SIZE as f64);
+        last_update = now;
+        if let Ok(grant) = cons_tx.read() {
+            let count = grant.len().min(uart_budget as usize);
+            let count = connection.transmit(&grant[..count]);
+            uart_budget -= count as f64;
+            grant.release(count);
+        }
+
+        next_update += Duration::from_micros(elapsed_micros as u64).div_f64(options.speed);
+        match next_update.checked_duration_since(Instant::now()) {
+            // far faster than real time it sleeps once several updates are ahead
+            Some(remaining) if remaining >= Duration::from_millis(1) => {
+                std::thread::sleep(remaining)
+            }
+            Some(_) => {}
+            // the simulation can't keep up, it doesn't try to catch up later
+            None => next_update = Instant::now(),
+        }
+    }
+}